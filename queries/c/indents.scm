@@ -0,0 +1,8 @@
+[
+  (compound_statement)
+  (field_declaration_list)
+  (enumerator_list)
+  (argument_list)
+  (parameter_list)
+  (initializer_list)
+] @indent
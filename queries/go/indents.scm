@@ -0,0 +1,8 @@
+[
+  (block)
+  (field_declaration_list)
+  (interface_type)
+  (argument_list)
+  (parameter_list)
+  (literal_value)
+] @indent
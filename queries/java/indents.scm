@@ -0,0 +1,8 @@
+[
+  (block)
+  (class_body)
+  (interface_body)
+  (argument_list)
+  (formal_parameters)
+  (array_initializer)
+] @indent
@@ -0,0 +1,9 @@
+[
+  (statement_block)
+  (object)
+  (array)
+  (arguments)
+  (formal_parameters)
+  (class_body)
+  (switch_body)
+] @indent
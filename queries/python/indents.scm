@@ -0,0 +1,9 @@
+[
+  (block)
+  (list)
+  (dictionary)
+  (set)
+  (tuple)
+  (argument_list)
+  (parameters)
+] @indent
@@ -0,0 +1,14 @@
+[
+  (block)
+  (declaration_list)
+  (field_declaration_list)
+  (enum_variant_list)
+  (match_block)
+  (arguments)
+  (parameters)
+  (array_expression)
+  (field_initializer_list)
+  (use_list)
+  (token_tree)
+  (tuple_expression)
+] @indent
@@ -0,0 +1,10 @@
+[
+  (statement_block)
+  (object)
+  (array)
+  (arguments)
+  (formal_parameters)
+  (class_body)
+  (switch_body)
+  (object_type)
+] @indent
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use inkjet::Language;
+use serde::Deserialize;
+use crate::editor::indent::Indentation;
+
+/// User settings read from `config.toml` in the config directory
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Key sequences such as `"ctrl+x ctrl+s"`, mapped to action names
+    pub(crate) keys: BTreeMap<String, String>,
+    /// Whether panes open with vim-style modal editing
+    pub(crate) vim: bool,
+    /// Whether brackets are colored by nesting depth
+    pub(crate) rainbow: bool,
+    /// Whether the workspace file in the working directory is opened on startup
+    pub(crate) open_workspace: bool,
+    /// Indentation for files whose own can't be detected, by language name or extension, or `default` for any language
+    pub(crate) indent: BTreeMap<String, IndentConfig>,
+    /// Language servers by language name or extension, replacing the built-in ones
+    pub(crate) lsp: BTreeMap<String, ServerConfig>,
+}
+
+#[derive(Debug, Default, Deserialize, Copy, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct IndentConfig {
+    style: Option<IndentStyle>,
+    size: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ServerConfig {
+    /// Program to run, or empty to not use a server for the language
+    pub(crate) command: String,
+    #[serde(default)]
+    pub(crate) args: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum IndentStyle {
+    Tabs,
+    Spaces,
+}
+
+impl Config {
+    /// `$BENCHIDE_CONFIG_DIR`, falling back to `benchide` in the platform's usual config location
+    pub(crate) fn dir() -> Option<PathBuf> {
+        if let Some(dir) = env::var_os("BENCHIDE_CONFIG_DIR") {
+            return Some(PathBuf::from(dir));
+        }
+
+        let base = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(base.join("benchide"))
+    }
+
+    /// Reads the config file, using the defaults if there isn't one
+    pub(crate) fn load() -> Result<Self, String> {
+        let Some(path) = Self::dir().map(|dir| dir.join("config.toml")) else {
+            return Ok(Self::default());
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).map_err(|error| format!("{}: {}", path.display(), error.message())),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(format!("{}: {}", path.display(), error)),
+        }
+    }
+
+    /// Indentation for `language`, with its settings layered over the `default` ones
+    pub(crate) fn indentation(&self, language: Language) -> Indentation {
+        let mut indent = Indentation::default();
+        let matching = self.indent.iter()
+            .filter(|(name, _)| Language::from_token(name) == Some(language))
+            .map(|(_, config)| config);
+
+        for config in self.indent.get("default").into_iter().chain(matching) {
+            if let Some(style) = config.style {
+                indent.tabs = style == IndentStyle::Tabs;
+            }
+            if let Some(size) = config.size {
+                indent.size = size.max(1);
+            }
+        }
+
+        indent
+    }
+}
@@ -1,15 +1,131 @@
+use std::collections::BTreeSet;
 use std::ffi::OsStr;
-use std::fs::File;
+use std::fs;
 use std::io;
-use std::io::{BufRead, BufReader, Write};
+use std::io::Write;
+use std::ops::Range;
 use std::path::Path;
 use macroquad::prelude::*;
+use crate::editorconfig::{Charset, EditorConfig, LineEnding};
+use crate::editor::brackets::Bracket;
+use crate::editor::diagnostics::Diagnostic;
+use crate::editor::folding::FoldRegion;
+use crate::editor::git::Hunk;
+use crate::editor::hex::Hex;
+use crate::editor::indent::Indentation;
+use crate::editor::completion::CompletionMenu;
+use crate::editor::history::{Edit, History};
+use crate::editor::snippets::{Snippet, SnippetSession};
+use crate::editor::vim::{Mode, Registers, Vim};
+use crate::keymap::Action;
+use crate::layout::TextLayout;
+use crate::lsp::{CompletionItem, Position, TextEdit};
 use crate::theme::Theme;
 use crate::window::{set_camera_window, set_fullscreen_camera};
 use inkjet::{Highlighter, Language};
 use inkjet::constants::HIGHLIGHT_NAMES;
 use inkjet::theme::vendored;
 use inkjet::tree_sitter_highlight::HighlightEvent;
+use tree_sitter::{InputEdit, Parser, Tree};
+
+pub(crate) mod vim;
+pub(crate) mod indent;
+pub(crate) mod brackets;
+pub(crate) mod folding;
+pub(crate) mod diagnostics;
+pub(crate) mod lsp;
+pub(crate) mod tags;
+pub(crate) mod completion;
+pub(crate) mod history;
+pub(crate) mod snippets;
+pub(crate) mod hex;
+pub(crate) mod git;
+
+/// Colors of the byte ranges of `code`, in order, as the highlighter sees them in `language`
+pub(crate) fn highlight(highlighter: &mut Highlighter, language: Language, code: &str, theme: &Theme) -> inkjet::Result<Vec<(Range<usize>, Color)>> {
+    let highlights = highlighter.highlight_raw(language, &code)?;
+    let inkjet_theme = inkjet::theme::Theme::from_helix(vendored::CATPPUCCIN_MOCHA)?;
+
+    let mut spans = vec![];
+    let mut color = theme.text;
+
+    for highlight in highlights {
+        match highlight? {
+            HighlightEvent::HighlightStart(style) => {
+                if let Some(fg) = inkjet_theme.get_style(HIGHLIGHT_NAMES[style.0]).and_then(|s| s.fg) {
+                    color = Color::from_rgba(fg.r, fg.g, fg.b, 255);
+                }
+            }
+            HighlightEvent::Source { start, end } => spans.push((start..end, color)),
+            HighlightEvent::HighlightEnd => color = theme.text,
+        }
+    }
+
+    Ok(spans)
+}
+
+/// Color spans of `code`, which starts on row `first`, from the byte ranges the highlighter gave them
+fn color_spans(code: &str, spans: Vec<(Range<usize>, Color)>, first: usize) -> Vec<ColorSpan> {
+    let (mut point, mut at) = (Point::new(first, 0), 0);
+    // Spans come in order, so each point is found from the last
+    let mut point_at = |byte: usize| {
+        for b in code[at..byte].bytes() {
+            point = match b {
+                b'\n' => Point::new(point.row + 1, 0),
+                _ => Point::new(point.row, point.column + 1),
+            };
+        }
+        at = byte;
+        point
+    };
+
+    spans.into_iter()
+        .map(|(range, color)| ColorSpan { start: point_at(range.start), end: point_at(range.end), color })
+        .collect()
+}
+
+/// How lines are written to a file
+struct Format {
+    line_ending: LineEnding,
+    charset: Charset,
+    final_newline: bool,
+    trim_trailing_whitespace: bool,
+}
+
+/// `lines` joined and encoded as they're saved in `format`
+fn encode_lines(lines: &[String], format: &Format) -> io::Result<Vec<u8>> {
+    let lines = lines.iter().map(|line| match format.trim_trailing_whitespace {
+        true => line.trim_end(),
+        false => line.as_str(),
+    });
+    let mut text = lines.collect::<Vec<_>>().join(format.line_ending.as_str());
+    // An empty buffer stays an empty file rather than a lone line ending
+    if format.final_newline && !text.is_empty() {
+        text.push_str(format.line_ending.as_str());
+    }
+
+    format.charset.encode(&text)
+}
+
+/// The edit tree-sitter needs to reuse a tree of `old` for `new`: everything between their common start and end
+fn input_edit(old: &[u8], new: &[u8]) -> InputEdit {
+    let start = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let end = old[start..].iter().rev().zip(new[start..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let position = |text: &[u8], byte: usize| {
+        let row = text[..byte].iter().filter(|&&b| b == b'\n').count();
+        let column = byte - text[..byte].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        tree_sitter::Point { row, column }
+    };
+
+    InputEdit {
+        start_byte: start,
+        old_end_byte: old.len() - end,
+        new_end_byte: new.len() - end,
+        start_position: position(old, start),
+        old_end_position: position(old, old.len() - end),
+        new_end_position: position(new, new.len() - end),
+    }
+}
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
 pub struct Point {
@@ -23,6 +139,31 @@ impl Point {
     }
 }
 
+/// Lines an edit added or removed just below `row`, for moving things attached to later rows along with them
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct RowShift {
+    pub(crate) row: usize,
+    delta: isize,
+}
+
+impl RowShift {
+    /// The shift from an edit starting on `row` that took the buffer from `rows_before` to `rows_after` lines
+    fn new(row: usize, rows_before: usize, rows_after: usize) -> Option<Self> {
+        let delta = rows_after as isize - rows_before as isize;
+        (delta != 0).then_some(Self { row, delta })
+    }
+
+    /// Where a row from before the edit is now, unless it was removed
+    pub(crate) fn moved(&self, row: usize) -> Option<usize> {
+        let removed = self.row + 1..=self.row.saturating_add_signed(-self.delta);
+        match row {
+            _ if removed.contains(&row) => None,
+            row if row > self.row => Some(row.saturating_add_signed(self.delta)),
+            row => Some(row),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct ColorSpan {
     start: Point,
@@ -30,150 +171,731 @@ struct ColorSpan {
     color: Color,
 }
 
+/// One row on screen: a whole line, or a segment of one when soft wrapping
+#[derive(Debug, Copy, Clone)]
+struct VisualLine {
+    row: usize,
+    start: usize,
+    end: usize,
+}
+
 pub(crate) struct Editor {
     lines: Vec<String>,
     cursor_position: Point, // line, character
     colors: Vec<ColorSpan>,
+    /// Whether `colors` is out of date with the text, as it is after loading
+    colors_stale: bool,
     pub(crate) window: Rect,
-    font_size: u16,
+    layout: TextLayout,
     offset: Vec2,
+    /// Horizontal position that vertical movement tries to return to
+    desired_x: Option<f32>,
+    moved_vertically: bool,
+    /// The other end of the selection from the cursor
+    anchor: Option<Point>,
+    soft_wrap: bool,
+    vim: Option<Vim>,
+    indent: Indentation,
+    /// Whether `indent` was worked out from the text rather than settings
+    indent_detected: bool,
+    /// Syntax tree of the buffer as of the last update
+    tree: Option<Tree>,
+    brackets: Vec<Bracket>,
+    fold_regions: Vec<FoldRegion>,
+    /// First rows of the regions that are folded
+    folded: BTreeSet<usize>,
+    /// Whether brackets are colored by how deeply they're nested
+    rainbow: bool,
+    /// Settings from `.editorconfig` files, which take precedence over detected and configured ones
+    editorconfig: EditorConfig,
+    line_ending: LineEnding,
+    charset: Charset,
+    /// Whether the file ends with a line ending
+    final_newline: bool,
+    /// Problems reported by the language server, in buffer order
+    diagnostics: Vec<Diagnostic>,
+    /// Hover text from the language server, shown until the next message
+    hover: Option<String>,
+    completion: Option<CompletionMenu>,
+    /// Set when the menu opens, for the app to add words from other panes and ask the language server
+    completion_requested: bool,
+    /// Rows moved by the last update, for the app to move connectors along with them
+    row_shift: Option<RowShift>,
+    /// Whether the text can't be edited or saved
+    read_only: bool,
+    /// Whether the app made the text rather than reading it from a file, as for `keys`
+    generated: bool,
+    history: History,
+    /// Rows edited while handling the current message, as they were before it
+    pending: Option<Edit>,
+    /// Snippets for the pane's language, by trigger word
+    snippets: Vec<Snippet>,
+    /// The snippet whose tab stops are being filled in
+    snippet: Option<SnippetSession>,
+    /// The file's bytes, when it's binary and shown as hex instead of text
+    hex: Option<Hex>,
+    /// Lines of the file as of git's HEAD, if it's committed
+    head: Option<Vec<String>>,
+    hunks: Vec<Hunk>,
+    /// Hunk whose old lines are drawn under it
+    shown_hunk: Option<Hunk>,
     filename: String
 }
 
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum CharClass {
+    Whitespace,
+    Word,
+    Punctuation,
+}
+
+impl CharClass {
+    fn of(character: char) -> Self {
+        if character.is_whitespace() {
+            CharClass::Whitespace
+        } else if character.is_alphanumeric() || character == '_' {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum EditorMessage {
     Keypress(KeyCode),
     Char(char),
+    Click(Vec2),
+    Action(Action),
+    /// Responses from the language server
+    Hover(String),
+    Completions(Vec<CompletionItem>),
+    Edits(Vec<TextEdit>),
+    JumpTo(Position),
+    Diagnostics(Vec<crate::lsp::Diagnostic>),
+    /// Words from every open pane, to complete from
+    Words(Vec<String>),
 }
 
 impl Editor {
     pub fn new(window: Rect, font_size: u16, filename: String) -> Self {
-        let editor = Self {
+        Self {
             lines: vec!["".to_owned()],
             cursor_position: Point::new(0, 0),
             colors: vec![],
+            colors_stale: true,
             window,
-            font_size,
+            layout: TextLayout::new(font_size),
             offset: Vec2::ZERO,
+            desired_x: None,
+            moved_vertically: false,
+            anchor: None,
+            soft_wrap: false,
+            vim: None,
+            indent: Indentation::default(),
+            indent_detected: false,
+            tree: None,
+            brackets: vec![],
+            fold_regions: vec![],
+            folded: BTreeSet::new(),
+            rainbow: false,
+            editorconfig: EditorConfig::default(),
+            line_ending: LineEnding::Lf,
+            charset: Charset::Utf8,
+            final_newline: true,
+            diagnostics: vec![],
+            hover: None,
+            completion: None,
+            completion_requested: false,
+            row_shift: None,
+            read_only: false,
+            generated: false,
+            history: History::default(),
+            pending: None,
+            snippets: vec![],
+            snippet: None,
+            hex: None,
+            head: None,
+            hunks: vec![],
+            shown_hunk: None,
             filename
-        };
-
-        editor
+        }
     }
 
     pub(crate) fn load_string(&mut self, string: String) {
         self.lines = string.lines().map(|s| s.to_string()).collect();
+        self.loaded();
     }
 
+    /// Reads the file, keeping its line endings, final newline and encoding for when it is saved.
+    /// `.editorconfig` settings override what the file uses, and also apply to files that don't exist yet.
     pub(crate) fn load_file(&mut self) -> io::Result<()> {
-        let file = File::open(&self.filename)?;
-        self.lines = BufReader::new(file).lines().collect::<Result<_, _>>()?;
+        self.editorconfig = EditorConfig::resolve(Path::new(&self.filename));
+        let config = &self.editorconfig;
+        self.line_ending = config.end_of_line.unwrap_or(self.line_ending);
+        self.charset = config.charset.unwrap_or(self.charset);
+        self.final_newline = config.insert_final_newline.unwrap_or(self.final_newline);
+
+        let bytes = fs::read(&self.filename)?;
+        if hex::is_binary(&bytes) {
+            // Binary files are only written to once asked for with `hex edit`
+            self.hex = Some(Hex::new(bytes, 0, false));
+            return Ok(());
+        }
+
+        self.load_text(&bytes)?;
+        self.read_head();
+        self.loaded();
 
         Ok(())
     }
 
-    fn move_cursor(&mut self, key: KeyCode) {
-        use KeyCode::*;
-        match key {
-            Up => {
-                if self.cursor_position.row > 0 {
-                    self.cursor_position.row -= 1
-                }
+    /// Decodes the buffer from a file's bytes, taking its charset, line endings and final newline from them
+    /// where `.editorconfig` doesn't set them
+    fn load_text(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let config = &self.editorconfig;
+        let charset = config.charset.unwrap_or_else(|| Charset::detect(bytes));
+        let text = charset.decode(bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "file doesn't match its charset"))?;
+
+        let (lines, final_newline) = LineEnding::split(&text);
+        self.lines = lines;
+        self.charset = charset;
+        self.line_ending = config.end_of_line.unwrap_or_else(|| LineEnding::detect(&text));
+        self.final_newline = config.insert_final_newline.unwrap_or(final_newline);
+
+        Ok(())
+    }
+
+    fn loaded(&mut self) {
+        if self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+
+        self.folded.clear();
+        self.history.clear();
+        self.replaced();
+    }
+
+    /// Works out everything that depends on the text again after all of it was replaced
+    fn replaced(&mut self) {
+        let detected = Indentation::detect(&self.lines);
+        self.indent_detected = detected.is_some();
+        self.set_indentation(detected.unwrap_or(self.indent));
+        self.tree = self.parse(None);
+        self.colors_stale = true;
+        self.brackets = self.find_brackets();
+        self.fold_regions = self.find_fold_regions();
+        let regions = &self.fold_regions;
+        self.folded.retain(|start| regions.binary_search_by_key(start, |region| region.start).is_ok());
+        self.snippet = None;
+        self.diff_head();
+
+        // Reloading can leave the cursor past the end of shorter text
+        self.cursor_position = self.clamp_point(self.cursor_position);
+        self.anchor = None;
+    }
+
+    /// Uses `indent`, except where `.editorconfig` says otherwise
+    pub(crate) fn set_indentation(&mut self, indent: Indentation) {
+        let config = &self.editorconfig;
+        self.indent = Indentation {
+            tabs: config.indent_tabs.unwrap_or(indent.tabs),
+            size: config.indent_size.unwrap_or(indent.size),
+        };
+        self.layout.set_tab_width(config.tab_width.unwrap_or(self.indent.size));
+    }
+
+    /// Uses `indent` unless the file's own indentation could be detected
+    pub(crate) fn set_default_indentation(&mut self, indent: Indentation) {
+        if !self.indent_detected {
+            self.set_indentation(indent);
+        }
+    }
+
+    pub(crate) fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    pub(crate) fn set_charset(&mut self, charset: Charset) {
+        self.charset = charset;
+    }
+
+    pub(crate) fn set_final_newline(&mut self, final_newline: bool) {
+        self.final_newline = final_newline;
+    }
+
+    /// Marks the text as made by the app, which makes it read-only and keeps the pane out of workspaces
+    pub(crate) fn set_generated(&mut self) {
+        self.generated = true;
+        self.read_only = true;
+    }
+
+    pub(crate) fn generated(&self) -> bool {
+        self.generated
+    }
+
+    /// Where the last update added or removed lines, if it did
+    pub(crate) fn take_row_shift(&mut self) -> Option<RowShift> {
+        self.row_shift.take()
+    }
+
+    /// Points the pane at a file that has been moved, without reloading it
+    pub(crate) fn set_filename(&mut self, filename: String) {
+        self.filename = filename;
+    }
+
+    pub(crate) fn set_rainbow(&mut self, enabled: bool) {
+        self.rainbow = enabled;
+    }
+
+    /// Switches vim-style modal editing on or off, starting in normal mode
+    pub(crate) fn set_vim(&mut self, enabled: bool) {
+        self.vim = enabled.then(Vim::new);
+        self.anchor = None;
+    }
+
+    /// Wraps lines at the pane width without changing the text
+    pub(crate) fn toggle_soft_wrap(&mut self) {
+        self.soft_wrap = !self.soft_wrap;
+        self.offset.x = 0.0;
+    }
+
+    fn move_cursor(&mut self, motion: Action) {
+        use Action::*;
+        let point = self.cursor_position;
+
+        self.cursor_position = match motion {
+            MoveUp => return self.move_vertically(-1),
+            MoveDown => return self.move_vertically(1),
+            PageUp | PageDown => {
+                let rows = if motion == PageUp { -(self.page_rows() as isize) } else { self.page_rows() as isize };
+                self.move_vertically(rows);
+                return self.scroll(vec2(0.0, rows as f32 * self.layout.line_height()));
             }
-            Left => {
-                if self.cursor_position.column > 0 {
-                    self.cursor_position.column -= 1
-                }
+            MoveLeft => self.prev_point(point),
+            MoveRight => self.next_point(point),
+            WordLeft => self.word_left(point),
+            WordRight => self.word_right(point),
+            LineStart => self.smart_home(point),
+            LineEnd => Point::new(point.row, self.lines[point.row].len()),
+            DocumentStart => Point::new(0, 0),
+            DocumentEnd => self.end_point(),
+            MatchBracket => self.matching_brackets(point).map_or(point, |(_, partner)| partner),
+            _ => point,
+        };
+    }
+
+    /// Moves the cursor by `delta` visual lines, aiming for the column it was on before it started moving vertically
+    fn move_vertically(&mut self, delta: isize) {
+        let visual = self.visual_lines();
+        let i = self.visual_index(&visual, self.cursor_position);
+        let target = i.saturating_add_signed(delta).min(visual.len() - 1);
+        let x = *self.desired_x.get_or_insert(self.segment_x(visual[i], self.cursor_position.column));
+
+        self.cursor_position = self.point_in_segment(visual[target], x);
+        self.moved_vertically = true;
+    }
+
+    /// Number of whole lines that fit in the pane
+    fn page_rows(&self) -> usize {
+        ((self.window.h / self.layout.line_height()) as usize).max(1)
+    }
+
+    fn end_point(&self) -> Point {
+        Point::new(self.lines.len() - 1, self.lines[self.lines.len() - 1].len())
+    }
+
+    /// The char boundary before `point`, wrapping to the end of the previous line
+    fn prev_point(&self, point: Point) -> Point {
+        match self.lines[point.row][..point.column].char_indices().next_back() {
+            Some((idx, _)) => Point::new(point.row, idx),
+            None if point.row > 0 => Point::new(point.row - 1, self.lines[point.row - 1].len()),
+            None => point,
+        }
+    }
+
+    /// The char boundary after `point`, wrapping to the start of the next line
+    fn next_point(&self, point: Point) -> Point {
+        match self.lines[point.row][point.column..].chars().next() {
+            Some(character) => Point::new(point.row, point.column + character.len_utf8()),
+            None if point.row + 1 < self.lines.len() => Point::new(point.row + 1, 0),
+            None => point,
+        }
+    }
+
+    fn char_at(&self, point: Point) -> Option<char> {
+        self.lines[point.row][point.column..].chars().next()
+    }
+
+    /// Start of the word before `point`, skipping any whitespace in between
+    fn word_left(&self, point: Point) -> Point {
+        if point.column == 0 {
+            return self.prev_point(point);
+        }
+
+        let mut point = point;
+        let mut class = None;
+
+        while point.column > 0 {
+            let prev = self.prev_point(point);
+            let prev_class = CharClass::of(self.char_at(prev).unwrap());
+
+            match class {
+                None if prev_class == CharClass::Whitespace => {}
+                None => class = Some(prev_class),
+                Some(class) if class != prev_class => break,
+                Some(_) => {}
+            }
+
+            point = prev;
+        }
+
+        point
+    }
+
+    /// End of the word after `point`, skipping any whitespace in between
+    fn word_right(&self, point: Point) -> Point {
+        if point.column == self.lines[point.row].len() {
+            return self.next_point(point);
+        }
+
+        let mut point = point;
+        let mut class = None;
+
+        while let Some(character) = self.char_at(point) {
+            let next_class = CharClass::of(character);
+
+            match class {
+                None if next_class == CharClass::Whitespace => {}
+                None => class = Some(next_class),
+                Some(class) if class != next_class => break,
+                Some(_) => {}
             }
-            Right => self.cursor_position.column += 1,
-            Down => self.cursor_position.row += 1,
-            _ => {}
+
+            point.column += character.len_utf8();
+        }
+
+        point
+    }
+
+    /// First non-whitespace char of the line, or the start of the line if already there
+    fn smart_home(&self, point: Point) -> Point {
+        let indent = self.indentation(point.row).len();
+
+        Point::new(point.row, if point.column == indent { 0 } else { indent })
+    }
+
+    /// Text between `start` and `end`, with lines joined by newlines
+    fn text_range(&self, start: Point, end: Point) -> String {
+        if start.row == end.row {
+            return self.lines[start.row][start.column..end.column].to_owned();
+        }
+
+        let mut text = self.lines[start.row][start.column..].to_owned();
+        for line in &self.lines[start.row + 1..end.row] {
+            text.push('\n');
+            text.push_str(line);
+        }
+        text.push('\n');
+        text.push_str(&self.lines[end.row][..end.column]);
+
+        text
+    }
+
+    /// Inserts `text` at `point`, splitting lines at newlines, and returns the point after it
+    fn insert_text(&mut self, point: Point, text: &str) -> Point {
+        self.touch(point.row..point.row + 1, 1 + text.matches('\n').count());
+        let tail = self.lines[point.row].split_off(point.column);
+        let mut inserted = text.split('\n');
+
+        self.lines[point.row].push_str(inserted.next().unwrap());
+
+        let mut row = point.row;
+        for line in inserted {
+            row += 1;
+            self.lines.insert(row, line.to_owned());
         }
 
-        if self.cursor_position.row >= self.lines.len() {
-            self.cursor_position.row = self.lines.len() - 1
+        let end = Point::new(row, self.lines[row].len());
+        self.lines[row].push_str(&tail);
+
+        end
+    }
+
+    /// Selected text as a range with an exclusive end
+    fn selection(&self) -> Option<(Point, Point)> {
+        let anchor = self.anchor?;
+        let (start, end) = (anchor.min(self.cursor_position), anchor.max(self.cursor_position));
+
+        match self.vim.as_ref().map(Vim::mode) {
+            Some(Mode::VisualLine) => Some((Point::new(start.row, 0), Point::new(end.row, self.lines[end.row].len()))),
+            Some(Mode::Visual) => Some((start, self.inclusive_end(end))),
+            _ => Some((start, end)),
         }
+    }
+
+    pub(crate) fn cursor_row(&self) -> usize {
+        self.cursor_position.row
+    }
+
+    /// First and last rows of the selection, or the cursor's row when nothing is selected
+    pub(crate) fn selected_rows(&self) -> (usize, usize) {
+        self.selection().map_or((self.cursor_position.row, self.cursor_position.row), |(start, end)| (start.row, end.row))
+    }
 
-        if self.cursor_position.column >= self.lines[self.cursor_position.row].len() {
-            self.cursor_position.column = self.lines[self.cursor_position.row].len()
+    /// Removes the selected text, if any, and ends the selection
+    fn delete_selection(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            self.delete_range(start, end);
         }
+        self.anchor = None;
     }
 
-    fn insert_str_single_line(&mut self, string: &str) {
-        self.lines[self.cursor_position.row].insert_str(self.cursor_position.column, string);
-        self.cursor_position.column += string.len();
+    /// Leading whitespace of line `row`
+    fn indentation(&self, row: usize) -> &str {
+        let line = &self.lines[row];
+        &line[..line.len() - line.trim_start().len()]
+    }
+
+    /// Removes the text between `start` and `end`, leaving the cursor at `start`
+    fn delete_range(&mut self, start: Point, end: Point) {
+        self.touch(start.row..end.row + 1, 1);
+        let tail = self.lines[end.row][end.column..].to_owned();
+
+        self.lines[start.row].truncate(start.column);
+        self.lines[start.row].push_str(&tail);
+        self.lines.drain(start.row + 1..=end.row);
+
+        self.cursor_position = start;
     }
 
     pub fn scroll(&mut self, offset: Vec2) {
+        if let Some(hex) = &mut self.hex {
+            return hex.scroll(offset, &self.layout, self.window);
+        }
+
         self.offset += offset;
 
-        if self.offset.y > self.lines.len() as f32 * self.font_size as f32 - self.window.h {
-            self.offset.y = self.lines.len() as f32 * self.font_size as f32 - self.window.h;
-        }
+        let line_height = self.layout.line_height();
+        let visual = self.visual_lines();
+        let rows = visual.len();
 
+        if self.offset.y > rows as f32 * line_height - self.window.h {
+            self.offset.y = rows as f32 * line_height - self.window.h;
+        }
 
         if self.offset.y < 0.0 {
             self.offset.y = 0.0;
         }
 
-    }
+        let max_x = self.gutter_width() + self.visible_width(&visual) - self.window.w;
 
-    pub fn update(&mut self, message: EditorMessage, highlighter: &mut Highlighter, theme: &Theme) {
-        match message {
-            EditorMessage::Keypress(key) =>
-                match key {
-                    KeyCode::Up | KeyCode::Left | KeyCode::Right | KeyCode::Down => self.move_cursor(key),
-                    KeyCode::Tab => {
-                        self.insert_str_single_line("    ")
-                    }
-                    _ => {}
-                },
+        if self.offset.x > max_x {
+            self.offset.x = max_x;
+        }
 
-            EditorMessage::Char(character) => {
-                let Point { row: y, column: x } = self.cursor_position;
-
-                match character {
-                    '\x08' => {
-                        if x > 0 {
-                            self.lines[y].remove(x - 1);
-                            self.cursor_position.column -= 1;
-                        } else if y > 0 {
-                            let original_line = &self.lines[y].clone();
-                            let restored_position = self.lines[y - 1].len();
-                            self.lines[y - 1] += original_line;
-                            self.lines.remove(y);
-                            self.cursor_position = Point::new(y - 1, restored_position);
-                        }
-                    }
+        if self.offset.x < 0.0 {
+            self.offset.x = 0.0;
+        }
+    }
 
-                    '\t' => {
-                        // Handled by Message::Keypress
-                    }
+    /// Buffer row shown at the top of the window
+    pub(crate) fn top_row(&self) -> usize {
+        let visual = self.visual_lines();
+        visual[self.layout.y_to_row(self.offset.y).min(visual.len() - 1)].row
+    }
 
-                    '\r' => {
-                        let original_line = self.lines[y].clone();
-                        let (first, last) = original_line.split_at(x);
+    /// Scrolls so that `row` is at the top of the window, as far as the buffer allows
+    pub(crate) fn scroll_to_row(&mut self, row: usize) {
+        let visual = self.visual_lines();
+        let k = self.visual_index(&visual, Point::new(row.min(self.lines.len() - 1), 0));
+        self.offset.y = k as f32 * self.layout.line_height();
+        self.scroll(Vec2::ZERO);
+    }
 
-                        self.lines[y] = first.to_owned();
+    /// Number of cells available for text before wrapping, leaving room for the cursor
+    fn wrap_width(&self) -> usize {
+        let cells = (self.window.w - self.gutter_width()) / self.layout.cell_width();
+        (cells as usize).saturating_sub(1)
+    }
 
-                        self.lines.insert(y + 1, last.to_owned());
-                        self.cursor_position = Point::new(y + 1, 0);
-                    }
-                    _ => {
-                        if character.is_ascii() && !character.is_control() {
-                            self.lines[y].insert(x, character);
-                            self.cursor_position.column += 1;
-                        }
-                    }
+    /// Rows as they appear on screen, re-wrapped for the current pane width and leaving out folded rows
+    fn visual_lines(&self) -> Vec<VisualLine> {
+        let mut visual = Vec::with_capacity(self.lines.len());
+        let mut row = 0;
+        let width = self.wrap_width();
+
+        while row < self.lines.len() {
+            let line = &self.lines[row];
+
+            if !self.soft_wrap {
+                visual.push(VisualLine { row, start: 0, end: line.len() });
+            } else {
+                let starts = self.layout.wrap(row, line, width);
+                for (i, &start) in starts.iter().enumerate() {
+                    let end = starts.get(i + 1).copied().unwrap_or(line.len());
+                    visual.push(VisualLine { row, start, end });
                 }
             }
+
+            row = self.folded_end(row).unwrap_or(row) + 1;
+        }
+
+        visual
+    }
+
+    /// Index of the visual line that `point` is drawn on
+    fn visual_index(&self, visual: &[VisualLine], point: Point) -> usize {
+        visual.partition_point(|line| (line.row, line.start) <= (point.row, point.column)).saturating_sub(1)
+    }
+
+    /// Horizontal pixel position of `column` relative to the start of its visual line
+    fn segment_x(&self, line: VisualLine, column: usize) -> f32 {
+        let text = &self.lines[line.row];
+        self.layout.column_to_x(line.row, text, column) - self.layout.column_to_x(line.row, text, line.start)
+    }
+
+    /// The nearest point to `point` that is inside the buffer
+    fn clamp_point(&self, point: Point) -> Point {
+        let row = point.row.min(self.lines.len() - 1);
+        Point::new(row, self.lines[row].floor_char_boundary(point.column))
+    }
+
+    /// Screen position of the bottom left corner of the cell at `point`, which is kept inside the buffer
+    pub(crate) fn point_on_screen(&self, point: Point) -> Vec2 {
+        let point = self.clamp_point(point);
+
+        let visual = self.visual_lines();
+        let k = self.visual_index(&visual, point);
+        let x = self.gutter_width() + self.segment_x(visual[k], point.column);
+        let y = (k + 1) as f32 * self.layout.line_height();
+
+        self.window.point() + vec2(x, y) - self.offset
+    }
+
+    pub(crate) fn line_height(&self) -> f32 {
+        self.layout.line_height()
+    }
+
+    /// Point of the buffer nearest to a position on screen
+    fn point_under(&self, position: Vec2) -> Point {
+        let local = position - self.window.point() + self.offset;
+        let visual = self.visual_lines();
+        let i = self.layout.y_to_row(local.y).min(visual.len() - 1);
+
+        self.point_in_segment(visual[i], local.x - self.gutter_width())
+    }
+
+    /// Point on `line` nearest to the horizontal pixel position `x`
+    fn point_in_segment(&self, line: VisualLine, x: f32) -> Point {
+        let text = &self.lines[line.row];
+        let start_x = self.layout.column_to_x(line.row, text, line.start);
+        let mut column = self.layout.x_to_column(line.row, text, x + start_x).clamp(line.start, line.end);
+
+        // The end of a wrapped segment is the start of the next one, so stay on the last char instead
+        if column == line.end && line.end < text.len() {
+            column = text[..line.end].char_indices().next_back().map_or(line.start, |(idx, _)| idx).max(line.start);
+        }
+
+        Point::new(line.row, column)
+    }
+
+    /// Range of visual lines at least partially inside the window
+    fn visible_rows(&self, rows: usize) -> std::ops::Range<usize> {
+        let first = self.layout.y_to_row(self.offset.y).min(rows);
+        let last = (self.layout.y_to_row(self.offset.y + self.window.h) + 1).min(rows);
+
+        first..last
+    }
+
+    /// Pixel width of the longest line inside the window, plus room for the cursor after it
+    fn visible_width(&self, visual: &[VisualLine]) -> f32 {
+        let cell_width = self.layout.cell_width();
+        let width = self.visible_rows(visual.len())
+            .map(|i| self.segment_x(visual[i], visual[i].end))
+            .fold(0.0, f32::max);
+
+        width + cell_width
+    }
+
+    /// Applies `message`, returning whether it changed the text
+    pub fn update(&mut self, message: EditorMessage, highlighter: &mut Highlighter, theme: &Theme, registers: &mut Registers) -> bool {
+        if let EditorMessage::Keypress(_) | EditorMessage::Char(_) | EditorMessage::Click(_) | EditorMessage::Action(_) = message {
+            self.hover = None;
+            self.shown_hunk = None;
+        }
+
+        // Bytes edited in hex only become text when hex mode is turned off
+        if let Some(hex) = &mut self.hex {
+            hex.update(message, &self.layout, self.window);
+            return false;
+        }
+
+        // Read-only panes turn away anything that would change the text, along with the rest of a vim command
+        if self.read_only && self.would_edit(&message) {
+            if let Some(vim) = &mut self.vim {
+                vim.cancel();
+            }
+            return false;
+        }
+
+        let before = self.cursor_position;
+        let inserting = self.vim.as_ref().is_some_and(|vim| vim.mode() == Mode::Insert);
+
+        let message = self.completion_input(message);
+        let message = message.and_then(|message| self.snippet_input(message));
+        let message = match self.vim.is_some() {
+            true => message.and_then(|message| self.vim_update(message, registers)),
+            false => message,
+        };
+
+        let typed = matches!(message, Some(EditorMessage::Char(character)) if CharClass::of(character) == CharClass::Word);
+        if let Some(message) = message {
+            self.handle(message);
+        }
+        if typed {
+            self.trigger_completion(false);
+        }
+        self.refilter_completions();
+        self.pending = self.take_edit();
+        self.track_snippet(before);
+        // Mirroring a snippet's stop edits again, on top of what the message did
+        let edit = self.take_edit();
+        let changed = edit.is_some();
+
+        if let Some(edit) = &edit {
+            self.reparse(edit);
+            self.brackets = self.find_brackets();
+            self.diff_edit(edit);
         }
 
-        if let Err(_) = self.syntax_highlight(highlighter, theme) {
-            self.colors = vec![]
+        let highlighted = match (&edit, self.colors_stale) {
+            (_, true) => self.syntax_highlight(highlighter, theme),
+            (Some(edit), false) => self.highlight_edit(edit, highlighter, theme),
+            (None, false) => Ok(()),
+        };
+        if highlighted.is_err() {
+            self.colors = vec![];
         }
 
-        let effective_height = self.cursor_position.row as f32 * self.font_size as f32 - self.offset.y;
+        // Lines are added or removed below the last row the edit kept
+        let shift = edit.as_ref().and_then(|edit| {
+            let kept = edit.lines.len().min(edit.rows);
+            RowShift::new((edit.row + kept).saturating_sub(1), edit.lines.len(), edit.rows)
+        });
+        self.record_change(edit, before, inserting, typed);
+
+        if !std::mem::take(&mut self.moved_vertically) {
+            self.desired_x = None;
+        }
+        self.row_shift = shift;
+        self.update_folds(before, shift);
+
+        let visual = self.visual_lines();
+        let cursor_index = self.visual_index(&visual, self.cursor_position);
+        let line_height = self.layout.line_height();
+        let effective_height = cursor_index as f32 * line_height - self.offset.y;
 
 
         if effective_height < 0.0 {
@@ -181,7 +903,130 @@ impl Editor {
         }
 
         if effective_height > self.window.h {
-            self.offset.y += effective_height - (self.window.h - self.font_size as f32);
+            self.offset.y += effective_height - (self.window.h - line_height);
+        }
+
+        let cell_width = self.layout.cell_width();
+        let text_width = self.window.w - self.gutter_width();
+        let effective_width = self.segment_x(visual[cursor_index], self.cursor_position.column) - self.offset.x;
+
+        if effective_width < 0.0 {
+            self.offset.x += effective_width;
+        }
+
+        if effective_width > text_width - cell_width {
+            self.offset.x += effective_width - (text_width - cell_width);
+        }
+
+        changed
+    }
+
+    /// Whether handling `message` would change the text, rather than only moving around or taking in results
+    fn would_edit(&self, message: &EditorMessage) -> bool {
+        let inserting = self.vim.as_ref().is_none_or(|vim| vim.mode() == Mode::Insert);
+        match message {
+            EditorMessage::Edits(_) => true,
+            EditorMessage::Char(_) if inserting => true,
+            EditorMessage::Char(character) => self.vim_key_edits(*character),
+            EditorMessage::Action(action) if inserting => action.edits(),
+            EditorMessage::Action(action) => matches!(action, Action::DeleteForward | Action::Undo | Action::Redo),
+            _ => false,
+        }
+    }
+
+    /// What the message being handled changed so far, leaving out rows it set back as they were
+    fn take_edit(&mut self) -> Option<Edit> {
+        self.pending.take().map(|edit| edit.trimmed(&self.lines)).filter(|edit| !edit.is_empty())
+    }
+
+    /// The rows `edit` replaced with a row either side of them, the first of those rows, and where the rows
+    /// now in their place end
+    fn edited_rows(&self, edit: &Edit) -> (usize, Vec<String>, usize) {
+        let first = edit.row.saturating_sub(1);
+        let below = edit.row + edit.rows;
+        let mut before = self.lines[first..edit.row].to_vec();
+        before.extend(edit.lines.iter().cloned());
+        before.extend(self.lines.get(below).cloned());
+        (first, before, (below + 1).min(self.lines.len()))
+    }
+
+    fn handle(&mut self, message: EditorMessage) {
+        match message {
+            EditorMessage::Keypress(_) => {}
+
+            EditorMessage::Hover(_) | EditorMessage::Completions(_) | EditorMessage::Edits(_)
+                | EditorMessage::JumpTo(_) | EditorMessage::Diagnostics(_) => self.lsp_update(message),
+            EditorMessage::Words(words) => self.offer_words(words),
+
+            EditorMessage::Action(action) => {
+                let point = self.cursor_position;
+
+                let selection = self.selection().filter(|(start, end)| start != end);
+
+                match action {
+                    Action::Indent | Action::Dedent if selection.is_some_and(|(start, end)| start.row != end.row) => {
+                        let (start, end) = selection.unwrap();
+                        self.indent_rows(start.row..=end.row, action == Action::Dedent);
+                    }
+                    Action::Dedent => self.indent_rows(point.row..=point.row, true),
+                    Action::DeleteBackward | Action::DeleteWordBackward | Action::DeleteForward | Action::DeleteWordForward
+                        if selection.is_some() => self.delete_selection(),
+                    Action::DeleteBackward if self.in_empty_pair(point) => {
+                        self.delete_range(self.prev_point(point), self.next_point(point))
+                    }
+                    Action::DeleteBackward => self.delete_range(self.backspace_start(point), point),
+                    Action::DeleteWordBackward => self.delete_range(self.word_left(point), point),
+                    Action::DeleteForward => self.delete_range(point, self.next_point(point)),
+                    Action::DeleteWordForward => self.delete_range(point, self.word_right(point)),
+                    Action::Indent => {
+                        self.delete_selection();
+                        self.insert_indent();
+                    }
+                    Action::Newline => {
+                        self.delete_selection();
+                        self.newline();
+                    }
+                    Action::Undo => self.undo(),
+                    Action::Redo => self.redo(),
+                    Action::Complete => self.trigger_completion(true),
+                    Action::Fold | Action::Unfold | Action::ToggleFold | Action::FoldAll | Action::UnfoldAll => {
+                        self.fold_action(action)
+                    }
+                    action => match action.selection_motion() {
+                        Some(motion) => {
+                            self.anchor.get_or_insert(point);
+                            self.move_cursor(motion);
+                        }
+                        None => {
+                            self.anchor = None;
+                            self.move_cursor(action);
+                        }
+                    },
+                }
+            }
+
+            EditorMessage::Click(position) => {
+                let local = position - self.window.point() + self.offset;
+                let visual = self.visual_lines();
+                let i = self.layout.y_to_row(local.y).min(visual.len() - 1);
+
+                // Clicking a fold marker toggles its fold without moving the cursor
+                if local.x - self.offset.x < self.gutter_width() && visual[i].start == 0 && self.fold_marker(visual[i].row).is_some() {
+                    return self.toggle_fold_at(visual[i].row);
+                }
+
+                self.cursor_position = self.point_under(position);
+                if self.vim.is_none() {
+                    self.anchor = None;
+                }
+            }
+
+            EditorMessage::Char(character) => {
+                // Control characters such as Enter and Backspace arrive as actions instead
+                if character.is_ascii() && !character.is_control() {
+                    self.insert_char(character);
+                }
+            }
         }
     }
 
@@ -192,47 +1037,120 @@ impl Editor {
         Point::new(lines.len() - 1, lines.last().unwrap().len())
     }
 
-    fn syntax_highlight(&mut self, highlighter: &mut Highlighter, theme: &Theme) -> inkjet::Result<()> {
-        let code = self.lines.join("\n");
-
-        let language = Path::new(&self.filename)
+    pub(crate) fn language(&self) -> Language {
+        Path::new(&self.filename)
             .extension()
             .and_then(OsStr::to_str)
             .and_then(Language::from_token)
-            .unwrap_or(Language::Plaintext);
+            .unwrap_or(Language::Plaintext)
+    }
 
-        let highlights = highlighter.highlight_raw(language, &code)?;
+    /// Byte offset of `point` in the buffer's text, with lines joined by newlines
+    fn point_to_idx(&self, point: Point) -> usize {
+        self.lines[..point.row].iter().map(|line| line.len() + 1).sum::<usize>() + point.column
+    }
 
-        self.colors = vec![];
+    /// Parses the buffer, reusing the parts of `old` that an edit hasn't touched. The parser reads the lines
+    /// where they are rather than from a copy of the whole text.
+    fn parse(&self, old: Option<&Tree>) -> Option<Tree> {
+        let mut parser = Parser::new();
+        parser.set_language(&self.language().config().language).ok()?;
+        let lines = &self.lines;
+        let mut read = |_, point: tree_sitter::Point| -> &[u8] {
+            match lines.get(point.row) {
+                Some(line) if point.column < line.len() => &line.as_bytes()[point.column..],
+                Some(_) if point.row + 1 < lines.len() => b"\n",
+                _ => &[],
+            }
+        };
+        parser.parse_with(&mut read, old)
+    }
 
-        let mut color = theme.text;
+    /// Parses the buffer again after `edit`
+    fn reparse(&mut self, edit: &Edit) {
+        let mut tree = self.tree.take();
+        if let Some(tree) = &mut tree {
+            tree.edit(&self.tree_edit(edit));
+        }
+        self.tree = self.parse(tree.as_ref());
+    }
 
-        for highlight in highlights {
-            let highlight = highlight?;
+    /// The edit tree-sitter needs to reuse a tree from before `edit`, found from just the rows around it
+    fn tree_edit(&self, edit: &Edit) -> InputEdit {
+        let (first, before, end) = self.edited_rows(edit);
+        let offset = self.point_to_idx(Point::new(first, 0));
+        let mut input = input_edit(before.join("\n").as_bytes(), self.lines[first..end].join("\n").as_bytes());
+        for byte in [&mut input.start_byte, &mut input.old_end_byte, &mut input.new_end_byte] {
+            *byte += offset;
+        }
+        for point in [&mut input.start_position, &mut input.old_end_position, &mut input.new_end_position] {
+            point.row += first;
+        }
+        input
+    }
 
-            match highlight {
-                HighlightEvent::HighlightStart(style) => {
-                    let name = HIGHLIGHT_NAMES[style.0];
-                    let inkjet_theme = inkjet::theme::Theme::from_helix(vendored::CATPPUCCIN_MOCHA)?;
-                    if let Some(fg) = inkjet_theme.get_style(name).and_then(|s| s.fg) {
-                        color = Color::from_rgba(fg.r, fg.g, fg.b, 255);
+    fn syntax_highlight(&mut self, highlighter: &mut Highlighter, theme: &Theme) -> inkjet::Result<()> {
+        self.colors_stale = false;
+        let code = self.lines.join("\n");
+        self.colors = color_spans(&code, highlight(highlighter, self.language(), &code, theme)?, 0);
+
+        Ok(())
+    }
+
+    /// Highlights the top-level syntax nodes around `edit` again, moving the colors of the rows after them along
+    /// with their text
+    fn highlight_edit(&mut self, edit: &Edit, highlighter: &mut Highlighter, theme: &Theme) -> inkjet::Result<()> {
+        let delta = edit.rows as isize - edit.lines.len() as isize;
+        // Rows from `first` up to `end` are highlighted again. Rows from `end` on are below the edit, so they were
+        // `old(end)` before it.
+        let old = |row: usize| row.saturating_add_signed(-delta);
+        let (mut first, mut end) = (edit.row, (edit.row + edit.rows).max(edit.row + 1).min(self.lines.len()));
+
+        loop {
+            let (was_first, was_end) = (first, end);
+            if let Some(tree) = &self.tree {
+                let mut cursor = tree.walk();
+                if cursor.goto_first_child_for_point(tree_sitter::Point::new(first, 0)).is_some() {
+                    loop {
+                        let (start, last) = (cursor.node().start_position().row, cursor.node().end_position().row);
+                        if start >= end {
+                            break;
+                        }
+                        (first, end) = (first.min(start), end.max(last + 1));
+                        if !cursor.goto_next_sibling() {
+                            break;
+                        }
                     }
                 }
+            }
 
-                HighlightEvent::Source { start, end } => {
-                    self.colors.push(ColorSpan {
-                        start: Self::idx_to_point(&code, start),
-                        end: Self::idx_to_point(&code, end),
-                        color,
-                    });
-                }
+            // Colors from before the edit reaching past the rows, like the rest of a comment, are redone too
+            let (from, to) = (Point::new(first, 0), Point::new(old(end), 0));
+            let start = self.colors.partition_point(|span| span.end <= from);
+            let stop = self.colors.partition_point(|span| span.start < to);
+            if start < stop {
+                first = first.min(self.colors[start].start.row);
+                let last = &self.colors[stop - 1].end;
+                let below = last.row + usize::from(last.column > 0);
+                end = end.max(below.saturating_add_signed(delta)).min(self.lines.len());
+            }
 
-                HighlightEvent::HighlightEnd => {
-                    color = theme.text;
-                }
+            if (first, end) == (was_first, was_end) {
+                break;
             }
         }
 
+        let code = self.lines[first..end].join("\n");
+        let spans = color_spans(&code, highlight(highlighter, self.language(), &code, theme)?, first);
+        let (from, to) = (Point::new(first, 0), Point::new(old(end), 0));
+        let start = self.colors.partition_point(|span| span.end <= from);
+        let stop = self.colors.partition_point(|span| span.start < to);
+        for span in &mut self.colors[stop..] {
+            span.start.row = span.start.row.saturating_add_signed(delta);
+            span.end.row = span.end.row.saturating_add_signed(delta);
+        }
+        self.colors.splice(start..stop, spans);
+
         Ok(())
     }
 
@@ -241,81 +1159,516 @@ impl Editor {
         format!("{:>width$} ", i + 1)
     }
 
-    pub fn save(&mut self) {
-        let mut f = std::fs::OpenOptions::new().write(true).truncate(true).create(true).open(&self.filename).unwrap();
+    /// Room for a diagnostic marker, the line numbers with a git change marker after them, and a fold marker
+    fn gutter_width(&self) -> f32 {
+        (self.format_line_number(0).len() + 3) as f32 * self.layout.cell_width()
+    }
+
+    /// Highlight color of the char at `point`
+    fn color_at(&self, point: Point, theme: &Theme) -> Color {
+        let i = self.colors.partition_point(|span| span.end <= point);
+
+        match self.colors.get(i) {
+            Some(span) if span.start <= point => span.color,
+            _ => theme.text,
+        }
+    }
+
+    /// Saves the buffer to its file, comparing it with git's HEAD again in case there's been a commit since
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the pane is read-only"));
+        }
+        if self.hex.as_ref().is_some_and(|hex| !hex.writable()) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "binary files are read-only until `hex edit`"));
+        }
+        self.write_to(&self.filename, &self.format())?;
+        if self.hex.is_none() {
+            self.read_head();
+        }
+        Ok(())
+    }
 
-        f.write_all(self.lines.join("\n").as_ref()).unwrap();
+    /// Saves the buffer at `path` and points the pane at that file from then on.
+    /// The file follows the `.editorconfig` that applies where it's saved, and keeps following it on later saves.
+    pub fn save_as(&mut self, path: String) -> io::Result<()> {
+        let config = EditorConfig::resolve(Path::new(&path));
+        let format = self.format_for(&config);
+        self.write_to(&path, &format)?;
+
+        self.filename = path;
+        self.editorconfig = config;
+        self.line_ending = format.line_ending;
+        self.charset = format.charset;
+        self.final_newline = format.final_newline;
+        // The pane shows a file of its own now, even if it was generated
+        self.read_only = false;
+        self.generated = false;
+        if self.hex.is_none() {
+            self.read_head();
+            // The new name can mean another language
+            self.replaced();
+        }
+        Ok(())
+    }
+
+    /// How the buffer is saved to its own file
+    fn format(&self) -> Format {
+        Format {
+            line_ending: self.line_ending,
+            charset: self.charset,
+            final_newline: self.final_newline,
+            trim_trailing_whitespace: self.editorconfig.trim_trailing_whitespace == Some(true),
+        }
+    }
+
+    /// How the buffer is saved somewhere `config` applies, which wins over the buffer's own settings
+    fn format_for(&self, config: &EditorConfig) -> Format {
+        Format {
+            line_ending: config.end_of_line.unwrap_or(self.line_ending),
+            charset: config.charset.unwrap_or(self.charset),
+            final_newline: config.insert_final_newline.unwrap_or(self.final_newline),
+            trim_trailing_whitespace: config.trim_trailing_whitespace == Some(true),
+        }
+    }
+
+    /// The buffer as it's saved in `format`, or in hex mode the bytes exactly as they are
+    fn encoded(&self, format: &Format) -> io::Result<Vec<u8>> {
+        match &self.hex {
+            Some(hex) => Ok(hex.bytes().to_vec()),
+            None => encode_lines(&self.lines, format),
+        }
+    }
 
-        f.flush().unwrap();
+    /// Writes the buffer to `path`, encoding it first so a buffer that can't be saved leaves the file alone
+    fn write_to(&self, path: &str, format: &Format) -> io::Result<()> {
+        let bytes = self.encoded(format)?;
+        let mut f = fs::OpenOptions::new().write(true).truncate(true).create(true).open(path)?;
+        f.write_all(&bytes)?;
 
+        f.flush()
     }
 
     pub fn titlebar(&self) -> Rect {
 
-        let titlebar_height = self.font_size as f32 + 8.0;
+        let titlebar_height = self.layout.font_size() as f32 + 8.0;
         Rect::new(self.window.x, self.window.y - titlebar_height, self.window.w, titlebar_height)
     }
 
     pub fn view(&self, theme: &Theme, font: Option<&Font>, focused: bool) {
         set_fullscreen_camera();
 
+        let font_size = self.layout.font_size();
         let titlebar = self.titlebar();
         draw_rectangle(titlebar.x, titlebar.y, titlebar.w, titlebar.h, theme.surface1);
 
         draw_text_ex(&self.filename, self.window.x, self.window.y - 4.0, TextParams {
             color: if focused { theme.lavender } else { theme.text },
             font,
-            font_size: self.font_size,
+            font_size,
+            ..Default::default()
+        });
+
+        let mut indicator = format!("{} {}", self.line_ending.name(), self.charset.name());
+        if !self.final_newline {
+            indicator.push_str(" noeol");
+        }
+        if let Some(hex) = &self.hex {
+            indicator = hex.indicator();
+        }
+        if self.read_only {
+            indicator.push_str(" read-only");
+        }
+        if let Some(summary) = self.diagnostic_summary() {
+            indicator = format!("{summary}  {indicator}");
+        }
+        if let Some(vim) = &self.vim {
+            indicator = format!("{} {}  {indicator}", vim.pending(), vim.mode().name());
+        }
+
+        let width = measure_text(&indicator, font, font_size, 1.0).width;
+        draw_text_ex(&indicator, titlebar.right() - width - 4.0, self.window.y - 4.0, TextParams {
+            color: theme.subtext0,
+            font,
+            font_size,
             ..Default::default()
         });
 
         draw_rectangle(self.window.x, self.window.y, self.window.w, self.window.h, theme.surface0);
 
+        if let Some(hex) = &self.hex {
+            hex.view(theme, font, &self.layout, self.window, focused);
+            return set_default_camera();
+        }
+
+        let cell_width = self.layout.measure(font);
+        let line_height = self.layout.line_height();
+        let gutter_width = self.gutter_width();
+
         set_camera_window(self.window, self.offset);
 
-        let mut x = 0.0;
-        let mut y = self.font_size as f32;
+        let visual = self.visual_lines();
+        let cursor_index = self.visual_index(&visual, self.cursor_position);
+        let selection = self.selection();
+        let matching = match focused {
+            true => self.matching_brackets(self.cursor_position).map_or(vec![], |(bracket, partner)| vec![bracket, partner]),
+            false => vec![],
+        };
 
-        for (i, line) in self.lines.iter().enumerate() {
-            let dimensions = draw_text_ex(&self.format_line_number(i).to_string(), x, y, TextParams {
-                color: theme.overlay1,
-                font,
-                font_size: self.font_size,
-                ..Default::default()
-            });
-            x += dimensions.width;
+        for k in self.visible_rows(visual.len()) {
+            let VisualLine { row: i, start, end } = visual[k];
+            let y = (k + 1) as f32 * line_height;
+
+            if let Some((from, to)) = selection.filter(|(from, to)| (from.row..=to.row).contains(&i)) {
+                let first = if i == from.row { from.column.clamp(start, end) } else { start };
+                let last = if i == to.row { to.column.clamp(start, end) } else { end };
+                // The line break is selected too when the selection carries on to the next line
+                let newline = if i < to.row && end == self.lines[i].len() { cell_width } else { 0.0 };
+                let x = self.segment_x(visual[k], first);
+
+                draw_rectangle(
+                    gutter_width + x,
+                    y - line_height,
+                    self.segment_x(visual[k], last) - x + newline,
+                    line_height,
+                    theme.surface2,
+                );
+            }
+
+            for bracket in matching.iter().filter(|point| point.row == i && (start..end).contains(&point.column)) {
+                let x = gutter_width + self.segment_x(visual[k], bracket.column);
+                draw_rectangle_lines(x, y - line_height, cell_width, line_height, 1.0, theme.overlay2);
+            }
+
+            let layout = self.layout.line(i, &self.lines[i]);
+            let start_cell = layout.cell_of(start);
+
+            // Consecutive glyphs of the same color are drawn as a single run
+            let mut run = String::new();
+            let mut run_start = 0;
+            let mut run_color = theme.text;
 
-            for (j, glyph) in line.chars().enumerate() {
-                let curr = Point::new(i, j);
+            for (j, cell, glyph) in layout.glyphs().filter(|(j, _, _)| (start..end).contains(j)) {
+                let cell = cell - start_cell;
+                let color = self.bracket_color(Point::new(i, j), theme).unwrap_or_else(|| self.color_at(Point::new(i, j), theme));
+                let contiguous = run_start + run.chars().count() == cell;
 
-                if curr == self.cursor_position && focused {
-                    draw_rectangle(x, y - self.font_size as f32, 2.0, self.font_size as f32, theme.rosewater);
+                if glyph.is_whitespace() || color != run_color || !contiguous {
+                    self.draw_run(&run, gutter_width + run_start as f32 * cell_width, y, run_color, font);
+                    run.clear();
                 }
 
-                let span = self.colors.iter().find(|span| {
-                    span.start <= curr && curr < span.end
-                });
+                if !glyph.is_whitespace() {
+                    if run.is_empty() {
+                        run_start = cell;
+                        run_color = color;
+                    }
+                    run.push(glyph);
+                }
+            }
 
-                let color = span.and_then(|span| Some(span.color)).unwrap_or(theme.text);
+            self.draw_run(&run, gutter_width + run_start as f32 * cell_width, y, run_color, font);
+            drop(layout);
 
-                let dimensions = draw_text_ex(&glyph.to_string(), x, y, TextParams {
-                    color,
-                    font,
-                    font_size: self.font_size,
-                    ..Default::default()
-                });
+            self.draw_squiggles(visual[k], y, theme);
 
-                x += dimensions.width;
+            // A folded line ends with a placeholder for the rows hidden under it
+            if end == self.lines[i].len() && self.folded_end(i).is_some() {
+                let x = gutter_width + self.segment_x(visual[k], end) + cell_width;
+                draw_rectangle(x, y - line_height + 2.0, cell_width * 3.0, line_height - 4.0, theme.surface1);
+                self.draw_run("...", x, y - 4.0, theme.overlay1, font);
             }
 
-            if Point::new(i, line.len()) == self.cursor_position && focused {
-                draw_rectangle(x, y - self.font_size as f32, 2.0, self.font_size as f32, theme.rosewater)
+            if focused && cursor_index == k {
+                let x = gutter_width + self.segment_x(visual[k], self.cursor_position.column);
+
+                // Outside insert mode, vim's cursor covers the char it's on
+                match self.vim.as_ref().map(Vim::mode) {
+                    Some(mode) if mode != Mode::Insert => {
+                        draw_rectangle(x, y - line_height, cell_width, line_height, Color { a: 0.5, ..theme.rosewater })
+                    }
+                    _ => draw_rectangle(x, y - line_height, 2.0, line_height, theme.rosewater),
+                }
             }
+        }
 
-            x = 0.0;
-            y += self.font_size as f32;
+        // The gutter stays put when scrolling horizontally, covering text scrolled underneath it
+        draw_rectangle(self.offset.x, self.offset.y, gutter_width, self.window.h, theme.surface0);
+
+        // Line numbers and fold markers only go next to the first visual line of each logical line
+        let marker_x = self.offset.x + (self.format_line_number(0).len() + 1) as f32 * cell_width;
+        for k in self.visible_rows(visual.len()).filter(|&k| visual[k].start == 0) {
+            let y = (k + 1) as f32 * line_height;
+            if let Some(severity) = self.row_severity(visual[k].row) {
+                draw_circle(self.offset.x + cell_width / 2.0, y - line_height / 2.0, cell_width * 0.3, severity.color(theme));
+            }
+
+            draw_text_ex(&self.format_line_number(visual[k].row), self.offset.x + cell_width, y, TextParams {
+                color: theme.overlay1,
+                font,
+                font_size,
+                ..Default::default()
+            });
+            self.draw_change_marker(visual[k].row, y, theme);
+
+            let (center, middle, size) = (marker_x + cell_width / 2.0, y - line_height / 2.0, cell_width * 0.4);
+            match self.fold_marker(visual[k].row) {
+                Some(true) => draw_triangle(
+                    vec2(center - size / 2.0, middle - size),
+                    vec2(center - size / 2.0, middle + size),
+                    vec2(center + size, middle),
+                    theme.overlay2,
+                ),
+                Some(false) => draw_triangle(
+                    vec2(center - size, middle - size / 2.0),
+                    vec2(center + size, middle - size / 2.0),
+                    vec2(center, middle + size),
+                    theme.overlay0,
+                ),
+                None => {}
+            }
+        }
+
+        self.draw_shown_hunk(&visual, theme, font);
+        self.layout.truncate(self.lines.len());
+
+        set_fullscreen_camera();
+        if focused {
+            self.draw_popups(theme, font);
         }
+        self.draw_diagnostic_tooltip(theme, font);
 
         set_default_camera();
     }
-}
\ No newline at end of file
+
+    fn draw_run(&self, run: &str, x: f32, y: f32, color: Color, font: Option<&Font>) {
+        if run.is_empty() {
+            return;
+        }
+
+        draw_text_ex(run, x, y, TextParams {
+            color,
+            font,
+            font_size: self.layout.font_size(),
+            ..Default::default()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(text: &str) -> Editor {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.txt".to_string());
+        editor.load_string(text.to_string());
+        editor
+    }
+
+    fn act(editor: &mut Editor, actions: &[Action]) {
+        let (mut highlighter, theme, mut registers) = (Highlighter::new(), Theme::mocha(), Registers::new());
+        for &action in actions {
+            editor.update(EditorMessage::Action(action), &mut highlighter, &theme, &mut registers);
+        }
+    }
+
+    /// Soft wraps `editor` at `cells` cells
+    fn wrap_at(editor: &mut Editor, cells: usize) {
+        editor.soft_wrap = true;
+        editor.window.w = editor.gutter_width() + (cells as f32 + 1.5) * editor.layout.cell_width();
+        assert_eq!(editor.wrap_width(), cells);
+    }
+
+    fn segments(editor: &Editor) -> Vec<(usize, usize, usize)> {
+        editor.visual_lines().iter().map(|line| (line.row, line.start, line.end)).collect()
+    }
+
+    #[test]
+    fn input_edit_covers_the_change() {
+        let edit = input_edit(b"ab\ncd", b"ab\nxcd");
+        assert_eq!((edit.start_byte, edit.old_end_byte, edit.new_end_byte), (3, 3, 4));
+        assert_eq!(edit.start_position, tree_sitter::Point { row: 1, column: 0 });
+        assert_eq!(edit.new_end_position, tree_sitter::Point { row: 1, column: 1 });
+    }
+
+    #[test]
+    fn read_only_panes_keep_their_text() {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "keys".to_string());
+        editor.load_string("ctrl+s  save".to_string());
+        editor.set_generated();
+
+        let (mut highlighter, theme, mut registers) = (Highlighter::new(), Theme::mocha(), Registers::new());
+        assert!(!editor.update(EditorMessage::Char('x'), &mut highlighter, &theme, &mut registers));
+        assert!(!editor.update(EditorMessage::Action(Action::Newline), &mut highlighter, &theme, &mut registers));
+        assert_eq!(editor.text(), "ctrl+s  save");
+
+        // A vim command that would edit is dropped whole, so the next key starts a new one
+        editor.set_vim(true);
+        for key in "ddxl".chars() {
+            assert!(!editor.update(EditorMessage::Char(key), &mut highlighter, &theme, &mut registers));
+        }
+        assert_eq!(editor.text(), "ctrl+s  save");
+        assert_eq!(editor.cursor_position, Point::new(0, 1));
+        assert_eq!(editor.save().unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn save_as_points_the_pane_at_the_new_file() {
+        let path = std::env::temp_dir().join(format!("benchide-save-as-{}.txt", uuid::Uuid::new_v4()));
+        let path = path.to_string_lossy().into_owned();
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "keys".to_string());
+        editor.load_string("ctrl+s  save".to_string());
+        editor.set_generated();
+
+        editor.save_as(path.clone()).unwrap();
+        let (mut highlighter, theme, mut registers) = (Highlighter::new(), Theme::mocha(), Registers::new());
+        assert!(editor.update(EditorMessage::Char('x'), &mut highlighter, &theme, &mut registers));
+        editor.save().unwrap();
+        let saved = fs::read_to_string(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(editor.filename(), path);
+        assert!(!editor.generated());
+        assert_eq!(saved.unwrap(), "xctrl+s  save\n");
+    }
+
+    #[test]
+    fn soft_wrap_rewraps_when_the_width_changes() {
+        let mut editor = editor("one two three\nfour");
+        assert_eq!(segments(&editor), [(0, 0, 13), (1, 0, 4)]);
+
+        wrap_at(&mut editor, 8);
+        assert_eq!(segments(&editor), [(0, 0, 8), (0, 8, 13), (1, 0, 4)]);
+        wrap_at(&mut editor, 5);
+        assert_eq!(segments(&editor), [(0, 0, 4), (0, 4, 8), (0, 8, 13), (1, 0, 4)]);
+        wrap_at(&mut editor, 20);
+        assert_eq!(segments(&editor), [(0, 0, 13), (1, 0, 4)]);
+    }
+
+    #[test]
+    fn up_and_down_move_by_visual_line() {
+        let mut editor = editor("one two three\nx\nfour five six");
+        wrap_at(&mut editor, 8);
+        editor.cursor_position = Point::new(0, 2);
+
+        act(&mut editor, &[Action::MoveDown]);
+        assert_eq!(editor.cursor_position, Point::new(0, 10));
+        act(&mut editor, &[Action::MoveDown]);
+        assert_eq!(editor.cursor_position, Point::new(1, 1));
+        act(&mut editor, &[Action::MoveDown]);
+        assert_eq!(editor.cursor_position, Point::new(2, 2));
+        act(&mut editor, &[Action::MoveDown]);
+        assert_eq!(editor.cursor_position, Point::new(2, 7));
+        act(&mut editor, &[Action::MoveUp, Action::MoveUp, Action::MoveUp]);
+        assert_eq!(editor.cursor_position, Point::new(0, 10));
+    }
+
+    /// Columns the cursor stops at moving repeatedly with `action` from `start`
+    fn stops(editor: &mut Editor, start: Point, action: Action, count: usize) -> Vec<(usize, usize)> {
+        editor.cursor_position = start;
+        (0..count).map(|_| {
+            act(editor, &[action]);
+            (editor.cursor_position.row, editor.cursor_position.column)
+        }).collect()
+    }
+
+    #[test]
+    fn word_motions_stop_between_words_and_punctuation() {
+        let mut editor = editor("x\nfoo.bar(  baz)\ny");
+
+        assert_eq!(stops(&mut editor, Point::new(1, 0), Action::WordRight, 7), [(1, 3), (1, 4), (1, 7), (1, 8), (1, 13), (1, 14), (2, 0)]);
+        assert_eq!(stops(&mut editor, Point::new(1, 14), Action::WordLeft, 7), [(1, 13), (1, 10), (1, 7), (1, 4), (1, 3), (1, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn word_deletes_take_whitespace_with_the_word() {
+        let mut editor = editor("let  value = 1;");
+        editor.cursor_position = Point::new(0, 10);
+        act(&mut editor, &[Action::DeleteWordBackward]);
+        assert_eq!(editor.text(), "let   = 1;");
+        act(&mut editor, &[Action::DeleteWordForward]);
+        assert_eq!(editor.text(), "let   1;");
+    }
+
+    #[test]
+    fn home_toggles_between_indentation_and_line_start() {
+        let mut editor = editor("    let x;");
+        assert_eq!(stops(&mut editor, Point::new(0, 7), Action::LineStart, 3), [(0, 4), (0, 0), (0, 4)]);
+        assert_eq!(stops(&mut editor, Point::new(0, 2), Action::LineStart, 1), [(0, 4)]);
+    }
+
+    #[test]
+    fn vertical_moves_remember_the_column_across_short_lines() {
+        let mut editor = editor("a long first line\nab\n\nanother long line");
+
+        assert_eq!(stops(&mut editor, Point::new(0, 12), Action::MoveDown, 3), [(1, 2), (2, 0), (3, 12)]);
+        assert_eq!(stops(&mut editor, Point::new(3, 12), Action::MoveUp, 3), [(2, 0), (1, 2), (0, 12)]);
+
+        // Moving sideways forgets the column
+        editor.cursor_position = Point::new(0, 12);
+        act(&mut editor, &[Action::MoveDown, Action::MoveLeft, Action::MoveDown]);
+        assert_eq!(editor.cursor_position, Point::new(2, 0));
+        act(&mut editor, &[Action::MoveDown]);
+        assert_eq!(editor.cursor_position, Point::new(3, 1));
+    }
+
+    #[test]
+    fn row_shifts_move_later_rows() {
+        let inserted = RowShift::new(2, 10, 12).unwrap();
+        assert_eq!([1, 2, 3].map(|row| inserted.moved(row)), [Some(1), Some(2), Some(5)]);
+
+        let removed = RowShift::new(2, 10, 8).unwrap();
+        assert_eq!([2, 3, 4, 5].map(|row| removed.moved(row)), [Some(2), None, None, Some(3)]);
+        assert!(RowShift::new(2, 10, 10).is_none());
+    }
+
+    #[test]
+    fn reparsing_matches_a_fresh_parse() {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.rs".to_string());
+        editor.load_string("fn main() {\n    let x = 1;\n}".to_string());
+
+        let before = editor.lines.clone();
+        editor.lines[1] = "    let é = (1, 2);".to_string();
+        editor.lines.insert(2, "    x;".to_string());
+        editor.reparse(&Edit::between(&before, &editor.lines, Point::new(0, 0)));
+
+        let fresh = editor.parse(None).unwrap();
+        assert_eq!(editor.tree.as_ref().unwrap().root_node().to_sexp(), fresh.root_node().to_sexp());
+    }
+
+    #[test]
+    fn edits_recolor_and_rediff_like_starting_over() {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.rs".to_string());
+        editor.load_string("fn main() {\n    let x = 1;\n    /* note */\n    x;\n}\n\nfn other() {}".to_string());
+        editor.head = Some(editor.lines.clone());
+        let (mut highlighter, theme, mut registers) = (Highlighter::new(), Theme::mocha(), Registers::new());
+        editor.update(EditorMessage::Keypress(KeyCode::Right), &mut highlighter, &theme, &mut registers);
+
+        // Typing, splitting and joining rows, and opening a comment that runs to the end of the file
+        let steps = [(6, 3, "z"), (1, 8, "y"), (2, 4, "\n"), (0, 0, "/*"), (0, 2, "\x08\x08"), (6, 0, "\x08"), (3, 6, "*/")];
+        for (row, column, keys) in steps {
+            editor.cursor_position = Point::new(row, column);
+            for key in keys.chars() {
+                let message = match key {
+                    '\n' => EditorMessage::Action(Action::Newline),
+                    '\x08' => EditorMessage::Action(Action::DeleteBackward),
+                    key => EditorMessage::Char(key),
+                };
+                assert!(editor.update(message, &mut highlighter, &theme, &mut registers));
+            }
+
+            let mut fresh = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.rs".to_string());
+            fresh.load_string(editor.text());
+            fresh.syntax_highlight(&mut highlighter, &theme).unwrap();
+            let colors = |editor: &Editor| {
+                let points = editor.lines.iter().enumerate().flat_map(|(row, line)| (0..line.len()).map(move |column| Point::new(row, column)));
+                points.map(|point| editor.color_at(point, &theme)).collect::<Vec<_>>()
+            };
+            assert_eq!(colors(&editor), colors(&fresh), "after typing {keys:?} at {row}:{column}");
+
+            let hunks = editor.hunks.clone();
+            editor.diff_head();
+            assert_eq!(hunks, editor.hunks, "after typing {keys:?} at {row}:{column}");
+        }
+    }
+}
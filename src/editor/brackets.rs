@@ -0,0 +1,219 @@
+use std::ops::Range;
+use macroquad::color::Color;
+use tree_sitter::{Node, QueryCursor};
+use crate::editor::{Editor, Point};
+use crate::theme::Theme;
+
+const PAIRS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+const QUOTES: &[char] = &['"', '\'', '`'];
+
+/// A bracket in the code, as opposed to one in a string or comment
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Bracket {
+    point: Point,
+    /// How many brackets enclose it
+    depth: usize,
+    /// Index of the bracket it pairs with
+    partner: Option<usize>,
+}
+
+fn closer(opener: char) -> Option<char> {
+    PAIRS.iter().find(|(open, _)| *open == opener).map(|(_, close)| *close)
+}
+
+fn is_closer(character: char) -> bool {
+    PAIRS.iter().any(|(_, close)| *close == character)
+}
+
+/// Whether `offset` falls in one of the sorted, disjoint `literals`
+fn in_literal(literals: &[Range<usize>], offset: usize) -> bool {
+    let i = literals.partition_point(|range| range.end <= offset);
+    literals.get(i).is_some_and(|range| range.start <= offset)
+}
+
+impl Editor {
+    /// Finds every bracket outside strings and comments, pairing them up by nesting
+    pub(super) fn find_brackets(&self) -> Vec<Bracket> {
+        let literals = self.literals(0..usize::MAX);
+        let mut brackets: Vec<Bracket> = vec![];
+        // Indices and closing chars of the brackets still open
+        let mut open: Vec<(usize, char)> = vec![];
+        let mut line_start = 0;
+
+        for (row, line) in self.lines.iter().enumerate() {
+            for (column, character) in line.char_indices() {
+                let point = Point::new(row, column);
+                if !(closer(character).is_some() || is_closer(character)) || in_literal(&literals, line_start + column) {
+                    continue;
+                }
+
+                if let Some(close) = closer(character) {
+                    open.push((brackets.len(), close));
+                    brackets.push(Bracket { point, depth: open.len() - 1, partner: None });
+                } else if let Some(&(i, _)) = open.last().filter(|(_, close)| *close == character) {
+                    open.pop();
+                    brackets[i].partner = Some(brackets.len());
+                    brackets.push(Bracket { point, depth: brackets[i].depth, partner: Some(i) });
+                } else {
+                    brackets.push(Bracket { point, depth: open.len(), partner: None });
+                }
+            }
+            line_start += line.len() + 1;
+        }
+
+        brackets
+    }
+
+    /// Whether the byte at `offset` is inside a string or comment
+    pub(super) fn in_string_or_comment(&self, offset: usize) -> bool {
+        in_literal(&self.literals(offset..offset + 1), offset)
+    }
+
+    /// Byte ranges of the strings and comments overlapping `range`, sorted and merged.
+    /// They're what the grammar's highlight query captures as such, run on the editor's own tree because
+    /// tree-sitter-highlight parses the text itself and doesn't hand its tree out.
+    fn literals(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let Some(tree) = &self.tree else {
+            return vec![];
+        };
+
+        let query = &self.language().config().query;
+        let names = query.capture_names();
+        let literal = |name: &str| ["string", "comment", "character"].into_iter()
+            .any(|kind| name.strip_prefix(kind).is_some_and(|rest| rest.is_empty() || rest.starts_with('.')));
+
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(range);
+
+        // Only predicates read the text, so it's handed over a line at a time rather than joined
+        let lines = &self.lines;
+        let text = |node: Node| (node.start_position().row..=node.end_position().row)
+            .filter_map(|row| lines.get(row))
+            .flat_map(|line| [line.as_bytes(), b"\n"]);
+
+        let mut ranges = cursor.captures(query, tree.root_node(), text)
+            .map(|(query_match, i)| query_match.captures[i])
+            .filter(|capture| literal(names[capture.index as usize]))
+            .map(|capture| capture.node.byte_range())
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<usize>> = vec![];
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    fn bracket_at(&self, point: Point) -> Option<&Bracket> {
+        let i = self.brackets.binary_search_by_key(&point, |bracket| bracket.point).ok()?;
+        Some(&self.brackets[i])
+    }
+
+    /// The bracket at `point` or just before it, and the one it pairs with
+    pub(super) fn matching_brackets(&self, point: Point) -> Option<(Point, Point)> {
+        let before = (point.column > 0).then(|| Point::new(point.row, point.column - 1));
+
+        [Some(point), before].into_iter().flatten()
+            .filter_map(|point| self.bracket_at(point))
+            .find_map(|bracket| Some((bracket.point, self.brackets[bracket.partner?].point)))
+    }
+
+    /// The partner of the first paired bracket at or after `point` on its line, like vim's `%`
+    pub(super) fn next_bracket_partner(&self, point: Point) -> Option<Point> {
+        let i = self.brackets.partition_point(|bracket| bracket.point < point);
+
+        self.brackets[i..].iter()
+            .take_while(|bracket| bracket.point.row == point.row)
+            .find_map(|bracket| Some(self.brackets[bracket.partner?].point))
+    }
+
+    /// Color for a bracket at `point` when rainbow brackets are on, cycling through the palette by depth
+    pub(super) fn bracket_color(&self, point: Point, theme: &Theme) -> Option<Color> {
+        if !self.rainbow {
+            return None;
+        }
+
+        let palette = [theme.yellow, theme.mauve, theme.blue, theme.peach, theme.green, theme.sapphire];
+        let bracket = self.bracket_at(point)?;
+
+        match bracket.partner {
+            Some(_) => Some(palette[bracket.depth % palette.len()]),
+            None => Some(theme.red),
+        }
+    }
+
+    /// Types `character` at the cursor, pairing brackets and quotes, wrapping a selection in them,
+    /// and stepping over a closing one that is already there
+    pub(super) fn insert_char(&mut self, character: char) {
+        let pair = closer(character).or(QUOTES.contains(&character).then_some(character));
+
+        if let (Some(close), Some((start, end))) = (pair, self.selection().filter(|(start, end)| start != end)) {
+            if self.vim.is_none() {
+                self.insert_text(end, &close.to_string());
+                self.insert_text(start, &character.to_string());
+                self.anchor = Some(Point::new(start.row, start.column + 1));
+                self.cursor_position = Point::new(end.row, end.column + usize::from(start.row == end.row));
+                return;
+            }
+        }
+
+        self.delete_selection();
+        let point = self.cursor_position;
+        let line = &self.lines[point.row];
+        let next = line[point.column..].chars().next();
+        let previous = line[..point.column].chars().next_back();
+
+        if (is_closer(character) || QUOTES.contains(&character)) && next == Some(character) {
+            self.cursor_position.column += 1;
+            return;
+        }
+
+        let next_free = next.is_none_or(|c| c.is_whitespace() || is_closer(c) || ",;".contains(c));
+        let auto_close = match pair {
+            Some(_) if !next_free => None,
+            Some(_) if QUOTES.contains(&character) => pair.filter(|_| {
+                previous.is_none_or(|c| c.is_whitespace() || closer(c).is_some() || "=,:;".contains(c))
+                    && !self.in_string_or_comment(self.point_to_idx(point))
+            }),
+            _ => pair,
+        };
+
+        let mut text = character.to_string();
+        text.extend(auto_close);
+        self.insert_text(point, &text);
+        self.cursor_position.column += 1;
+    }
+
+    /// Whether the cursor sits between an empty pair of brackets or quotes, which backspace deletes together
+    pub(super) fn in_empty_pair(&self, point: Point) -> bool {
+        let line = &self.lines[point.row];
+        let next = line[point.column..].chars().next();
+        let previous = line[..point.column].chars().next_back();
+
+        match previous {
+            Some(open) if QUOTES.contains(&open) => next == Some(open),
+            Some(open) => closer(open).is_some() && next == closer(open),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::math::Rect;
+    use super::*;
+
+    #[test]
+    fn skips_brackets_in_strings_chars_and_comments() {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.rs".to_string());
+        editor.load_string("fn f() {\n    let s = \"(\";\n    let c = '[';\n    // {\n}".to_string());
+
+        let points = editor.find_brackets().iter().map(|bracket| bracket.point).collect::<Vec<_>>();
+        assert_eq!(points, [Point::new(0, 4), Point::new(0, 5), Point::new(0, 7), Point::new(4, 0)]);
+        assert_eq!(editor.matching_brackets(Point::new(0, 7)), Some((Point::new(0, 7), Point::new(4, 0))));
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::{BTreeSet, HashSet};
+use macroquad::prelude::*;
+use inkjet::Language;
+use crate::editor::{CharClass, Editor, EditorMessage, Point};
+use crate::keymap::Action;
+use crate::lsp::CompletionItem;
+use crate::theme::Theme;
+
+/// Most completions shown at once
+const COMPLETION_ROWS: usize = 8;
+
+/// How much of a word has to be typed before completions are offered without asking
+const MIN_PREFIX: usize = 2;
+
+/// Where a completion came from, in the order they're preferred when several offer the same text
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+pub(crate) enum Source {
+    /// The options of a snippet's choice
+    Choice,
+    Lsp,
+    Snippet,
+    Keyword,
+    Word,
+}
+
+impl Source {
+    fn name(self) -> &'static str {
+        match self {
+            Source::Choice => "choice",
+            Source::Lsp => "lsp",
+            Source::Snippet => "snippet",
+            Source::Keyword => "keyword",
+            Source::Word => "word",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Candidate {
+    label: String,
+    detail: Option<String>,
+    /// Text that replaces what has been typed
+    text: String,
+    source: Source,
+}
+
+/// Completions offered at the cursor
+#[derive(Debug)]
+pub(super) struct CompletionMenu {
+    candidates: Vec<Candidate>,
+    selected: usize,
+    /// Start of the text being completed
+    start: Point,
+}
+
+/// How well `typed` matches `label` as a subsequence, favouring prefixes, runs and the starts of words.
+/// `None` if it doesn't match at all.
+fn fuzzy_score(typed: &str, label: &str) -> Option<i64> {
+    let mut score = 0;
+    // Where the next char would be if the match carries on without a gap
+    let mut run: Option<usize> = None;
+    let mut labels = label.char_indices();
+
+    for wanted in typed.chars() {
+        let (index, found) = labels.find(|(_, character)| character.to_lowercase().eq(wanted.to_lowercase()))?;
+
+        if index == 0 {
+            score += 30;
+        } else if run == Some(index) {
+            score += 15;
+        }
+
+        let before = label[..index].chars().next_back();
+        let starts_word = before.is_some_and(|before| before == '_' || (before.is_lowercase() && found.is_uppercase()));
+        if starts_word {
+            score += 10;
+        }
+        if found == wanted {
+            score += 1;
+        }
+
+        run = Some(index + found.len_utf8());
+    }
+
+    // Shorter labels leave less left to type
+    Some(score * 100 - label.chars().count() as i64)
+}
+
+/// Keywords of a language: the grammar's unnamed tokens that are spelled like words
+fn keywords(language: Language) -> Vec<String> {
+    let grammar = &language.config().language;
+
+    (0..grammar.node_kind_count() as u16)
+        .filter(|&id| !grammar.node_kind_is_named(id) && grammar.node_kind_is_visible(id))
+        .filter_map(|id| grammar.node_kind_for_id(id))
+        .filter(|kind| kind.chars().count() >= MIN_PREFIX && kind.chars().all(|character| character.is_alphabetic() || character == '_'))
+        .map(str::to_owned)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+impl Editor {
+    /// Words of the buffer long enough to be worth completing
+    pub(crate) fn words(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter()
+            .flat_map(|line| line.split(|character| CharClass::of(character) != CharClass::Word))
+            .filter(|word| word.chars().count() > MIN_PREFIX && !word.starts_with(|character: char| character.is_numeric()))
+    }
+
+    /// Whether the pane has just asked for completions from other panes and the language server
+    pub(crate) fn take_completion_request(&mut self) -> bool {
+        std::mem::take(&mut self.completion_requested)
+    }
+
+    /// Start of the word before the cursor
+    pub(super) fn completion_start(&self) -> Point {
+        let cursor = self.cursor_position;
+        let word = self.lines[cursor.row][..cursor.column].chars().rev()
+            .take_while(|&character| CharClass::of(character) == CharClass::Word)
+            .map(char::len_utf8)
+            .sum::<usize>();
+
+        Point::new(cursor.row, cursor.column - word)
+    }
+
+    /// Opens the menu with what this pane knows itself, and asks for the rest.
+    /// Unless `forced`, only once enough of a word has been typed.
+    pub(super) fn trigger_completion(&mut self, forced: bool) {
+        let start = self.completion_start();
+        if self.completion.is_some() || (!forced && self.cursor_position.column - start.column < MIN_PREFIX) {
+            return;
+        }
+
+        let language = self.language();
+        let snippets = self.snippets.iter()
+            .map(|snippet| Candidate {
+                label: snippet.trigger().to_owned(),
+                detail: Some(snippet.description()),
+                text: snippet.body().to_owned(),
+                source: Source::Snippet,
+            })
+            .collect::<Vec<_>>();
+        let keywords = keywords(language).into_iter()
+            .map(|keyword| Candidate { label: keyword.clone(), detail: None, text: keyword, source: Source::Keyword });
+
+        self.completion = Some(CompletionMenu { candidates: vec![], selected: 0, start });
+        self.offer(snippets.into_iter().chain(keywords).collect());
+        self.completion_requested = true;
+    }
+
+    /// Adds candidates to the open menu, replacing any earlier ones from the same source
+    fn offer(&mut self, candidates: Vec<Candidate>) {
+        let Some(menu) = &mut self.completion else {
+            return;
+        };
+
+        let sources = candidates.iter().map(|candidate| candidate.source).collect::<HashSet<_>>();
+        menu.candidates.retain(|candidate| !sources.contains(&candidate.source));
+        menu.candidates.extend(candidates);
+
+        // When sources offer the same text, the preferred one is kept
+        menu.candidates.sort_by_key(|candidate| candidate.source);
+        let mut seen = HashSet::new();
+        menu.candidates.retain(|candidate| seen.insert(candidate.text.clone()));
+
+        self.refilter_completions();
+    }
+
+    /// Words from every open pane
+    pub(super) fn offer_words(&mut self, words: Vec<String>) {
+        let candidates = words.into_iter()
+            .map(|word| Candidate { label: word.clone(), detail: None, text: word, source: Source::Word })
+            .collect();
+        self.offer(candidates);
+    }
+
+    /// Completions from the language server, unless the menu was closed while waiting for them
+    pub(super) fn offer_lsp(&mut self, items: Vec<CompletionItem>) {
+        let candidates = items.into_iter()
+            .map(|item| Candidate { label: item.label, detail: item.detail, text: item.text, source: Source::Lsp })
+            .collect();
+        self.offer(candidates);
+    }
+
+    /// Opens the menu at the cursor with just the options of a snippet's choice
+    pub(super) fn offer_choices(&mut self, choices: Vec<String>) {
+        let candidates = choices.into_iter()
+            .map(|choice| Candidate { label: choice.clone(), detail: None, text: choice, source: Source::Choice })
+            .collect();
+
+        self.completion = Some(CompletionMenu { candidates: vec![], selected: 0, start: self.cursor_position });
+        self.offer(candidates);
+    }
+
+    /// What has been typed since the menu opened
+    fn typed(&self) -> &str {
+        match &self.completion {
+            Some(menu) => &self.lines[menu.start.row][menu.start.column..self.cursor_position.column],
+            None => "",
+        }
+    }
+
+    /// Candidates matching what has been typed, best first. Before anything is typed they're in the order offered.
+    fn completion_matches(&self) -> Vec<&Candidate> {
+        let Some(menu) = &self.completion else {
+            return vec![];
+        };
+
+        let typed = self.typed();
+        if typed.is_empty() {
+            return menu.candidates.iter().collect();
+        }
+
+        let mut matches = menu.candidates.iter()
+            .filter(|candidate| candidate.text != typed)
+            .filter_map(|candidate| Some((fuzzy_score(typed, &candidate.label)?, candidate)))
+            .collect::<Vec<_>>();
+        matches.sort_by(|(a, first), (b, second)| b.cmp(a).then(first.source.cmp(&second.source)).then(first.label.cmp(&second.label)));
+
+        matches.into_iter().map(|(_, candidate)| candidate).collect()
+    }
+
+    /// Closes the menu once the cursor leaves the text being completed. It stays open while nothing matches,
+    /// as later sources or more typing may bring matches.
+    pub(super) fn refilter_completions(&mut self) {
+        let Some(menu) = &self.completion else {
+            return;
+        };
+
+        let cursor = self.cursor_position;
+        if cursor.row != menu.start.row || cursor.column < menu.start.column {
+            self.completion = None;
+            return;
+        }
+
+        let count = self.completion_matches().len();
+        if let Some(menu) = &mut self.completion {
+            menu.selected = menu.selected.min(count.saturating_sub(1));
+        }
+    }
+
+    /// Replaces what has been typed with a candidate, indenting its lines like the line it goes on.
+    /// Snippets are expanded with their tab stops.
+    fn accept(&mut self, candidate: Candidate) {
+        let Some(menu) = self.completion.take() else {
+            return;
+        };
+        if candidate.source == Source::Snippet {
+            return self.expand_snippet(menu.start, self.cursor_position, &candidate.text);
+        }
+
+        let indentation = self.indentation(menu.start.row).to_owned();
+        let unit = self.indent.unit();
+        let text = candidate.text.replace('\t', &unit).replace('\n', &format!("\n{indentation}"));
+
+        self.delete_range(menu.start, self.cursor_position);
+        self.cursor_position = self.insert_text(menu.start, &text);
+        self.anchor = None;
+    }
+
+    /// Handles keys meant for the completion menu while it's open, passing the rest on
+    pub(super) fn completion_input(&mut self, message: EditorMessage) -> Option<EditorMessage> {
+        let Some(menu) = &self.completion else {
+            return Some(message);
+        };
+        let selected = menu.selected;
+        let matches = self.completion_matches();
+        let count = matches.len();
+
+        let select = |editor: &mut Self, selected: usize| {
+            editor.completion.as_mut().unwrap().selected = selected;
+            None
+        };
+
+        match message {
+            EditorMessage::Action(Action::MoveUp) if count > 0 => select(self, (selected + count - 1) % count),
+            EditorMessage::Action(Action::MoveDown) if count > 0 => select(self, (selected + 1) % count),
+            EditorMessage::Action(Action::PageUp) if count > 0 => select(self, selected.saturating_sub(COMPLETION_ROWS)),
+            EditorMessage::Action(Action::PageDown) if count > 0 => select(self, (selected + COMPLETION_ROWS).min(count - 1)),
+            EditorMessage::Action(Action::Newline | Action::Indent) if count > 0 => {
+                let candidate = matches[selected].clone();
+                self.accept(candidate);
+                None
+            }
+            // Escape only closes a menu that's showing something; an empty one lets it through, to leave insert mode
+            EditorMessage::Keypress(KeyCode::Escape) if count > 0 => {
+                self.completion = None;
+                None
+            }
+            EditorMessage::Char(character) if CharClass::of(character) == CharClass::Word => Some(message),
+            EditorMessage::Action(Action::DeleteBackward | Action::Complete) => Some(message),
+            EditorMessage::Keypress(_) | EditorMessage::Char(_) | EditorMessage::Click(_) | EditorMessage::Action(_) => {
+                self.completion = None;
+                Some(message)
+            }
+            message => Some(message),
+        }
+    }
+
+    pub(super) fn draw_completions(&self, theme: &Theme, font: Option<&Font>) {
+        let Some(menu) = &self.completion else {
+            return;
+        };
+
+        let matches = self.completion_matches();
+        if matches.is_empty() {
+            return;
+        }
+        let first = menu.selected.saturating_sub(COMPLETION_ROWS - 1);
+        let lines = matches.iter().skip(first).take(COMPLETION_ROWS)
+            .map(|candidate| {
+                let detail = candidate.detail.as_deref().and_then(|detail| detail.lines().next()).unwrap_or(candidate.source.name());
+                format!("{}  {}", candidate.label, detail)
+            })
+            .collect::<Vec<_>>();
+        let lines = lines.iter().map(String::as_str).collect::<Vec<_>>();
+
+        self.draw_popup(&lines, Some(menu.selected - first), self.point_on_screen(self.cursor_position), theme, font);
+    }
+}
@@ -0,0 +1,152 @@
+use macroquad::prelude::*;
+use crate::editor::{Editor, Point, VisualLine};
+use crate::theme::Theme;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Information => "info",
+            Severity::Hint => "hint",
+        }
+    }
+
+    pub(crate) fn color(self, theme: &Theme) -> Color {
+        match self {
+            Severity::Error => theme.red,
+            Severity::Warning => theme.yellow,
+            Severity::Information => theme.blue,
+            Severity::Hint => theme.teal,
+        }
+    }
+}
+
+/// A problem reported for a range of the buffer
+#[derive(Debug, Clone)]
+pub(crate) struct Diagnostic {
+    pub(crate) start: Point,
+    pub(crate) end: Point,
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+    pub(crate) source: Option<String>,
+}
+
+impl Diagnostic {
+    /// Whether `point` is in the range, counting an empty range as covering the char after it
+    fn covers(&self, point: Point) -> bool {
+        match self.start == self.end {
+            true => point == self.start,
+            false => self.start <= point && point < self.end,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match &self.source {
+            Some(source) => format!("{}: {} ({source})", self.severity.name(), self.message),
+            None => format!("{}: {}", self.severity.name(), self.message),
+        }
+    }
+}
+
+impl Editor {
+    pub(crate) fn set_diagnostics(&mut self, mut diagnostics: Vec<Diagnostic>) {
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.start, diagnostic.severity));
+        self.diagnostics = diagnostics;
+    }
+
+    /// Counts of errors and warnings for the titlebar, such as `2E 1W`
+    pub(super) fn diagnostic_summary(&self) -> Option<String> {
+        let count = |severity| self.diagnostics.iter().filter(|diagnostic| diagnostic.severity == severity).count();
+        let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+
+        match (errors, warnings) {
+            (0, 0) => None,
+            (errors, 0) => Some(format!("{errors}E")),
+            (0, warnings) => Some(format!("{warnings}W")),
+            (errors, warnings) => Some(format!("{errors}E {warnings}W")),
+        }
+    }
+
+    /// One line per diagnostic for the problems pane, such as `src/main.rs:3:9: error: ...`, with where it points
+    pub(crate) fn problems(&self) -> Vec<(String, Point)> {
+        self.diagnostics.iter()
+            .map(|diagnostic| {
+                let Point { row, column } = diagnostic.start;
+                let character = self.lines.get(row).map_or(0, |line| line[..line.floor_char_boundary(column)].chars().count());
+                let message = diagnostic.describe().lines().next().unwrap_or_default().to_owned();
+                (format!("{}:{}:{}: {message}", self.filename, row + 1, character + 1), diagnostic.start)
+            })
+            .collect()
+    }
+
+    /// The most severe problem starting or ending on `row`, for the gutter
+    pub(super) fn row_severity(&self, row: usize) -> Option<Severity> {
+        self.diagnostics.iter()
+            .filter(|diagnostic| diagnostic.start.row <= row && row <= diagnostic.end.row)
+            .map(|diagnostic| diagnostic.severity)
+            .min()
+    }
+
+    /// Wavy underlines beneath the parts of `line` that have problems, drawn in the pane's camera
+    pub(super) fn draw_squiggles(&self, line: VisualLine, y: f32, theme: &Theme) {
+        let cell_width = self.layout.cell_width();
+        let gutter_width = self.gutter_width();
+
+        for diagnostic in self.diagnostics.iter().filter(|diagnostic| diagnostic.start.row <= line.row && line.row <= diagnostic.end.row) {
+            let from = if diagnostic.start.row == line.row { diagnostic.start.column } else { 0 };
+            let to = if diagnostic.end.row == line.row { diagnostic.end.column } else { self.lines[line.row].len() };
+            let last_segment = line.end == self.lines[line.row].len();
+            let before = to < line.start || (to == line.start && from < to);
+            let after = from > line.end || (from == line.end && !last_segment);
+            if before || after {
+                continue;
+            }
+
+            let left = gutter_width + self.segment_x(line, from.clamp(line.start, line.end));
+            // Empty ranges still get a cell's worth of underline so they can be seen
+            let right = (gutter_width + self.segment_x(line, to.clamp(line.start, line.end))).max(left + cell_width);
+            let color = diagnostic.severity.color(theme);
+
+            let (step, height) = (cell_width / 4.0, 1.5);
+            let mut x = left;
+            let mut up = true;
+            while x < right {
+                let next = (x + step).min(right);
+                let (y1, y2) = if up { (y - 1.0, y - 1.0 - height) } else { (y - 1.0 - height, y - 1.0) };
+                draw_line(x, y1, next, y2, 1.0, color);
+                x = next;
+                up = !up;
+            }
+        }
+    }
+
+    /// The messages of the problems under the mouse, next to it
+    pub(super) fn draw_diagnostic_tooltip(&self, theme: &Theme, font: Option<&Font>) {
+        let mouse = Vec2::from(mouse_position());
+        let in_text = Rect::new(self.window.x + self.gutter_width(), self.window.y, self.window.w - self.gutter_width(), self.window.h);
+        if self.diagnostics.is_empty() || !in_text.contains(mouse) {
+            return;
+        }
+
+        let point = self.point_under(mouse);
+        let messages = self.diagnostics.iter()
+            .filter(|diagnostic| diagnostic.covers(point))
+            .map(Diagnostic::describe)
+            .collect::<Vec<_>>();
+        if messages.is_empty() {
+            return;
+        }
+
+        let lines = messages.iter().flat_map(|message| message.lines()).take(20).collect::<Vec<_>>();
+        self.draw_popup(&lines, None, mouse + vec2(0.0, self.layout.line_height()), theme, font);
+    }
+}
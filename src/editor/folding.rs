@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use crate::editor::{Editor, Point, RowShift};
+use crate::keymap::Action;
+
+/// Rows that can be folded away underneath the first one
+#[derive(Debug, Copy, Clone)]
+pub(super) struct FoldRegion {
+    pub(super) start: usize,
+    pub(super) end: usize,
+    /// How many regions contain this one
+    depth: usize,
+}
+
+impl Editor {
+    /// Foldable regions from syntax nodes spanning several lines, or from indentation when the syntax tree has none
+    pub(super) fn find_fold_regions(&self) -> Vec<FoldRegion> {
+        let mut ends = self.syntax_fold_ends();
+        if ends.is_empty() {
+            ends = self.indent_fold_ends();
+        }
+
+        // Regions are sorted by start, so the open ones form a stack
+        let mut regions: Vec<FoldRegion> = vec![];
+        let mut open: Vec<usize> = vec![];
+
+        for (start, end) in ends {
+            open.retain(|&open_end| open_end >= start);
+            regions.push(FoldRegion { start, end, depth: open.len() });
+            open.push(end);
+        }
+
+        regions
+    }
+
+    /// Last row of the largest multi-line node starting on each row, except the root
+    fn syntax_fold_ends(&self) -> BTreeMap<usize, usize> {
+        let mut ends = BTreeMap::new();
+        let Some(tree) = &self.tree else {
+            return ends;
+        };
+
+        let mut cursor = tree.walk();
+        if !cursor.goto_first_child() {
+            return ends;
+        }
+
+        'nodes: loop {
+            let node = cursor.node();
+            let start = node.start_position().row;
+            // A node ending at the very start of a line doesn't really cover it
+            let end = match node.end_position().column {
+                0 => node.end_position().row.saturating_sub(1),
+                _ => node.end_position().row,
+            };
+
+            if node.is_named() && end > start {
+                let longest = ends.entry(start).or_insert(end);
+                *longest = end.max(*longest);
+            }
+
+            if cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'nodes;
+                }
+            }
+        }
+
+        ends
+    }
+
+    /// Regions of lines indented deeper than the line before them, ignoring blank lines
+    fn indent_fold_ends(&self) -> BTreeMap<usize, usize> {
+        let mut ends = BTreeMap::new();
+        let depth = |row: usize| self.indentation(row).len();
+        let blank = |row: usize| self.lines[row].trim().is_empty();
+
+        for start in (0..self.lines.len()).filter(|&row| !blank(row)) {
+            let mut end = start;
+
+            for row in start + 1..self.lines.len() {
+                if blank(row) {
+                    continue;
+                }
+                if depth(row) <= depth(start) {
+                    break;
+                }
+                end = row;
+            }
+
+            if end > start {
+                ends.insert(start, end);
+            }
+        }
+
+        ends
+    }
+
+    fn fold_region(&self, start: usize) -> Option<FoldRegion> {
+        let i = self.fold_regions.binary_search_by_key(&start, |region| region.start).ok()?;
+        Some(self.fold_regions[i])
+    }
+
+    /// Last row hidden by a fold on `row`, if it's folded
+    pub(super) fn folded_end(&self, row: usize) -> Option<usize> {
+        self.folded.contains(&row).then(|| self.fold_region(row)).flatten().map(|region| region.end)
+    }
+
+    /// The folded region hiding `row`, if any
+    fn hidden_by(&self, row: usize) -> Option<FoldRegion> {
+        self.folded.range(..row)
+            .filter_map(|&start| self.fold_region(start))
+            .filter(|region| row <= region.end)
+            .min_by_key(|region| region.start)
+    }
+
+    /// Opens any folds hiding `row`
+    pub(super) fn reveal(&mut self, row: usize) {
+        while let Some(region) = self.hidden_by(row) {
+            self.folded.remove(&region.start);
+        }
+    }
+
+    /// The innermost region around `row` that matches `filter`
+    fn region_around(&self, row: usize, filter: impl Fn(&FoldRegion) -> bool) -> Option<FoldRegion> {
+        self.fold_regions.iter()
+            .filter(|region| (region.start..=region.end).contains(&row) && filter(region))
+            .max_by_key(|region| region.start)
+            .copied()
+    }
+
+    fn fold(&mut self, region: FoldRegion) {
+        self.folded.insert(region.start);
+
+        if self.hidden_by(self.cursor_position.row).is_some() {
+            let line = &self.lines[region.start];
+            self.cursor_position = Point::new(region.start, line.floor_char_boundary(self.cursor_position.column));
+        }
+    }
+
+    /// Folds every region nested at least `level - 1` deep, so level 1 folds everything
+    pub(crate) fn fold_level(&mut self, level: usize) {
+        let regions = self.fold_regions.iter()
+            .filter(|region| region.depth + 1 >= level)
+            .copied()
+            .collect::<Vec<_>>();
+
+        for region in regions {
+            self.fold(region);
+        }
+    }
+
+    pub(crate) fn unfold_all(&mut self) {
+        self.folded.clear();
+    }
+
+    pub(super) fn fold_action(&mut self, action: Action) {
+        let row = self.cursor_position.row;
+
+        match action {
+            Action::Fold => {
+                if let Some(region) = self.region_around(row, |region| !self.folded.contains(&region.start)) {
+                    self.fold(region);
+                }
+            }
+            Action::Unfold => {
+                if let Some(region) = self.region_around(row, |region| self.folded.contains(&region.start)) {
+                    self.folded.remove(&region.start);
+                }
+            }
+            Action::ToggleFold if self.folded.contains(&row) => {
+                self.folded.remove(&row);
+            }
+            Action::ToggleFold => self.fold_action(Action::Fold),
+            Action::FoldAll => self.fold_level(1),
+            Action::UnfoldAll => self.unfold_all(),
+            _ => {}
+        }
+    }
+
+    /// Toggles the fold starting on `row`, for clicks on the gutter marker
+    pub(super) fn toggle_fold_at(&mut self, row: usize) {
+        match self.fold_region(row) {
+            Some(_) if self.folded.remove(&row) => {}
+            Some(region) => self.fold(region),
+            None => {}
+        }
+    }
+
+    /// Whether a fold marker goes next to `row`, and whether it's folded
+    pub(super) fn fold_marker(&self, row: usize) -> Option<bool> {
+        self.fold_region(row).map(|region| self.folded.contains(&region.start))
+    }
+
+    /// Keeps folds attached to their lines after an edit, and the cursor out of folded lines.
+    /// Edits that add or remove lines inside a fold open it; moving into one skips past it.
+    pub(super) fn update_folds(&mut self, before: Point, shift: Option<RowShift>) {
+        if let Some(shift) = shift {
+            self.folded = std::mem::take(&mut self.folded).into_iter()
+                .filter_map(|start| shift.moved(start))
+                .collect();
+        }
+
+        self.fold_regions = self.find_fold_regions();
+        let regions = &self.fold_regions;
+        self.folded.retain(|start| regions.binary_search_by_key(start, |region| region.start).is_ok());
+
+        while let Some(region) = self.hidden_by(self.cursor_position.row) {
+            if shift.is_some() {
+                self.folded.remove(&region.start);
+            } else if self.cursor_position > before && region.end + 1 < self.lines.len() {
+                self.cursor_position = Point::new(region.end + 1, 0);
+            } else {
+                self.cursor_position = Point::new(region.start, self.lines[region.start].len());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::math::Rect;
+    use super::*;
+
+    #[test]
+    fn joining_lines_above_a_fold_keeps_it_folded() {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.txt".to_string());
+        editor.load_string("a\nb\nc\n    d\n    e\nf".to_string());
+        editor.fold_regions = editor.find_fold_regions();
+        editor.toggle_fold_at(2);
+        assert_eq!(editor.folded_end(2), Some(4));
+
+        // Backspace at the start of `b` joins it onto `a`
+        let before = Point::new(1, 0);
+        editor.lines.remove(1);
+        editor.lines[0].push('b');
+        editor.cursor_position = Point::new(0, 1);
+        editor.update_folds(before, RowShift::new(0, 6, 5));
+
+        assert_eq!(editor.folded_end(1), Some(3));
+        assert_eq!(editor.folded_end(2), None);
+    }
+}
@@ -0,0 +1,439 @@
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use macroquad::prelude::*;
+use crate::editor::{encode_lines, Editor, Format, Point, VisualLine};
+use crate::editor::history::Edit;
+use crate::editorconfig::{Charset, LineEnding};
+use crate::lsp::{Range as LspRange, TextEdit};
+use crate::theme::Theme;
+
+/// How many lines can differ before the rest of a changed stretch is shown as one hunk
+const MAX_EDITS: usize = 1000;
+
+/// Lines of the buffer that differ from a base version of the file: `old` in the base is now `new` in the buffer
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Hunk {
+    old: Range<usize>,
+    new: Range<usize>,
+}
+
+impl Hunk {
+    /// Whether the cursor on `row` is on the hunk, counting the rows either side of lines that were deleted
+    fn touches(&self, row: usize) -> bool {
+        match self.new.is_empty() {
+            true => row + 1 == self.new.start || row == self.new.start,
+            false => self.new.contains(&row),
+        }
+    }
+}
+
+/// Runs git in `dir`, handing back what it printed or what it complained about
+fn git(dir: &Path, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("Couldn't run git: {error}"))?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input).map_err(|error| error.to_string())?;
+    }
+
+    let output = child.wait_with_output().map_err(|error| error.to_string())?;
+    match output.status.success() {
+        true => Ok(output.stdout),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    }
+}
+
+/// Lines of a file's bytes, which always has at least one, and whether it ends with a line ending
+fn decode(bytes: &[u8], charset: Charset) -> Result<(Vec<String>, bool), String> {
+    let text = charset.decode(bytes).ok_or("the file in git doesn't match its charset")?;
+    Ok(LineEnding::split(&text))
+}
+
+/// The hunks that turn `old` into `new`
+fn diff(old: &[String], new: &[String]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let hunks = match old_middle.is_empty() || new_middle.is_empty() {
+        true => None,
+        false => shortest_edits(old_middle, new_middle),
+    };
+    let hunks = hunks.unwrap_or_else(|| match old_middle.is_empty() && new_middle.is_empty() {
+        true => vec![],
+        false => vec![Hunk { old: 0..old_middle.len(), new: 0..new_middle.len() }],
+    });
+
+    hunks.into_iter()
+        .map(|hunk| Hunk { old: hunk.old.start + prefix..hunk.old.end + prefix, new: hunk.new.start + prefix..hunk.new.end + prefix })
+        .collect()
+}
+
+/// Hunks making up the fewest lines deleted and added, found with Myers' algorithm,
+/// or `None` when there are more than `MAX_EDITS`
+fn shortest_edits(old: &[String], new: &[String]) -> Option<Vec<Hunk>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    // How far along `old` each diagonal `k` gets with `d` edits, at `fronts[d][k + d]`
+    let mut fronts: Vec<Vec<isize>> = vec![];
+
+    for d in 0..=(n + m).min(MAX_EDITS as isize) {
+        let mut front = vec![0; 2 * d as usize + 1];
+        for k in (-d..=d).step_by(2) {
+            let mut x = match d {
+                0 => 0,
+                _ => {
+                    let previous = |k: isize| fronts[d as usize - 1][(k + d - 1) as usize];
+                    match k == -d || (k != d && previous(k - 1) < previous(k + 1)) {
+                        true => previous(k + 1),
+                        false => previous(k - 1) + 1,
+                    }
+                }
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                (x, y) = (x + 1, y + 1);
+            }
+
+            front[(k + d) as usize] = x;
+            if x >= n && y >= m {
+                fronts.push(front);
+                return Some(backtrack(&fronts, n, m));
+            }
+        }
+        fronts.push(front);
+    }
+
+    None
+}
+
+/// Walks the furthest points back from the end, gathering the edits between them into hunks
+fn backtrack(fronts: &[Vec<isize>], n: isize, m: isize) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = vec![];
+    let (mut x, mut y) = (n, m);
+
+    for d in (1..fronts.len() as isize).rev() {
+        let k = x - y;
+        let previous = |k: isize| fronts[d as usize - 1][(k + d - 1) as usize];
+        let added = k == -d || (k != d && previous(k - 1) < previous(k + 1));
+        let from = if added { k + 1 } else { k - 1 };
+        let (from_x, from_y) = (previous(from) as usize, (previous(from) - from) as usize);
+
+        let (old, new) = match added {
+            true => (from_x..from_x, from_y..from_y + 1),
+            false => (from_x..from_x + 1, from_y..from_y),
+        };
+        match hunks.last_mut() {
+            Some(last) if last.old.start == old.end && last.new.start == new.end => {
+                (last.old.start, last.new.start) = (old.start, new.start);
+            }
+            _ => hunks.push(Hunk { old, new }),
+        }
+
+        (x, y) = (from_x as isize, from_y as isize);
+    }
+
+    hunks.reverse();
+    hunks
+}
+
+impl Editor {
+    /// The directory git runs in for the file, and the file's name in it
+    fn git_path(&self) -> (PathBuf, String) {
+        let path = Path::new(&self.filename);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        (dir, name)
+    }
+
+    /// Reads the file as it is in git's HEAD, for the gutter to compare the buffer with.
+    /// Files outside a repository or not yet committed have no markers.
+    pub(super) fn read_head(&mut self) {
+        let (dir, name) = self.git_path();
+        self.head = git(&dir, &["show", &format!("HEAD:./{name}")], None)
+            .and_then(|bytes| decode(&bytes, self.charset))
+            .map(|(lines, _)| lines)
+            .ok();
+        self.diff_head();
+    }
+
+    pub(super) fn diff_head(&mut self) {
+        self.hunks = match &self.head {
+            Some(head) => diff(head, &self.lines),
+            None => vec![],
+        };
+    }
+
+    /// Compares the rows around `edit` with HEAD again, along with the hunks it touches, moving the hunks below it
+    pub(super) fn diff_edit(&mut self, edit: &Edit) {
+        let Some(head) = &self.head else {
+            return;
+        };
+        let delta = edit.rows as isize - edit.lines.len() as isize;
+        let (first, last) = (
+            self.hunks.partition_point(|hunk| hunk.new.end < edit.row),
+            self.hunks.partition_point(|hunk| hunk.new.start <= edit.row + edit.lines.len()),
+        );
+
+        // The rows to compare, as they were before the edit
+        let (mut start, mut end) = (edit.row, edit.row + edit.lines.len());
+        if first < last {
+            start = start.min(self.hunks[first].new.start);
+            end = end.max(self.hunks[last - 1].new.end);
+        }
+        // Rows outside the hunks are the same in HEAD, so the rows line up with HEAD's from the hunk above
+        let head_start = match first {
+            0 => start,
+            _ => self.hunks[first - 1].old.end + start - self.hunks[first - 1].new.end,
+        };
+        let removed = self.hunks[first..last].iter().map(|hunk| hunk.old.len() as isize - hunk.new.len() as isize).sum::<isize>();
+        let head_end = (head_start + end - start).saturating_add_signed(removed);
+        let new_end = end.saturating_add_signed(delta);
+
+        let hunks = diff(&head[head_start..head_end], &self.lines[start..new_end]).into_iter()
+            .map(|hunk| Hunk { old: hunk.old.start + head_start..hunk.old.end + head_start, new: hunk.new.start + start..hunk.new.end + start });
+        for hunk in &mut self.hunks[last..] {
+            hunk.new = hunk.new.start.saturating_add_signed(delta)..hunk.new.end.saturating_add_signed(delta);
+        }
+        self.hunks.splice(first..last, hunks);
+    }
+
+    fn hunk_at_cursor(&mut self) -> Result<Hunk, String> {
+        if self.hex.is_some() {
+            return Err("Hunks aren't shown in hex mode".to_string());
+        }
+
+        self.read_head();
+        if self.head.is_none() {
+            return Err(format!("{} isn't committed to a git repository", self.filename));
+        }
+
+        let row = self.cursor_position.row;
+        self.hunks.iter().find(|hunk| hunk.touches(row)).cloned().ok_or("No changes since HEAD at the cursor".to_string())
+    }
+
+    /// Shows the lines the hunk at the cursor replaced under it, until the next key or click
+    pub(crate) fn show_hunk(&mut self) -> Result<(), String> {
+        let hunk = self.hunk_at_cursor()?;
+        if hunk.old.is_empty() {
+            return Err("The lines at the cursor were added since HEAD".to_string());
+        }
+
+        self.shown_hunk = Some(hunk);
+        Ok(())
+    }
+
+    /// The edit putting back the lines the hunk at the cursor replaced
+    pub(crate) fn revert_hunk(&mut self) -> Result<TextEdit, String> {
+        let hunk = self.hunk_at_cursor()?;
+        let old = self.head.as_ref().map_or(vec![], |head| head[hunk.old.clone()].to_vec());
+
+        // Whole lines are replaced, along with the line break before them when they run to the end of the buffer
+        let (start, end, text) = match (hunk.new.start, hunk.new.end == self.lines.len()) {
+            (_, false) => (Point::new(hunk.new.start, 0), Point::new(hunk.new.end, 0), old.iter().map(|line| format!("{line}\n")).collect()),
+            (0, true) => (Point::new(0, 0), self.end_point(), old.join("\n")),
+            (start, true) => (
+                Point::new(start - 1, self.lines[start - 1].len()),
+                self.end_point(),
+                old.iter().map(|line| format!("\n{line}")).collect(),
+            ),
+        };
+
+        Ok(TextEdit { range: LspRange { start: self.lsp_position(start), end: self.lsp_position(end) }, text })
+    }
+
+    /// Stages the hunk at the cursor on its own, leaving the rest of the file's changes as they are in the index
+    pub(crate) fn stage_hunk(&mut self) -> Result<(), String> {
+        self.hunk_at_cursor()?;
+
+        let (dir, name) = self.git_path();
+        let entry = String::from_utf8_lossy(&git(&dir, &["ls-files", "--stage", "--full-name", "--", &name], None)?).into_owned();
+        // Such as `100644 <blob> 0\tsrc/main.rs`
+        let (mode, blob, path) = match entry.trim_end().split_once('\t') {
+            Some((info, path)) => match info.split(' ').collect::<Vec<_>>()[..] {
+                [mode, blob, _] => (mode, blob, path),
+                _ => return Err(format!("Unexpected output from git: {entry}")),
+            },
+            None => return Err(format!("{} isn't tracked by git", self.filename)),
+        };
+
+        // The hunk is found again against the index, so hunks staged before stay staged
+        let staged = git(&dir, &["cat-file", "blob", blob], None)?;
+        let (mut lines, final_newline) = decode(&staged, self.charset)?;
+        let row = self.cursor_position.row;
+        let hunk = diff(&lines, &self.lines).into_iter()
+            .find(|hunk| hunk.touches(row))
+            .ok_or("The hunk at the cursor is already staged")?;
+
+        let final_newline = match hunk.new.end == self.lines.len() {
+            true => self.final_newline,
+            false => final_newline,
+        };
+        lines.splice(hunk.old, self.lines[hunk.new].iter().cloned());
+
+        // The rest of the index's copy is left as it was: its line endings, and any trailing whitespace saving would trim
+        let line_ending = match staged.iter().any(|&byte| byte == b'\r' || byte == b'\n') {
+            true => LineEnding::detect(&String::from_utf8_lossy(&staged)),
+            false => self.line_ending,
+        };
+        let format = Format { line_ending, charset: self.charset, final_newline, trim_trailing_whitespace: false };
+        let bytes = encode_lines(&lines, &format).map_err(|error| error.to_string())?;
+        // The path picks the filters and attributes git would use when adding the file
+        let staged = git(&dir, &["hash-object", "-w", "--stdin", "--path", &name], Some(&bytes))?;
+        let staged = String::from_utf8_lossy(&staged).trim().to_string();
+        git(&dir, &["update-index", "--cacheinfo", &format!("{mode},{staged},{path}")], None)?;
+        Ok(())
+    }
+
+    /// Marks the row as added or modified since HEAD, or the gap above it where lines were deleted
+    pub(super) fn draw_change_marker(&self, row: usize, y: f32, theme: &Theme) {
+        let (cell_width, line_height) = (self.layout.cell_width(), self.layout.line_height());
+        let x = self.offset.x + self.format_line_number(0).len() as f32 * cell_width + cell_width / 4.0;
+
+        for hunk in &self.hunks {
+            if hunk.new.contains(&row) {
+                let color = if hunk.old.is_empty() { theme.green } else { theme.blue };
+                draw_rectangle(x, y - line_height, 3.0, line_height, color);
+            }
+
+            // Deletions at the very end are marked under the last row
+            let gap = match hunk.new.is_empty() {
+                true if hunk.new.start == row => Some(y - line_height),
+                true if hunk.new.start == self.lines.len() && row + 1 == hunk.new.start => Some(y),
+                _ => None,
+            };
+            if let Some(gap) = gap {
+                let size = cell_width * 0.4;
+                draw_triangle(vec2(x, gap - size), vec2(x, gap + size), vec2(x + size * 1.5, gap), theme.red);
+            }
+        }
+    }
+
+    /// Draws the lines of the shown hunk as they are in HEAD, under the rows that replaced them
+    pub(super) fn draw_shown_hunk(&self, visual: &[VisualLine], theme: &Theme, font: Option<&Font>) {
+        let (Some(hunk), Some(head)) = (&self.shown_hunk, &self.head) else {
+            return;
+        };
+
+        let (cell_width, line_height) = (self.layout.cell_width(), self.layout.line_height());
+        let below = match hunk.new.end {
+            0 => 0,
+            end => self.visual_index(visual, Point::new(end - 1, self.lines[end - 1].len())) + 1,
+        };
+        let old = &head[hunk.old.clone()];
+        let (left, top, height) = (self.offset.x, below as f32 * line_height, old.len() as f32 * line_height);
+
+        draw_rectangle(left, top, self.window.w, height, theme.mantle);
+        for (i, line) in old.iter().enumerate() {
+            let y = top + (i + 1) as f32 * line_height;
+            self.draw_run(&line.replace('\t', "    "), self.gutter_width(), y, theme.subtext0, font);
+        }
+
+        // The old lines scroll under the gutter like the buffer's, and are marked there instead of numbered
+        draw_rectangle(left, top, self.gutter_width(), height, theme.mantle);
+        draw_rectangle(left, top, self.window.w, height, Color { a: 0.15, ..theme.red });
+        for i in 0..old.len() {
+            self.draw_run("-", left + cell_width, top + (i + 1) as f32 * line_height, theme.red, font);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    /// A directory of its own for a test, removed again when the test ends, even when it fails
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("benchide-git-{}", uuid::Uuid::new_v4()));
+            fs::create_dir(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split(',').filter(|line| !line.is_empty()).map(str::to_owned).collect()
+    }
+
+    fn hunks(old: &str, new: &str) -> Vec<(Range<usize>, Range<usize>)> {
+        diff(&lines(old), &lines(new)).into_iter().map(|hunk| (hunk.old, hunk.new)).collect()
+    }
+
+    #[test]
+    fn diffs_insertions_deletions_and_modifications() {
+        assert_eq!(hunks("a,b,c", "a,b,c"), []);
+        assert_eq!(hunks("a,b", "a,x,b"), [(1..1, 1..2)]);
+        assert_eq!(hunks("a,b,c", "a,c"), [(1..2, 1..1)]);
+        assert_eq!(hunks("a,b,c", "a,x,c"), [(1..2, 1..2)]);
+        assert_eq!(hunks("a,b,c,d,e", "x,b,c,e,f"), [(0..1, 0..1), (3..4, 3..3), (5..5, 4..5)]);
+    }
+
+    #[test]
+    fn diffs_at_the_ends() {
+        assert_eq!(hunks("a,b", "a,b,c"), [(2..2, 2..3)]);
+        assert_eq!(hunks("a,b,c", "a,b"), [(2..3, 2..2)]);
+        assert_eq!(hunks("a,b", "x,a,b"), [(0..0, 0..1)]);
+        assert_eq!(hunks("", "a"), [(0..0, 0..1)]);
+    }
+
+    #[test]
+    fn too_many_edits_make_one_hunk() {
+        let old = (0..MAX_EDITS).map(|i| format!("old {i}")).collect::<Vec<_>>();
+        let mut new = (0..MAX_EDITS).map(|i| format!("new {i}")).collect::<Vec<_>>();
+        new[MAX_EDITS / 2] = old[MAX_EDITS / 2].clone();
+
+        let hunks = diff(&old, &new);
+        assert_eq!(hunks, [Hunk { old: 0..MAX_EDITS, new: 0..MAX_EDITS }]);
+    }
+
+    #[test]
+    fn stages_only_the_hunk_at_the_cursor() {
+        let dir = TempDir::new();
+        fs::create_dir(dir.0.join("src")).unwrap();
+        let run = |args: &[&str]| git(&dir.0, args, None).unwrap();
+        run(&["init", "--quiet"]);
+        run(&["config", "core.autocrlf", "false"]);
+        // The index has CRLF line endings and trailing whitespace, which staging keeps
+        fs::write(dir.0.join("src/main.rs"), "a\r\nb \r\nc\r\nd\r\n").unwrap();
+        run(&["add", "."]);
+        run(&["-c", "commit.gpgsign=false", "-c", "user.name=Test", "-c", "user.email=test@example.com", "commit", "--quiet", "-m", "Add main"]);
+
+        let path = dir.0.join("src/main.rs");
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, path.to_string_lossy().into_owned());
+        editor.load_file().unwrap();
+        editor.line_ending = LineEnding::Lf;
+        editor.editorconfig.trim_trailing_whitespace = Some(true);
+        editor.lines[0] = "x".to_string();
+        editor.lines[2] = "z".to_string();
+        editor.diff_head();
+        assert_eq!(editor.hunks.len(), 2);
+
+        editor.cursor_position = Point::new(2, 0);
+        editor.stage_hunk().unwrap();
+        let staged = String::from_utf8(run(&["diff", "--cached", "--no-color", "-U0"])).unwrap();
+        assert!(staged.contains("-c\r\n+z\r\n"), "{staged}");
+        assert!(!staged.contains("+x"), "{staged}");
+        assert_eq!(String::from_utf8(run(&["show", ":src/main.rs"])).unwrap(), "a\r\nb \r\nz\r\nd\r\n");
+        assert_eq!(editor.stage_hunk(), Err("The hunk at the cursor is already staged".to_string()));
+    }
+}
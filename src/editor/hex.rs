@@ -0,0 +1,427 @@
+use macroquad::prelude::*;
+use inkjet::Highlighter;
+use crate::editor::{Editor, EditorMessage};
+use crate::editor::history::Edit;
+use crate::editorconfig::Charset;
+use crate::keymap::Action;
+use crate::layout::TextLayout;
+use crate::theme::Theme;
+use crate::window::set_camera_window;
+
+const BYTES_PER_ROW: usize = 16;
+/// Cells taken by a margin, the offset column and the space after it
+const OFFSET_CELLS: usize = 11;
+/// Cell the ASCII gutter starts at
+const ASCII_CELL: usize = OFFSET_CELLS + BYTES_PER_ROW * 3 + 2;
+
+/// Whether a file looks like something other than text: a NUL near the start, unless it's UTF-16 with a byte order mark
+pub(crate) fn is_binary(bytes: &[u8]) -> bool {
+    let head = &bytes[..bytes.len().min(8000)];
+    head.contains(&0) && !matches!(Charset::detect(bytes), Charset::Utf16Le | Charset::Utf16Be)
+}
+
+/// Bytes to search for, written as pairs of hex digits with optional spaces between them, or as text in double quotes
+pub(crate) fn parse_pattern(pattern: &str) -> Option<Vec<u8>> {
+    let pattern = pattern.trim();
+    if let Some(text) = pattern.strip_prefix('"').and_then(|pattern| pattern.strip_suffix('"')) {
+        return (!text.is_empty()).then(|| text.as_bytes().to_vec());
+    }
+
+    let digits = pattern.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    if digits.is_empty() || digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok()).collect()
+}
+
+impl Editor {
+    /// Switches between showing the buffer as text and as the bytes it's saved as, which are edited in place.
+    /// Binary files can only be shown as hex.
+    pub(crate) fn toggle_hex(&mut self, highlighter: &mut Highlighter, theme: &Theme) -> Result<(), String> {
+        let Some(hex) = &self.hex else {
+            // The cursor stays on the same char, at the first of its bytes
+            let point = self.cursor_position;
+            let mut before = self.lines[..point.row].iter()
+                .map(|line| format!("{line}{}", self.line_ending.as_str()))
+                .collect::<String>();
+            before.push_str(&self.lines[point.row][..point.column]);
+
+            let bytes = self.encoded(&self.format()).map_err(|error| error.to_string())?;
+            let offset = self.charset.encode(&before).map_err(|error| error.to_string())?.len();
+            self.hex = Some(Hex::new(bytes, offset, !self.read_only));
+            return Ok(());
+        };
+
+        if is_binary(hex.bytes()) {
+            return Err(format!("{} is binary, so it can only be shown as hex", self.filename));
+        }
+
+        let bytes = hex.bytes().to_vec();
+        let (lines, cursor) = (self.lines.clone(), self.cursor_position);
+        self.load_text(&bytes).map_err(|error| error.to_string())?;
+        self.hex = None;
+
+        // Whatever was changed in hex is undone as one change, after the changes from before it
+        self.replaced();
+        let edit = Edit::between(&lines, &self.lines, cursor);
+        self.record_change((!edit.is_empty()).then_some(edit), cursor, false, false);
+        if self.syntax_highlight(highlighter, theme).is_err() {
+            self.colors = vec![];
+        }
+
+        Ok(())
+    }
+
+    /// Lets the bytes of a binary file be changed and saved, which they can't be when it's opened
+    pub(crate) fn edit_hex(&mut self) -> Result<(), String> {
+        if self.read_only {
+            return Err("The pane is read-only".to_string());
+        }
+        match &mut self.hex {
+            Some(hex) => hex.writable = true,
+            None => return Err("Turn on hex mode with `hex` to edit bytes".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Moves to the next place the bytes of `pattern` appear in hex mode, or those of the last pattern when it's empty
+    pub(crate) fn find_bytes(&mut self, pattern: &str) -> Result<(), String> {
+        let Some(hex) = &mut self.hex else {
+            return Err("Turn on hex mode with `hex` to search for bytes".to_string());
+        };
+
+        let pattern = match pattern.trim() {
+            "" => None,
+            pattern => Some(parse_pattern(pattern).ok_or("Usage: hex find <hex bytes>|\"text\"")?),
+        };
+        hex.find(pattern, &self.layout, self.window)
+    }
+}
+
+/// Which part of a row typing goes to
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Side {
+    Hex,
+    Ascii,
+}
+
+/// The bytes of a file, shown as rows of offset, hex and ASCII and edited in place
+pub(crate) struct Hex {
+    bytes: Vec<u8>,
+    /// Byte the cursor is on, which is one past the end to add bytes there
+    cursor: usize,
+    /// Whether the cursor is on the low nibble of its byte, on the hex side
+    low: bool,
+    side: Side,
+    /// How far down the rows are scrolled, in pixels
+    scroll: f32,
+    /// Bytes as they were before each change, with `None` for bytes that were added
+    undo: Vec<(usize, Option<u8>)>,
+    redo: Vec<(usize, Option<u8>)>,
+    /// The last bytes searched for, to search for again
+    pattern: Vec<u8>,
+    /// Whether typing changes the bytes and saving writes them
+    writable: bool,
+}
+
+impl Hex {
+    pub(crate) fn new(bytes: Vec<u8>, cursor: usize, writable: bool) -> Self {
+        Self {
+            cursor: cursor.min(bytes.len()),
+            bytes,
+            low: false,
+            side: Side::Hex,
+            scroll: 0.0,
+            undo: vec![],
+            redo: vec![],
+            pattern: vec![],
+            writable,
+        }
+    }
+
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Rows of bytes, including the place after the last one
+    fn rows(&self) -> usize {
+        (self.bytes.len() + 1).div_ceil(BYTES_PER_ROW)
+    }
+
+    /// Cell the byte at `column` of a row starts at, with a gap after the first eight
+    fn hex_cell(column: usize) -> usize {
+        OFFSET_CELLS + column * 3 + column / 8
+    }
+
+    /// Where the cursor is and which side typing goes to, for the titlebar
+    pub(crate) fn indicator(&self) -> String {
+        let side = match self.side {
+            Side::Hex => "hex",
+            Side::Ascii => "ascii",
+        };
+        let access = if self.writable { "" } else { " read-only" };
+        format!("{:#x} of {} bytes  {side}{access}", self.cursor, self.bytes.len())
+    }
+
+    pub(crate) fn update(&mut self, message: EditorMessage, layout: &TextLayout, window: Rect) {
+        match message {
+            EditorMessage::Action(action) => self.perform(action, layout, window),
+            EditorMessage::Char(character) if self.writable => self.type_char(character),
+            EditorMessage::Click(position) => {
+                if let Some((byte, side, low)) = self.byte_under(position - window.point(), layout) {
+                    (self.cursor, self.side, self.low) = (byte, side, low);
+                }
+            }
+            _ => {}
+        }
+
+        self.scroll_to_cursor(layout, window);
+    }
+
+    fn perform(&mut self, action: Action, layout: &TextLayout, window: Rect) {
+        let end = self.bytes.len();
+        let page = ((window.h / layout.line_height()) as usize).max(1) * BYTES_PER_ROW;
+        let row_start = self.cursor - self.cursor % BYTES_PER_ROW;
+        let nibbles = self.side == Side::Hex;
+
+        let (cursor, low) = match action {
+            // On the hex side, left and right go a nibble at a time
+            Action::MoveLeft if nibbles && self.low => (self.cursor, false),
+            Action::MoveLeft if nibbles && self.cursor > 0 => (self.cursor - 1, true),
+            Action::MoveRight if nibbles && !self.low && self.cursor < end => (self.cursor, true),
+            Action::MoveLeft | Action::WordLeft => (self.cursor.saturating_sub(1), false),
+            Action::MoveRight | Action::WordRight => (self.cursor + 1, false),
+            Action::MoveUp => (self.cursor.checked_sub(BYTES_PER_ROW).unwrap_or(self.cursor), self.low),
+            Action::MoveDown if self.cursor + BYTES_PER_ROW <= end => (self.cursor + BYTES_PER_ROW, self.low),
+            Action::MoveDown => (self.cursor, self.low),
+            Action::LineStart => (row_start, false),
+            Action::LineEnd => (row_start + BYTES_PER_ROW - 1, false),
+            Action::PageUp => (self.cursor.saturating_sub(page), self.low),
+            Action::PageDown => (self.cursor + page, self.low),
+            Action::DocumentStart => (0, false),
+            Action::DocumentEnd => (end, false),
+            Action::Indent | Action::Dedent => {
+                self.side = if nibbles { Side::Ascii } else { Side::Hex };
+                (self.cursor, false)
+            }
+            Action::Undo => return self.restore(true),
+            Action::Redo => return self.restore(false),
+            _ => return,
+        };
+
+        self.cursor = cursor.min(end);
+        self.low = low && nibbles && self.cursor < end;
+    }
+
+    /// Overwrites the nibble or byte under the cursor, or adds a byte after the last one, and moves on
+    fn type_char(&mut self, character: char) {
+        let byte = self.bytes.get(self.cursor).copied().unwrap_or(0);
+
+        match self.side {
+            Side::Hex => {
+                let Some(nibble) = character.to_digit(16).map(|nibble| nibble as u8) else {
+                    return;
+                };
+                let byte = if self.low { byte & 0xf0 | nibble } else { byte & 0x0f | nibble << 4 };
+                self.write(byte);
+                if self.low {
+                    self.cursor += 1;
+                }
+                self.low = !self.low;
+            }
+            Side::Ascii if character.is_ascii() && !character.is_ascii_control() => {
+                self.write(character as u8);
+                self.cursor += 1;
+            }
+            Side::Ascii => {}
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        let previous = self.set(self.cursor, Some(byte));
+        if previous != Some(byte) {
+            self.undo.push((self.cursor, previous));
+            self.redo.clear();
+        }
+    }
+
+    /// Puts `byte` at `index`, or takes away the byte there and any after it, handing back what was there
+    fn set(&mut self, index: usize, byte: Option<u8>) -> Option<u8> {
+        let previous = self.bytes.get(index).copied();
+        match byte {
+            Some(byte) if index < self.bytes.len() => self.bytes[index] = byte,
+            Some(byte) => self.bytes.push(byte),
+            None => self.bytes.truncate(index),
+        }
+        previous
+    }
+
+    /// Undoes the last change, or redoes the last undone one
+    fn restore(&mut self, undo: bool) {
+        let from = if undo { &mut self.undo } else { &mut self.redo };
+        let Some((index, byte)) = from.pop() else {
+            return;
+        };
+
+        let previous = self.set(index, byte);
+        let to = if undo { &mut self.redo } else { &mut self.undo };
+        to.push((index, previous));
+        (self.cursor, self.low) = (index.min(self.bytes.len()), false);
+    }
+
+    /// Moves to the next place `pattern` appears after the cursor, carrying on from the start,
+    /// or to the next place the last pattern appears when there isn't one
+    pub(crate) fn find(&mut self, pattern: Option<Vec<u8>>, layout: &TextLayout, window: Rect) -> Result<(), String> {
+        if let Some(pattern) = pattern {
+            self.pattern = pattern;
+        }
+        if self.pattern.is_empty() {
+            return Err("Nothing to search for yet".to_string());
+        }
+
+        let end = self.bytes.len();
+        let found = (self.cursor + 1..end)
+            .chain(0..=self.cursor.min(end))
+            .find(|&i| self.bytes[i..].starts_with(&self.pattern))
+            .ok_or("Not found")?;
+
+        (self.cursor, self.low) = (found, false);
+        self.scroll_to_cursor(layout, window);
+        Ok(())
+    }
+
+    /// The byte drawn under a point in the window, the side it's drawn on, and whether it's on its low nibble
+    fn byte_under(&self, local: Vec2, layout: &TextLayout) -> Option<(usize, Side, bool)> {
+        let row = layout.y_to_row(local.y + self.scroll);
+        let cell = (local.x / layout.cell_width()) as usize;
+
+        let (column, side, low) = match cell {
+            cell if cell >= ASCII_CELL => (cell - ASCII_CELL, Side::Ascii, false),
+            cell if cell >= OFFSET_CELLS => {
+                let column = (0..BYTES_PER_ROW).find(|&column| cell < Self::hex_cell(column) + 3)?;
+                (column, Side::Hex, cell == Self::hex_cell(column) + 1)
+            }
+            _ => return None,
+        };
+
+        let byte = row * BYTES_PER_ROW + column;
+        (column < BYTES_PER_ROW && byte <= self.bytes.len()).then_some((byte, side, low && byte < self.bytes.len()))
+    }
+
+    pub(crate) fn scroll(&mut self, offset: Vec2, layout: &TextLayout, window: Rect) {
+        let most = (self.rows() as f32 * layout.line_height() - window.h).max(0.0);
+        self.scroll = (self.scroll + offset.y).clamp(0.0, most);
+    }
+
+    fn scroll_to_cursor(&mut self, layout: &TextLayout, window: Rect) {
+        let line_height = layout.line_height();
+        let y = (self.cursor / BYTES_PER_ROW) as f32 * line_height;
+
+        if y < self.scroll {
+            self.scroll = y;
+        }
+        if y + line_height > self.scroll + window.h {
+            self.scroll = y + line_height - window.h;
+        }
+    }
+
+    pub(crate) fn view(&self, theme: &Theme, font: Option<&Font>, layout: &TextLayout, window: Rect, focused: bool) {
+        set_camera_window(window, vec2(0.0, self.scroll));
+
+        let cell_width = layout.measure(font);
+        let line_height = layout.line_height();
+        let params = |color| TextParams { color, font, font_size: layout.font_size(), ..Default::default() };
+
+        let first = layout.y_to_row(self.scroll);
+        let last = (layout.y_to_row(self.scroll + window.h) + 1).min(self.rows());
+        // Where the last search matched, if the cursor is still on it
+        let found = match self.bytes[self.cursor..].starts_with(&self.pattern) && !self.pattern.is_empty() {
+            true => self.cursor..self.cursor + self.pattern.len(),
+            false => 0..0,
+        };
+
+        for row in first..last {
+            let y = (row + 1) as f32 * line_height;
+            let start = row * BYTES_PER_ROW;
+
+            draw_text_ex(&format!("{start:08x}"), cell_width, y, params(theme.overlay1));
+
+            for column in 0..BYTES_PER_ROW.min(self.bytes.len() + 1 - start) {
+                let index = start + column;
+                let (x, ascii_x) = (Self::hex_cell(column) as f32 * cell_width, (ASCII_CELL + column) as f32 * cell_width);
+
+                if found.contains(&index) {
+                    draw_rectangle(x, y - line_height, cell_width * 2.0, line_height, theme.surface1);
+                    draw_rectangle(ascii_x, y - line_height, cell_width, line_height, theme.surface1);
+                }
+                // The side typing goes to gets a solid cursor, on the nibble being typed on the hex side
+                if focused && index == self.cursor {
+                    let nibble_x = x + if self.low { cell_width } else { 0.0 };
+                    let (hex, ascii) = match self.side {
+                        Side::Hex => (theme.rosewater, theme.surface2),
+                        Side::Ascii => (theme.surface2, theme.rosewater),
+                    };
+                    draw_rectangle(nibble_x, y - line_height, cell_width, line_height, Color { a: 0.5, ..hex });
+                    draw_rectangle(ascii_x, y - line_height, cell_width, line_height, Color { a: 0.5, ..ascii });
+                }
+
+                let Some(&byte) = self.bytes.get(index) else {
+                    continue;
+                };
+                // NULs fade into the background, and bytes that aren't printable ASCII stand out from those that are
+                let (color, glyph) = match byte {
+                    0 => (theme.overlay0, '.'),
+                    b' '..=b'~' => (theme.text, byte as char),
+                    _ => (theme.peach, '.'),
+                };
+                draw_text_ex(&format!("{byte:02x}"), x, y, params(color));
+                draw_text_ex(&glyph.to_string(), ascii_x, y, params(color));
+            }
+        }
+
+        let separator = (ASCII_CELL as f32 - 1.0) * cell_width;
+        draw_line(separator, self.scroll, separator, self.scroll + window.h, 1.0, theme.surface2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::vim::Registers;
+    use super::*;
+
+    #[test]
+    fn leaving_hex_mode_keeps_earlier_changes_undoable() {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.txt".to_string());
+        editor.load_string("ab".to_string());
+        let (mut highlighter, theme, mut registers) = (Highlighter::new(), Theme::mocha(), Registers::new());
+
+        editor.update(EditorMessage::Char('x'), &mut highlighter, &theme, &mut registers);
+        editor.toggle_hex(&mut highlighter, &theme).unwrap();
+        editor.update(EditorMessage::Char('7'), &mut highlighter, &theme, &mut registers);
+        editor.update(EditorMessage::Char('9'), &mut highlighter, &theme, &mut registers);
+        editor.toggle_hex(&mut highlighter, &theme).unwrap();
+        assert_eq!(editor.text(), "xyb");
+
+        editor.update(EditorMessage::Action(Action::Undo), &mut highlighter, &theme, &mut registers);
+        assert_eq!(editor.text(), "xab");
+        editor.update(EditorMessage::Action(Action::Undo), &mut highlighter, &theme, &mut registers);
+        assert_eq!(editor.text(), "ab");
+    }
+
+    #[test]
+    fn binary_files_are_read_only_until_hex_edit() {
+        let mut hex = Hex::new(vec![0, 1], 0, false);
+        let (layout, window) = (TextLayout::new(16), Rect::new(0.0, 0.0, 800.0, 600.0));
+
+        hex.update(EditorMessage::Char('f'), &layout, window);
+        assert_eq!(hex.bytes(), [0, 1]);
+        hex.writable = true;
+        hex.update(EditorMessage::Char('f'), &layout, window);
+        assert_eq!(hex.bytes(), [0xf0, 1]);
+    }
+}
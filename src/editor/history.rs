@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+use crate::editor::{Editor, Point};
+use crate::editor::vim::Mode;
+
+/// Most changes that can be undone
+const LIMIT: usize = 500;
+
+/// One change to the text, kept as just the rows it replaced so undoing doesn't need a copy of the whole buffer
+#[derive(Debug)]
+pub(super) struct Edit {
+    /// First row the change touched
+    pub(super) row: usize,
+    /// The rows from before the change
+    pub(super) lines: Vec<String>,
+    /// How many rows the change left in their place
+    pub(super) rows: usize,
+    cursor: Point,
+}
+
+impl Edit {
+    /// The edit replacing `lines` with `after`, which are the whole text before and after the change
+    pub(super) fn between(before: &[String], after: &[String], cursor: Point) -> Self {
+        Self { row: 0, lines: before.to_vec(), rows: after.len(), cursor }.trimmed(after)
+    }
+
+    /// The edit replacing `replaced` rows from `row` with `rows` rows
+    pub(super) fn of(row: usize, replaced: &[String], rows: usize) -> Self {
+        Self { row, lines: replaced.to_vec(), rows, cursor: Point::new(row, 0) }
+    }
+
+    /// The same edit without the rows at either end it left as they were, given the text after it
+    pub(super) fn trimmed(mut self, after: &[String]) -> Self {
+        let after = &after[self.row..self.row + self.rows];
+        let prefix = self.lines.iter().zip(after).take_while(|(a, b)| a == b).count();
+        let suffix = self.lines[prefix..].iter().rev().zip(after[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+
+        self.lines.truncate(self.lines.len() - suffix);
+        self.lines.drain(..prefix);
+        self.row += prefix;
+        self.rows -= prefix + suffix;
+        self
+    }
+
+    /// Whether the edit left the text as it was
+    pub(super) fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.rows == 0
+    }
+
+    /// The edit making this one and then one that replaced `replaced` rows from `row` with `rows` rows.
+    /// `after` gives the rows of the text between the two edits that are around this one.
+    pub(super) fn then(self, row: usize, replaced: usize, rows: usize, after: impl Fn(usize) -> String) -> Self {
+        let first = self.row.min(row);
+        let end = (self.row + self.rows).max(row + replaced);
+
+        let mut lines = (first..self.row).map(&after).collect::<Vec<_>>();
+        lines.extend(self.lines);
+        lines.extend((self.row + self.rows..end).map(&after));
+        Self { row: first, lines, rows: end - first - replaced + rows, cursor: self.cursor }
+    }
+
+    /// Puts the edit's rows back into `lines`, returning the edit that reverses it
+    fn apply(self, lines: &mut Vec<String>, cursor: Point) -> Self {
+        let rows = self.lines.len();
+        let replaced = lines.splice(self.row..self.row + self.rows, self.lines).collect();
+        Self { row: self.row, lines: replaced, rows, cursor }
+    }
+}
+
+/// Changes that can be undone and redone. Typing a word, or everything typed in one go in vim's insert mode,
+/// is undone as one change.
+#[derive(Debug, Default)]
+pub(super) struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    /// Where the cursor was left by a change that further typing joins
+    open: Option<Point>,
+    /// Whether the message being handled undid or redid, so it isn't recorded as a change itself
+    restored: bool,
+}
+
+impl History {
+    pub(super) fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl Editor {
+    /// Notes that `rows` are about to be replaced with `count` rows, so the message's change is known
+    /// without copying the whole buffer
+    pub(super) fn touch(&mut self, rows: Range<usize>, count: usize) {
+        let lines = &self.lines;
+        self.pending = Some(match self.pending.take() {
+            None => Edit::of(rows.start, &lines[rows.clone()], count),
+            Some(edit) => edit.then(rows.start, rows.len(), count, |row| lines[row].clone()),
+        });
+    }
+
+    /// Records what a message changed, if it changed anything, with the cursor from before it.
+    /// `typed` is whether the message typed a word char.
+    pub(super) fn record_change(&mut self, edit: Option<Edit>, cursor: Point, inserting: bool, typed: bool) {
+        if std::mem::take(&mut self.history.restored) {
+            self.history.open = None;
+            return;
+        }
+
+        let changed = edit.is_some();
+        let history = &mut self.history;
+        let joins = match self.vim.is_some() {
+            true => inserting && history.open.is_some(),
+            false => typed && history.open == Some(cursor),
+        };
+
+        if let Some(edit) = edit {
+            // A change joining the last one replaces it with one covering both
+            let edit = match joins.then(|| history.undo.pop_back()).flatten() {
+                Some(last) => {
+                    let lines = &self.lines;
+                    // Rows the last change left that this one didn't replace are still in the text, further down
+                    // by however many rows this one added
+                    let after = |row: usize| match row {
+                        row if row < edit.row => lines[row].clone(),
+                        row if row < edit.row + edit.lines.len() => edit.lines[row - edit.row].clone(),
+                        row => lines[row - edit.lines.len() + edit.rows].clone(),
+                    };
+                    last.then(edit.row, edit.lines.len(), edit.rows, after)
+                }
+                None => Edit { cursor, ..edit },
+            };
+
+            history.undo.push_back(edit);
+            if history.undo.len() > LIMIT {
+                history.undo.pop_front();
+            }
+            history.redo.clear();
+        }
+
+        let open = match &self.vim {
+            Some(vim) => vim.mode() == Mode::Insert && (changed || history.open.is_some()),
+            None => changed && typed,
+        };
+        history.open = open.then_some(self.cursor_position);
+    }
+
+    pub(super) fn undo(&mut self) {
+        if let Some(edit) = self.history.undo.pop_back() {
+            let reverse = self.restore(edit);
+            self.history.redo.push(reverse);
+        }
+    }
+
+    pub(super) fn redo(&mut self) {
+        if let Some(edit) = self.history.redo.pop() {
+            let reverse = self.restore(edit);
+            self.history.undo.push_back(reverse);
+        }
+    }
+
+    /// Reverts an edit and puts the cursor back where it was, returning the edit that undoes that
+    fn restore(&mut self, edit: Edit) -> Edit {
+        self.history.restored = true;
+        self.anchor = None;
+        self.completion = None;
+        self.snippet = None;
+
+        self.touch(edit.row..edit.row + edit.rows, edit.lines.len());
+        let cursor = std::mem::replace(&mut self.cursor_position, edit.cursor);
+        edit.apply(&mut self.lines, cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use inkjet::Highlighter;
+    use macroquad::math::Rect;
+    use crate::editor::EditorMessage;
+    use crate::editor::vim::Registers;
+    use crate::keymap::Action;
+    use crate::theme::Theme;
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split(',').map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn edits_keep_only_the_changed_rows() {
+        let before = lines("a,b,c,d");
+        let after = lines("a,x,y,z,d");
+        let edit = Edit::between(&before, &after, Point::new(1, 0));
+        assert_eq!((edit.row, edit.lines.clone(), edit.rows), (1, lines("b,c"), 3));
+
+        let mut text = after.clone();
+        let reverse = edit.apply(&mut text, Point::new(3, 1));
+        assert_eq!(text, before);
+        reverse.apply(&mut text, Point::new(1, 0));
+        assert_eq!(text, after);
+    }
+
+    #[test]
+    fn edits_made_one_after_another_undo_together() {
+        let between = lines("a,x,y,c,d");
+        let first = Edit::between(&lines("a,b,c,d"), &between, Point::new(1, 0));
+        let edit = first.then(3, 2, 1, |row| between[row].clone());
+        assert_eq!((edit.row, edit.lines.clone(), edit.rows), (1, lines("b,c,d"), 3));
+
+        let mut text = lines("a,x,y,z");
+        edit.apply(&mut text, Point::new(0, 0));
+        assert_eq!(text, lines("a,b,c,d"));
+    }
+
+    #[test]
+    fn edits_of_repeated_lines_stay_in_bounds() {
+        let edit = Edit::between(&lines("a,a"), &lines("a,a,a"), Point::new(0, 0));
+        assert_eq!((edit.row, edit.lines.len(), edit.rows), (2, 0, 1));
+    }
+
+    #[test]
+    fn typing_a_word_undoes_as_one_change() {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.txt".to_string());
+        editor.load_string("x\ny".to_string());
+        let (mut highlighter, theme, mut registers) = (Highlighter::new(), Theme::mocha(), Registers::new());
+        let mut send = |editor: &mut Editor, message| editor.update(message, &mut highlighter, &theme, &mut registers);
+
+        for character in "ab c".chars() {
+            send(&mut editor, EditorMessage::Char(character));
+        }
+        assert_eq!(editor.text(), "ab cx\ny");
+
+        send(&mut editor, EditorMessage::Action(Action::Undo));
+        assert_eq!(editor.text(), "ab x\ny");
+        send(&mut editor, EditorMessage::Action(Action::Undo));
+        assert_eq!(editor.text(), "abx\ny");
+        send(&mut editor, EditorMessage::Action(Action::Undo));
+        assert_eq!(editor.text(), "x\ny");
+        assert_eq!(editor.cursor_position, Point::new(0, 0));
+
+        send(&mut editor, EditorMessage::Action(Action::Redo));
+        assert_eq!(editor.text(), "abx\ny");
+        assert_eq!(editor.cursor_position, Point::new(0, 2));
+    }
+}
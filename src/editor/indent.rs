@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use inkjet::Language;
+use tree_sitter::{Node, Query, QueryCursor};
+use crate::editor::{Editor, Point};
+
+/// How a buffer is indented
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub(crate) struct Indentation {
+    pub(crate) tabs: bool,
+    /// Columns per indent level, which is also the width of a tab
+    pub(crate) size: usize,
+}
+
+impl Default for Indentation {
+    fn default() -> Self {
+        Self { tabs: false, size: 4 }
+    }
+}
+
+impl Indentation {
+    /// Guesses the indentation of existing text, from whether lines mostly start with tabs or spaces
+    /// and the most common step between the indents of neighbouring lines
+    pub(crate) fn detect(lines: &[String]) -> Option<Self> {
+        let mut tabs = 0;
+        let mut spaces = 0;
+        let mut steps: HashMap<usize, usize> = HashMap::new();
+        let mut previous = 0;
+
+        for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+            if line.starts_with('\t') {
+                tabs += 1;
+                continue;
+            }
+
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            if indent > 0 {
+                spaces += 1;
+            }
+            if indent > previous {
+                *steps.entry(indent - previous).or_default() += 1;
+            }
+            previous = indent;
+        }
+
+        if tabs == 0 && spaces == 0 {
+            return None;
+        }
+
+        let size = steps.into_iter()
+            .filter(|(step, _)| (2..=8).contains(step))
+            .max_by_key(|(step, count)| (*count, *step))
+            .map_or(4, |(step, _)| step);
+
+        Some(Self { tabs: tabs > spaces, size })
+    }
+
+    /// Text for one level of indentation
+    pub(crate) fn unit(&self) -> String {
+        match self.tabs {
+            true => "\t".to_owned(),
+            false => " ".repeat(self.size),
+        }
+    }
+}
+
+/// Nodes whose contents are indented one level deeper than the line they start on, captured as `@indent`.
+/// inkjet vendors each grammar's highlight, injection and locals queries but not its indent queries,
+/// so these live in `queries/` in the `indents.scm` layout editors such as Helix use.
+const INDENT_QUERIES: &[(Language, &str)] = &[
+    (Language::Rust, include_str!("../../queries/rust/indents.scm")),
+    (Language::Python, include_str!("../../queries/python/indents.scm")),
+    (Language::C, include_str!("../../queries/c/indents.scm")),
+    (Language::Cpp, include_str!("../../queries/cpp/indents.scm")),
+    (Language::Go, include_str!("../../queries/go/indents.scm")),
+    (Language::Java, include_str!("../../queries/java/indents.scm")),
+    (Language::Javascript, include_str!("../../queries/javascript/indents.scm")),
+    (Language::Typescript, include_str!("../../queries/typescript/indents.scm")),
+    (Language::Json, include_str!("../../queries/json/indents.scm")),
+];
+
+/// Compiled indent queries. Languages whose query doesn't match their grammar fall back to the bracket heuristic.
+static QUERIES: LazyLock<HashMap<Language, Query>> = LazyLock::new(|| {
+    INDENT_QUERIES.iter()
+        .filter_map(|(language, source)| Some((*language, Query::new(&language.config().language, source).ok()?)))
+        .collect()
+});
+
+impl Editor {
+    /// The indentation for a line inserted by splitting the line at `point`, and whether it is one level deeper
+    pub(super) fn newline_indentation(&self, point: Point) -> (String, bool) {
+        let base = self.indentation(point.row).to_owned();
+        let before = self.lines[point.row][..point.column].trim_end();
+
+        let opened = before.ends_with(['{', '[', '('])
+            || (before.ends_with(':') && self.language() == Language::Python)
+            || self.opens_indent_node(point);
+
+        match opened {
+            true => (base + &self.indent.unit(), true),
+            false => (base, false),
+        }
+    }
+
+    /// Whether an `@indent` node starts on the line of `point` and continues past it
+    fn opens_indent_node(&self, point: Point) -> bool {
+        let (Some(tree), Some(query)) = (&self.tree, QUERIES.get(&self.language())) else {
+            return false;
+        };
+
+        let at = tree_sitter::Point { row: point.row, column: point.column };
+        let mut cursor = QueryCursor::new();
+        cursor.set_point_range(tree_sitter::Point { row: point.row, column: 0 }..tree_sitter::Point { row: point.row + 1, column: 0 });
+
+        // Only predicates read the text, so it's handed over a line at a time rather than joined
+        let lines = &self.lines;
+        let text = |node: Node| (node.start_position().row..=node.end_position().row)
+            .filter_map(|row| lines.get(row))
+            .flat_map(|line| [line.as_bytes(), b"\n"]);
+
+        let found = cursor.captures(query, tree.root_node(), text)
+            .map(|(query_match, i)| query_match.captures[i].node)
+            .any(|node| node.start_position().row == point.row && node.start_position() < at && at < node.end_position());
+        found
+    }
+
+    /// Splits the line at the cursor, indenting the new line to suit the code around it.
+    /// A closing bracket right after the cursor moves down to a line of its own.
+    pub(super) fn newline(&mut self) {
+        let point = self.cursor_position;
+        let base = self.indentation(point.row).to_owned();
+        let (indentation, deeper) = self.newline_indentation(point);
+        let closes = self.lines[point.row][point.column..].trim_start().starts_with(['}', ']', ')']);
+
+        self.touch(point.row..point.row + 1, 1);
+        let tail = self.lines[point.row].split_off(point.column);
+        let tail = tail.trim_start();
+        let mut text = format!("\n{indentation}");
+        let cursor = Point::new(point.row + 1, indentation.len());
+
+        if deeper && closes {
+            text.push('\n');
+            text.push_str(&base);
+        }
+        text.push_str(tail);
+
+        let kept = self.lines[point.row].trim_end().len();
+        self.lines[point.row].truncate(kept);
+        self.insert_text(Point::new(point.row, self.lines[point.row].len()), &text);
+        self.cursor_position = cursor;
+    }
+
+    /// Inserts one level of indentation at the cursor, lining up with the next indent stop
+    pub(super) fn insert_indent(&mut self) {
+        let point = self.cursor_position;
+        let text = match self.indent.tabs {
+            true => "\t".to_owned(),
+            false => {
+                let cell = self.layout.line(point.row, &self.lines[point.row]).cell_of(point.column);
+                " ".repeat(self.indent.size - cell % self.indent.size)
+            }
+        };
+
+        self.cursor_position = self.insert_text(point, &text);
+    }
+
+    /// Adds or removes a level of indentation at the start of every line in `rows`
+    pub(super) fn indent_rows(&mut self, rows: std::ops::RangeInclusive<usize>, dedent: bool) {
+        let unit = self.indent.unit();
+
+        self.touch(*rows.start()..*rows.end() + 1, rows.clone().count());
+        for row in rows {
+            let shift = match dedent {
+                true => {
+                    let line = &mut self.lines[row];
+                    let width = match line.starts_with('\t') {
+                        true => 1,
+                        false => line.len() - line.trim_start_matches(' ').len(),
+                    };
+                    let removed = width.min(unit.len());
+                    line.drain(..removed);
+                    -(removed as isize)
+                }
+                false if self.lines[row].is_empty() => 0,
+                false => {
+                    self.lines[row].insert_str(0, &unit);
+                    unit.len() as isize
+                }
+            };
+
+            for point in [Some(&mut self.cursor_position), self.anchor.as_mut()].into_iter().flatten() {
+                if point.row == row {
+                    point.column = point.column.saturating_add_signed(shift);
+                }
+            }
+        }
+    }
+
+    /// Where backspace should delete back to: the previous indent stop when the cursor is in leading spaces
+    pub(super) fn backspace_start(&self, point: Point) -> Point {
+        let before = &self.lines[point.row][..point.column];
+
+        if point.column == 0 || self.indent.tabs || !before.chars().all(|c| c == ' ') {
+            return self.prev_point(point);
+        }
+
+        let removed = (point.column - 1) % self.indent.size + 1;
+        Point::new(point.row, point.column - removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::prelude::Rect;
+    use super::*;
+
+    #[test]
+    fn indents_inside_an_unclosed_indent_node() {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.rs".to_string());
+        editor.load_string("fn f() {\n    let v = vec![1,\n    2];\n}".to_string());
+
+        let end = Point::new(1, editor.lines[1].len());
+        assert_eq!(editor.newline_indentation(end), ("        ".to_string(), true));
+        assert_eq!(editor.newline_indentation(Point::new(2, 7)), ("    ".to_string(), false));
+    }
+}
@@ -0,0 +1,142 @@
+use macroquad::prelude::*;
+use crate::editor::{Editor, EditorMessage, Point};
+use crate::editor::diagnostics::Diagnostic;
+use crate::lsp::{Position, TextEdit};
+use crate::theme::Theme;
+
+impl Editor {
+    pub(crate) fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub(crate) fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    /// `point` as a server counts it
+    pub(crate) fn lsp_position(&self, point: Point) -> Position {
+        let point = self.clamp_point(point);
+        let line = &self.lines[point.row];
+        Position { line: point.row, character: line[..point.column].encode_utf16().count() }
+    }
+
+    pub(crate) fn cursor_lsp(&self) -> Position {
+        self.lsp_position(self.cursor_position)
+    }
+
+    /// The point a server means by `position`, clamped to the buffer
+    pub(crate) fn point_at(&self, position: Position) -> Point {
+        let Some(line) = self.lines.get(position.line) else {
+            return self.end_point();
+        };
+
+        let mut units = 0;
+        let column = line.char_indices()
+            .find(|(_, character)| {
+                units += character.len_utf16();
+                units > position.character
+            })
+            .map_or(line.len(), |(column, _)| column);
+
+        Point::new(position.line, column)
+    }
+
+    pub(super) fn lsp_update(&mut self, message: EditorMessage) {
+        match message {
+            EditorMessage::Hover(text) => {
+                // Problems at the cursor go above what the server says about it
+                let cursor = self.cursor_position;
+                let problems = self.diagnostics.iter()
+                    .filter(|diagnostic| diagnostic.start <= cursor && cursor <= diagnostic.end)
+                    .map(|diagnostic| match &diagnostic.source {
+                        Some(source) => format!("{source}: {}", diagnostic.message),
+                        None => diagnostic.message.clone(),
+                    });
+                self.hover = Some(problems.chain([text]).collect::<Vec<_>>().join("\n\n"));
+            }
+            EditorMessage::Completions(items) => self.offer_lsp(items),
+            EditorMessage::Edits(edits) => self.apply_edits(edits),
+            EditorMessage::JumpTo(position) => {
+                self.cursor_position = self.point_at(position);
+                self.anchor = None;
+                self.reveal(self.cursor_position.row);
+            }
+            EditorMessage::Diagnostics(diagnostics) => {
+                let diagnostics = diagnostics.into_iter()
+                    .map(|diagnostic| Diagnostic {
+                        start: self.point_at(diagnostic.range.start),
+                        end: self.point_at(diagnostic.range.end),
+                        severity: diagnostic.severity(),
+                        message: diagnostic.message,
+                        source: diagnostic.source,
+                    })
+                    .collect();
+                self.set_diagnostics(diagnostics);
+            }
+            _ => {}
+        }
+    }
+
+    /// Replaces ranges of the buffer, keeping the cursor on the same text
+    fn apply_edits(&mut self, edits: Vec<TextEdit>) {
+        let mut edits = edits.into_iter()
+            .map(|edit| (self.point_at(edit.range.start), self.point_at(edit.range.end), edit.text))
+            .collect::<Vec<_>>();
+        edits.sort_by_key(|(start, end, _)| (*start, *end));
+
+        let mut cursor = self.cursor_position;
+
+        // Working backwards keeps the earlier ranges valid
+        for (start, end, text) in edits.into_iter().rev() {
+            self.delete_range(start, end);
+            let inserted = self.insert_text(start, &text);
+
+            if cursor >= end {
+                cursor = match cursor.row == end.row {
+                    true => Point::new(inserted.row, inserted.column + cursor.column - end.column),
+                    false => Point::new(cursor.row + inserted.row - end.row, cursor.column),
+                };
+            } else if cursor > start {
+                cursor = start;
+            }
+        }
+
+        self.cursor_position = cursor;
+        self.anchor = None;
+    }
+
+    /// Draws the hover text and completion menu over the pane, next to the cursor
+    pub(super) fn draw_popups(&self, theme: &Theme, font: Option<&Font>) {
+        if let Some(hover) = &self.hover {
+            let lines = hover.lines().take(20).collect::<Vec<_>>();
+            self.draw_popup(&lines, None, self.point_on_screen(self.cursor_position), theme, font);
+        }
+
+        self.draw_completions(theme, font);
+    }
+
+    /// A box of text lines with its top left corner at `position`, with one optionally highlighted
+    pub(super) fn draw_popup(&self, lines: &[&str], selected: Option<usize>, position: Vec2, theme: &Theme, font: Option<&Font>) {
+        let (line_height, cell_width) = (self.layout.line_height(), self.layout.cell_width());
+        let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0).min(80);
+        let size = vec2((columns + 2) as f32 * cell_width, lines.len() as f32 * line_height + 4.0);
+
+        draw_rectangle(position.x, position.y, size.x, size.y, theme.mantle);
+        draw_rectangle_lines(position.x, position.y, size.x, size.y, 1.0, theme.surface2);
+
+        for (i, line) in lines.iter().enumerate() {
+            let top = position.y + 2.0 + i as f32 * line_height;
+            if selected == Some(i) {
+                draw_rectangle(position.x, top, size.x, line_height, theme.surface1);
+            }
+
+            let line = line.chars().take(80).collect::<String>();
+            draw_text_ex(&line, position.x + cell_width, top + line_height - 4.0, TextParams {
+                color: theme.text,
+                font,
+                font_size: self.layout.font_size(),
+                ..Default::default()
+            });
+        }
+    }
+}
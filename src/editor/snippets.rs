@@ -0,0 +1,977 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use inkjet::Language;
+use macroquad::prelude::KeyCode;
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::config::Config;
+use crate::editor::{Editor, EditorMessage, Point};
+use crate::editor::vim::Mode;
+use crate::keymap::Action;
+
+/// Snippets every pane has, before any from the config directory. `\t` is a level of indentation.
+const BUILT_IN: &[(Language, &str, &str)] = &[
+    (Language::Rust, "fn", "fn ${1:name}($2) {\n\t$0\n}"),
+    (Language::Rust, "impl", "impl ${1:Type} {\n\t$0\n}"),
+    (Language::Rust, "match", "match ${1:value} {\n\t${2:_} => {$0}\n}"),
+    (Language::Rust, "test", "#[test]\nfn ${1:name}() {\n\t$0\n}"),
+    (Language::Rust, "derive", "#[derive(${1|Debug,Clone,Copy,PartialEq,Eq,Hash,Default|})]"),
+    (Language::Python, "def", "def ${1:name}($2):\n\t${0:pass}"),
+    (Language::Python, "class", "class ${1:Name}:\n\tdef __init__(self$2):\n\t\t${0:pass}"),
+    (Language::Python, "main", "if __name__ == \"__main__\":\n\t${0:main()}"),
+    (Language::Javascript, "function", "function ${1:name}($2) {\n\t$0\n}"),
+    (Language::Typescript, "function", "function ${1:name}($2) {\n\t$0\n}"),
+    (Language::Go, "func", "func ${1:name}($2) {\n\t$0\n}"),
+    (Language::Go, "iferr", "if err != nil {\n\treturn ${1:err}\n}"),
+    (Language::C, "main", "int main(int argc, char **argv) {\n\t${0:return 0;}\n}"),
+];
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December",
+];
+const DAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// Text expanded from a trigger word, in the TextMate syntax: `$1` or `${1:placeholder}` for tab stops, `${1|a,b|}` for
+/// a choice, `$0` for where the cursor ends up, and `$NAME` or `${NAME:default}` for variables
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Snippet {
+    #[serde(skip)]
+    trigger: String,
+    body: String,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+impl Snippet {
+    pub(super) fn trigger(&self) -> &str {
+        &self.trigger
+    }
+
+    pub(super) fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// What to show for the snippet in the completion menu
+    pub(super) fn description(&self) -> String {
+        self.description.clone().unwrap_or_else(|| {
+            let text = plain(&parse(&self.body), &|name: &str| Some(name.to_owned()));
+            text.lines().next().unwrap_or_default().to_owned()
+        })
+    }
+}
+
+/// Snippets from the `snippets` folder of the config directory, with one file per language named by language or
+/// extension, and `all.toml` for every language
+#[derive(Debug, Default)]
+pub(crate) struct Snippets {
+    /// Which language each snippet is for, or `None` for all of them
+    snippets: Vec<(Option<Language>, Snippet)>,
+}
+
+impl Snippets {
+    /// Reads every snippet file, along with the first problem found in one
+    pub(crate) fn load() -> (Self, Option<String>) {
+        let mut snippets = Self::default();
+        let Some(dir) = Config::dir().map(|dir| dir.join("snippets")) else {
+            return (snippets, None);
+        };
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == ErrorKind::NotFound => return (snippets, None),
+            Err(error) => return (snippets, Some(format!("{}: {}", dir.display(), error))),
+        };
+
+        let mut paths = entries.filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension() == Some(OsStr::new("toml")))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut problem = None;
+        for path in paths {
+            if let Err(error) = snippets.load_file(&path) {
+                problem.get_or_insert(error);
+            }
+        }
+
+        (snippets, problem)
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        let name = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+        let language = match name {
+            "all" => None,
+            name => Some(Language::from_token(name).ok_or_else(|| format!("{}: unknown language {name}", path.display()))?),
+        };
+
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        let snippets: BTreeMap<String, Snippet> = toml::from_str(&text)
+            .map_err(|error| format!("{}: {}", path.display(), error.message()))?;
+
+        self.snippets.extend(snippets.into_iter().map(|(trigger, snippet)| (language, Snippet { trigger, ..snippet })));
+        Ok(())
+    }
+
+    /// Snippets for `language`: ones for it first, then ones for every language, then the built-in ones,
+    /// each trigger only once
+    pub(crate) fn for_language(&self, language: Language) -> Vec<Snippet> {
+        let own = self.snippets.iter().filter(|(for_language, _)| *for_language == Some(language));
+        let shared = self.snippets.iter().filter(|(for_language, _)| for_language.is_none());
+        let built_in = BUILT_IN.iter()
+            .filter(|(for_language, _, _)| *for_language == language)
+            .map(|(_, trigger, body)| Snippet { trigger: trigger.to_string(), body: body.to_string(), description: None });
+
+        let mut seen = HashSet::new();
+        own.chain(shared).map(|(_, snippet)| snippet.clone())
+            .chain(built_in)
+            .filter(|snippet| seen.insert(snippet.trigger.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Stop { number: usize, placeholder: Vec<Part> },
+    Choice { number: usize, options: Vec<String> },
+    Variable { name: String, default: Vec<Part> },
+}
+
+fn parse(body: &str) -> Vec<Part> {
+    let chars = body.chars().collect::<Vec<_>>();
+    parse_parts(&chars, &mut 0, false)
+}
+
+/// Parses up to the end, or to an unescaped `}` when `nested`. A `$` that doesn't start anything is left as text.
+fn parse_parts(chars: &[char], position: &mut usize, nested: bool) -> Vec<Part> {
+    let mut parts = vec![];
+    let mut text = String::new();
+
+    while let Some(&character) = chars.get(*position) {
+        match character {
+            '\\' if chars.get(*position + 1).is_some_and(|next| "$}\\".contains(*next)) => {
+                text.push(chars[*position + 1]);
+                *position += 2;
+            }
+            '}' if nested => break,
+            '$' => {
+                let start = *position;
+                match parse_dollar(chars, position) {
+                    Some(part) => {
+                        if !text.is_empty() {
+                            parts.push(Part::Text(std::mem::take(&mut text)));
+                        }
+                        parts.push(part);
+                    }
+                    None => {
+                        *position = start + 1;
+                        text.push('$');
+                    }
+                }
+            }
+            _ => {
+                text.push(character);
+                *position += 1;
+            }
+        }
+    }
+
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    parts
+}
+
+fn parse_dollar(chars: &[char], position: &mut usize) -> Option<Part> {
+    *position += 1;
+    let braced = chars.get(*position) == Some(&'{');
+    if braced {
+        *position += 1;
+    }
+
+    let digits = chars[*position..].iter().take_while(|character| character.is_ascii_digit()).count();
+    let name = chars[*position..].iter()
+        .enumerate()
+        .take_while(|(i, character)| character.is_ascii_alphabetic() || **character == '_' || (*i > 0 && character.is_ascii_digit()))
+        .count();
+
+    if digits > 0 {
+        let number = chars[*position..*position + digits].iter().collect::<String>().parse().ok()?;
+        *position += digits;
+        if !braced {
+            return Some(Part::Stop { number, placeholder: vec![] });
+        }
+
+        let next = *chars.get(*position)?;
+        *position += 1;
+        match next {
+            '}' => Some(Part::Stop { number, placeholder: vec![] }),
+            ':' => {
+                let placeholder = parse_parts(chars, position, true);
+                close(chars, position)?;
+                Some(Part::Stop { number, placeholder })
+            }
+            '|' => Some(Part::Choice { number, options: parse_options(chars, position)? }),
+            _ => None,
+        }
+    } else if name > 0 {
+        let name_text = chars[*position..*position + name].iter().collect::<String>();
+        *position += name;
+        if !braced {
+            return Some(Part::Variable { name: name_text, default: vec![] });
+        }
+
+        let next = *chars.get(*position)?;
+        *position += 1;
+        match next {
+            '}' => Some(Part::Variable { name: name_text, default: vec![] }),
+            ':' => {
+                let default = parse_parts(chars, position, true);
+                close(chars, position)?;
+                Some(Part::Variable { name: name_text, default })
+            }
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+fn close(chars: &[char], position: &mut usize) -> Option<()> {
+    (chars.get(*position) == Some(&'}')).then(|| *position += 1)
+}
+
+/// The options of `${1|a,b|}`, after the first `|`
+fn parse_options(chars: &[char], position: &mut usize) -> Option<Vec<String>> {
+    let mut options = vec![String::new()];
+
+    loop {
+        match *chars.get(*position)? {
+            '\\' if chars.get(*position + 1).is_some_and(|next| ",|\\".contains(*next)) => {
+                options.last_mut()?.push(chars[*position + 1]);
+                *position += 2;
+            }
+            ',' => {
+                options.push(String::new());
+                *position += 1;
+            }
+            '|' if chars.get(*position + 1) == Some(&'}') => {
+                *position += 2;
+                return Some(options);
+            }
+            character => {
+                options.last_mut()?.push(character);
+                *position += 1;
+            }
+        }
+    }
+}
+
+/// Text of parts with placeholders filled in and no stops
+fn plain(parts: &[Part], variable: &dyn Fn(&str) -> Option<String>) -> String {
+    parts.iter()
+        .map(|part| match part {
+            Part::Text(text) => text.clone(),
+            Part::Stop { placeholder, .. } => plain(placeholder, variable),
+            Part::Choice { options, .. } => options[0].clone(),
+            Part::Variable { name, default } => variable(name).unwrap_or_else(|| plain(default, variable)),
+        })
+        .collect()
+}
+
+/// Where a tab stop is in the buffer. Its first range is the one that's edited, and the others mirror it.
+#[derive(Debug)]
+struct Stop {
+    number: usize,
+    ranges: Vec<(Point, Point)>,
+    choices: Vec<String>,
+}
+
+/// A snippet that has been expanded and whose tab stops can still be visited
+#[derive(Debug)]
+pub(super) struct SnippetSession {
+    /// In the order they're visited, with `$0` last
+    stops: Vec<Stop>,
+    current: usize,
+    /// Whether the ranges already account for the changes of the message being handled
+    tracked: bool,
+}
+
+/// Text replaced by a change: what was between `start` and `old_end` is now between `start` and `new_end`
+#[derive(Debug, Copy, Clone)]
+struct Change {
+    start: Point,
+    old_end: Point,
+    new_end: Point,
+}
+
+impl Change {
+    /// The same change `rows` further down
+    fn down(self, rows: usize) -> Self {
+        let down = |point: Point| Point::new(point.row + rows, point.column);
+        Self { start: down(self.start), old_end: down(self.old_end), new_end: down(self.new_end) }
+    }
+
+    /// Where a point at or after the replaced text moves to
+    fn after(&self, point: Point) -> Point {
+        match point.row == self.old_end.row {
+            true => Point::new(self.new_end.row, self.new_end.column + point.column - self.old_end.column),
+            false => Point::new(point.row + self.new_end.row - self.old_end.row, point.column),
+        }
+    }
+
+    /// Moves a range that wasn't being edited, so that text inserted at its edges stays outside it
+    fn shift(&self, (start, end): (Point, Point)) -> (Point, Point) {
+        let start = match start {
+            start if start < self.start => start,
+            start if start >= self.old_end => self.after(start),
+            _ => self.new_end,
+        };
+        let end = match end {
+            end if end <= self.start => end,
+            end if end >= self.old_end => self.after(end),
+            _ => self.start,
+        };
+        (start, end.max(start))
+    }
+
+    /// Moves the range being edited, so that text inserted at its edges becomes part of it
+    fn grow(&self, (start, end): (Point, Point)) -> (Point, Point) {
+        let start = match start {
+            start if start <= self.start => start,
+            start if start >= self.old_end => self.after(start),
+            _ => self.start,
+        };
+        let end = match end {
+            end if end < self.start => end,
+            end if end >= self.old_end => self.after(end),
+            _ => self.new_end,
+        };
+        (start, end.max(start))
+    }
+
+    /// Works out what changed between two versions of the text. The change is assumed to start no later than either
+    /// cursor, which tells apart typing `a` before or after another `a`.
+    fn between(before: &[String], after: &[String], cursors: (Point, Point)) -> Option<Self> {
+        let (old, new) = (before.join("\n"), after.join("\n"));
+        if old == new {
+            return None;
+        }
+
+        let index = |lines: &[String], point: Point| lines[..point.row].iter().map(|line| line.len() + 1).sum::<usize>() + point.column;
+        let limit = index(before, cursors.0).min(index(after, cursors.1));
+
+        let mut prefix = old.bytes().zip(new.bytes()).take(limit).take_while(|(a, b)| a == b).count();
+        while !old.is_char_boundary(prefix) {
+            prefix -= 1;
+        }
+        let most = old.len().min(new.len()) - prefix;
+        let mut suffix = old.bytes().rev().zip(new.bytes().rev()).take(most).take_while(|(a, b)| a == b).count();
+        while !old.is_char_boundary(old.len() - suffix) {
+            suffix -= 1;
+        }
+
+        Some(Change {
+            start: Editor::idx_to_point(&old, prefix),
+            old_end: Editor::idx_to_point(&old, old.len() - suffix),
+            new_end: Editor::idx_to_point(&new, new.len() - suffix),
+        })
+    }
+}
+
+/// Lays out a snippet's text as it will be inserted, keeping track of where its stops end up
+struct Layout<'a> {
+    text: String,
+    /// Where the end of `text` will be in the buffer
+    end: Point,
+    stops: Vec<Stop>,
+    indentation: &'a str,
+    unit: &'a str,
+    /// The placeholder of each stop, for the occurrences of it without one
+    defaults: HashMap<usize, String>,
+    variable: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl Layout<'_> {
+    /// Adds text, indenting new lines like the first one. In the snippet's own text, tabs are levels of indentation.
+    fn push(&mut self, text: &str, literal: bool) {
+        for character in text.chars() {
+            match character {
+                '\n' => {
+                    self.text.push('\n');
+                    self.text.push_str(self.indentation);
+                    self.end = Point::new(self.end.row + 1, self.indentation.len());
+                }
+                '\t' if literal => {
+                    self.text.push_str(self.unit);
+                    self.end.column += self.unit.len();
+                }
+                character => {
+                    self.text.push(character);
+                    self.end.column += character.len_utf8();
+                }
+            }
+        }
+    }
+
+    fn add_range(&mut self, number: usize, range: (Point, Point), choices: Vec<String>, defining: bool) {
+        match self.stops.iter_mut().find(|stop| stop.number == number) {
+            Some(stop) if defining => {
+                stop.ranges.insert(0, range);
+                stop.choices = choices;
+            }
+            Some(stop) => stop.ranges.push(range),
+            None => self.stops.push(Stop { number, ranges: vec![range], choices }),
+        }
+    }
+
+    fn lay_out(&mut self, parts: &[Part], defined: &mut HashSet<usize>) {
+        for part in parts {
+            let start = self.end;
+            match part {
+                Part::Text(text) => self.push(text, true),
+                Part::Stop { number, placeholder } if !placeholder.is_empty() && defined.insert(*number) => {
+                    self.lay_out(placeholder, defined);
+                    self.add_range(*number, (start, self.end), vec![], true);
+                }
+                Part::Choice { number, options } if defined.insert(*number) => {
+                    self.push(&options[0], false);
+                    self.add_range(*number, (start, self.end), options.clone(), true);
+                }
+                Part::Stop { number, .. } | Part::Choice { number, .. } => {
+                    let default = self.defaults.get(number).cloned().unwrap_or_default();
+                    self.push(&default, false);
+                    self.add_range(*number, (start, self.end), vec![], false);
+                }
+                Part::Variable { name, default } => match (self.variable)(name) {
+                    Some(value) => self.push(&value, false),
+                    None => self.lay_out(default, defined),
+                },
+            }
+        }
+    }
+}
+
+/// The placeholder of each stop as text, from the first occurrence of it that has one
+fn defaults(parts: &[Part], variable: &dyn Fn(&str) -> Option<String>, found: &mut HashMap<usize, String>) {
+    for part in parts {
+        match part {
+            Part::Stop { number, placeholder } if !placeholder.is_empty() => {
+                found.entry(*number).or_insert_with(|| plain(placeholder, variable));
+                defaults(placeholder, variable, found);
+            }
+            Part::Choice { number, options } => {
+                found.entry(*number).or_insert_with(|| options[0].clone());
+            }
+            Part::Variable { default, .. } => defaults(default, variable, found),
+            _ => {}
+        }
+    }
+}
+
+/// Year, month and day of a number of days since 1970-01-01
+fn civil_date(days: i64) -> (i64, usize, usize) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+
+    (year_of_era + era * 400 + i64::from(month <= 2), month as usize, day as usize)
+}
+
+impl Editor {
+    pub(crate) fn set_snippets(&mut self, snippets: Vec<Snippet>) {
+        self.snippets = snippets;
+    }
+
+    /// Value of a snippet variable, with dates and times in UTC
+    fn snippet_variable(&self, name: &str) -> Option<String> {
+        let path = Path::new(&self.filename);
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i64);
+        let days = seconds.div_euclid(86_400);
+        let (year, month, day) = civil_date(days);
+        let time = seconds.rem_euclid(86_400);
+        let weekday = DAYS[(days + 4).rem_euclid(7) as usize];
+
+        let value = match name {
+            "TM_FILENAME" => path.file_name()?.to_string_lossy().into_owned(),
+            "TM_FILENAME_BASE" => path.file_stem()?.to_string_lossy().into_owned(),
+            "TM_DIRECTORY" => path.canonicalize().ok()?.parent()?.display().to_string(),
+            "TM_FILEPATH" => path.canonicalize().map_or_else(|_| self.filename.clone(), |path| path.display().to_string()),
+            "TM_SELECTED_TEXT" => self.selection().map(|(start, end)| self.text_range(start, end)).unwrap_or_default(),
+            "TM_CURRENT_LINE" => self.lines[self.cursor_position.row].clone(),
+            "TM_CURRENT_WORD" => self.word_at_cursor().unwrap_or_default(),
+            "TM_LINE_INDEX" => self.cursor_position.row.to_string(),
+            "TM_LINE_NUMBER" => (self.cursor_position.row + 1).to_string(),
+            "CURRENT_YEAR" => year.to_string(),
+            "CURRENT_YEAR_SHORT" => format!("{:02}", year % 100),
+            "CURRENT_MONTH" => format!("{month:02}"),
+            "CURRENT_MONTH_NAME" => MONTHS[month - 1].to_owned(),
+            "CURRENT_MONTH_NAME_SHORT" => MONTHS[month - 1][..3].to_owned(),
+            "CURRENT_DATE" => format!("{day:02}"),
+            "CURRENT_DAY_NAME" => weekday.to_owned(),
+            "CURRENT_DAY_NAME_SHORT" => weekday[..3].to_owned(),
+            "CURRENT_HOUR" => format!("{:02}", time / 3600),
+            "CURRENT_MINUTE" => format!("{:02}", time / 60 % 60),
+            "CURRENT_SECOND" => format!("{:02}", time % 60),
+            "CURRENT_SECONDS_UNIX" => seconds.to_string(),
+            "UUID" => Uuid::new_v4().to_string(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Replaces the text between `start` and `end` with a snippet and goes to its first stop
+    pub(super) fn expand_snippet(&mut self, start: Point, end: Point, body: &str) {
+        let parts = parse(body);
+        let variable = |name: &str| self.snippet_variable(name);
+        let mut found = HashMap::new();
+        defaults(&parts, &variable, &mut found);
+
+        let indentation = self.indentation(start.row).to_owned();
+        let unit = self.indent.unit();
+        let mut layout = Layout {
+            text: String::new(),
+            end: start,
+            stops: vec![],
+            indentation: &indentation,
+            unit: &unit,
+            defaults: found,
+            variable: &variable,
+        };
+        layout.lay_out(&parts, &mut HashSet::new());
+
+        let Layout { text, end: text_end, mut stops, .. } = layout;
+        if !stops.iter().any(|stop| stop.number == 0) {
+            stops.push(Stop { number: 0, ranges: vec![(text_end, text_end)], choices: vec![] });
+        }
+        stops.sort_by_key(|stop| (stop.number == 0, stop.number));
+
+        self.delete_range(start, end);
+        self.insert_text(start, &text);
+        self.anchor = None;
+        self.completion = None;
+
+        self.snippet = Some(SnippetSession { stops, current: 0, tracked: true });
+        self.select_stop(0);
+    }
+
+    /// Selects the placeholder of a stop, or offers its choices. Reaching `$0` ends the snippet.
+    fn select_stop(&mut self, index: usize) {
+        let Some(session) = &mut self.snippet else {
+            return;
+        };
+        session.current = index;
+        let stop = &session.stops[index];
+        let (start, end) = stop.ranges[0];
+        let (number, choices) = (stop.number, stop.choices.clone());
+
+        self.reveal(start.row);
+        self.cursor_position = end;
+        self.anchor = (start != end).then_some(start);
+
+        if !choices.is_empty() {
+            self.replace_tracked(start, end, "");
+            self.mirror_stop();
+            self.cursor_position = start;
+            self.anchor = None;
+            self.offer_choices(choices);
+        }
+        if number == 0 {
+            self.snippet = None;
+        }
+    }
+
+    /// Replaces text inside the snippet, moving its stops, the cursor and the selection to match
+    fn replace_tracked(&mut self, start: Point, end: Point, text: &str) -> Point {
+        let (cursor, anchor) = (self.cursor_position, self.anchor);
+        self.delete_range(start, end);
+        let new_end = self.insert_text(start, text);
+
+        let change = Change { start, old_end: end, new_end };
+        self.cursor_position = change.shift((cursor, cursor)).0;
+        self.anchor = anchor.map(|anchor| change.shift((anchor, anchor)).0);
+        if let Some(session) = &mut self.snippet {
+            session.tracked = true;
+            for range in session.stops.iter_mut().flat_map(|stop| stop.ranges.iter_mut()) {
+                *range = change.shift(*range);
+            }
+        }
+
+        new_end
+    }
+
+    /// Copies the text of the current stop to its mirrors
+    fn mirror_stop(&mut self) {
+        let Some(session) = &self.snippet else {
+            return;
+        };
+        let stop = &session.stops[session.current];
+        let (start, end) = stop.ranges[0];
+        let count = stop.ranges.len();
+        let text = self.text_range(start, end);
+
+        for mirror in 1..count {
+            let Some(session) = &self.snippet else {
+                return;
+            };
+            let (mirror_start, mirror_end) = session.stops[session.current].ranges[mirror];
+            if self.text_range(mirror_start, mirror_end) != text {
+                let new_end = self.replace_tracked(mirror_start, mirror_end, &text);
+                if let Some(session) = &mut self.snippet {
+                    session.stops[session.current].ranges[mirror] = (mirror_start, new_end);
+                }
+            }
+        }
+    }
+
+    /// Moves the snippet's stops to follow what a message changed, and ends the snippet once something changes outside
+    /// the current stop or the cursor leaves it
+    pub(super) fn track_snippet(&mut self, cursor_before: Point) {
+        let Some(session) = &mut self.snippet else {
+            return;
+        };
+        if std::mem::take(&mut session.tracked) {
+            return;
+        }
+
+        // Only the rows the message edited are compared, with a row either side
+        let change = self.pending.as_ref().and_then(|edit| {
+            let (first, before, end) = self.edited_rows(edit);
+            let after = &self.lines[first..end];
+            let within = |lines: &[String], point: Point| match point.row.checked_sub(first) {
+                None => Point::new(0, 0),
+                Some(row) if row < lines.len() => Point::new(row, point.column),
+                Some(_) => Point::new(lines.len() - 1, lines[lines.len() - 1].len()),
+            };
+            let cursors = (within(&before, cursor_before), within(after, self.cursor_position));
+            Change::between(&before, after, cursors).map(|change| change.down(first))
+        });
+        if let Some(change) = change {
+            let session = self.snippet.as_mut().unwrap();
+            let current = session.current;
+            let (start, end) = session.stops[current].ranges[0];
+            if change.start < start || change.old_end > end {
+                self.snippet = None;
+                return;
+            }
+
+            for (index, stop) in session.stops.iter_mut().enumerate() {
+                for (i, range) in stop.ranges.iter_mut().enumerate() {
+                    *range = match index == current && i == 0 {
+                        true => change.grow(*range),
+                        false => change.shift(*range),
+                    };
+                }
+            }
+            self.mirror_stop();
+            if let Some(session) = &mut self.snippet {
+                session.tracked = false;
+            }
+        }
+
+        let Some(session) = &self.snippet else {
+            return;
+        };
+        let (start, end) = session.stops[session.current].ranges[0];
+        if !(start..=end).contains(&self.cursor_position) {
+            self.snippet = None;
+        }
+    }
+
+    /// The snippet whose trigger is the word before the cursor
+    fn triggered_snippet(&self) -> Option<(Point, String)> {
+        let start = self.completion_start();
+        let word = &self.lines[start.row][start.column..self.cursor_position.column];
+        let snippet = self.snippets.iter().find(|snippet| snippet.trigger == word)?;
+
+        Some((start, snippet.body.clone()))
+    }
+
+    /// Handles Tab and Shift+Tab while a snippet is being filled in, and Tab after a trigger word, passing the rest on
+    pub(super) fn snippet_input(&mut self, message: EditorMessage) -> Option<EditorMessage> {
+        if self.vim.as_ref().is_some_and(|vim| vim.mode() != Mode::Insert) {
+            return Some(message);
+        }
+
+        match message {
+            EditorMessage::Action(Action::Indent | Action::Dedent) if self.snippet.is_some() => {
+                let session = self.snippet.as_ref().unwrap();
+                let next = match message {
+                    EditorMessage::Action(Action::Indent) => (session.current + 1).min(session.stops.len() - 1),
+                    _ => session.current.saturating_sub(1),
+                };
+                self.completion = None;
+                self.select_stop(next);
+                None
+            }
+            EditorMessage::Action(Action::Indent) if self.anchor.is_none() => match self.triggered_snippet() {
+                Some((start, body)) => {
+                    self.expand_snippet(start, self.cursor_position, &body);
+                    None
+                }
+                None => Some(message),
+            },
+            EditorMessage::Keypress(KeyCode::Escape) => {
+                self.snippet = None;
+                Some(message)
+            }
+            message => Some(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use inkjet::Highlighter;
+    use macroquad::math::Rect;
+    use crate::editor::vim::Registers;
+    use crate::theme::Theme;
+    use super::*;
+
+    fn text(text: &str) -> Part {
+        Part::Text(text.to_owned())
+    }
+
+    fn stop(number: usize, placeholder: Vec<Part>) -> Part {
+        Part::Stop { number, placeholder }
+    }
+
+    fn at(row: usize, column: usize) -> Point {
+        Point::new(row, column)
+    }
+
+    /// A stop's number, ranges and choices
+    type Laid = (usize, Vec<(Point, Point)>, Vec<String>);
+
+    /// Text of `body` expanded at `start` on a line indented with `indentation`, with its stops
+    fn lay_out(body: &str, start: Point, indentation: &str) -> (String, Vec<Laid>) {
+        let parts = parse(body);
+        let variable = |name: &str| (name == "NAME").then(|| "value".to_owned());
+        let mut found = HashMap::new();
+        defaults(&parts, &variable, &mut found);
+
+        let mut layout = Layout {
+            text: String::new(),
+            end: start,
+            stops: vec![],
+            indentation,
+            unit: "    ",
+            defaults: found,
+            variable: &variable,
+        };
+        layout.lay_out(&parts, &mut HashSet::new());
+        let stops = layout.stops.into_iter().map(|stop| (stop.number, stop.ranges, stop.choices)).collect();
+        (layout.text, stops)
+    }
+
+    /// An editor for `test.rs` holding `text`, with one extra snippet
+    fn editor(text: &str, trigger: &str, body: &str) -> Editor {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.rs".to_string());
+        editor.load_string(text.to_string());
+        let mut snippets = Snippets::default().for_language(Language::Rust);
+        snippets.push(Snippet { trigger: trigger.to_owned(), body: body.to_owned(), description: None });
+        editor.set_snippets(snippets);
+        editor
+    }
+
+    fn send(editor: &mut Editor, messages: impl IntoIterator<Item = EditorMessage>) {
+        let (mut highlighter, theme, mut registers) = (Highlighter::new(), Theme::mocha(), Registers::new());
+        for message in messages {
+            editor.update(message, &mut highlighter, &theme, &mut registers);
+        }
+    }
+
+    fn typed(text: &str) -> Vec<EditorMessage> {
+        text.chars().map(EditorMessage::Char).collect()
+    }
+
+    fn tab(back: bool) -> EditorMessage {
+        EditorMessage::Action(if back { Action::Dedent } else { Action::Indent })
+    }
+
+    #[test]
+    fn parses_stops_placeholders_and_variables() {
+        assert_eq!(parse("fn ${1:name}($2) {\n\t$0\n}"), [
+            text("fn "), stop(1, vec![text("name")]), text("("), stop(2, vec![]), text(") {\n\t"), stop(0, vec![]), text("\n}"),
+        ]);
+        assert_eq!(parse("$TM_FILENAME ${X:a${1:b}} ${Y}"), [
+            Part::Variable { name: "TM_FILENAME".to_owned(), default: vec![] },
+            text(" "),
+            Part::Variable { name: "X".to_owned(), default: vec![text("a"), stop(1, vec![text("b")])] },
+            text(" "),
+            Part::Variable { name: "Y".to_owned(), default: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn leaves_escapes_and_stray_dollars_as_text() {
+        assert_eq!(parse(r"\$1 costs $ 5 \} \\ \n"), [text(r"$1 costs $ 5 } \ \n")]);
+        assert_eq!(parse("${1:abc"), [text("${1:abc")]);
+        assert_eq!(parse("${1x} $"), [text("${1x} $")]);
+        assert_eq!(parse("${2:a}}"), [stop(2, vec![text("a")]), text("}")]);
+    }
+
+    #[test]
+    fn parses_choices() {
+        let options = |options: &[&str]| options.iter().map(|option| option.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse(r"${1|a,b\,c,d\|e|}"), [Part::Choice { number: 1, options: options(&["a", "b,c", "d|e"]) }]);
+        assert_eq!(parse("${1|a||}"), [Part::Choice { number: 1, options: options(&["a|"]) }]);
+        assert_eq!(parse("${1|a,b"), [text("${1|a,b")]);
+    }
+
+    #[test]
+    fn mirrors_take_the_placeholder_of_the_stop() {
+        let (text, stops) = lay_out("$1 = ${1:x}; $1", at(0, 4), "    ");
+        assert_eq!(text, "x = x; x");
+        assert_eq!(stops, [(1, vec![(at(0, 8), at(0, 9)), (at(0, 4), at(0, 5)), (at(0, 11), at(0, 12))], vec![])]);
+    }
+
+    #[test]
+    fn choices_start_with_the_first_option() {
+        let (text, stops) = lay_out("${1|a,bb|} $1", at(0, 0), "");
+        assert_eq!(text, "a a");
+        assert_eq!(stops, [(1, vec![(at(0, 0), at(0, 1)), (at(0, 2), at(0, 3))], vec!["a".to_owned(), "bb".to_owned()])]);
+    }
+
+    #[test]
+    fn new_lines_keep_the_indentation_and_tabs_indent_further() {
+        let (text, stops) = lay_out("if x {\n\t$0\n}", at(3, 4), "    ");
+        assert_eq!(text, "if x {\n        \n    }");
+        assert_eq!(stops, [(0, vec![(at(4, 8), at(4, 8))], vec![])]);
+
+        // Tabs typed into a placeholder are text, not indentation
+        let (text, _) = lay_out("${1:a\tb}", at(0, 0), "");
+        assert_eq!(text, "a    b");
+        let (text, _) = lay_out("${NAME}\t$NAME", at(0, 0), "");
+        assert_eq!(text, "value    value");
+    }
+
+    #[test]
+    fn unknown_variables_fall_back_to_their_defaults() {
+        let (text, stops) = lay_out("${NAME} ${OTHER:${1:d}} $OTHER.", at(0, 0), "");
+        assert_eq!(text, "value d .");
+        assert_eq!(stops, [(1, vec![(at(0, 6), at(0, 7))], vec![])]);
+    }
+
+    #[test]
+    fn changes_are_found_next_to_the_cursor() {
+        let lines = |text: &str| text.split('\n').map(str::to_owned).collect::<Vec<_>>();
+        let change = |before: &str, after: &str, cursors| {
+            let change = Change::between(&lines(before), &lines(after), cursors).unwrap();
+            (change.start, change.old_end, change.new_end)
+        };
+
+        // Typing `a` after an `a` or before it
+        assert_eq!(change("ab", "aab", (at(0, 1), at(0, 2))), (at(0, 1), at(0, 1), at(0, 2)));
+        assert_eq!(change("ab", "aab", (at(0, 0), at(0, 1))), (at(0, 0), at(0, 0), at(0, 1)));
+        assert_eq!(change("a\nb", "a\nxy\nb", (at(1, 0), at(2, 0))), (at(1, 0), at(1, 0), at(2, 0)));
+        assert_eq!(change("aé", "a", (at(0, 3), at(0, 1))), (at(0, 1), at(0, 3), at(0, 1)));
+        assert!(Change::between(&lines("a"), &lines("a"), (at(0, 0), at(0, 0))).is_none());
+
+        let moved = Change { start: at(0, 1), old_end: at(0, 2), new_end: at(1, 0) }.down(3);
+        assert_eq!((moved.start, moved.old_end, moved.new_end), (at(3, 1), at(3, 2), at(4, 0)));
+    }
+
+    #[test]
+    fn inserting_at_a_range_edge_grows_only_the_edited_range() {
+        let insert = Change { start: at(0, 2), old_end: at(0, 2), new_end: at(0, 4) };
+        assert_eq!(insert.shift((at(0, 2), at(0, 5))), (at(0, 4), at(0, 7)));
+        assert_eq!(insert.grow((at(0, 2), at(0, 5))), (at(0, 2), at(0, 7)));
+        assert_eq!(insert.shift((at(0, 0), at(0, 2))), (at(0, 0), at(0, 2)));
+        assert_eq!(insert.grow((at(0, 0), at(0, 2))), (at(0, 0), at(0, 4)));
+
+        // Deleting across rows collapses ranges inside it and pulls later ones up
+        let delete = Change { start: at(0, 1), old_end: at(1, 1), new_end: at(0, 1) };
+        assert_eq!(delete.shift((at(0, 3), at(1, 0))), (at(0, 1), at(0, 1)));
+        assert_eq!(delete.grow((at(0, 3), at(1, 0))), (at(0, 1), at(0, 1)));
+        assert_eq!(delete.shift((at(1, 2), at(1, 4))), (at(0, 2), at(0, 4)));
+        assert_eq!(delete.shift((at(2, 0), at(2, 3))), (at(1, 0), at(1, 3)));
+    }
+
+    #[test]
+    fn civil_dates_count_leap_days() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(11_017), (2000, 3, 1));
+        assert_eq!(civil_date(19_782), (2024, 2, 29));
+        assert_eq!(civil_date(47_541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn variables_come_from_the_file_and_cursor() {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "src/snippet_test.rs".to_string());
+        editor.load_string("let word = 1;\nfoo bar".to_string());
+        editor.cursor_position = at(1, 1);
+
+        let variable = |editor: &Editor, name| editor.snippet_variable(name);
+        assert_eq!(variable(&editor, "TM_FILENAME").as_deref(), Some("snippet_test.rs"));
+        assert_eq!(variable(&editor, "TM_FILENAME_BASE").as_deref(), Some("snippet_test"));
+        assert_eq!(variable(&editor, "TM_CURRENT_LINE").as_deref(), Some("foo bar"));
+        assert_eq!(variable(&editor, "TM_CURRENT_WORD").as_deref(), Some("foo"));
+        assert_eq!(variable(&editor, "TM_LINE_INDEX").as_deref(), Some("1"));
+        assert_eq!(variable(&editor, "TM_LINE_NUMBER").as_deref(), Some("2"));
+        assert_eq!(variable(&editor, "TM_SELECTED_TEXT").as_deref(), Some(""));
+        assert_eq!(variable(&editor, "NOT_A_VARIABLE"), None);
+
+        editor.anchor = Some(at(0, 4));
+        editor.cursor_position = at(0, 8);
+        assert_eq!(variable(&editor, "TM_SELECTED_TEXT").as_deref(), Some("word"));
+
+        let month = variable(&editor, "CURRENT_MONTH_NAME").unwrap();
+        assert!(MONTHS.contains(&month.as_str()));
+        assert_eq!(variable(&editor, "CURRENT_MONTH_NAME_SHORT").unwrap(), month[..3]);
+        assert!(variable(&editor, "CURRENT_YEAR").unwrap().parse::<i64>().unwrap() >= 2024);
+        assert!(DAYS.contains(&variable(&editor, "CURRENT_DAY_NAME").unwrap().as_str()));
+    }
+
+    #[test]
+    fn tab_and_shift_tab_visit_the_stops() {
+        let mut editor = editor("", "m", "$1");
+        send(&mut editor, typed("fn"));
+        send(&mut editor, [tab(false)]);
+        assert_eq!(editor.text(), "fn name() {\n    \n}");
+        assert_eq!((editor.anchor, editor.cursor_position), (Some(at(0, 3)), at(0, 7)));
+
+        send(&mut editor, typed("g"));
+        send(&mut editor, [tab(false)]);
+        assert_eq!((editor.anchor, editor.cursor_position), (None, at(0, 5)));
+        send(&mut editor, typed("x"));
+        assert_eq!(editor.text(), "fn g(x) {\n    \n}");
+
+        send(&mut editor, [tab(true)]);
+        assert_eq!((editor.anchor, editor.cursor_position), (Some(at(0, 3)), at(0, 4)));
+        send(&mut editor, [tab(false), tab(false)]);
+        assert_eq!(editor.cursor_position, at(1, 4));
+        assert!(editor.snippet.is_none());
+    }
+
+    #[test]
+    fn mirrors_follow_the_stop_being_edited() {
+        let mut editor = editor("", "m", "${1:a} = $1;");
+        send(&mut editor, typed("m"));
+        send(&mut editor, [tab(false)]);
+        assert_eq!(editor.text(), "a = a;");
+
+        send(&mut editor, typed("bc"));
+        assert_eq!(editor.text(), "bc = bc;");
+        send(&mut editor, [EditorMessage::Action(Action::DeleteBackward)]);
+        assert_eq!(editor.text(), "b = b;");
+
+        // Moving out of the stop ends the snippet, so mirrors stay as they are
+        send(&mut editor, [EditorMessage::Action(Action::LineEnd), EditorMessage::Char('x')]);
+        assert_eq!(editor.text(), "b = b;x");
+        assert!(editor.snippet.is_none());
+    }
+}
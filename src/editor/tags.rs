@@ -0,0 +1,53 @@
+use crate::editor::{CharClass, Editor, Point};
+
+/// Endings of the node kinds that declare something, such as `function_item` or `class_definition`
+const DEFINITION_KINDS: &[&str] = &["_item", "_definition", "_declaration", "_declarator", "_spec"];
+
+impl Editor {
+    /// The identifier the cursor is on or just after
+    pub(crate) fn word_at_cursor(&self) -> Option<String> {
+        let Point { row, column } = self.cursor_position;
+        let line = &self.lines[row];
+        let is_word = |character: char| CharClass::of(character) == CharClass::Word;
+
+        let start = line[..column].char_indices().rev()
+            .take_while(|(_, character)| is_word(*character))
+            .last()
+            .map_or(column, |(start, _)| start);
+        let end = line[column..].char_indices()
+            .find(|(_, character)| !is_word(*character))
+            .map_or(line.len(), |(end, _)| column + end);
+
+        Some(line[start..end].to_owned()).filter(|word| !word.is_empty())
+    }
+
+    /// Where a declaration named `name` names it, found from the syntax tree without a language server
+    pub(crate) fn find_definition(&self, name: &str) -> Option<Point> {
+        let tree = self.tree.as_ref()?;
+        let text = self.text();
+        let mut cursor = tree.walk();
+
+        'nodes: loop {
+            let node = cursor.node();
+            let declared = node.child_by_field_name("name")
+                .filter(|_| DEFINITION_KINDS.iter().any(|kind| node.kind().ends_with(kind)))
+                .filter(|name_node| &text[name_node.byte_range()] == name);
+
+            if let Some(name_node) = declared {
+                let start = name_node.start_position();
+                return Some(Point::new(start.row, start.column));
+            }
+
+            if cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'nodes;
+                }
+            }
+        }
+
+        None
+    }
+}
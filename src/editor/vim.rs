@@ -0,0 +1,1031 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+use macroquad::prelude::*;
+use crate::editor::{CharClass, Editor, EditorMessage, Point};
+use crate::keymap::Action;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub(crate) enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+}
+
+impl Mode {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "VISUAL LINE",
+        }
+    }
+}
+
+/// Text held by a register, and whether it was taken as whole lines
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Register {
+    text: String,
+    linewise: bool,
+}
+
+/// Registers are shared by every pane, so text yanked in one can be put in another
+pub(crate) type Registers = HashMap<char, Register>;
+
+/// Input that makes up a change, so `.` can replay it
+#[derive(Debug, Copy, Clone)]
+enum Key {
+    Char(char),
+    Action(Action),
+    Escape,
+}
+
+/// Modal editing state of a single pane
+#[derive(Debug)]
+pub(crate) struct Vim {
+    mode: Mode,
+    /// Keys of the normal mode command typed so far
+    pending: String,
+    /// Keys of the change in progress, including anything typed in insert mode
+    change: Option<Vec<Key>>,
+    last_change: Vec<Key>,
+}
+
+impl Vim {
+    pub(crate) fn new() -> Self {
+        Self { mode: Mode::Normal, pending: String::new(), change: None, last_change: vec![] }
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub(crate) fn pending(&self) -> &str {
+        &self.pending
+    }
+
+    /// Drops the keys of the command typed so far
+    pub(crate) fn cancel(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordStart { big: bool },
+    WordBack { big: bool },
+    WordEnd { big: bool },
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FirstLine,
+    LastLine,
+    Find { target: char, forward: bool, till: bool },
+    MatchingBracket,
+}
+
+impl Motion {
+    fn linewise(&self) -> bool {
+        matches!(self, Motion::Up | Motion::Down | Motion::FirstLine | Motion::LastLine)
+    }
+
+    /// Whether an operator over this motion includes the char the motion lands on
+    fn inclusive(&self) -> bool {
+        matches!(self, Motion::WordEnd { .. } | Motion::Find { forward: true, .. } | Motion::MatchingBracket)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Dedent,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Object {
+    Word { around: bool, big: bool },
+    Delimited { open: char, close: char, around: bool },
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Target {
+    Motion(Motion),
+    Object(Object),
+    /// The operator doubled, as in `dd`
+    Lines,
+    Selection,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Insert {
+    Before,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Kind {
+    Move(Motion),
+    Operate(Operator, Target),
+    Insert(Insert),
+    Put { before: bool },
+    Visual { linewise: bool },
+    Repeat,
+    Undo,
+    /// `z` commands, which act on folds
+    Fold(Action),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Command {
+    register: Option<char>,
+    count: Option<usize>,
+    kind: Kind,
+}
+
+impl Command {
+    /// Whether `.` should repeat this command
+    fn is_change(&self) -> bool {
+        match self.kind {
+            Kind::Operate(Operator::Yank, _) | Kind::Operate(_, Target::Selection) => false,
+            Kind::Operate(..) | Kind::Insert(_) | Kind::Put { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether running the command changes the text
+    fn edits(&self) -> bool {
+        match self.kind {
+            Kind::Operate(operator, _) => operator != Operator::Yank,
+            Kind::Insert(_) | Kind::Put { .. } | Kind::Repeat | Kind::Undo => true,
+            _ => false,
+        }
+    }
+}
+
+enum Parsed {
+    Incomplete,
+    Invalid,
+    Done(Command),
+}
+
+fn parse_count(keys: &mut Peekable<Chars>) -> Option<usize> {
+    let mut count = None;
+
+    // A leading zero is the motion to the start of the line, not a count
+    while let Some(digit) = keys.peek().and_then(|c| c.to_digit(10)).filter(|d| *d != 0 || count.is_some()) {
+        count = Some(count.unwrap_or(0) * 10 + digit as usize);
+        keys.next();
+    }
+
+    count
+}
+
+fn parse_motion(key: char, keys: &mut Peekable<Chars>) -> Result<Option<Motion>, ()> {
+    let motion = match key {
+        'h' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'k' => Motion::Up,
+        'j' => Motion::Down,
+        'w' | 'W' => Motion::WordStart { big: key == 'W' },
+        'b' | 'B' => Motion::WordBack { big: key == 'B' },
+        'e' | 'E' => Motion::WordEnd { big: key == 'E' },
+        '0' => Motion::LineStart,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::LineEnd,
+        'G' => Motion::LastLine,
+        '%' => Motion::MatchingBracket,
+        'g' => match keys.next() {
+            None => return Ok(None),
+            Some('g') => Motion::FirstLine,
+            Some(_) => return Err(()),
+        },
+        'f' | 't' | 'F' | 'T' => match keys.next() {
+            None => return Ok(None),
+            Some(target) => Motion::Find {
+                target,
+                forward: key.is_lowercase(),
+                till: key.eq_ignore_ascii_case(&'t'),
+            },
+        },
+        _ => return Err(()),
+    };
+
+    Ok(Some(motion))
+}
+
+fn parse_object(key: char, target: char) -> Option<Object> {
+    let around = key == 'a';
+    let (open, close) = match target {
+        'w' | 'W' => return Some(Object::Word { around, big: target == 'W' }),
+        '(' | ')' | 'b' => ('(', ')'),
+        '{' | '}' | 'B' => ('{', '}'),
+        '[' | ']' => ('[', ']'),
+        '<' | '>' => ('<', '>'),
+        '"' | '\'' | '`' => (target, target),
+        _ => return None,
+    };
+
+    Some(Object::Delimited { open, close, around })
+}
+
+/// Parses a normal or visual mode command: `["x][count]` followed by a motion, an operator or a simple command
+fn parse(keys: &str, visual: bool) -> Parsed {
+    let mut keys = keys.chars().peekable();
+    let mut register = None;
+
+    if keys.peek() == Some(&'"') {
+        keys.next();
+        match keys.next() {
+            Some(name) => register = Some(name),
+            None => return Parsed::Incomplete,
+        }
+    }
+
+    let count = parse_count(&mut keys);
+    let Some(key) = keys.next() else {
+        return Parsed::Incomplete;
+    };
+
+    let operate = |operator, motion| match visual {
+        true => Kind::Operate(operator, Target::Selection),
+        false => Kind::Operate(operator, Target::Motion(motion)),
+    };
+
+    let mut count = count;
+    let kind = match key {
+        'd' | 'c' | 'y' | '>' | '<' if visual => Kind::Operate(operator(key), Target::Selection),
+        'd' | 'c' | 'y' | '>' | '<' => {
+            let motion_count = parse_count(&mut keys);
+            if count.is_some() || motion_count.is_some() {
+                count = Some(count.unwrap_or(1) * motion_count.unwrap_or(1));
+            }
+
+            let target = match keys.next() {
+                None => return Parsed::Incomplete,
+                Some(repeated) if repeated == key => Target::Lines,
+                Some(object @ ('i' | 'a')) => match keys.next() {
+                    None => return Parsed::Incomplete,
+                    Some(target) => match parse_object(object, target) {
+                        Some(object) => Target::Object(object),
+                        None => return Parsed::Invalid,
+                    },
+                },
+                // `cw` changes to the end of the word, like `ce`
+                Some('w' | 'W') if key == 'c' => Target::Motion(Motion::WordEnd { big: false }),
+                Some(motion) => match parse_motion(motion, &mut keys) {
+                    Ok(Some(motion)) => Target::Motion(motion),
+                    Ok(None) => return Parsed::Incomplete,
+                    Err(()) => return Parsed::Invalid,
+                },
+            };
+
+            Kind::Operate(operator(key), target)
+        }
+        'x' => operate(Operator::Delete, Motion::Right),
+        'X' => operate(Operator::Delete, Motion::Left),
+        's' => operate(Operator::Change, Motion::Right),
+        'D' => Kind::Operate(Operator::Delete, if visual { Target::Selection } else { Target::Motion(Motion::LineEnd) }),
+        'C' => Kind::Operate(Operator::Change, if visual { Target::Selection } else { Target::Motion(Motion::LineEnd) }),
+        'Y' => Kind::Operate(Operator::Yank, if visual { Target::Selection } else { Target::Lines }),
+        'S' => Kind::Operate(Operator::Change, Target::Lines),
+        'i' if !visual => Kind::Insert(Insert::Before),
+        'a' if !visual => Kind::Insert(Insert::After),
+        'I' if !visual => Kind::Insert(Insert::LineStart),
+        'A' if !visual => Kind::Insert(Insert::LineEnd),
+        'o' if !visual => Kind::Insert(Insert::LineBelow),
+        'O' if !visual => Kind::Insert(Insert::LineAbove),
+        'p' | 'P' => Kind::Put { before: key == 'P' },
+        'v' | 'V' => Kind::Visual { linewise: key == 'V' },
+        '.' if !visual => Kind::Repeat,
+        'u' if !visual => Kind::Undo,
+        'z' => match keys.next() {
+            None => return Parsed::Incomplete,
+            Some('c') => Kind::Fold(Action::Fold),
+            Some('o') => Kind::Fold(Action::Unfold),
+            Some('a') => Kind::Fold(Action::ToggleFold),
+            Some('M') => Kind::Fold(Action::FoldAll),
+            Some('R') => Kind::Fold(Action::UnfoldAll),
+            Some(_) => return Parsed::Invalid,
+        },
+        motion => match parse_motion(motion, &mut keys) {
+            Ok(Some(motion)) => Kind::Move(motion),
+            Ok(None) => return Parsed::Incomplete,
+            Err(()) => return Parsed::Invalid,
+        },
+    };
+
+    Parsed::Done(Command { register, count, kind })
+}
+
+fn operator(key: char) -> Operator {
+    match key {
+        'd' => Operator::Delete,
+        'c' => Operator::Change,
+        '>' => Operator::Indent,
+        '<' => Operator::Dedent,
+        _ => Operator::Yank,
+    }
+}
+
+/// Classifies chars for word motions, where WORDs only break at whitespace
+fn class_of(character: char, big: bool) -> CharClass {
+    match CharClass::of(character) {
+        CharClass::Punctuation if big => CharClass::Word,
+        class => class,
+    }
+}
+
+impl Editor {
+    fn vim_mut(&mut self) -> &mut Vim {
+        self.vim.as_mut().unwrap()
+    }
+
+    /// Handles a message in modal editing. Returns the message if it should be handled as in a regular editor.
+    pub(super) fn vim_update(&mut self, message: EditorMessage, registers: &mut Registers) -> Option<EditorMessage> {
+        let mode = self.vim_mut().mode;
+
+        match message {
+            EditorMessage::Keypress(KeyCode::Escape) => {
+                self.record(Key::Escape);
+                self.escape();
+                None
+            }
+
+            EditorMessage::Char(character) if mode != Mode::Insert => {
+                self.vim_key(character, registers);
+                None
+            }
+
+            // Editing keys move around like they do in vim, rather than changing text
+            EditorMessage::Action(action) if mode != Mode::Insert => {
+                match action {
+                    Action::DeleteBackward => self.vim_key('h', registers),
+                    Action::DeleteForward => self.vim_key('x', registers),
+                    Action::Newline => self.vim_key('+', registers),
+                    Action::Indent | Action::Dedent | Action::DeleteWordBackward | Action::DeleteWordForward => {}
+                    Action::Fold | Action::Unfold | Action::ToggleFold | Action::FoldAll | Action::UnfoldAll => self.fold_action(action),
+                    Action::Undo => self.undo(),
+                    Action::Redo => self.redo(),
+                    motion => {
+                        self.move_cursor(motion);
+                        self.clamp_to_line();
+                    }
+                }
+                None
+            }
+
+            EditorMessage::Char(character) => {
+                self.record(Key::Char(character));
+                Some(message)
+            }
+
+            EditorMessage::Action(action) => {
+                self.record(Key::Action(action));
+                Some(message)
+            }
+
+            EditorMessage::Click(_) => {
+                self.handle(message);
+                if mode != Mode::Insert {
+                    self.clamp_to_line();
+                }
+                None
+            }
+
+            message => Some(message),
+        }
+    }
+
+    /// Whether typing `key` outside insert mode finishes a command that changes the text
+    pub(super) fn vim_key_edits(&self, key: char) -> bool {
+        let Some(vim) = &self.vim else {
+            return false;
+        };
+        let keys = format!("{}{key}", vim.pending);
+        matches!(parse(&keys, matches!(vim.mode, Mode::Visual | Mode::VisualLine)), Parsed::Done(command) if command.edits())
+    }
+
+    fn record(&mut self, key: Key) {
+        if let Some(change) = &mut self.vim_mut().change {
+            change.push(key);
+        }
+    }
+
+    fn escape(&mut self) {
+        let vim = self.vim_mut();
+        let mode = vim.mode;
+
+        vim.pending.clear();
+        vim.mode = Mode::Normal;
+
+        if mode == Mode::Insert {
+            if let Some(change) = vim.change.take() {
+                vim.last_change = change;
+            }
+
+            let point = self.cursor_position;
+            if point.column > 0 {
+                self.cursor_position = self.prev_point(point);
+            }
+        }
+
+        self.anchor = None;
+        self.clamp_to_line();
+    }
+
+    fn vim_key(&mut self, key: char, registers: &mut Registers) {
+        // `+` moves down to the first non-blank char, which is also what Enter does
+        if key == '+' && self.vim_mut().pending.is_empty() {
+            self.move_vertically(1);
+            self.cursor_position = Point::new(self.cursor_position.row, self.indentation(self.cursor_position.row).len());
+            return;
+        }
+
+        let vim = self.vim_mut();
+        vim.pending.push(key);
+
+        let visual = matches!(vim.mode, Mode::Visual | Mode::VisualLine);
+        let command = match parse(&vim.pending, visual) {
+            Parsed::Incomplete => return,
+            Parsed::Invalid => {
+                vim.pending.clear();
+                return;
+            }
+            Parsed::Done(command) => command,
+        };
+
+        let keys = std::mem::take(&mut vim.pending);
+        if command.is_change() {
+            vim.change = Some(keys.chars().map(Key::Char).collect());
+        }
+
+        self.run(command, registers);
+
+        let vim = self.vim_mut();
+        if vim.mode != Mode::Insert {
+            if let Some(change) = vim.change.take() {
+                vim.last_change = change;
+            }
+            self.clamp_to_line();
+        }
+    }
+
+    fn run(&mut self, command: Command, registers: &mut Registers) {
+        let count = command.count.unwrap_or(1);
+
+        match command.kind {
+            Kind::Move(Motion::Up) => self.move_vertically(-(count as isize)),
+            Kind::Move(Motion::Down) => self.move_vertically(count as isize),
+            Kind::Move(motion) => self.cursor_position = self.motion_target(motion, command.count, self.cursor_position),
+            Kind::Operate(operator, target) => {
+                if let Some((start, end, linewise)) = self.target_range(target, operator, command.count) {
+                    self.operate(operator, start, end, linewise, command.register, registers);
+                }
+            }
+            Kind::Insert(insert) => self.start_insert(insert),
+            Kind::Put { before } => self.put(before, count, command.register, registers),
+            Kind::Visual { linewise } => {
+                let mode = if linewise { Mode::VisualLine } else { Mode::Visual };
+                let vim = self.vim_mut();
+
+                if vim.mode == mode {
+                    return self.escape();
+                }
+                vim.mode = mode;
+                self.anchor.get_or_insert(self.cursor_position);
+            }
+            Kind::Fold(action) => self.fold_action(action),
+            Kind::Undo => (0..count).for_each(|_| self.undo()),
+            Kind::Repeat => {
+                let keys = self.vim_mut().last_change.clone();
+
+                for _ in 0..count {
+                    for &key in &keys {
+                        let message = match key {
+                            Key::Char(character) => EditorMessage::Char(character),
+                            Key::Action(action) => EditorMessage::Action(action),
+                            Key::Escape => EditorMessage::Keypress(KeyCode::Escape),
+                        };
+
+                        if let Some(message) = self.vim_update(message, registers) {
+                            self.handle(message);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keeps the cursor on a char in normal mode, rather than after the end of the line
+    fn clamp_to_line(&mut self) {
+        if self.vim.as_ref().is_some_and(|vim| vim.mode == Mode::Insert) {
+            return;
+        }
+
+        let point = self.cursor_position;
+        if point.column > 0 && point.column >= self.lines[point.row].len() {
+            self.cursor_position = self.prev_point(Point::new(point.row, self.lines[point.row].len()));
+        }
+    }
+
+    fn first_non_blank(&self, row: usize) -> Point {
+        Point::new(row, self.indentation(row).len())
+    }
+
+    /// The char or line break at `point`, or `None` at the end of the buffer
+    fn char_or_newline(&self, point: Point) -> Option<char> {
+        self.char_at(point).or((point.row + 1 < self.lines.len()).then_some('\n'))
+    }
+
+    fn motion_target(&self, motion: Motion, count: Option<usize>, from: Point) -> Point {
+        let n = count.unwrap_or(1);
+        let last_row = self.lines.len() - 1;
+
+        match motion {
+            Motion::Left => (0..n).fold(from, |point, _| match point.column {
+                0 => point,
+                _ => self.prev_point(point),
+            }),
+            Motion::Right => (0..n).fold(from, |point, _| match point.column < self.lines[point.row].len() {
+                true => self.next_point(point),
+                false => point,
+            }),
+            Motion::Up => Point::new(from.row.saturating_sub(n), from.column),
+            Motion::Down => Point::new((from.row + n).min(last_row), from.column),
+            Motion::WordStart { big } => (0..n).fold(from, |point, _| self.word_start(point, big)),
+            Motion::WordBack { big } => (0..n).fold(from, |point, _| self.word_back(point, big)),
+            Motion::WordEnd { big } => (0..n).fold(from, |point, _| self.word_end(point, big)),
+            Motion::LineStart => Point::new(from.row, 0),
+            Motion::FirstNonBlank => self.first_non_blank(from.row),
+            Motion::LineEnd => {
+                let row = (from.row + n - 1).min(last_row);
+                Point::new(row, self.lines[row].len())
+            }
+            Motion::FirstLine => self.first_non_blank(count.map_or(0, |n| n - 1).min(last_row)),
+            Motion::LastLine => self.first_non_blank(count.map_or(last_row, |n| n - 1).min(last_row)),
+            Motion::Find { target, forward, till } => {
+                let line = &self.lines[from.row];
+                let mut found = from.column;
+
+                for _ in 0..n {
+                    let next = match forward {
+                        true => line[found..].char_indices().skip(1 + till as usize).find(|(_, c)| *c == target).map(|(i, _)| found + i),
+                        // `T` skips the char right before the cursor, so repeating it moves on
+                        false => {
+                            let end = match till {
+                                true => line[..found].char_indices().next_back().map_or(0, |(i, _)| i),
+                                false => found,
+                            };
+                            line[..end].rfind(target)
+                        }
+                    };
+                    match next {
+                        Some(next) => found = next,
+                        None => return from,
+                    }
+                }
+
+                match (till, forward) {
+                    (false, _) => Point::new(from.row, found),
+                    (true, true) => self.prev_point(Point::new(from.row, found)),
+                    (true, false) => self.next_point(Point::new(from.row, found)),
+                }
+            }
+            Motion::MatchingBracket => self.next_bracket_partner(from).unwrap_or(from),
+        }
+    }
+
+    /// `w`: the start of the next word, stopping at empty lines
+    fn word_start(&self, from: Point, big: bool) -> Point {
+        let mut point = from;
+
+        if let Some(class) = self.char_at(point).map(|c| class_of(c, big)).filter(|c| *c != CharClass::Whitespace) {
+            while self.char_at(point).is_some_and(|c| class_of(c, big) == class) {
+                point = self.next_point(point);
+            }
+        }
+
+        while let Some(character) = self.char_or_newline(point) {
+            if !character.is_whitespace() || (point != from && self.lines[point.row].is_empty()) {
+                break;
+            }
+            point = self.next_point(point);
+        }
+
+        point
+    }
+
+    /// `e`: the last char of the current or next word
+    fn word_end(&self, from: Point, big: bool) -> Point {
+        let mut point = self.next_point(from);
+
+        while self.char_or_newline(point).is_some_and(char::is_whitespace) {
+            point = self.next_point(point);
+        }
+
+        let Some(class) = self.char_at(point).map(|c| class_of(c, big)) else {
+            return point;
+        };
+
+        loop {
+            let next = self.next_point(point);
+            if next.row != point.row || !self.char_at(next).is_some_and(|c| class_of(c, big) == class) {
+                return point;
+            }
+            point = next;
+        }
+    }
+
+    /// `b`: the start of the current or previous word
+    fn word_back(&self, from: Point, big: bool) -> Point {
+        let mut point = self.prev_point(from);
+
+        while point != Point::new(0, 0) && self.char_or_newline(point).is_some_and(char::is_whitespace) {
+            if point.row != from.row && self.lines[point.row].is_empty() {
+                return point;
+            }
+            point = self.prev_point(point);
+        }
+
+        let Some(class) = self.char_at(point).map(|c| class_of(c, big)) else {
+            return point;
+        };
+
+        while point.column > 0 {
+            let prev = self.prev_point(point);
+            if self.char_at(prev).map(|c| class_of(c, big)) != Some(class) {
+                break;
+            }
+            point = prev;
+        }
+
+        point
+    }
+
+    /// The range an operator applies to, as (start, end, linewise)
+    fn target_range(&mut self, target: Target, operator: Operator, count: Option<usize>) -> Option<(Point, Point, bool)> {
+        let cursor = self.cursor_position;
+        let n = count.unwrap_or(1);
+
+        match target {
+            Target::Lines => Some((cursor, Point::new((cursor.row + n - 1).min(self.lines.len() - 1), 0), true)),
+            Target::Selection => {
+                let anchor = self.anchor.take()?;
+                let linewise = self.vim_mut().mode == Mode::VisualLine;
+                let (start, end) = (anchor.min(cursor), anchor.max(cursor));
+
+                self.vim_mut().mode = Mode::Normal;
+                Some((start, self.inclusive_end(end), linewise))
+            }
+            Target::Object(object) => self.object_range(object, cursor).map(|(start, end)| (start, end, false)),
+            Target::Motion(motion) => {
+                let mut target = self.motion_target(motion, count, cursor);
+
+                // `dw` on the last word of a line stops at the end of the line
+                if matches!(motion, Motion::WordStart { .. }) && target.row > cursor.row && operator != Operator::Yank {
+                    target = Point::new(cursor.row, self.lines[cursor.row].len());
+                }
+
+                let (start, end) = (cursor.min(target), cursor.max(target));
+                let end = if motion.inclusive() { self.inclusive_end(end) } else { end };
+
+                (start != end || motion.linewise()).then_some((start, end, motion.linewise()))
+            }
+        }
+    }
+
+    /// The point just after the char at `point`, which includes the line break at the end of a line
+    pub(super) fn inclusive_end(&self, point: Point) -> Point {
+        match self.char_at(point) {
+            Some(character) => Point::new(point.row, point.column + character.len_utf8()),
+            None => self.next_point(point),
+        }
+    }
+
+    fn object_range(&self, object: Object, cursor: Point) -> Option<(Point, Point)> {
+        let line = &self.lines[cursor.row];
+
+        match object {
+            Object::Word { around, big } => {
+                let class = class_of(self.char_at(cursor)?, big);
+                let same = |c: char| class_of(c, big) == class;
+
+                let start = line[..cursor.column].char_indices().rev()
+                    .take_while(|(_, c)| same(*c))
+                    .last()
+                    .map_or(cursor.column, |(i, _)| i);
+                let mut end = line[cursor.column..].char_indices()
+                    .find(|(_, c)| !same(*c))
+                    .map_or(line.len(), |(i, _)| cursor.column + i);
+
+                if around {
+                    end = line[end..].char_indices()
+                        .find(|(_, c)| !c.is_whitespace())
+                        .map_or(line.len(), |(i, _)| end + i);
+                }
+
+                Some((Point::new(cursor.row, start), Point::new(cursor.row, end)))
+            }
+
+            // Quotes can't nest, so they are only looked for on the cursor's line
+            Object::Delimited { open, close, around } if open == close => {
+                let under = cursor.column + self.char_at(cursor).map_or(0, char::len_utf8);
+                let before = line[..under.min(line.len())].rfind(open)?;
+                let after = before + 1 + line[before + 1..].find(close)?;
+
+                Some(match around {
+                    true => (Point::new(cursor.row, before), Point::new(cursor.row, after + 1)),
+                    false => (Point::new(cursor.row, before + 1), Point::new(cursor.row, after)),
+                })
+            }
+
+            Object::Delimited { open, close, around } => {
+                // On a closing delimiter, the pair it closes is the one meant
+                let from = if self.char_at(cursor) == Some(close) { self.prev_point(cursor) } else { cursor };
+                let start = self.find_unmatched(from, open, close, false)?;
+                let end = self.find_unmatched(self.next_point(start), close, open, true)?;
+
+                if around {
+                    return Some((start, self.next_point(end)));
+                }
+
+                // Like vim, a block with its delimiters on lines of their own keeps those lines
+                let mut inner = (self.next_point(start), end);
+                if inner.0.row < end.row && inner.0.column == self.lines[inner.0.row].len() {
+                    inner.0 = Point::new(inner.0.row + 1, 0);
+                }
+                if inner.0.row < end.row && self.lines[end.row][..end.column].trim().is_empty() {
+                    inner.1 = Point::new(end.row, 0);
+                }
+                Some(inner)
+            }
+        }
+    }
+
+    /// Searches from `point` for a `target` not balanced by a `nested` in between
+    fn find_unmatched(&self, point: Point, target: char, nested: char, forward: bool) -> Option<Point> {
+        let mut point = point;
+        let mut depth = 0;
+
+        loop {
+            match self.char_at(point) {
+                Some(c) if c == target && depth == 0 => return Some(point),
+                Some(c) if c == target => depth -= 1,
+                Some(c) if c == nested => depth += 1,
+                _ => {}
+            }
+
+            let next = if forward { self.next_point(point) } else { self.prev_point(point) };
+            if next == point {
+                return None;
+            }
+            point = next;
+        }
+    }
+
+    fn operate(&mut self, operator: Operator, start: Point, end: Point, linewise: bool, register: Option<char>, registers: &mut Registers) {
+        if matches!(operator, Operator::Indent | Operator::Dedent) {
+            self.indent_rows(start.row..=end.row, operator == Operator::Dedent);
+            self.cursor_position = self.first_non_blank(start.row);
+            return;
+        }
+
+        let text = match linewise {
+            true => self.lines[start.row..=end.row].join("\n") + "\n",
+            false => self.text_range(start, end),
+        };
+
+        store(registers, register, Register { text, linewise }, operator == Operator::Yank);
+
+        match (operator, linewise) {
+            (Operator::Yank, _) => self.cursor_position = start,
+            (Operator::Delete, true) => {
+                self.touch(start.row..end.row + 1, 0);
+                self.lines.drain(start.row..=end.row);
+                if self.lines.is_empty() {
+                    self.touch(0..0, 1);
+                    self.lines.push(String::new());
+                }
+                self.cursor_position = self.first_non_blank(start.row.min(self.lines.len() - 1));
+            }
+            (Operator::Change, true) => {
+                let indentation = self.indentation(start.row).to_owned();
+                self.touch(start.row..end.row + 1, 1);
+                self.lines.drain(start.row + 1..=end.row);
+                self.lines[start.row] = indentation;
+                self.cursor_position = Point::new(start.row, self.lines[start.row].len());
+                self.vim_mut().mode = Mode::Insert;
+            }
+            (Operator::Delete, false) => self.delete_range(start, end),
+            (Operator::Indent | Operator::Dedent, _) => unreachable!(),
+            (Operator::Change, false) => {
+                self.delete_range(start, end);
+                self.vim_mut().mode = Mode::Insert;
+            }
+        }
+    }
+
+    fn start_insert(&mut self, insert: Insert) {
+        let point = self.cursor_position;
+
+        self.cursor_position = match insert {
+            Insert::Before => point,
+            Insert::After => self.motion_target(Motion::Right, None, point),
+            Insert::LineStart => self.first_non_blank(point.row),
+            Insert::LineEnd => Point::new(point.row, self.lines[point.row].len()),
+            Insert::LineBelow | Insert::LineAbove => {
+                let indentation = self.indentation(point.row).to_owned();
+                let row = if insert == Insert::LineBelow { point.row + 1 } else { point.row };
+
+                self.touch(row..row, 1);
+                self.lines.insert(row, indentation);
+                Point::new(row, self.lines[row].len())
+            }
+        };
+
+        self.vim_mut().mode = Mode::Insert;
+    }
+
+    fn put(&mut self, before: bool, count: usize, register: Option<char>, registers: &Registers) {
+        let Some(register) = registers.get(&register.unwrap_or('"')) else {
+            return;
+        };
+        let text = register.text.repeat(count);
+        let point = self.cursor_position;
+
+        if register.linewise {
+            let row = if before { point.row } else { point.row + 1 };
+            let lines = text.strip_suffix('\n').unwrap_or(&text).split('\n').map(str::to_owned).collect::<Vec<_>>();
+
+            self.touch(row..row, lines.len());
+            self.lines.splice(row..row, lines);
+            self.cursor_position = self.first_non_blank(row);
+        } else {
+            let at = if before { point } else { self.motion_target(Motion::Right, None, point) };
+            let end = self.insert_text(at, &text);
+
+            self.cursor_position = self.prev_point(end).max(at);
+        }
+    }
+}
+
+/// Stores text in the named register as well as the unnamed one. Uppercase names append to the register.
+fn store(registers: &mut Registers, name: Option<char>, register: Register, yank: bool) {
+    match name {
+        Some('_') => return,
+        Some(name) if name.is_ascii_uppercase() => {
+            let existing = registers.entry(name.to_ascii_lowercase()).or_default();
+            existing.text.push_str(&register.text);
+            existing.linewise |= register.linewise;
+        }
+        Some(name) => {
+            registers.insert(name, register.clone());
+        }
+        None if yank => {
+            registers.insert('0', register.clone());
+        }
+        None => {}
+    }
+
+    registers.insert('"', register);
+}
+
+#[cfg(test)]
+mod tests {
+    use inkjet::Highlighter;
+    use crate::theme::Theme;
+    use super::*;
+
+    fn editor(text: &str) -> Editor {
+        let mut editor = Editor::new(Rect::new(0.0, 0.0, 800.0, 600.0), 16, "test.txt".to_string());
+        editor.load_string(text.to_string());
+        editor
+    }
+
+    /// Types `keys` into a vim pane holding `text`, with `\x1b` for Escape, returning the text and cursor after
+    fn typed(text: &str, keys: &str) -> (String, (usize, usize)) {
+        let mut editor = editor(text);
+        editor.set_vim(true);
+        let (mut highlighter, theme, mut registers) = (Highlighter::new(), Theme::mocha(), Registers::new());
+
+        for key in keys.chars() {
+            let message = match key {
+                '\x1b' => EditorMessage::Keypress(KeyCode::Escape),
+                '\n' => EditorMessage::Action(Action::Newline),
+                key => EditorMessage::Char(key),
+            };
+            editor.update(message, &mut highlighter, &theme, &mut registers);
+        }
+
+        (editor.text(), (editor.cursor_position.row, editor.cursor_position.column))
+    }
+
+    fn assert_typed(cases: &[(&str, &str, &str, (usize, usize))]) {
+        for &(text, keys, expected, cursor) in cases {
+            assert_eq!(typed(text, keys), (expected.to_string(), cursor), "{keys:?} in {text:?}");
+        }
+    }
+
+    #[test]
+    fn operators_with_motions_and_counts() {
+        assert_typed(&[
+            ("one two three", "dw", "two three", (0, 0)),
+            ("one two three", "2dw", "three", (0, 0)),
+            ("one two three", "d2w", "three", (0, 0)),
+            ("one two three", "wde", "one  three", (0, 4)),
+            ("one two three", "cwxyz\x1b", "xyz two three", (0, 2)),
+            ("one two three", "wD", "one ", (0, 3)),
+            ("x.y.z", "dfy", ".z", (0, 0)),
+            ("x.y.z", "$dTx", "xz", (0, 1)),
+            ("abcdef", "3x", "def", (0, 0)),
+            ("abcdef", "3l", "abcdef", (0, 3)),
+            ("a\nb\nc\nd", "2dd", "c\nd", (0, 0)),
+            ("a\nb\nc\nd", "jdj", "a\nd", (1, 0)),
+            ("a\nb\nc\nd", "Gdgg", "", (0, 0)),
+            ("x\ny", ">j", "    x\n    y", (0, 4)),
+        ]);
+    }
+
+    #[test]
+    fn text_objects() {
+        assert_typed(&[
+            ("foo(bar, baz)", "fbdi(", "foo()", (0, 4)),
+            ("foo(bar, baz)", "fbda(", "foo", (0, 2)),
+            ("say \"héllo\" now", "fhci\"x\x1b", "say \"x\" now", (0, 5)),
+            ("one two", "daw", "two", (0, 0)),
+            ("wörld x", "ciwy\x1b", "y x", (0, 0)),
+            ("a {\n  b\n}", "jdiB", "a {\n}", (1, 0)),
+        ]);
+    }
+
+    #[test]
+    fn repeats_the_last_change() {
+        assert_typed(&[
+            ("a b c d", "x..", " c d", (0, 0)),
+            ("one two three four", "dw.", "three four", (0, 0)),
+            ("abc", "A!\x1b.", "abc!!", (0, 4)),
+            ("a\nb\nc", "ddj.", "b", (0, 0)),
+            ("one two", "yw.", "one two", (0, 0)),
+        ]);
+    }
+
+    #[test]
+    fn yanks_and_puts_through_registers() {
+        assert_typed(&[
+            ("ab", "yyp", "ab\nab", (1, 0)),
+            ("one\ntwo", "\"ayyjyy\"ap", "one\ntwo\none", (2, 0)),
+            ("one\ntwo", "\"ayyjyyp", "one\ntwo\ntwo", (2, 0)),
+            ("one two", "dwwP", "twone o", (0, 5)),
+            ("one two", "dwu", "one two", (0, 0)),
+        ]);
+    }
+
+    #[test]
+    fn visual_selections_end_after_the_char_under_the_cursor() {
+        assert_typed(&[
+            ("héllo", "vld", "llo", (0, 0)),
+            ("aé", "lvd", "a", (0, 0)),
+            ("ab\ncd", "vjd", "d", (0, 0)),
+            ("ab\ncd", "Vjd", "", (0, 0)),
+            ("ab\n\ncd", "jvd", "ab\ncd", (1, 0)),
+        ]);
+    }
+
+    #[test]
+    fn quote_object_on_empty_line() {
+        let editor = editor("");
+        assert_eq!(editor.object_range(parse_object('i', '"').unwrap(), Point::new(0, 0)), None);
+    }
+
+    #[test]
+    fn quote_object_on_multibyte_char() {
+        let editor = editor("s = \"héllo wörld\";");
+        let (start, end) = (5, 5 + "héllo wörld".len());
+
+        let cursor = Point::new(0, "s = \"h".len());
+        assert_eq!(editor.object_range(parse_object('i', '"').unwrap(), cursor), Some((Point::new(0, start), Point::new(0, end))));
+        assert_eq!(editor.object_range(parse_object('a', '"').unwrap(), cursor), Some((Point::new(0, start - 1), Point::new(0, end + 1))));
+    }
+
+    #[test]
+    fn till_backward_after_multibyte_char() {
+        let editor = editor("aéü");
+        let till = Motion::Find { target: 'a', forward: false, till: true };
+
+        assert_eq!(editor.motion_target(till, None, Point::new(0, "aé".len())), Point::new(0, 1));
+        assert_eq!(editor.motion_target(till, None, Point::new(0, 1)), Point::new(0, 1));
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{self, Path};
+
+/// Line terminator written between lines on save
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub(crate) enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            "cr" => Some(LineEnding::Cr),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /// The most common line ending in `text`, or LF if it has none
+    pub(crate) fn detect(text: &str) -> Self {
+        let crlf = text.matches("\r\n").count();
+        let cr = text.matches('\r').count() - crlf;
+        let lf = text.matches('\n').count() - crlf;
+
+        match crlf.max(cr).max(lf) {
+            0 => LineEnding::Lf,
+            most if most == lf => LineEnding::Lf,
+            most if most == crlf => LineEnding::CrLf,
+            _ => LineEnding::Cr,
+        }
+    }
+
+    /// Splits `text` at any kind of line ending, and says whether it ended with one.
+    /// Empty text counts as ending with one, so lines typed into an empty file get one too.
+    pub(crate) fn split(text: &str) -> (Vec<String>, bool) {
+        let mut lines = vec![];
+        let mut rest = text;
+
+        while let Some(i) = rest.find(['\r', '\n']) {
+            lines.push(rest[..i].to_owned());
+            let length = if rest[i..].starts_with("\r\n") { 2 } else { 1 };
+            rest = &rest[i + length..];
+        }
+
+        let final_newline = rest.is_empty();
+        if !final_newline || lines.is_empty() {
+            lines.push(rest.to_owned());
+        }
+
+        (lines, final_newline)
+    }
+}
+
+/// Encoding of a file on disk
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub(crate) enum Charset {
+    Latin1,
+    Utf8,
+    Utf8Bom,
+    Utf16Be,
+    Utf16Le,
+}
+
+impl Charset {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "latin1" => Some(Charset::Latin1),
+            "utf-8" => Some(Charset::Utf8),
+            "utf-8-bom" => Some(Charset::Utf8Bom),
+            "utf-16be" => Some(Charset::Utf16Be),
+            "utf-16le" => Some(Charset::Utf16Le),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Charset::Latin1 => "Latin-1",
+            Charset::Utf8 => "UTF-8",
+            Charset::Utf8Bom => "UTF-8 BOM",
+            Charset::Utf16Be => "UTF-16 BE",
+            Charset::Utf16Le => "UTF-16 LE",
+        }
+    }
+
+    /// Works out the encoding from a byte order mark, treating text that isn't valid UTF-8 as latin1
+    pub(crate) fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"\xEF\xBB\xBF") {
+            Charset::Utf8Bom
+        } else if bytes.starts_with(b"\xFF\xFE") {
+            Charset::Utf16Le
+        } else if bytes.starts_with(b"\xFE\xFF") {
+            Charset::Utf16Be
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Charset::Utf8
+        } else {
+            Charset::Latin1
+        }
+    }
+
+    pub(crate) fn decode(&self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Charset::Latin1 => Some(bytes.iter().map(|&byte| byte as char).collect()),
+            Charset::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Charset::Utf8Bom => String::from_utf8(bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes).to_vec()).ok(),
+            Charset::Utf16Be | Charset::Utf16Le => {
+                let bom: &[u8] = if *self == Charset::Utf16Be { b"\xFE\xFF" } else { b"\xFF\xFE" };
+                let bytes = bytes.strip_prefix(bom).unwrap_or(bytes);
+                if !bytes.len().is_multiple_of(2) {
+                    return None;
+                }
+
+                let units = bytes.chunks_exact(2).map(|pair| match self {
+                    Charset::Utf16Be => u16::from_be_bytes([pair[0], pair[1]]),
+                    _ => u16::from_le_bytes([pair[0], pair[1]]),
+                });
+                char::decode_utf16(units).collect::<Result<_, _>>().ok()
+            }
+        }
+    }
+
+    /// Encodes `text`, failing if it has a character latin1 can't represent
+    pub(crate) fn encode(&self, text: &str) -> io::Result<Vec<u8>> {
+        Ok(match self {
+            Charset::Latin1 => text.chars()
+                .map(|c| u8::try_from(c).map_err(|_| io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("`{c}` can't be saved as {}, change the encoding with `encoding`", self.name()),
+                )))
+                .collect::<io::Result<_>>()?,
+            Charset::Utf8 => text.as_bytes().to_vec(),
+            Charset::Utf8Bom => [b"\xEF\xBB\xBF", text.as_bytes()].concat(),
+            Charset::Utf16Be => [0xFEFF].into_iter().chain(text.encode_utf16()).flat_map(u16::to_be_bytes).collect(),
+            Charset::Utf16Le => [0xFEFF].into_iter().chain(text.encode_utf16()).flat_map(u16::to_le_bytes).collect(),
+        })
+    }
+}
+
+/// The `.editorconfig` properties that apply to one file. Unset properties are left to the editor.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct EditorConfig {
+    pub(crate) indent_tabs: Option<bool>,
+    pub(crate) indent_size: Option<usize>,
+    pub(crate) tab_width: Option<usize>,
+    pub(crate) end_of_line: Option<LineEnding>,
+    pub(crate) charset: Option<Charset>,
+    pub(crate) trim_trailing_whitespace: Option<bool>,
+    pub(crate) insert_final_newline: Option<bool>,
+}
+
+impl EditorConfig {
+    /// Resolves the properties for `file` from every `.editorconfig` between its directory and the nearest
+    /// one marked `root = true`, with closer files and later sections taking precedence
+    pub(crate) fn resolve(file: &Path) -> Self {
+        let Ok(file) = path::absolute(file) else {
+            return Self::default();
+        };
+
+        let mut configs = vec![];
+        for dir in file.ancestors().skip(1) {
+            let Ok(text) = fs::read_to_string(dir.join(".editorconfig")) else {
+                continue;
+            };
+            let (root, sections) = parse(&text);
+            configs.push((dir, sections));
+            if root {
+                break;
+            }
+        }
+
+        let mut properties = HashMap::new();
+        for (dir, sections) in configs.into_iter().rev() {
+            let Ok(relative) = file.strip_prefix(dir) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+
+            for (glob, section) in sections {
+                if section_matches(&glob, &relative) {
+                    properties.extend(section);
+                }
+            }
+        }
+
+        Self::from_properties(&properties)
+    }
+
+    fn from_properties(properties: &HashMap<String, String>) -> Self {
+        let get = |key: &str| properties.get(key).map(String::as_str).filter(|value| *value != "unset");
+        let number = |key: &str| get(key).and_then(|value| value.parse().ok()).filter(|&size: &usize| size > 0);
+        let flag = |key: &str| match get(key) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        };
+
+        let indent_tabs = match get("indent_style") {
+            Some("tab") => Some(true),
+            Some("space") => Some(false),
+            _ => None,
+        };
+        let tab_width = number("tab_width");
+        let indent_size = match get("indent_size") {
+            Some("tab") => tab_width,
+            _ => number("indent_size"),
+        };
+
+        Self {
+            indent_tabs,
+            indent_size: indent_size.or(tab_width.filter(|_| indent_tabs == Some(true))),
+            tab_width: tab_width.or(indent_size),
+            end_of_line: get("end_of_line").and_then(LineEnding::parse),
+            charset: get("charset").and_then(Charset::parse),
+            trim_trailing_whitespace: flag("trim_trailing_whitespace"),
+            insert_final_newline: flag("insert_final_newline"),
+        }
+    }
+}
+
+type Section = (String, Vec<(String, String)>);
+
+/// Splits an `.editorconfig` into whether it is a root file and its sections in order
+fn parse(text: &str) -> (bool, Vec<Section>) {
+    let mut root = false;
+    let mut sections: Vec<Section> = vec![];
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+
+        if let Some(glob) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            sections.push((glob.to_owned(), vec![]));
+        } else if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_lowercase();
+
+            match sections.last_mut() {
+                Some((_, properties)) => properties.push((key, value)),
+                None if key == "root" => root = value == "true",
+                None => {}
+            }
+        }
+    }
+
+    (root, sections)
+}
+
+/// Whether a section glob applies to `path`, given relative to the `.editorconfig` that contains it.
+/// Globs without a `/` match the file name in any directory.
+fn section_matches(glob: &str, path: &str) -> bool {
+    let glob = match glob.contains('/') {
+        true => glob.strip_prefix('/').unwrap_or(glob).to_owned(),
+        false => format!("**/{glob}"),
+    };
+
+    let pattern = Glob::tokens(&glob.chars().collect::<Vec<_>>());
+    Glob::matches(&pattern, &path.chars().collect::<Vec<_>>())
+}
+
+#[derive(Debug, Clone)]
+enum Glob {
+    Literal(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `**`
+    Globstar,
+    /// `[abc]`, `[a-z]` or `[!abc]`
+    Class(Vec<(char, char)>, bool),
+    /// `{a,b,c}`
+    Alternatives(Vec<Vec<Glob>>),
+    /// `{1..10}`
+    Range(i64, i64),
+}
+
+impl Glob {
+    fn tokens(glob: &[char]) -> Vec<Glob> {
+        let mut tokens = vec![];
+        let mut i = 0;
+
+        while i < glob.len() {
+            match glob[i] {
+                '\\' if i + 1 < glob.len() => {
+                    tokens.push(Glob::Literal(glob[i + 1]));
+                    i += 1;
+                }
+                '?' => tokens.push(Glob::Any),
+                '*' if glob.get(i + 1) == Some(&'*') => {
+                    tokens.push(Glob::Globstar);
+                    i += 1;
+                }
+                '*' => tokens.push(Glob::Star),
+                '[' => match Self::class(&glob[i + 1..]) {
+                    Some((token, length)) => {
+                        tokens.push(token);
+                        i += length;
+                    }
+                    None => tokens.push(Glob::Literal('[')),
+                },
+                '{' => match Self::braces(&glob[i + 1..]) {
+                    Some((token, length)) => {
+                        tokens.push(token);
+                        i += length;
+                    }
+                    None => tokens.push(Glob::Literal('{')),
+                },
+                c => tokens.push(Glob::Literal(c)),
+            }
+            i += 1;
+        }
+
+        tokens
+    }
+
+    /// Parses the inside of `[...]`, returning the class and how many characters it used including the `]`
+    fn class(glob: &[char]) -> Option<(Glob, usize)> {
+        let end = glob.iter().skip(1).position(|&c| c == ']')? + 1;
+        let (negated, body) = match glob[0] {
+            '!' | '^' => (true, &glob[1..end]),
+            _ => (false, &glob[..end]),
+        };
+
+        let mut ranges = vec![];
+        let mut i = 0;
+        while i < body.len() {
+            if i + 2 < body.len() && body[i + 1] == '-' {
+                ranges.push((body[i], body[i + 2]));
+                i += 3;
+            } else {
+                ranges.push((body[i], body[i]));
+                i += 1;
+            }
+        }
+
+        Some((Glob::Class(ranges, negated), end + 1))
+    }
+
+    /// Parses the inside of `{...}`, returning the alternatives or range and how many characters it used including the `}`
+    fn braces(glob: &[char]) -> Option<(Glob, usize)> {
+        let mut depth = 0;
+        let mut parts = vec![];
+        let mut start = 0;
+        let mut end = None;
+
+        for (i, &c) in glob.iter().enumerate() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                '}' => {
+                    parts.push(&glob[start..i]);
+                    end = Some(i);
+                    break;
+                }
+                ',' if depth == 0 => {
+                    parts.push(&glob[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        let end = end?;
+
+        if let [part] = parts[..] {
+            let text = part.iter().collect::<String>();
+            if let Some((low, high)) = text.split_once("..") {
+                if let (Ok(low), Ok(high)) = (low.parse(), high.parse()) {
+                    return Some((Glob::Range(low, high), end + 1));
+                }
+            }
+        }
+
+        let alternatives = parts.into_iter().map(Self::tokens).collect();
+        Some((Glob::Alternatives(alternatives), end + 1))
+    }
+
+    fn matches(pattern: &[Glob], path: &[char]) -> bool {
+        let Some((token, rest)) = pattern.split_first() else {
+            return path.is_empty();
+        };
+
+        match token {
+            Glob::Literal(c) => path.first() == Some(c) && Self::matches(rest, &path[1..]),
+            Glob::Any => path.first().is_some_and(|&c| c != '/') && Self::matches(rest, &path[1..]),
+            Glob::Star => (0..=path.len())
+                .take_while(|&i| i == 0 || path[i - 1] != '/')
+                .any(|i| Self::matches(rest, &path[i..])),
+            // `**/` also matches no directories at all
+            Glob::Globstar => (0..=path.len()).any(|i| Self::matches(rest, &path[i..]))
+                || matches!(rest.first(), Some(Glob::Literal('/'))) && Self::matches(&rest[1..], path),
+            Glob::Class(ranges, negated) => path.first().is_some_and(|&c| {
+                c != '/' && ranges.iter().any(|&(low, high)| (low..=high).contains(&c)) != *negated
+            }) && Self::matches(rest, &path[1..]),
+            Glob::Alternatives(alternatives) => alternatives.iter().any(|alternative| {
+                let joined = alternative.iter().chain(rest).cloned().collect::<Vec<_>>();
+                Self::matches(&joined, path)
+            }),
+            Glob::Range(low, high) => {
+                let sign = usize::from(path.first() == Some(&'-'));
+                let digits = path[sign..].iter().take_while(|c| c.is_ascii_digit()).count();
+
+                (1..=digits).any(|length| {
+                    let number = path[..sign + length].iter().collect::<String>();
+                    number.parse::<i64>().is_ok_and(|n| (*low..=*high).contains(&n))
+                        && Self::matches(rest, &path[sign + length..])
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_line_endings() {
+        assert_eq!(LineEnding::detect(""), LineEnding::Lf);
+        assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), LineEnding::CrLf);
+        assert_eq!(LineEnding::detect("a\rb\rc\r\n"), LineEnding::Cr);
+        assert_eq!(LineEnding::detect("a\nb\r\n"), LineEnding::Lf);
+    }
+
+    #[test]
+    fn splits_lines() {
+        assert_eq!(LineEnding::split(""), (vec![String::new()], true));
+        assert_eq!(LineEnding::split("a\r\nb\rc\n"), (vec!["a".into(), "b".into(), "c".into()], true));
+        assert_eq!(LineEnding::split("a\n\nb"), (vec!["a".into(), String::new(), "b".into()], false));
+        assert_eq!(LineEnding::split("\n"), (vec![String::new()], true));
+    }
+
+    #[test]
+    fn detects_and_round_trips_charsets() {
+        assert_eq!(Charset::detect(b"plain"), Charset::Utf8);
+        assert_eq!(Charset::detect("caf\u{e9}".as_bytes()), Charset::Utf8);
+        assert_eq!(Charset::detect(b"caf\xE9"), Charset::Latin1);
+        assert_eq!(Charset::detect(b"\xEF\xBB\xBFa"), Charset::Utf8Bom);
+        assert_eq!(Charset::detect(b"\xFF\xFEa\0"), Charset::Utf16Le);
+        assert_eq!(Charset::detect(b"\xFE\xFF\0a"), Charset::Utf16Be);
+
+        for charset in [Charset::Latin1, Charset::Utf8, Charset::Utf8Bom, Charset::Utf16Be, Charset::Utf16Le] {
+            let bytes = charset.encode("caf\u{e9}").unwrap();
+            assert_eq!(Charset::detect(&bytes), charset);
+            assert_eq!(charset.decode(&bytes).as_deref(), Some("caf\u{e9}"));
+        }
+    }
+
+    #[test]
+    fn refuses_characters_latin1_lacks() {
+        let error = Charset::Latin1.encode("\u{2192}").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn globs() {
+        assert!(section_matches("*", "main.rs"));
+        assert!(section_matches("*.rs", "src/editor/main.rs"));
+        assert!(!section_matches("*.rs", "main.rsx"));
+        assert!(section_matches("src/*.rs", "src/main.rs"));
+        assert!(!section_matches("src/*.rs", "src/editor/main.rs"));
+        assert!(section_matches("/src/**.rs", "src/editor/main.rs"));
+        assert!(!section_matches("/src/*.rs", "lib/src/main.rs"));
+        assert!(section_matches("*.{rs,toml}", "Cargo.toml"));
+        assert!(!section_matches("*.{rs,toml}", "Cargo.lock"));
+        assert!(section_matches("file{1..3}.txt", "file2.txt"));
+        assert!(!section_matches("file{1..3}.txt", "file4.txt"));
+        assert!(section_matches("[abc].md", "b.md"));
+        assert!(!section_matches("[!abc].md", "b.md"));
+        assert!(section_matches("[!abc].md", "d.md"));
+        assert!(section_matches("?.md", "a.md"));
+        assert!(!section_matches("?.md", "ab.md"));
+    }
+
+    #[test]
+    fn closer_files_and_later_sections_win() {
+        let root = std::env::temp_dir().join(format!("benchide-editorconfig-{}", uuid::Uuid::new_v4()));
+        let inner = root.join("project/src");
+        fs::create_dir_all(&inner).unwrap();
+
+        fs::write(root.join(".editorconfig"), "[*]\ncharset = latin1\n").unwrap();
+        fs::write(
+            root.join("project/.editorconfig"),
+            "root = true\n[*]\nindent_style = tab\nend_of_line = crlf\n[*.rs]\nindent_style = space\nindent_size = 2\n",
+        ).unwrap();
+        fs::write(inner.join(".editorconfig"), "[*.rs]\nindent_size = 4\n").unwrap();
+
+        let config = EditorConfig::resolve(&inner.join("main.rs"));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(config.indent_tabs, Some(false));
+        assert_eq!(config.indent_size, Some(4));
+        assert_eq!(config.end_of_line, Some(LineEnding::CrLf));
+        assert_eq!(config.charset, None, "files above the root one are ignored");
+    }
+}
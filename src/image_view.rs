@@ -0,0 +1,169 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use image::ImageFormat;
+use macroquad::prelude::*;
+use resvg::{tiny_skia, usvg};
+use crate::keymap::Action;
+use crate::theme::Theme;
+use crate::window::{set_camera_window, set_fullscreen_camera};
+
+const FONT_SIZE: u16 = 16;
+/// How many times its own size an SVG is rasterized at, so it stays sharp when zoomed in a little
+const SVG_SCALE: f32 = 2.0;
+const ZOOM_STEP: f32 = 1.25;
+/// How far the arrow keys move a zoomed in image, in pixels
+const PAN_STEP: f32 = 40.0;
+
+#[derive(Debug)]
+pub enum ImageMessage {
+    Char(char),
+    Action(Action),
+    Scroll(Vec2),
+}
+
+/// A picture decoded to straight RGBA, with the size it's shown at when not zoomed
+struct Decoded {
+    size: Vec2,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Whether a file is a picture the image pane can show, going by its first bytes, or its extension for SVG
+pub(crate) fn is_image(filename: &str) -> bool {
+    if Path::new(filename).extension().and_then(|extension| extension.to_str()) == Some("svg") {
+        return true;
+    }
+
+    let mut head = vec![];
+    File::open(filename).and_then(|file| file.take(16).read_to_end(&mut head)).is_ok() && raster_format(&head).is_some()
+}
+
+fn raster_format(bytes: &[u8]) -> Option<ImageFormat> {
+    image::guess_format(bytes).ok().filter(|format| matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif))
+}
+
+fn decode(bytes: &[u8]) -> Result<Decoded, String> {
+    if let Some(format) = raster_format(bytes) {
+        // Only the first frame of an animated GIF is shown
+        let image = image::load_from_memory_with_format(bytes, format).map_err(|error| error.to_string())?.to_rgba8();
+        let (width, height) = image.dimensions();
+        return Ok(Decoded { size: vec2(width as f32, height as f32), width, height, pixels: image.into_raw() });
+    }
+
+    let tree = usvg::Tree::from_data(bytes, &usvg::Options::default()).map_err(|error| error.to_string())?;
+    let size = vec2(tree.size().width(), tree.size().height());
+    let (width, height) = ((size.x * SVG_SCALE).ceil() as u32, (size.y * SVG_SCALE).ceil() as u32);
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("the picture is empty")?;
+    resvg::render(&tree, tiny_skia::Transform::from_scale(SVG_SCALE, SVG_SCALE), &mut pixmap.as_mut());
+
+    let pixels = pixmap.pixels().iter()
+        .map(|pixel| pixel.demultiply())
+        .flat_map(|pixel| [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()])
+        .collect();
+    Ok(Decoded { size, width, height, pixels })
+}
+
+/// A read-only pane showing a picture, fitted to the window until it's zoomed
+pub(crate) struct ImageView {
+    pub(crate) window: Rect,
+    filename: String,
+    texture: Texture2D,
+    /// Size of the picture in its own pixels
+    size: Vec2,
+    /// How many screen pixels each of the picture's take up, or `None` to fit it to the window
+    zoom: Option<f32>,
+    /// How far the picture's center is from the window's
+    pan: Vec2,
+}
+
+impl ImageView {
+    pub(crate) fn open(filename: String, window: Rect) -> io::Result<Self> {
+        let bytes = fs::read(&filename)?;
+        let decoded = decode(&bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let texture = Texture2D::from_rgba8(decoded.width as u16, decoded.height as u16, &decoded.pixels);
+
+        Ok(Self { window, filename, texture, size: decoded.size, zoom: None, pan: Vec2::ZERO })
+    }
+
+    pub(crate) fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub(crate) fn titlebar(&self) -> Rect {
+        let titlebar_height = FONT_SIZE as f32 + 8.0;
+        Rect::new(self.window.x, self.window.y - titlebar_height, self.window.w, titlebar_height)
+    }
+
+    fn scale(&self) -> f32 {
+        self.zoom.unwrap_or_else(|| (self.window.w / self.size.x).min(self.window.h / self.size.y))
+    }
+
+    pub(crate) fn update(&mut self, message: ImageMessage) {
+        match message {
+            ImageMessage::Char('+' | '=') => self.zoom_by(ZOOM_STEP),
+            ImageMessage::Char('-') => self.zoom_by(1.0 / ZOOM_STEP),
+            ImageMessage::Char('0') => self.zoom = None,
+            ImageMessage::Char('1') => self.zoom = Some(1.0),
+            ImageMessage::Char(_) => {}
+            ImageMessage::Action(action) => {
+                self.pan += match action {
+                    Action::MoveLeft => vec2(PAN_STEP, 0.0),
+                    Action::MoveRight => vec2(-PAN_STEP, 0.0),
+                    Action::MoveUp => vec2(0.0, PAN_STEP),
+                    Action::MoveDown => vec2(0.0, -PAN_STEP),
+                    _ => Vec2::ZERO,
+                };
+            }
+            // The wheel zooms rather than scrolls, a step at a time whatever the platform's wheel deltas
+            ImageMessage::Scroll(offset) if offset.y < 0.0 => self.zoom_by(ZOOM_STEP),
+            ImageMessage::Scroll(offset) if offset.y > 0.0 => self.zoom_by(1.0 / ZOOM_STEP),
+            ImageMessage::Scroll(_) => {}
+        }
+
+        // Panning stops at the picture's edges, and there's none when it fits in the window
+        let room = ((self.size * self.scale() - self.window.size()) / 2.0).max(Vec2::ZERO);
+        self.pan = self.pan.clamp(-room, room);
+    }
+
+    fn zoom_by(&mut self, factor: f32) {
+        self.zoom = Some((self.scale() * factor).clamp(0.01, 64.0));
+    }
+
+    pub(crate) fn view(&self, theme: &Theme, font: Option<&Font>, focused: bool) {
+        set_fullscreen_camera();
+
+        let titlebar = self.titlebar();
+        draw_rectangle(titlebar.x, titlebar.y, titlebar.w, titlebar.h, theme.surface1);
+        draw_text_ex(&self.filename, self.window.x, self.window.y - 4.0, TextParams {
+            color: if focused { theme.lavender } else { theme.text },
+            font,
+            font_size: FONT_SIZE,
+            ..Default::default()
+        });
+
+        let zoom = match self.zoom {
+            Some(_) => format!("{:.0}%", self.scale() * 100.0),
+            None => "fit".to_string(),
+        };
+        let indicator = format!("{}x{}  {zoom}", self.size.x.round(), self.size.y.round());
+        let width = measure_text(&indicator, font, FONT_SIZE, 1.0).width;
+        draw_text_ex(&indicator, titlebar.right() - width - 4.0, self.window.y - 4.0, TextParams {
+            color: theme.subtext0,
+            font,
+            font_size: FONT_SIZE,
+            ..Default::default()
+        });
+
+        draw_rectangle(self.window.x, self.window.y, self.window.w, self.window.h, theme.crust);
+        set_camera_window(self.window, Vec2::ZERO);
+
+        let size = self.size * self.scale();
+        let corner = (self.window.size() - size) / 2.0 + self.pan;
+        draw_texture_ex(&self.texture, corner.x, corner.y, WHITE, DrawTextureParams {
+            dest_size: Some(size),
+            ..Default::default()
+        });
+    }
+}
@@ -0,0 +1,495 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use macroquad::miniquad::{EventHandler, KeyMods};
+use macroquad::prelude::*;
+
+/// Something a key sequence can be bound to
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
+pub enum Action {
+    Prompt,
+    Save,
+    Close,
+    Help,
+    Keys,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    WordLeft,
+    WordRight,
+    LineStart,
+    LineEnd,
+    PageUp,
+    PageDown,
+    DocumentStart,
+    DocumentEnd,
+    MatchBracket,
+    Fold,
+    Unfold,
+    ToggleFold,
+    FoldAll,
+    UnfoldAll,
+    Hover,
+    GotoDefinition,
+    Complete,
+    Rename,
+    GoBack,
+    Problems,
+    Undo,
+    Redo,
+    Terminal,
+    Files,
+    DeleteBackward,
+    DeleteForward,
+    DeleteWordBackward,
+    DeleteWordForward,
+    Newline,
+    Indent,
+    Dedent,
+    SelectLeft,
+    SelectRight,
+    SelectUp,
+    SelectDown,
+    SelectWordLeft,
+    SelectWordRight,
+    SelectLineStart,
+    SelectLineEnd,
+    SelectDocumentStart,
+    SelectDocumentEnd,
+}
+
+/// Names used for actions in the config file and the `keys` pane
+const ACTION_NAMES: &[(&str, Action)] = &[
+    ("prompt", Action::Prompt),
+    ("save", Action::Save),
+    ("close", Action::Close),
+    ("help", Action::Help),
+    ("keys", Action::Keys),
+    ("move-left", Action::MoveLeft),
+    ("move-right", Action::MoveRight),
+    ("move-up", Action::MoveUp),
+    ("move-down", Action::MoveDown),
+    ("word-left", Action::WordLeft),
+    ("word-right", Action::WordRight),
+    ("line-start", Action::LineStart),
+    ("line-end", Action::LineEnd),
+    ("page-up", Action::PageUp),
+    ("page-down", Action::PageDown),
+    ("document-start", Action::DocumentStart),
+    ("document-end", Action::DocumentEnd),
+    ("match-bracket", Action::MatchBracket),
+    ("fold", Action::Fold),
+    ("unfold", Action::Unfold),
+    ("toggle-fold", Action::ToggleFold),
+    ("fold-all", Action::FoldAll),
+    ("unfold-all", Action::UnfoldAll),
+    ("hover", Action::Hover),
+    ("goto-definition", Action::GotoDefinition),
+    ("complete", Action::Complete),
+    ("rename", Action::Rename),
+    ("go-back", Action::GoBack),
+    ("problems", Action::Problems),
+    ("undo", Action::Undo),
+    ("redo", Action::Redo),
+    ("terminal", Action::Terminal),
+    ("files", Action::Files),
+    ("delete-backward", Action::DeleteBackward),
+    ("delete-forward", Action::DeleteForward),
+    ("delete-word-backward", Action::DeleteWordBackward),
+    ("delete-word-forward", Action::DeleteWordForward),
+    ("newline", Action::Newline),
+    ("indent", Action::Indent),
+    ("dedent", Action::Dedent),
+    ("select-left", Action::SelectLeft),
+    ("select-right", Action::SelectRight),
+    ("select-up", Action::SelectUp),
+    ("select-down", Action::SelectDown),
+    ("select-word-left", Action::SelectWordLeft),
+    ("select-word-right", Action::SelectWordRight),
+    ("select-line-start", Action::SelectLineStart),
+    ("select-line-end", Action::SelectLineEnd),
+    ("select-document-start", Action::SelectDocumentStart),
+    ("select-document-end", Action::SelectDocumentEnd),
+];
+
+/// Bindings used unless the config file overrides them
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("ctrl+k", "prompt"),
+    ("ctrl+s", "save"),
+    ("left", "move-left"),
+    ("right", "move-right"),
+    ("up", "move-up"),
+    ("down", "move-down"),
+    ("ctrl+left", "word-left"),
+    ("ctrl+right", "word-right"),
+    ("home", "line-start"),
+    ("end", "line-end"),
+    ("pageup", "page-up"),
+    ("pagedown", "page-down"),
+    ("ctrl+home", "document-start"),
+    ("ctrl+end", "document-end"),
+    ("ctrl+]", "match-bracket"),
+    ("ctrl+shift+[", "fold"),
+    ("ctrl+shift+]", "unfold"),
+    ("ctrl+i", "hover"),
+    ("f12", "goto-definition"),
+    ("ctrl+space", "complete"),
+    ("f2", "rename"),
+    ("alt+left", "go-back"),
+    ("ctrl+shift+m", "problems"),
+    ("ctrl+z", "undo"),
+    ("ctrl+y", "redo"),
+    ("ctrl+shift+z", "redo"),
+    ("ctrl+`", "terminal"),
+    ("ctrl+shift+e", "files"),
+    ("backspace", "delete-backward"),
+    ("delete", "delete-forward"),
+    ("ctrl+backspace", "delete-word-backward"),
+    ("ctrl+delete", "delete-word-forward"),
+    ("enter", "newline"),
+    ("tab", "indent"),
+    ("shift+tab", "dedent"),
+    ("shift+left", "select-left"),
+    ("shift+right", "select-right"),
+    ("shift+up", "select-up"),
+    ("shift+down", "select-down"),
+    ("ctrl+shift+left", "select-word-left"),
+    ("ctrl+shift+right", "select-word-right"),
+    ("shift+home", "select-line-start"),
+    ("shift+end", "select-line-end"),
+    ("ctrl+shift+home", "select-document-start"),
+    ("ctrl+shift+end", "select-document-end"),
+];
+
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("space", KeyCode::Space), ("'", KeyCode::Apostrophe), (",", KeyCode::Comma), ("-", KeyCode::Minus),
+    (".", KeyCode::Period), ("/", KeyCode::Slash), (";", KeyCode::Semicolon), ("=", KeyCode::Equal),
+    ("[", KeyCode::LeftBracket), ("\\", KeyCode::Backslash), ("]", KeyCode::RightBracket), ("`", KeyCode::GraveAccent),
+    ("0", KeyCode::Key0), ("1", KeyCode::Key1), ("2", KeyCode::Key2), ("3", KeyCode::Key3), ("4", KeyCode::Key4),
+    ("5", KeyCode::Key5), ("6", KeyCode::Key6), ("7", KeyCode::Key7), ("8", KeyCode::Key8), ("9", KeyCode::Key9),
+    ("a", KeyCode::A), ("b", KeyCode::B), ("c", KeyCode::C), ("d", KeyCode::D), ("e", KeyCode::E),
+    ("f", KeyCode::F), ("g", KeyCode::G), ("h", KeyCode::H), ("i", KeyCode::I), ("j", KeyCode::J),
+    ("k", KeyCode::K), ("l", KeyCode::L), ("m", KeyCode::M), ("n", KeyCode::N), ("o", KeyCode::O),
+    ("p", KeyCode::P), ("q", KeyCode::Q), ("r", KeyCode::R), ("s", KeyCode::S), ("t", KeyCode::T),
+    ("u", KeyCode::U), ("v", KeyCode::V), ("w", KeyCode::W), ("x", KeyCode::X), ("y", KeyCode::Y),
+    ("z", KeyCode::Z),
+    ("escape", KeyCode::Escape), ("enter", KeyCode::Enter), ("tab", KeyCode::Tab), ("backspace", KeyCode::Backspace),
+    ("insert", KeyCode::Insert), ("delete", KeyCode::Delete), ("right", KeyCode::Right), ("left", KeyCode::Left),
+    ("down", KeyCode::Down), ("up", KeyCode::Up), ("pageup", KeyCode::PageUp), ("pagedown", KeyCode::PageDown),
+    ("home", KeyCode::Home), ("end", KeyCode::End),
+    ("f1", KeyCode::F1), ("f2", KeyCode::F2), ("f3", KeyCode::F3), ("f4", KeyCode::F4), ("f5", KeyCode::F5),
+    ("f6", KeyCode::F6), ("f7", KeyCode::F7), ("f8", KeyCode::F8), ("f9", KeyCode::F9), ("f10", KeyCode::F10),
+    ("f11", KeyCode::F11), ("f12", KeyCode::F12),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTION_NAMES.iter().find(|(n, _)| *n == name).map(|(_, action)| *action)
+    }
+
+    pub fn name(&self) -> &'static str {
+        ACTION_NAMES.iter().find(|(_, action)| action == self).map(|(name, _)| *name).unwrap()
+    }
+
+    /// Whether the action changes the text of the pane it's sent to
+    pub fn edits(&self) -> bool {
+        matches!(self, Action::Undo | Action::Redo | Action::DeleteBackward | Action::DeleteForward
+            | Action::DeleteWordBackward | Action::DeleteWordForward | Action::Newline | Action::Indent | Action::Dedent)
+    }
+
+    /// The motion a selecting action extends the selection by
+    pub fn selection_motion(&self) -> Option<Action> {
+        match self {
+            Action::SelectLeft => Some(Action::MoveLeft),
+            Action::SelectRight => Some(Action::MoveRight),
+            Action::SelectUp => Some(Action::MoveUp),
+            Action::SelectDown => Some(Action::MoveDown),
+            Action::SelectWordLeft => Some(Action::WordLeft),
+            Action::SelectWordRight => Some(Action::WordRight),
+            Action::SelectLineStart => Some(Action::LineStart),
+            Action::SelectLineEnd => Some(Action::LineEnd),
+            Action::SelectDocumentStart => Some(Action::DocumentStart),
+            Action::SelectDocumentEnd => Some(Action::DocumentEnd),
+            _ => None,
+        }
+    }
+}
+
+/// A single key press together with the modifiers held down
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub struct Chord {
+    pub key: KeyCode,
+    pub control: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+}
+
+impl Ord for Chord {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let key = |chord: &Chord| (chord.key as u16, chord.control, chord.alt, chord.shift, chord.logo);
+        key(self).cmp(&key(other))
+    }
+}
+
+impl PartialOrd for Chord {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Chord {
+    fn new(key: KeyCode, mods: KeyMods) -> Self {
+        Self { key, control: mods.ctrl, alt: mods.alt, shift: mods.shift, logo: mods.logo }
+    }
+
+    /// Parses chords written like `ctrl+shift+k`
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut chord = Chord { key: KeyCode::Unknown, control: false, alt: false, shift: false, logo: false };
+        let (modifiers, key) = text.rsplit_once('+').filter(|(_, key)| !key.is_empty()).unwrap_or(("", text));
+
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => chord.control = true,
+                "alt" => chord.alt = true,
+                "shift" => chord.shift = true,
+                "super" | "cmd" | "logo" => chord.logo = true,
+                other => return Err(format!("unknown modifier `{other}`")),
+            }
+        }
+
+        chord.key = KEY_NAMES.iter()
+            .find(|(name, _)| *name == key.to_lowercase())
+            .map(|(_, key)| *key)
+            .ok_or(format!("unknown key `{key}`"))?;
+
+        Ok(chord)
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [(self.control, "ctrl+"), (self.alt, "alt+"), (self.shift, "shift+"), (self.logo, "super+")] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+
+        match key_name(self.key) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:?}", self.key),
+        }
+    }
+}
+
+/// The name of a key as it's written in bindings
+pub(crate) fn key_name(key: KeyCode) -> Option<&'static str> {
+    KEY_NAMES.iter().find(|(_, named)| *named == key).map(|(name, _)| *name)
+}
+
+fn parse_sequence(text: &str) -> Result<Vec<Chord>, String> {
+    let sequence = text.split_whitespace().map(Chord::parse).collect::<Result<Vec<_>, _>>()?;
+
+    if sequence.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+
+    Ok(sequence)
+}
+
+fn format_sequence(sequence: &[Chord]) -> String {
+    sequence.iter().map(Chord::to_string).collect::<Vec<_>>().join(" ")
+}
+
+/// Input that came in since the last frame, in the order it was typed
+#[derive(Debug)]
+pub(crate) enum InputEvent {
+    Chord(Chord),
+    Char(char),
+}
+
+/// Collects key presses from miniquad's event stream, which unlike `get_keys_pressed` includes key repeats
+#[derive(Default)]
+pub(crate) struct InputEvents(pub(crate) Vec<InputEvent>);
+
+impl EventHandler for InputEvents {
+    fn update(&mut self) {}
+
+    fn draw(&mut self) {}
+
+    fn char_event(&mut self, character: char, mods: KeyMods, _repeat: bool) {
+        // Ctrl+Alt is how some platforms report AltGr, which is used for typing
+        if (mods.ctrl && !mods.alt) || mods.logo {
+            return;
+        }
+
+        self.0.push(InputEvent::Char(character));
+    }
+
+    fn key_down_event(&mut self, key: KeyCode, mods: KeyMods, _repeat: bool) {
+        use KeyCode::*;
+        if matches!(key, LeftShift | RightShift | LeftControl | RightControl | LeftAlt | RightAlt | LeftSuper | RightSuper) {
+            return;
+        }
+
+        self.0.push(InputEvent::Chord(Chord::new(key, mods)));
+    }
+}
+
+/// What a chord did to the keymap
+#[derive(Debug)]
+pub(crate) enum Resolution {
+    Action(Action),
+    /// The chord starts a longer sequence
+    Pending(String),
+    /// The chords don't make up any binding, and should be handled as plain key presses
+    Unbound(Vec<Chord>),
+}
+
+/// Maps key sequences to actions
+pub(crate) struct Keymap {
+    bindings: BTreeMap<Vec<Chord>, Action>,
+    pending: Vec<Chord>,
+    problems: Vec<String>,
+}
+
+impl Keymap {
+    /// Builds the default keymap with `overrides` from the config file applied on top.
+    /// Bindings to the action `none` remove the default for that sequence.
+    pub(crate) fn new(overrides: &BTreeMap<String, String>) -> Self {
+        let mut keymap = Self { bindings: BTreeMap::new(), pending: vec![], problems: vec![] };
+
+        for (sequence, action) in DEFAULT_BINDINGS {
+            let sequence = parse_sequence(sequence).unwrap();
+            keymap.bindings.insert(sequence, Action::from_name(action).unwrap());
+        }
+
+        for (text, name) in overrides {
+            let sequence = match parse_sequence(text) {
+                Ok(sequence) => sequence,
+                Err(error) => {
+                    keymap.problems.push(format!("`{text}`: {error}"));
+                    continue;
+                }
+            };
+
+            if name == "none" {
+                keymap.bindings.remove(&sequence);
+                continue;
+            }
+
+            let Some(action) = Action::from_name(name) else {
+                keymap.problems.push(format!("`{text}`: unknown action `{name}`"));
+                continue;
+            };
+
+            keymap.bindings.insert(sequence, action);
+        }
+
+        keymap.report_conflicts();
+        keymap
+    }
+
+    /// A sequence that is the prefix of another can never complete the longer one, so the longer one is dropped
+    fn report_conflicts(&mut self) {
+        let shadowed: Vec<(Vec<Chord>, Vec<Chord>)> = self.bindings.keys()
+            .flat_map(|sequence| (1..sequence.len())
+                .map(|len| &sequence[..len])
+                .filter(|prefix| self.bindings.contains_key(*prefix))
+                .map(|prefix| (prefix.to_vec(), sequence.clone())))
+            .collect();
+
+        for (prefix, sequence) in shadowed {
+            self.problems.push(format!(
+                "`{}` is never reached because `{}` is bound to {}",
+                format_sequence(&sequence),
+                format_sequence(&prefix),
+                self.bindings[&prefix].name()
+            ));
+            self.bindings.remove(&sequence);
+        }
+    }
+
+    pub(crate) fn problems(&self) -> &[String] {
+        &self.problems
+    }
+
+    /// Feeds the next chord typed, completing or extending any pending sequence
+    pub(crate) fn feed(&mut self, chord: Chord) -> Resolution {
+        self.pending.push(chord);
+
+        if let Some(action) = self.bindings.get(&self.pending) {
+            self.pending.clear();
+            return Resolution::Action(*action);
+        }
+
+        if self.bindings.range(self.pending.clone()..).next().is_some_and(|(sequence, _)| sequence.starts_with(&self.pending)) {
+            return Resolution::Pending(format_sequence(&self.pending));
+        }
+
+        Resolution::Unbound(std::mem::take(&mut self.pending))
+    }
+
+    /// Every binding and any problems loading them, for the `keys` pane
+    pub(crate) fn describe(&self) -> String {
+        let width = self.bindings.keys().map(|sequence| format_sequence(sequence).len()).max().unwrap_or(0);
+        let mut description: Vec<String> = self.bindings.iter()
+            .map(|(sequence, action)| format!("{:<width$}  {}", format_sequence(sequence), action.name()))
+            .collect();
+
+        if !self.problems.is_empty() {
+            description.push(String::new());
+            description.push("Problems:".to_string());
+            description.extend(self.problems.iter().map(|problem| format!("  {problem}")));
+        }
+
+        description.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(text: &str) -> Chord {
+        Chord::parse(text).unwrap()
+    }
+
+    fn keymap(overrides: &[(&str, &str)]) -> Keymap {
+        Keymap::new(&overrides.iter().map(|(sequence, action)| (sequence.to_string(), action.to_string())).collect())
+    }
+
+    #[test]
+    fn parses_chords() {
+        let parsed = chord("Ctrl+Shift+K");
+        assert_eq!(parsed.key, KeyCode::K);
+        assert!(parsed.control && parsed.shift && !parsed.alt && !parsed.logo);
+        assert_eq!(parsed.to_string(), "ctrl+shift+k");
+        assert_eq!(chord("cmd+f5").to_string(), "super+f5");
+
+        assert!(Chord::parse("hyper+k").is_err());
+        assert!(Chord::parse("ctrl+nokey").is_err());
+        assert!(parse_sequence("  ").is_err());
+        assert_eq!(parse_sequence("ctrl+k ctrl+s").unwrap(), vec![chord("ctrl+k"), chord("ctrl+s")]);
+    }
+
+    #[test]
+    fn feeds_sequences() {
+        let mut keymap = keymap(&[("f7 f8", "save"), ("ctrl+k", "none")]);
+
+        assert!(matches!(keymap.feed(chord("f7")), Resolution::Pending(pending) if pending == "f7"));
+        assert!(matches!(keymap.feed(chord("f8")), Resolution::Action(Action::Save)));
+        assert!(matches!(keymap.feed(chord("ctrl+k")), Resolution::Unbound(chords) if chords == [chord("ctrl+k")]));
+    }
+
+    #[test]
+    fn reports_problems_and_shadowed_sequences() {
+        let mut keymap = keymap(&[("f5", "save"), ("f5 f6", "prompt"), ("f9", "no-such-action"), ("hyper+x", "save")]);
+
+        let problems = keymap.problems().join("\n");
+        assert!(problems.contains("`f5 f6` is never reached because `f5` is bound to save"), "{problems}");
+        assert!(problems.contains("unknown action `no-such-action`"), "{problems}");
+        assert!(problems.contains("unknown modifier `hyper`"), "{problems}");
+        assert!(matches!(keymap.feed(chord("f5")), Resolution::Action(Action::Save)));
+    }
+}
@@ -0,0 +1,248 @@
+use std::cell::{Cell, Ref, RefCell};
+use macroquad::prelude::*;
+
+/// Characters loaded into the font's glyph atlas up front, so the first frame doesn't stall on them
+const PRINTABLE_ASCII: std::ops::RangeInclusive<char> = ' '..='~';
+
+/// Monospace text layout shared by drawing and hit-testing.
+/// The cell width is measured once per font size, and each line's layout is cached until its text changes.
+pub(crate) struct TextLayout {
+    font_size: u16,
+    tab_width: usize,
+    cell_width: Cell<Option<(u16, f32)>>,
+    lines: RefCell<Vec<LineLayout>>,
+}
+
+/// Where every char of a line starts, in cells
+#[derive(Debug, Default)]
+pub(crate) struct LineLayout {
+    text: String,
+    /// (byte index, starting cell) for every char, followed by the end of the line
+    cells: Vec<(usize, usize)>,
+    /// The width last wrapped at, and where the visual rows started
+    wrapped: Option<(usize, Vec<usize>)>,
+}
+
+impl LineLayout {
+    fn new(text: &str, tab_width: usize) -> Self {
+        let mut cells = Vec::with_capacity(text.len() + 1);
+        let mut cell = 0;
+
+        for (idx, character) in text.char_indices() {
+            cells.push((idx, cell));
+            cell += match character {
+                '\t' => tab_width - cell % tab_width,
+                _ => 1,
+            };
+        }
+        cells.push((text.len(), cell));
+
+        Self { text: text.to_owned(), cells, wrapped: None }
+    }
+
+    /// Cell at which the char starting at byte `column` is drawn
+    pub(crate) fn cell_of(&self, column: usize) -> usize {
+        match self.cells.binary_search_by_key(&column, |(idx, _)| *idx) {
+            Ok(i) => self.cells[i].1,
+            Err(i) => self.cells[i.min(self.cells.len() - 1)].1,
+        }
+    }
+
+    /// Byte column of the char boundary nearest to `cell`
+    pub(crate) fn column_at(&self, cell: f32) -> usize {
+        self.cells.iter()
+            .min_by(|(_, a), (_, b)| (*a as f32 - cell).abs().total_cmp(&(*b as f32 - cell).abs()))
+            .map(|(idx, _)| *idx)
+            .unwrap_or(0)
+    }
+
+    /// Byte columns at which each visual row starts when wrapping at `width` cells,
+    /// breaking after whitespace where possible
+    pub(crate) fn wrap(&self, width: usize) -> Vec<usize> {
+        let width = width.max(1);
+        let mut starts = vec![0];
+        let mut start_cell = 0;
+        let mut break_at = None;
+
+        for (i, &(idx, cell)) in self.cells.iter().enumerate().skip(1) {
+            let (prev_idx, prev_cell) = self.cells[i - 1];
+
+            if cell - start_cell > width && prev_idx > *starts.last().unwrap() {
+                let (break_idx, break_cell) = break_at.unwrap_or((prev_idx, prev_cell));
+                starts.push(break_idx);
+                start_cell = break_cell;
+                break_at = None;
+            }
+
+            if self.text[prev_idx..idx].chars().all(char::is_whitespace) && idx < self.text.len() {
+                break_at = Some((idx, cell));
+            }
+        }
+
+        starts
+    }
+
+    /// Every char with its byte index and starting cell
+    pub(crate) fn glyphs(&self) -> impl Iterator<Item=(usize, usize, char)> + '_ {
+        self.text.char_indices()
+            .zip(&self.cells)
+            .map(|((idx, character), (_, cell))| (idx, *cell, character))
+    }
+}
+
+impl TextLayout {
+    pub(crate) fn new(font_size: u16) -> Self {
+        Self {
+            font_size,
+            tab_width: 4,
+            cell_width: Cell::new(None),
+            lines: RefCell::new(vec![]),
+        }
+    }
+
+    pub(crate) fn font_size(&self) -> u16 {
+        self.font_size
+    }
+
+    pub(crate) fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width.max(1);
+        self.lines.get_mut().clear();
+    }
+
+    /// Measures the cell width for the current font size, unless it has been measured already
+    pub(crate) fn measure(&self, font: Option<&Font>) -> f32 {
+        match self.cell_width.get() {
+            Some((size, width)) if size == self.font_size => width,
+            _ => {
+                if let Some(font) = font {
+                    font.populate_font_cache(&PRINTABLE_ASCII.collect::<Vec<_>>(), self.font_size);
+                }
+                let width = measure_text("M", font, self.font_size, 1.0).width;
+                self.cell_width.set(Some((self.font_size, width)));
+                width
+            }
+        }
+    }
+
+    /// Width of a single cell, falling back to an estimate before the first measurement
+    pub(crate) fn cell_width(&self) -> f32 {
+        match self.cell_width.get() {
+            Some((size, width)) if size == self.font_size => width,
+            _ => self.font_size as f32 * 0.6,
+        }
+    }
+
+    pub(crate) fn line_height(&self) -> f32 {
+        self.font_size as f32
+    }
+
+    /// Layout of line `row`, reshaped only if `text` differs from what was cached
+    pub(crate) fn line(&self, row: usize, text: &str) -> Ref<'_, LineLayout> {
+        {
+            let mut lines = self.lines.borrow_mut();
+            if lines.len() <= row {
+                lines.resize_with(row + 1, LineLayout::default);
+            }
+            if lines[row].cells.is_empty() || lines[row].text != text {
+                lines[row] = LineLayout::new(text, self.tab_width);
+            }
+        }
+
+        Ref::map(self.lines.borrow(), |lines| &lines[row])
+    }
+
+    /// Byte columns at which line `row` is wrapped at `width` cells, worked out again only when its text or the width changed
+    pub(crate) fn wrap(&self, row: usize, text: &str, width: usize) -> Ref<'_, [usize]> {
+        drop(self.line(row, text));
+        {
+            let line = &mut self.lines.borrow_mut()[row];
+            if !matches!(line.wrapped, Some((wrapped_at, _)) if wrapped_at == width) {
+                line.wrapped = Some((width, line.wrap(width)));
+            }
+        }
+
+        Ref::map(self.lines.borrow(), |lines| lines[row].wrapped.as_ref().map_or(&[][..], |(_, starts)| starts))
+    }
+
+    /// Drops cached layouts past the end of the buffer
+    pub(crate) fn truncate(&self, len: usize) {
+        self.lines.borrow_mut().truncate(len);
+    }
+
+    /// Horizontal pixel position of byte `column` on line `row`
+    pub(crate) fn column_to_x(&self, row: usize, text: &str, column: usize) -> f32 {
+        self.line(row, text).cell_of(column) as f32 * self.cell_width()
+    }
+
+    /// Byte column on line `row` nearest to pixel position `x`
+    pub(crate) fn x_to_column(&self, row: usize, text: &str, x: f32) -> usize {
+        self.line(row, text).column_at(x / self.cell_width())
+    }
+
+    /// Row containing pixel position `y`, where row 0 starts at y = 0
+    pub(crate) fn y_to_row(&self, y: f32) -> usize {
+        (y / self.line_height()).max(0.0) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_multibyte_chars_and_tabs_to_cells() {
+        let line = LineLayout::new("é\tx", 4);
+
+        assert_eq!([0, 2, 3, 4].map(|column| line.cell_of(column)), [0, 1, 4, 5]);
+        assert_eq!([-3.0, 0.4, 2.4, 3.0, 4.6, 100.0].map(|cell| line.column_at(cell)), [0, 0, 2, 3, 4, 4]);
+        assert_eq!(line.glyphs().collect::<Vec<_>>(), [(0, 0, 'é'), (2, 1, '\t'), (3, 4, 'x')]);
+    }
+
+    #[test]
+    fn tabs_stop_at_multiples_of_the_tab_width() {
+        let line = LineLayout::new("ab\tc\t\td", 4);
+        assert_eq!(line.glyphs().map(|(_, cell, _)| cell).collect::<Vec<_>>(), [0, 1, 2, 4, 5, 8, 12]);
+    }
+
+    #[test]
+    fn wraps_after_whitespace() {
+        let line = LineLayout::new("one two three", 4);
+        assert_eq!(line.wrap(8), [0, 8]);
+        assert_eq!(line.wrap(5), [0, 4, 8]);
+        assert_eq!(line.wrap(13), [0]);
+    }
+
+    #[test]
+    fn breaks_words_longer_than_the_width() {
+        let line = LineLayout::new("a abcdefghij", 4);
+        assert_eq!(line.wrap(4), [0, 2, 6, 10]);
+        assert_eq!(LineLayout::new("abc", 4).wrap(0), [0, 1, 2]);
+    }
+
+    #[test]
+    fn wraps_wide_and_multibyte_chars_at_char_boundaries() {
+        let line = LineLayout::new("ééé漢字	x", 4);
+        let starts = line.wrap(3);
+        assert!(starts.iter().all(|&start| line.text.is_char_boundary(start)), "{starts:?}");
+        assert_eq!(starts, [0, 6, 12, 13]);
+    }
+
+    #[test]
+    fn relays_out_rows_whose_text_changed() {
+        let mut layout = TextLayout::new(16);
+        assert_eq!(layout.line(1, "a\tb").cell_of(2), 4);
+        assert_eq!(layout.line(1, "ab\tb").cell_of(2), 2);
+        assert_eq!(layout.line(1, "ab\tb").cell_of(3), 4);
+
+        layout.set_tab_width(8);
+        assert_eq!(layout.line(1, "ab\tb").cell_of(3), 8);
+
+        assert_eq!(&*layout.wrap(1, "ab\tb", 4), [0, 2, 3]);
+        assert_eq!(&*layout.wrap(1, "ab\tb", 9), [0]);
+        assert_eq!(&*layout.wrap(1, "ab cd", 3), [0, 3]);
+
+        layout.truncate(1);
+        assert_eq!(layout.lines.borrow().len(), 1);
+        assert_eq!(layout.line(0, "").cell_of(0), 0);
+    }
+}
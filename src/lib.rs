@@ -1,69 +1,740 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::path;
 use inkjet::Highlighter;
+use macroquad::input::utils::{register_input_subscriber, repeat_all_miniquad_input};
 use macroquad::prelude::*;
+use serde_json::Value;
 use uuid::Uuid;
-use crate::editor::{Editor, EditorMessage};
+use crate::config::Config;
+use crate::editor::{Editor, EditorMessage, Point};
+use crate::editor::snippets::Snippets;
+use crate::editor::vim::Registers;
+use crate::keymap::{Action, Chord, InputEvent, InputEvents, Keymap, Resolution};
+use crate::links::{shift_rows, Link};
+use crate::notes::Note;
+use crate::lsp::{Lsp, LspEvent, Position};
 use crate::prompt::{Prompt, PromptUpdate};
+use crate::pane::{Pane, PaneMessage};
+use crate::markdown::{Preview, PreviewMessage};
+use crate::image_view::ImageView;
+use crate::terminal::{Terminal, TerminalMessage};
+use crate::run::{Run, RunMessage};
+use crate::tree::{FileTree, TreeMessage, TreeUpdate};
 use crate::theme::Theme;
+use crate::workspace::{LinkState, NoteState, PaneKind, Workspace};
+use crate::window::set_fullscreen_camera;
 
 pub mod theme;
 mod editor;
 mod window;
 mod prompt;
+mod layout;
+mod keymap;
+mod config;
+mod editorconfig;
+pub mod lsp;
+mod links;
+mod notes;
+mod workspace;
+mod terminal;
+mod run;
+mod tree;
+mod pane;
+mod markdown;
+mod image_view;
 
 pub struct App {
     theme: Theme,
     pub font: Option<Font>,
-    editors: HashMap<Uuid, Editor>,
+    panes: HashMap<Uuid, Pane>,
     highlighter: Highlighter,
     focused: Option<Uuid>,
     move_target: Option<Uuid>,
     prompt_focused: bool,
-    released: bool,
     prompt: Prompt,
+    status: Option<String>,
+    keymap: Keymap,
+    input_subscriber: usize,
+    vim: bool,
+    rainbow: bool,
+    registers: Registers,
+    config: Config,
+    lsp: Lsp,
+    /// Connectors from call sites to the definition panes they were followed to
+    links: Vec<Link>,
+    /// Where definitions were followed from, most recent last
+    back: Vec<(Uuid, Position)>,
+    /// The file explorer, of which there's only ever one
+    files: Option<Uuid>,
+    /// The first end of a connector being made with `connect`, waiting for the other end
+    pending_link: Option<(Uuid, (usize, usize), Option<String>)>,
+    /// The pane listing every diagnostic, and where each of its rows points
+    problems: Option<Uuid>,
+    problem_targets: Vec<(Uuid, Point)>,
+    snippets: Snippets,
 }
 
 impl App {
     pub fn new() -> Self {
+        let (config, config_error) = match Config::load() {
+            Ok(config) => (config, None),
+            Err(error) => (Config::default(), Some(error)),
+        };
+        let keymap = Keymap::new(&config.keys);
+        let (snippets, snippets_error) = Snippets::load();
+
+        let status = config_error.or(snippets_error).or_else(|| match keymap.problems().len() {
+            0 => None,
+            n => Some(format!("{n} problem(s) with key bindings, run `keys` for details")),
+        });
+
         let mut app = Self {
             theme: Theme::mocha(),
             font: None,
-            editors: HashMap::new(),
+            panes: HashMap::new(),
             highlighter: Highlighter::new(),
             focused: None,
             move_target: None,
             prompt_focused: false,
-            released: true,
             prompt: Prompt::new(),
+            status,
+            keymap,
+            input_subscriber: register_input_subscriber(),
+            vim: config.vim,
+            rainbow: config.rainbow,
+            registers: Registers::new(),
+            lsp: Lsp::new(config.lsp.clone()),
+            links: vec![],
+            back: vec![],
+            files: None,
+            pending_link: None,
+            problems: None,
+            problem_targets: vec![],
+            snippets,
+            config,
         };
 
-        app.open_help();
+        // The workspace here is only opened at startup when the config asks for it, and otherwise pointed out
+        match app.config.open_workspace {
+            true => match Workspace::load(workspace::DEFAULT_PATH) {
+                Ok(Some(workspace)) => app.open_workspace(workspace),
+                Ok(None) => app.open_help(),
+                Err(error) => {
+                    app.status = Some(error);
+                    app.open_help();
+                }
+            },
+            false => {
+                if path::Path::new(workspace::DEFAULT_PATH).exists() {
+                    app.status.get_or_insert(format!("Run `workspace open` to open {}", workspace::DEFAULT_PATH));
+                }
+                app.open_help();
+            }
+        }
 
         app
     }
 }
 
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub enum Message {
-    Edit(Uuid, EditorMessage),
+    Pane(Uuid, PaneMessage),
     Focus(Option<Uuid>),
     Scroll(Uuid, Vec2),
     Pan(Vec2),
     MoveTarget(Option<Uuid>),
-    FocusPrompt(bool),
-    KeyComboDone,
+    Key(Chord),
     PromptEdit(EditorMessage),
+    /// A message from the language server with this id
+    Lsp(usize, Value),
 }
 
 impl App {
+    fn editor(&self, uuid: Uuid) -> Option<&Editor> {
+        self.panes.get(&uuid).and_then(Pane::editor)
+    }
+
+    fn editor_mut(&mut self, uuid: Uuid) -> Option<&mut Editor> {
+        self.panes.get_mut(&uuid).and_then(Pane::editor_mut)
+    }
+
+    fn focused_editor_mut(&mut self) -> Option<&mut Editor> {
+        self.focused.and_then(|focused| self.editor_mut(focused))
+    }
+
+    fn editors(&self) -> impl Iterator<Item = (&Uuid, &Editor)> {
+        self.panes.iter().filter_map(|(uuid, pane)| Some((uuid, pane.editor()?)))
+    }
+
+    fn add_pane(&mut self, pane: Pane) -> Uuid {
+        let uuid = Uuid::new_v4();
+        self.panes.insert(uuid, pane);
+        uuid
+    }
+
+    fn add_editor(&mut self, mut editor: Editor) -> Uuid {
+        let uuid = Uuid::new_v4();
+
+        editor.set_vim(self.vim);
+        editor.set_rainbow(self.rainbow);
+        editor.set_default_indentation(self.config.indentation(editor.language()));
+        editor.set_snippets(self.snippets.for_language(editor.language()));
+        if let Err(error) = self.lsp.open(uuid, editor.filename(), editor.language(), editor.text()) {
+            self.status = Some(error);
+        }
+        self.panes.insert(uuid, Pane::Editor(editor));
+
+        uuid
+    }
+
+    /// Opens a file in a pane: pictures in an image pane, and anything else in an editor, which shows binary files as hex
+    fn open_file(&mut self, filename: String, window: Rect) -> Uuid {
+        if image_view::is_image(&filename) {
+            match ImageView::open(filename.clone(), window) {
+                Ok(image) => return self.add_pane(Pane::Image(image)),
+                Err(error) => self.status = Some(format!("Couldn't show {filename} as a picture: {error}")),
+            }
+        }
+
+        let mut editor = Editor::new(window, 16, filename);
+        let _ = editor.load_file();
+        self.add_editor(editor)
+    }
+
+    /// Shows a definition in a pane linked to `source`, opening one beside it unless another pane has the file open
+    fn follow_definition(&mut self, source: Uuid, filename: String, position: Position) {
+        let Some(editor) = self.editor(source) else {
+            return;
+        };
+        let (call_site, back) = (editor.cursor_row(), editor.cursor_lsp());
+
+        let uuid = self.open_beside(source, filename);
+        self.edit(uuid, EditorMessage::JumpTo(position));
+        self.focused = Some(uuid);
+        self.back.push((source, back));
+
+        self.links.retain(|link| !(link.from == source && link.to == uuid));
+        let definition = self.editor(uuid).map_or(0, Editor::cursor_row);
+        self.links.push(Link { from: source, from_rows: (call_site, call_site), to: uuid, to_rows: (definition, definition), label: None });
+        self.bring_into_view(uuid);
+    }
+
+    /// The pane other than `source` that has a file open, or a new one for it beside `source`
+    fn open_beside(&mut self, source: Uuid, filename: String) -> Uuid {
+        let target = path::absolute(&filename).ok();
+        let existing = self.panes.iter()
+            .find(|(uuid, pane)| **uuid != source && pane.filename().and_then(|file| path::absolute(file).ok()) == target)
+            .map(|(uuid, _)| *uuid);
+
+        match existing {
+            Some(uuid) => uuid,
+            None => {
+                let window = self.beside(source);
+                self.open_file(filename, window)
+            }
+        }
+    }
+
+    /// Saves the focused editor under a new name, and treats it as that file from then on
+    fn save_as(&mut self, path: String) {
+        let Some(uuid) = self.focused else {
+            return;
+        };
+        let (snippets, config) = (&self.snippets, &self.config);
+        let Some(editor) = self.panes.get_mut(&uuid).and_then(Pane::editor_mut) else {
+            return;
+        };
+
+        if let Err(error) = editor.save_as(path.clone()) {
+            self.status = Some(format!("Couldn't save {path}: {error}"));
+            return;
+        }
+        editor.set_default_indentation(config.indentation(editor.language()));
+        editor.set_snippets(snippets.for_language(editor.language()));
+
+        let reopened = self.lsp.close(uuid).and_then(|()| self.lsp.open(uuid, editor.filename(), editor.language(), editor.text()));
+        self.status = Some(match reopened {
+            Ok(()) => format!("Saved {path}"),
+            Err(error) => error,
+        });
+    }
+
+    /// Looks up the definition of the word under the cursor in the syntax of the open panes, this one first
+    fn find_definition(&mut self, source: Uuid) {
+        let Some(editor) = self.editor(source) else {
+            return;
+        };
+        let Some(name) = editor.word_at_cursor() else {
+            return;
+        };
+        let language = editor.language();
+
+        let mut candidates = self.editors()
+            .filter(|(uuid, editor)| **uuid != source && editor.language() == language)
+            .collect::<Vec<_>>();
+        candidates.insert(0, (&source, editor));
+
+        let found = candidates.into_iter().find_map(|(_, editor)| {
+            let point = editor.find_definition(&name)?;
+            Some((editor.filename().to_owned(), editor.lsp_position(point)))
+        });
+
+        match found {
+            Some((filename, position)) => self.follow_definition(source, filename, position),
+            None => self.status = Some(format!("No definition of {name} found")),
+        }
+    }
+
+    /// Room for a new pane to the right of `source`, below any panes already there
+    fn beside(&self, source: Uuid) -> Rect {
+        let windows = self.panes.iter()
+            .map(|(uuid, pane)| (*uuid, pane.window(), pane.titlebar().h))
+            .collect::<Vec<_>>();
+        let Some(&(_, source, titlebar)) = windows.iter().find(|(uuid, _, _)| *uuid == source) else {
+            return Rect::new(20.0, 20.0, 800.0, 800.0);
+        };
+
+        let gap = 40.0;
+        let mut window = Rect::new(source.right() + gap, source.y, source.w, source.h);
+
+        for _ in 0..windows.len() {
+            let with_titlebar = |window: Rect| Rect::new(window.x, window.y - titlebar, window.w, window.h + titlebar);
+            let Some((_, blocking, _)) = windows.iter().find(|(_, other, _)| with_titlebar(*other).overlaps(&with_titlebar(window))) else {
+                break;
+            };
+            window.y = blocking.bottom() + gap + titlebar;
+        }
+
+        window
+    }
+
+    /// Pans the canvas so the whole pane is on screen, or at least its top left corner
+    fn bring_into_view(&mut self, uuid: Uuid) {
+        let Some(pane) = self.panes.get(&uuid) else {
+            return;
+        };
+
+        let margin = 20.0;
+        let (window, titlebar) = (pane.window(), pane.titlebar());
+        let area = Rect::new(window.x, titlebar.y, window.w, window.h + titlebar.h);
+        let shift = |start: f32, end: f32, screen: f32| match (start < margin, end > screen - margin) {
+            (true, _) => margin - start,
+            (false, true) => (screen - margin - end).max(margin - start),
+            (false, false) => 0.0,
+        };
+
+        let delta = vec2(shift(area.left(), area.right(), screen_width()), shift(area.top(), area.bottom(), screen_height()));
+        if delta != Vec2::ZERO {
+            self.pan(delta);
+        }
+    }
+
+    /// Starts a connector at the focused pane's selected rows, or finishes one started in another pane
+    fn connect(&mut self, label: Option<String>) {
+        let Some((focused, editor)) = self.focused.and_then(|focused| Some((focused, self.editor(focused)?))) else {
+            self.status = Some("Focus a pane to connect from".to_string());
+            return;
+        };
+        let rows = editor.selected_rows();
+
+        match self.pending_link.take() {
+            Some((from, from_rows, first_label)) if from != focused && self.editor(from).is_some() => {
+                let label = label.or(first_label);
+                self.links.push(Link { from, from_rows, to: focused, to_rows: rows, label });
+            }
+            _ => {
+                self.pending_link = Some((focused, rows, label));
+                self.status = Some("Select the other end in another pane and run `connect` again".to_string());
+            }
+        }
+    }
+
+    /// Puts a note under the mouse, or changes the focused note's text, removing it if there's none
+    fn note(&mut self, text: String) {
+        match self.focused.and_then(|focused| self.panes.get_mut(&focused)) {
+            Some(Pane::Note(_)) if text.is_empty() => {
+                self.panes.remove(&self.focused.unwrap());
+            }
+            Some(Pane::Note(note)) => note.set_text(text),
+            _ if text.is_empty() => self.status = Some("Usage: note <text>".to_string()),
+            _ => self.focused = Some(self.add_pane(Pane::Note(Note::new(Vec2::from(mouse_position()), text)))),
+        }
+    }
+
+    /// The canvas as it is now, leaving out panes that can't be kept, such as editors that aren't showing a file on disk
+    fn workspace(&self) -> Workspace {
+        let mut panes = self.panes.iter()
+            .filter_map(|(uuid, pane)| Some((*uuid, pane.state()?)))
+            .collect::<Vec<_>>();
+        panes.sort_by(|(_, a), (_, b)| (a.window[0], a.window[1]).partial_cmp(&(b.window[0], b.window[1])).unwrap());
+        let index = |uuid: Uuid| panes.iter().position(|(pane, _)| *pane == uuid);
+
+        Workspace {
+            links: self.links.iter()
+                .filter_map(|link| Some(LinkState {
+                    from: index(link.from)?,
+                    from_rows: [link.from_rows.0, link.from_rows.1],
+                    to: index(link.to)?,
+                    to_rows: [link.to_rows.0, link.to_rows.1],
+                    label: link.label.clone(),
+                }))
+                .collect(),
+            notes: self.panes.values()
+                .filter_map(|pane| match pane {
+                    Pane::Note(note) => Some(NoteState { text: note.text().to_owned(), position: note.position.to_array() }),
+                    _ => None,
+                })
+                .collect(),
+            panes: panes.into_iter().map(|(_, state)| state).collect(),
+        }
+    }
+
+    /// Adds the panes, connectors and notes of a workspace to the canvas
+    fn open_workspace(&mut self, workspace: Workspace) {
+        let panes = workspace.panes.into_iter()
+            .map(|pane| {
+                let [x, y, w, h] = pane.window;
+                let window = Rect::new(x, y, w, h);
+                match pane.kind {
+                    PaneKind::Editor | PaneKind::Image => Some(self.open_file(pane.file, window)),
+                    PaneKind::Terminal => self.spawn_terminal(window),
+                    PaneKind::Files => Some(self.files_pane(window)),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for link in workspace.links {
+            let pane = |index: usize| panes.get(index).copied().flatten();
+            if let (Some(from), Some(to)) = (pane(link.from), pane(link.to)) {
+                let rows = |[first, last]: [usize; 2]| (first.min(last), first.max(last));
+                self.links.push(Link { from, from_rows: rows(link.from_rows), to, to_rows: rows(link.to_rows), label: link.label });
+            }
+        }
+
+        for note in workspace.notes {
+            self.add_pane(Pane::Note(Note::new(Vec2::from(note.position), note.text)));
+        }
+    }
+
+    /// Focuses the problems pane, opening it if it isn't already
+    fn show_problems(&mut self) {
+        let uuid = match self.problems.filter(|problems| self.editor(*problems).is_some()) {
+            Some(uuid) => uuid,
+            None => {
+                let mut editor = Editor::new(Rect::new(40.0, 40.0, 800.0, 300.0), 16, "problems".to_string());
+                editor.set_generated();
+                self.add_editor(editor)
+            }
+        };
+
+        self.problems = Some(uuid);
+        self.focused = Some(uuid);
+        self.refresh_problems();
+        self.bring_into_view(uuid);
+    }
+
+    /// Relists the diagnostics of every open pane in the problems pane
+    fn refresh_problems(&mut self) {
+        let Some(pane) = self.problems.filter(|problems| self.editor(*problems).is_some()) else {
+            return;
+        };
+
+        let mut sources = self.editors().filter(|(uuid, _)| **uuid != pane).collect::<Vec<_>>();
+        sources.sort_by(|(_, a), (_, b)| a.filename().cmp(b.filename()));
+        let (lines, targets): (Vec<_>, Vec<_>) = sources.into_iter()
+            .flat_map(|(uuid, editor)| editor.problems().into_iter().map(|(line, point)| (line, (*uuid, point))))
+            .unzip();
+
+        self.problem_targets = targets;
+        let text = match lines.is_empty() {
+            true => "No problems".to_string(),
+            false => lines.join("\n"),
+        };
+        self.editor_mut(pane).unwrap().load_string(text);
+    }
+
+    fn jump_to_problem(&mut self) {
+        let Some(row) = self.problems.and_then(|problems| self.editor(problems)).map(Editor::cursor_row) else {
+            return;
+        };
+        let Some(&(uuid, point)) = self.problem_targets.get(row) else {
+            return;
+        };
+        let Some(position) = self.editor(uuid).map(|editor| editor.lsp_position(point)) else {
+            return;
+        };
+
+        self.focused = Some(uuid);
+        self.edit(uuid, EditorMessage::JumpTo(position));
+        self.bring_into_view(uuid);
+    }
+
+    /// Offers a pane's completion menu the words of every open pane, and asks its language server for more
+    fn request_completions(&mut self, uuid: Uuid) {
+        let words = self.editors()
+            .flat_map(|(_, editor)| editor.words())
+            .map(str::to_owned)
+            .collect::<BTreeSet<_>>();
+        self.edit(uuid, EditorMessage::Words(words.into_iter().collect()));
+
+        if let Some(position) = self.editor(uuid).filter(|_| self.lsp.tracks(uuid)).map(Editor::cursor_lsp) {
+            if let Err(error) = self.lsp.completion(uuid, position) {
+                self.status = Some(error);
+            }
+        }
+    }
+
+    /// Room for a new pane beside the focused one
+    fn beside_focused(&self) -> Rect {
+        match self.focused {
+            Some(focused) if self.panes.contains_key(&focused) => self.beside(focused),
+            _ => Rect::new(20.0, 20.0, 800.0, 480.0),
+        }
+    }
+
+    fn spawn_terminal(&mut self, window: Rect) -> Option<Uuid> {
+        match Terminal::spawn(window) {
+            Ok(terminal) => Some(self.add_pane(Pane::Terminal(terminal))),
+            Err(error) => {
+                self.status = Some(format!("Couldn't start a terminal: {error}"));
+                None
+            }
+        }
+    }
+
+    /// Starts a shell in a new pane beside the focused one
+    fn open_terminal(&mut self) {
+        let window = self.beside_focused();
+        if let Some(uuid) = self.spawn_terminal(window) {
+            self.focused = Some(uuid);
+        }
+    }
+
+    /// Runs a command in a new pane beside the focused one
+    fn run(&mut self, command: String) {
+        match Run::spawn(&command, self.beside_focused()) {
+            Ok(run) => self.focused = Some(self.add_pane(Pane::Run(run))),
+            Err(error) => self.status = Some(format!("Couldn't run {command}: {error}")),
+        }
+    }
+
+    /// Opens the file a run pane's output refers to at the point clicked, focusing it at that position
+    fn follow_output(&mut self, source: Uuid, position: Vec2) {
+        let Some(Pane::Run(run)) = self.panes.get(&source) else {
+            return;
+        };
+        let Some(link) = run.link_at(position).cloned() else {
+            return;
+        };
+
+        let uuid = self.open_beside(source, link.path);
+        let position = Position { line: link.line.saturating_sub(1), character: link.column.saturating_sub(1) };
+        self.edit(uuid, EditorMessage::JumpTo(position));
+        self.focused = Some(uuid);
+        self.bring_into_view(uuid);
+    }
+
+    /// Opens a rendered preview of the focused markdown file beside it, leaving the file focused to carry on editing
+    fn open_preview(&mut self) {
+        let Some((source, editor)) = self.focused
+            .and_then(|focused| Some((focused, self.editor(focused)?)))
+            .filter(|(_, editor)| markdown::is_markdown(editor.filename()))
+        else {
+            self.status = Some("Focus a markdown file to preview it".to_string());
+            return;
+        };
+
+        let open = self.panes.iter().any(|(_, pane)| matches!(pane, Pane::Preview(preview) if preview.source == source));
+        if !open {
+            let preview = Preview::new(self.beside(source), source, editor.filename().to_owned());
+            let uuid = self.add_pane(Pane::Preview(preview));
+            self.refresh_previews(source);
+            self.bring_into_view(uuid);
+        }
+    }
+
+    /// Renders the previews of an editor again after it has changed
+    fn refresh_previews(&mut self, source: Uuid) {
+        let previewed = self.panes.values().any(|pane| matches!(pane, Pane::Preview(preview) if preview.source == source));
+        let Some(editor) = self.editor(source).filter(|_| previewed) else {
+            return;
+        };
+
+        let (text, line) = (editor.text(), editor.top_row());
+        for pane in self.panes.values_mut() {
+            match pane {
+                Pane::Preview(preview) if preview.source == source => {
+                    preview.set_text(text.clone(), &mut self.highlighter, &self.theme);
+                    preview.show_line(line);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Keeps a markdown file and its previews showing the same part of it after one of them has scrolled
+    fn sync_scroll(&mut self, uuid: Uuid) {
+        match self.panes.get(&uuid) {
+            Some(Pane::Preview(preview)) => {
+                let (source, line) = (preview.source, preview.top_line());
+                if let Some(editor) = self.editor_mut(source) {
+                    editor.scroll_to_row(line);
+                }
+            }
+            Some(Pane::Editor(editor)) => {
+                let line = editor.top_row();
+                for pane in self.panes.values_mut() {
+                    match pane {
+                        Pane::Preview(preview) if preview.source == uuid => preview.show_line(line),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Opens the file a preview's link points to beside it, or shows where the link goes if it isn't a file
+    fn follow_link(&mut self, source: Uuid, position: Vec2) {
+        let Some(Pane::Preview(preview)) = self.panes.get(&source) else {
+            return;
+        };
+        let Some(link) = preview.link_at(position) else {
+            return;
+        };
+
+        match preview.link_path(&link) {
+            Some(path) => {
+                let uuid = self.open_beside(source, path);
+                self.focused = Some(uuid);
+                self.bring_into_view(uuid);
+            }
+            None => self.status = Some(format!("Link to {link}")),
+        }
+    }
+
+    /// The file explorer, opening it in `window` if it isn't already
+    fn files_pane(&mut self, window: Rect) -> Uuid {
+        let uuid = match self.files.filter(|files| self.panes.contains_key(files)) {
+            Some(uuid) => uuid,
+            None => self.add_pane(Pane::Files(FileTree::new(window))),
+        };
+
+        self.files = Some(uuid);
+        uuid
+    }
+
+    /// Focuses the file explorer, opening it at the left of the screen if it isn't already
+    fn show_files(&mut self) -> Uuid {
+        let uuid = self.files_pane(Rect::new(20.0, 44.0, 280.0, screen_height() - 88.0));
+        self.focused = Some(uuid);
+        uuid
+    }
+
+    fn tree_update(&mut self, tree: Uuid, update: TreeUpdate) {
+        match update {
+            TreeUpdate::Open(filename) => {
+                let uuid = self.open_beside(tree, filename);
+                self.focused = Some(uuid);
+                self.bring_into_view(uuid);
+            }
+            TreeUpdate::Prompt(text) => {
+                self.prompt.set_text(&text);
+                self.prompt_focused = true;
+            }
+            TreeUpdate::Status(status) => self.status = Some(status),
+        }
+    }
+
+    /// Creates a file or directory from the file explorer, opening the file
+    fn create_path(&mut self, path: String) {
+        let uuid = self.show_files();
+        let Some(Pane::Files(tree)) = self.panes.get_mut(&uuid) else {
+            return;
+        };
+
+        match tree.create(&path) {
+            Ok(created) if created.is_file() => {
+                let display = created.strip_prefix(".").unwrap_or(&created).to_string_lossy().into_owned();
+                self.tree_update(uuid, TreeUpdate::Open(display));
+            }
+            Ok(_) => self.focused = Some(uuid),
+            Err(error) => self.status = Some(format!("Couldn't create {path}: {error}")),
+        }
+    }
+
+    /// Moves what's selected in the file explorer, keeping the panes of the files moved with it open on them
+    fn move_path(&mut self, path: String) {
+        let Some(Pane::Files(tree)) = self.files.and_then(|files| self.panes.get_mut(&files)) else {
+            self.status = Some("Select what to move in the file tree first".to_string());
+            return;
+        };
+
+        let (from, to) = match tree.move_selected(&path) {
+            Ok(moved) => moved,
+            Err(error) => {
+                self.status = Some(format!("Couldn't move to {path}: {error}"));
+                return;
+            }
+        };
+
+        let Ok(absolute) = path::absolute(&from) else {
+            return;
+        };
+        for (uuid, pane) in &mut self.panes {
+            let Some(editor) = pane.editor_mut() else {
+                continue;
+            };
+            let Some(rest) = path::absolute(editor.filename()).ok().and_then(|file| Some(file.strip_prefix(&absolute).ok()?.to_path_buf())) else {
+                continue;
+            };
+            let moved = to.join(rest);
+            let filename = moved.strip_prefix(".").unwrap_or(&moved).to_string_lossy().trim_end_matches('/').to_string();
+            editor.set_filename(filename);
+
+            let reopened = self.lsp.close(*uuid).and_then(|()| self.lsp.open(*uuid, editor.filename(), editor.language(), editor.text()));
+            if let Err(error) = reopened {
+                self.status = Some(error);
+            }
+        }
+        self.status.get_or_insert(format!("Moved to {path}"));
+    }
+
+    /// Sends a key to the focused terminal, unless it's bound to something that isn't about editing text
+    fn terminal_key(&mut self, uuid: Uuid, chord: Chord) {
+        match self.keymap.feed(chord) {
+            Resolution::Action(action @ (Action::Prompt | Action::Close | Action::Help | Action::Keys | Action::Problems
+                | Action::GoBack | Action::Terminal)) => {
+                self.status = None;
+                self.perform(action);
+            }
+            Resolution::Pending(sequence) => self.status = Some(format!("{sequence} -")),
+            Resolution::Action(_) => self.update(Message::Pane(uuid, PaneMessage::Terminal(TerminalMessage::Key(chord)))),
+            Resolution::Unbound(chords) => {
+                self.status = None;
+                for chord in chords {
+                    self.update(Message::Pane(uuid, PaneMessage::Terminal(TerminalMessage::Key(chord))));
+                }
+            }
+        }
+    }
+
     fn open_help(&mut self) {
-        self.editors.insert(Uuid::new_v4(), {
+        self.add_editor({
             let mut editor = Editor::new(
                 Rect::new(20.0, 20.0, 800.0, 800.0),
                 16,
                 "usage.md".to_string()
             );
             editor.load_string(include_str!("../usage.md").to_string());
+            editor.set_generated();
+            editor.toggle_soft_wrap();
             editor
         });
     }
@@ -71,15 +742,46 @@ impl App {
     pub fn update(&mut self, message: Message) {
         match message {
             Message::Focus(uuid) => self.focused = uuid,
-            Message::Edit(uuid, edit) => {
-                self.editors.get_mut(&uuid).and_then(|editor| Some(editor.update(edit, &mut self.highlighter, &self.theme)));
-            },
-            Message::Scroll(uuid, offset) => self.editors.get_mut(&uuid).unwrap().scroll(offset),
+            Message::Pane(uuid, PaneMessage::Editor(edit)) => self.edit(uuid, edit),
+            Message::Pane(uuid, PaneMessage::Run(RunMessage::Click(position))) => self.follow_output(uuid, position),
+            Message::Pane(uuid, PaneMessage::Preview(PreviewMessage::Click(position))) => self.follow_link(uuid, position),
+            Message::Pane(uuid, message) => {
+                let update = match (self.panes.get_mut(&uuid), message) {
+                    (Some(Pane::Terminal(terminal)), PaneMessage::Terminal(message)) => {
+                        terminal.update(message);
+                        None
+                    }
+                    (Some(Pane::Run(run)), PaneMessage::Run(message)) => {
+                        run.update(message);
+                        None
+                    }
+                    (Some(Pane::Files(tree)), PaneMessage::Files(message)) => tree.update(message),
+                    (Some(Pane::Preview(preview)), PaneMessage::Preview(message)) => {
+                        preview.update(message);
+                        None
+                    }
+                    (Some(Pane::Image(image)), PaneMessage::Image(message)) => {
+                        image.update(message);
+                        None
+                    }
+                    _ => None,
+                };
+                if let Some(update) = update {
+                    self.tree_update(uuid, update);
+                }
+            }
+            Message::Scroll(uuid, offset) => {
+                if let Some(pane) = self.panes.get_mut(&uuid) {
+                    pane.scroll(offset);
+                }
+                self.sync_scroll(uuid);
+            }
             Message::Pan(delta) => {
                 match self.move_target {
                     Some(target) => {
-                        let editor = self.editors.get_mut(&target).unwrap();
-                        editor.window = editor.window.offset(delta)
+                        if let Some(pane) = self.panes.get_mut(&target) {
+                            pane.move_by(delta)
+                        }
                     }
                     None => {
                         self.pan(delta)
@@ -89,96 +791,398 @@ impl App {
             Message::MoveTarget(target) => {
                 self.move_target = target
             }
-            Message::FocusPrompt(focused) => {
-                if self.released {
-                    self.prompt_focused = focused;
-                }
-                self.released = false;
+            Message::Key(chord) if !self.prompt_focused && matches!(self.focused_pane(), Some(Pane::Terminal(_))) => {
+                self.terminal_key(self.focused.unwrap(), chord)
             }
-            Message::KeyComboDone => {
-                self.released = true;
+            Message::Key(chord) => {
+                match self.keymap.feed(chord) {
+                    Resolution::Action(action) if self.prompt_focused && action != Action::Prompt => {
+                        self.status = None;
+                        self.send_key(EditorMessage::Keypress(chord.key));
+                    }
+                    Resolution::Action(action) => {
+                        self.status = None;
+                        self.perform(action);
+                    }
+                    Resolution::Pending(sequence) => self.status = Some(format!("{sequence} -")),
+                    Resolution::Unbound(chords) => {
+                        self.status = None;
+                        for chord in chords {
+                            self.send_key(EditorMessage::Keypress(chord.key));
+                        }
+                    }
+                }
             }
             Message::PromptEdit(msg) => {
                 if let Some(update) = self.prompt.update(msg) {
                     match update {
-                        PromptUpdate::CloseActiveFile => {
-                            if let Some(focused) = self.focused {
-                                self.editors.remove(&focused);
-                            }
-                        }
+                        PromptUpdate::CloseActiveFile => self.perform(Action::Close),
 
                         PromptUpdate::OpenFile(filename) => {
-                            self.editors.insert(Uuid::new_v4(), {
-                                let mut editor = Editor::new(Rect::new(20.0, 20.0, 800.0, 800.0), 16, filename);
-                                let _ = editor.load_file();
-                                editor
-                            });
+                            self.open_file(filename, Rect::new(20.0, 20.0, 800.0, 800.0));
                         }
 
-                        PromptUpdate::SaveActiveFile => {
-                            self.editors.get_mut(&self.focused.unwrap()).unwrap().save()
+                        PromptUpdate::SaveActiveFile => self.perform(Action::Save),
+                        PromptUpdate::SaveAs(path) => self.save_as(path),
+                        PromptUpdate::OpenHelp => self.perform(Action::Help),
+                        PromptUpdate::ShowKeys => self.perform(Action::Keys),
+                        PromptUpdate::ShowProblems => self.perform(Action::Problems),
+                        PromptUpdate::OpenTerminal => self.perform(Action::Terminal),
+                        PromptUpdate::Run(command) => self.run(command),
+                        PromptUpdate::ShowFiles => self.perform(Action::Files),
+                        PromptUpdate::NewPath(path) => self.create_path(path),
+                        PromptUpdate::MovePath(path) => self.move_path(path),
+                        PromptUpdate::Preview => self.open_preview(),
+                        PromptUpdate::ToggleHex => {
+                            if let Some((uuid, editor)) = self.focused.and_then(|focused| Some((focused, self.panes.get_mut(&focused)?.editor_mut()?))) {
+                                let changed = editor.toggle_hex(&mut self.highlighter, &self.theme)
+                                    .and_then(|()| self.lsp.changed(uuid, editor.text()));
+                                if let Err(error) = changed {
+                                    self.status = Some(error);
+                                }
+                            }
+                        }
+                        PromptUpdate::ShowHunk => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                if let Err(error) = editor.show_hunk() {
+                                    self.status = Some(error);
+                                }
+                            }
+                        }
+                        PromptUpdate::RevertHunk => {
+                            if let Some(focused) = self.focused {
+                                match self.editor_mut(focused).map(Editor::revert_hunk) {
+                                    Some(Ok(edit)) => self.edit(focused, EditorMessage::Edits(vec![edit])),
+                                    Some(Err(error)) => self.status = Some(error),
+                                    None => {}
+                                }
+                            }
+                        }
+                        PromptUpdate::StageHunk => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                self.status = Some(match editor.stage_hunk() {
+                                    Ok(()) => "Staged the hunk at the cursor".to_string(),
+                                    Err(error) => error,
+                                });
+                            }
+                        }
+                        PromptUpdate::EditHex => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                self.status = Some(match editor.edit_hex() {
+                                    Ok(()) => format!("{} can be edited as hex", editor.filename()),
+                                    Err(error) => error,
+                                });
+                            }
+                        }
+                        PromptUpdate::FindBytes(pattern) => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                if let Err(error) = editor.find_bytes(&pattern) {
+                                    self.status = Some(error);
+                                }
+                            }
+                        }
+                        PromptUpdate::ToggleWrap => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                editor.toggle_soft_wrap()
+                            }
+                        }
+                        PromptUpdate::SetVim(enabled) => {
+                            self.vim = enabled;
+                            for editor in self.panes.values_mut().filter_map(Pane::editor_mut) {
+                                editor.set_vim(enabled);
+                            }
+                        }
+                        PromptUpdate::SetRainbow(enabled) => {
+                            self.rainbow = enabled;
+                            for editor in self.panes.values_mut().filter_map(Pane::editor_mut) {
+                                editor.set_rainbow(enabled);
+                            }
                         }
-                        PromptUpdate::SaveAs(_) => {}
-                        PromptUpdate::OpenHelp => {
-                            self.open_help();
+                        PromptUpdate::FoldLevel(level) => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                editor.fold_level(level)
+                            }
+                        }
+                        PromptUpdate::UnfoldAll => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                editor.unfold_all()
+                            }
+                        }
+                        PromptUpdate::SetLineEnding(line_ending) => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                editor.set_line_ending(line_ending)
+                            }
+                        }
+                        PromptUpdate::SetCharset(charset) => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                editor.set_charset(charset)
+                            }
+                        }
+                        PromptUpdate::SetFinalNewline(final_newline) => {
+                            if let Some(editor) = self.focused_editor_mut() {
+                                editor.set_final_newline(final_newline)
+                            }
+                        }
+                        PromptUpdate::Rename(name) => {
+                            if let Some((focused, editor)) = self.focused.and_then(|focused| Some((focused, self.editor(focused)?))) {
+                                if let Err(error) = self.lsp.rename(focused, editor.cursor_lsp(), &name) {
+                                    self.status = Some(error);
+                                }
+                            }
+                        }
+                        PromptUpdate::Connect(label) => self.connect(label),
+                        PromptUpdate::Disconnect => {
+                            if let Some((focused, editor)) = self.focused.and_then(|focused| Some((focused, self.editor(focused)?))) {
+                                let row = editor.cursor_row();
+                                self.links.retain(|link| !link.touches(focused, row));
+                            }
+                        }
+                        PromptUpdate::Note(text) => self.note(text),
+                        PromptUpdate::SaveWorkspace(path) => {
+                            self.status = Some(match self.workspace().save(&path) {
+                                Ok(()) => format!("Saved {path}"),
+                                Err(error) => error,
+                            });
+                        }
+                        PromptUpdate::OpenWorkspace(path) => match Workspace::load(&path) {
+                            Ok(Some(workspace)) => self.open_workspace(workspace),
+                            Ok(None) => self.status = Some(format!("{path} doesn't exist")),
+                            Err(error) => self.status = Some(error),
+                        },
+                        PromptUpdate::Status(status) => {
+                            self.status = Some(status);
                         }
-                        PromptUpdate::Status(_) => {}
                     }
                     self.prompt_focused = false;
                 }
             }
+            Message::Lsp(server, message) => {
+                for event in self.lsp.handle(server, message) {
+                    self.lsp_event(event);
+                }
+            }
         }
     }
 
-    fn pan(&mut self, delta: Vec2) {
-        for (_, editor) in &mut self.editors {
-            editor.window = editor.window.offset(delta);
+    fn focused_pane(&self) -> Option<&Pane> {
+        self.focused.and_then(|focused| self.panes.get(&focused))
+    }
+
+    /// Applies a message to an editor, then tells its language server about the change
+    fn edit(&mut self, uuid: Uuid, edit: EditorMessage) {
+        // Enter or a click in the problems pane jumps to the problem on that row
+        let clicked = matches!(edit, EditorMessage::Click(_));
+        if self.problems == Some(uuid) && matches!(edit, EditorMessage::Action(Action::Newline)) {
+            self.jump_to_problem();
+            return;
+        }
+
+        let mut changed = false;
+        if let Some(editor) = self.panes.get_mut(&uuid).and_then(Pane::editor_mut) {
+            changed = editor.update(edit, &mut self.highlighter, &self.theme, &mut self.registers);
+            if changed {
+                if let Err(error) = self.lsp.changed(uuid, editor.text()) {
+                    self.status = Some(error);
+                }
+            }
+
+            if let Some(shift) = editor.take_row_shift() {
+                for link in &mut self.links {
+                    link.shift(uuid, shift);
+                }
+                if let Some((from, rows, _)) = &mut self.pending_link {
+                    if *from == uuid {
+                        *rows = shift_rows(*rows, shift);
+                    }
+                }
+            }
+        }
+        if clicked && self.problems == Some(uuid) {
+            self.jump_to_problem();
+        }
+        if self.editor_mut(uuid).is_some_and(Editor::take_completion_request) {
+            self.request_completions(uuid);
+        }
+        if changed {
+            self.refresh_previews(uuid);
         }
     }
 
-    fn handle_input(&self, messages: &mut Vec<Message>) {
-        if get_keys_down().contains(&KeyCode::K)
-            && (get_keys_down().contains(&KeyCode::LeftControl)
-            || get_keys_down().contains(&KeyCode::RightControl)) {
-            messages.push(Message::FocusPrompt(!self.prompt_focused));
-            if !self.prompt_focused {
-                return;
+    fn lsp_event(&mut self, event: LspEvent) {
+        match event {
+            LspEvent::Diagnostics(path, diagnostics) => {
+                for uuid in self.lsp.panes_for(&path).collect::<Vec<_>>() {
+                    self.edit(uuid, EditorMessage::Diagnostics(diagnostics.clone()));
+                }
+                self.refresh_problems();
             }
-        } else {
-            if !self.released {
-                messages.push(Message::KeyComboDone);
+            LspEvent::Hover(uuid, text) => self.edit(uuid, EditorMessage::Hover(text)),
+            LspEvent::Completion(uuid, items) => self.edit(uuid, EditorMessage::Completions(items)),
+            LspEvent::Definition(source, location) => {
+                self.follow_definition(source, location.path.to_string_lossy().into_owned(), location.range.start)
             }
-            if self.prompt_focused {
-                for key in get_keys_pressed() {
-                    messages.push(Message::PromptEdit(EditorMessage::Keypress(key)));
+            LspEvent::Edit(files) => {
+                for (path, edits) in files {
+                    let mut panes = self.lsp.panes_for(&path).collect::<Vec<_>>();
+                    if panes.is_empty() {
+                        panes.push(self.open_file(path.to_string_lossy().into_owned(), Rect::new(20.0, 20.0, 800.0, 800.0)));
+                    }
+
+                    for uuid in panes {
+                        self.edit(uuid, EditorMessage::Edits(edits.clone()));
+                    }
                 }
+            }
+            LspEvent::Status(status) => self.status = Some(status),
+        }
+    }
 
-                if let Some(character) = get_char_pressed() {
-                    messages.push(Message::PromptEdit(EditorMessage::Char(character)));
+    fn perform(&mut self, action: Action) {
+        match action {
+            Action::Prompt => self.prompt_focused = !self.prompt_focused,
+            Action::Save => {
+                if let Some(focused) = self.focused {
+                    if let Some(editor) = self.editor_mut(focused) {
+                        if let Err(error) = editor.save() {
+                            self.status = Some(format!("Couldn't save {}: {error}", editor.filename()));
+                            return;
+                        }
+                    }
+                    if let Err(error) = self.lsp.saved(focused) {
+                        self.status = Some(error);
+                    }
+                }
+            }
+            Action::Close => {
+                if let Some(focused) = self.focused {
+                    self.panes.remove(&focused);
+                    self.panes.retain(|_, pane| !matches!(pane, Pane::Preview(preview) if preview.source == focused));
+                    self.links.retain(|link| !link.involves(focused));
+                    self.back.retain(|(uuid, _)| *uuid != focused);
+                    self.refresh_problems();
+                    if let Err(error) = self.lsp.close(focused) {
+                        self.status = Some(error);
+                    }
+                }
+            }
+            Action::Help => self.open_help(),
+            Action::Problems => self.show_problems(),
+            Action::Terminal => self.open_terminal(),
+            Action::Files => {
+                self.show_files();
+            }
+            Action::GotoDefinition if self.focused.is_some_and(|focused| !self.lsp.tracks(focused)) => {
+                self.find_definition(self.focused.unwrap())
+            }
+            Action::GoBack => {
+                while let Some((uuid, position)) = self.back.pop() {
+                    if self.editor(uuid).is_some() {
+                        self.focused = Some(uuid);
+                        self.edit(uuid, EditorMessage::JumpTo(position));
+                        self.bring_into_view(uuid);
+                        break;
+                    }
+                }
+            }
+            Action::Hover | Action::GotoDefinition => {
+                if let Some((focused, editor)) = self.focused.and_then(|focused| Some((focused, self.editor(focused)?))) {
+                    let position = editor.cursor_lsp();
+                    let sent = match action {
+                        Action::Hover => self.lsp.hover(focused, position),
+                        _ => self.lsp.definition(focused, position),
+                    };
+                    if let Err(error) = sent {
+                        self.status = Some(error);
+                    }
+                }
+            }
+            Action::Rename if matches!(self.focused_pane(), Some(Pane::Files(_))) => {
+                self.update(Message::Pane(self.focused.unwrap(), PaneMessage::Files(TreeMessage::Action(action))))
+            }
+            Action::Rename => {
+                self.prompt.set_text("rename ");
+                self.prompt_focused = true;
+            }
+            Action::Keys => {
+                let mut editor = Editor::new(Rect::new(40.0, 40.0, 600.0, 600.0), 16, "keys".to_string());
+                editor.load_string(self.keymap.describe());
+                editor.set_generated();
+                self.add_editor(editor);
+            }
+            _ => {
+                if let Some((focused, message)) = self.focused.and_then(|focused| Some((focused, self.panes.get(&focused)?.action(action)?))) {
+                    self.update(Message::Pane(focused, message));
                 }
+            }
+        }
+    }
 
-                return;
+    /// Sends input that isn't bound to an action to whatever has keyboard focus
+    fn send_key(&mut self, message: EditorMessage) {
+        if self.prompt_focused {
+            self.update(Message::PromptEdit(message));
+        } else if let Some(focused) = self.focused {
+            self.edit(focused, message);
+        }
+    }
+
+    fn pan(&mut self, delta: Vec2) {
+        for pane in self.panes.values_mut() {
+            pane.move_by(delta);
+        }
+    }
+
+    fn handle_input(&self, messages: &mut Vec<Message>) {
+        let mut input = InputEvents::default();
+        repeat_all_miniquad_input(&mut input, self.input_subscriber);
+
+        for event in input.0 {
+            match event {
+                InputEvent::Chord(chord) => messages.push(Message::Key(chord)),
+                InputEvent::Char(character) if self.prompt_focused => {
+                    messages.push(Message::PromptEdit(EditorMessage::Char(character)))
+                }
+                InputEvent::Char(character) => {
+                    if let Some(focused) = self.focused {
+                        if let Some(message) = self.panes.get(&focused).and_then(|pane| pane.char(character)) {
+                            messages.push(Message::Pane(focused, message));
+                        }
+                    }
+                }
             }
         }
 
-        let hovered_editor = self.editors.iter()
-            .find(|(_, editor)|
-                editor.window.contains(Vec2::from(mouse_position()))
-                    || editor.titlebar().contains(Vec2::from(mouse_position()))
-            );
+        if self.prompt_focused {
+            return;
+        }
+
+        // Notes are drawn over panes, so they're under the mouse first
+        let hovered_pane = self.panes.iter()
+            .filter(|(_, pane)| pane.hit(Vec2::from(mouse_position())))
+            .min_by_key(|(_, pane)| !matches!(pane, Pane::Note(_)));
 
         if is_mouse_button_pressed(MouseButton::Left) {
-            if let Some((uuid, _)) = hovered_editor {
-                messages.push(Message::Focus(Some(*uuid)))
+            if let Some((uuid, pane)) = hovered_pane {
+                messages.push(Message::Focus(Some(*uuid)));
+
+                if let Some(message) = pane.click(Vec2::from(mouse_position())) {
+                    messages.push(Message::Pane(*uuid, message));
+                }
             } else {
                 messages.push(Message::Focus(None))
             }
         }
 
-        if let Some((uuid, _)) = hovered_editor {
-            let wheel = mouse_wheel();
-            if wheel.1 != 0.0 {
-                messages.push(Message::Scroll(*uuid, Vec2::from(wheel) * 0.25 * vec2(0.0, -1.0)));
+        if let Some((uuid, _)) = hovered_pane {
+            let mut wheel = Vec2::from(mouse_wheel());
+
+            // Shift turns a vertical wheel into a horizontal one
+            if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                wheel = vec2(wheel.y + wheel.x, 0.0);
+            }
+
+            if wheel != Vec2::ZERO {
+                messages.push(Message::Scroll(*uuid, wheel * 0.25 * vec2(-1.0, -1.0)));
             }
         }
 
@@ -187,24 +1191,16 @@ impl App {
         if is_mouse_button_down(MouseButton::Left) && delta != Vec2::ZERO {
             messages.push(Message::Pan(delta));
         } else {
-            let hovered_titlebar = self.editors.iter().find(|(_, editor)| editor.titlebar().contains(Vec2::from(mouse_position())));
-            let target = hovered_titlebar.and_then(|(uuid, _)| Some(*uuid));
+            let hovered_grip = self.panes.iter()
+                .filter(|(_, pane)| pane.grip().contains(Vec2::from(mouse_position())))
+                .min_by_key(|(_, pane)| !matches!(pane, Pane::Note(_)));
+            let target = hovered_grip.map(|(uuid, _)| *uuid);
 
             if self.move_target != target {
                 messages.push(Message::MoveTarget(target));
             }
         }
 
-        if let Some(uuid) = self.focused {
-            for key in get_keys_pressed() {
-                messages.push(Message::Edit(uuid, EditorMessage::Keypress(key)));
-            }
-
-            if let Some(character) = get_char_pressed() {
-                messages.push(Message::Edit(uuid, EditorMessage::Char(character)));
-            }
-        }
-
         clear_input_queue();
     }
 
@@ -215,12 +1211,39 @@ impl App {
         set_default_camera();
         // draw_text(format!("FPS: {}", get_fps()).as_str(), screen_width() - 160., 32., 32., WHITE);
 
-        for (uuid, editor) in &self.editors {
-            editor.view(&self.theme, self.font.as_ref(), self.focused == Some(*uuid));
+        // Connectors go over the panes they join, and notes over everything
+        let (notes, panes): (Vec<_>, Vec<_>) = self.panes.iter().partition(|(_, pane)| matches!(pane, Pane::Note(_)));
+        for (uuid, pane) in panes {
+            pane.view(&self.theme, self.font.as_ref(), self.focused == Some(*uuid));
+        }
+
+        set_fullscreen_camera();
+        for link in &self.links {
+            if let (Some(from), Some(to)) = (self.editor(link.from), self.editor(link.to)) {
+                link.draw(from, to, &self.theme, self.font.as_ref());
+            }
         }
+        for (uuid, note) in notes {
+            note.view(&self.theme, self.font.as_ref(), self.focused == Some(*uuid));
+        }
+        set_default_camera();
 
         if self.prompt_focused {
             self.prompt.view(&self.theme, self.font.as_ref())
+        } else if let Some(status) = &self.status {
+            draw_text_ex(status, 0.0, screen_height() - 8.0, TextParams {
+                color: self.theme.subtext0,
+                font: self.font.as_ref(),
+                font_size: 16,
+                ..Default::default()
+            });
+        }
+
+        for (server, message) in self.lsp.poll() {
+            messages.push(Message::Lsp(server, message));
+        }
+        for (uuid, pane) in &self.panes {
+            messages.extend(pane.poll().into_iter().map(|message| Message::Pane(*uuid, message)));
         }
 
         self.handle_input(&mut messages);
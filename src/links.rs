@@ -0,0 +1,83 @@
+use macroquad::prelude::*;
+use uuid::Uuid;
+use crate::editor::{Editor, Point, RowShift};
+use crate::theme::Theme;
+
+/// A connector drawn on the canvas from rows of one pane to rows of another,
+/// such as from a call to the definition it was followed to
+#[derive(Debug, Clone)]
+pub(crate) struct Link {
+    pub(crate) from: Uuid,
+    /// First and last rows at each end
+    pub(crate) from_rows: (usize, usize),
+    pub(crate) to: Uuid,
+    pub(crate) to_rows: (usize, usize),
+    pub(crate) label: Option<String>,
+}
+
+impl Link {
+    pub(crate) fn involves(&self, uuid: Uuid) -> bool {
+        self.from == uuid || self.to == uuid
+    }
+
+    /// Whether either end covers `row` of the pane
+    pub(crate) fn touches(&self, uuid: Uuid, row: usize) -> bool {
+        let covers = |(first, last): (usize, usize)| (first..=last).contains(&row);
+        (self.from == uuid && covers(self.from_rows)) || (self.to == uuid && covers(self.to_rows))
+    }
+
+    /// Keeps the ends in `uuid` on the same lines after lines are added or removed above them
+    pub(crate) fn shift(&mut self, uuid: Uuid, shift: RowShift) {
+        if self.from == uuid {
+            self.from_rows = shift_rows(self.from_rows, shift);
+        }
+        if self.to == uuid {
+            self.to_rows = shift_rows(self.to_rows, shift);
+        }
+    }
+
+    /// Draws a line between the facing sides of the two panes, from beside one range of rows to beside the other
+    pub(crate) fn draw(&self, from: &Editor, to: &Editor, theme: &Theme, font: Option<&Font>) {
+        let rightwards = from.window.center().x <= to.window.center().x;
+        let color = Color { a: 0.8, ..theme.lavender };
+        let start = attach(from, self.from_rows, rightwards, color);
+        let end = attach(to, self.to_rows, !rightwards, color);
+
+        draw_line(start.x, start.y, end.x, end.y, 2.0, color);
+
+        let direction = (end - start).normalize_or_zero();
+        let side = direction.perp() * 5.0;
+        draw_triangle(end, end - direction * 10.0 + side, end - direction * 10.0 - side, color);
+
+        if let Some(label) = &self.label {
+            let middle = (start + end) / 2.0;
+            let size = measure_text(label, font, 14, 1.0);
+            draw_rectangle(middle.x - size.width / 2.0 - 4.0, middle.y - size.height / 2.0 - 4.0, size.width + 8.0, size.height + 8.0, theme.surface1);
+            draw_text_ex(label, middle.x - size.width / 2.0, middle.y + size.height / 2.0, TextParams {
+                color: theme.text,
+                font,
+                font_size: 14,
+                ..Default::default()
+            });
+        }
+    }
+}
+
+/// Moves a range of rows with `shift`, with rows that were removed landing on the row they were joined into
+pub(crate) fn shift_rows((first, last): (usize, usize), shift: RowShift) -> (usize, usize) {
+    let moved = |row| shift.moved(row).unwrap_or(shift.row);
+    (moved(first), moved(last))
+}
+
+/// Marks the rows along one side of `editor`, returning the middle of the mark for the line to start from.
+/// Rows scrolled out of view are marked at the nearest edge of the pane.
+fn attach(editor: &Editor, (first, last): (usize, usize), right: bool, color: Color) -> Vec2 {
+    let window = editor.window;
+    let top = editor.point_on_screen(Point::new(first, 0)).y - editor.line_height();
+    let bottom = editor.point_on_screen(Point::new(last, usize::MAX)).y;
+    let (top, bottom) = (top.clamp(window.top(), window.bottom()), bottom.clamp(window.top(), window.bottom()));
+    let x = if right { window.right() } else { window.left() };
+
+    draw_line(x, top, x, bottom, 4.0, color);
+    vec2(x, (top + bottom) / 2.0)
+}
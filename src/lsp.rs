@@ -0,0 +1,594 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::path::{self, Path, PathBuf};
+use std::process;
+use inkjet::Language;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use crate::config::ServerConfig;
+use crate::editor::diagnostics::Severity;
+
+mod client;
+
+pub use client::{read_message, write_message, Client};
+
+/// Servers used for languages that don't have one set in the config file
+const DEFAULT_SERVERS: &[(Language, &str, &[&str])] = &[
+    (Language::Rust, "rust-analyzer", &[]),
+    (Language::Python, "pylsp", &[]),
+    (Language::Go, "gopls", &[]),
+    (Language::C, "clangd", &[]),
+    (Language::Cpp, "clangd", &[]),
+    (Language::Javascript, "typescript-language-server", &["--stdio"]),
+    (Language::Typescript, "typescript-language-server", &["--stdio"]),
+];
+
+/// Files that mark the directory they're in as the root of a project
+const ROOT_MARKERS: &[&str] = &[".git", "Cargo.toml", "package.json", "go.mod", "pyproject.toml", "setup.py", "compile_commands.json"];
+
+/// JSON-RPC error code for a request the server dropped because a newer one replaced it
+const REQUEST_CANCELLED: i64 = -32800;
+
+/// A position as the server counts it, in UTF-16 code units along the line
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+pub struct Position {
+    pub(crate) line: usize,
+    pub(crate) character: usize,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct Range {
+    pub(crate) start: Position,
+    pub(crate) end: Position,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TextEdit {
+    pub(crate) range: Range,
+    #[serde(rename = "newText")]
+    pub(crate) text: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Location {
+    pub(crate) path: PathBuf,
+    pub(crate) range: Range,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Diagnostic {
+    pub(crate) range: Range,
+    severity: Option<u8>,
+    pub(crate) message: String,
+    pub(crate) source: Option<String>,
+}
+
+impl Diagnostic {
+    pub(crate) fn severity(&self) -> Severity {
+        match self.severity {
+            Some(2) => Severity::Warning,
+            Some(3) => Severity::Information,
+            Some(4) => Severity::Hint,
+            _ => Severity::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompletionItem {
+    pub(crate) label: String,
+    pub(crate) detail: Option<String>,
+    /// Text to insert in place of the word before the cursor
+    pub(crate) text: String,
+}
+
+/// Something from a server that the app has to act on
+#[derive(Debug)]
+pub(crate) enum LspEvent {
+    Diagnostics(PathBuf, Vec<Diagnostic>),
+    Hover(Uuid, String),
+    Definition(Uuid, Location),
+    Completion(Uuid, Vec<CompletionItem>),
+    Edit(Vec<(PathBuf, Vec<TextEdit>)>),
+    Status(String),
+}
+
+/// What a request was for, so its response can be routed back to the pane that asked
+#[derive(Debug, Copy, Clone)]
+enum Pending {
+    Initialize,
+    Hover(Uuid),
+    Definition(Uuid),
+    Completion(Uuid),
+    Rename,
+}
+
+struct Server {
+    client: Client,
+    command: String,
+    language: Language,
+    root: PathBuf,
+    /// Messages held back until the server has answered `initialize`
+    queued: Option<Vec<(String, Value, Option<Pending>)>>,
+    pending: HashMap<i64, Pending>,
+}
+
+impl Server {
+    fn send(&mut self, method: &str, params: Value, request: Option<Pending>) -> io::Result<()> {
+        if let Some(queued) = &mut self.queued {
+            queued.push((method.to_owned(), params, request));
+            return Ok(());
+        }
+
+        match request {
+            Some(pending) => {
+                let id = self.client.request(method, params)?;
+                self.pending.insert(id, pending);
+            }
+            None => self.client.notify(method, params)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// A pane's file as a server knows it
+struct Document {
+    server: usize,
+    path: PathBuf,
+    version: i32,
+    /// Hash of the text last sent, to skip sending changes that didn't change anything
+    hash: u64,
+}
+
+/// Language servers for the open files, one per language and project
+pub(crate) struct Lsp {
+    configs: BTreeMap<String, ServerConfig>,
+    servers: HashMap<usize, Server>,
+    next_server: usize,
+    documents: HashMap<Uuid, Document>,
+    /// Commands that couldn't be started, so they aren't tried again for every file
+    failed: HashSet<String>,
+}
+
+impl Lsp {
+    pub(crate) fn new(configs: BTreeMap<String, ServerConfig>) -> Self {
+        Self {
+            configs,
+            servers: HashMap::new(),
+            next_server: 0,
+            documents: HashMap::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    /// The command for `language`'s server, if it has one that isn't turned off
+    fn command(&self, language: Language) -> Option<(String, Vec<String>)> {
+        let configured = self.configs.iter()
+            .find(|(name, _)| Language::from_token(name) == Some(language))
+            .map(|(_, config)| (config.command.clone(), config.args.clone()));
+
+        let default = || DEFAULT_SERVERS.iter()
+            .find(|(default, _, _)| *default == language)
+            .map(|(_, command, args)| (command.to_string(), args.iter().map(|arg| arg.to_string()).collect()));
+
+        configured.or_else(default).filter(|(command, _)| !command.is_empty())
+    }
+
+    /// Starts tracking a pane's file, starting a server for it if there isn't one running yet
+    pub(crate) fn open(&mut self, uuid: Uuid, filename: &str, language: Language, text: String) -> Result<(), String> {
+        let Some((command, args)) = self.command(language) else {
+            return Ok(());
+        };
+        let Ok(path) = path::absolute(filename) else {
+            return Ok(());
+        };
+        let root = find_root(&path);
+
+        let existing = self.servers.iter()
+            .find(|(_, server)| server.language == language && server.root == root)
+            .map(|(id, _)| *id);
+
+        let id = match existing {
+            Some(id) => id,
+            None if self.failed.contains(&command) => return Ok(()),
+            None => self.start(&command, &args, language, root)?,
+        };
+
+        let hash = hash(&text);
+        let params = json!({
+            "textDocument": {
+                "uri": uri(&path),
+                "languageId": format!("{language:?}").to_lowercase(),
+                "version": 0,
+                "text": text,
+            }
+        });
+
+        self.documents.insert(uuid, Document { server: id, path, version: 0, hash });
+        self.send(id, "textDocument/didOpen", params, None)
+    }
+
+    fn start(&mut self, command: &str, args: &[String], language: Language, root: PathBuf) -> Result<usize, String> {
+        let mut client = Client::spawn(command, args, &root).map_err(|error| {
+            self.failed.insert(command.to_owned());
+            format!("Couldn't start {command}: {error}")
+        })?;
+
+        let id = client.request("initialize", json!({
+            "processId": process::id(),
+            "clientInfo": { "name": "benchide" },
+            "rootUri": uri(&root),
+            "workspaceFolders": [{ "uri": uri(&root), "name": root.file_name().map(|name| name.to_string_lossy()) }],
+            "capabilities": {
+                "general": { "positionEncodings": ["utf-16"] },
+                "workspace": { "applyEdit": true, "configuration": true, "workspaceFolders": true },
+                "textDocument": {
+                    "synchronization": { "didSave": true },
+                    "publishDiagnostics": {},
+                    "hover": { "contentFormat": ["plaintext", "markdown"] },
+                    "definition": {},
+                    "completion": { "completionItem": { "snippetSupport": false } },
+                    "rename": {},
+                },
+            },
+        })).map_err(|error| format!("{command}: {error}"))?;
+
+        let server_id = self.next_server;
+        self.next_server += 1;
+        self.servers.insert(server_id, Server {
+            client,
+            command: command.to_owned(),
+            language,
+            root,
+            queued: Some(vec![]),
+            pending: HashMap::from([(id, Pending::Initialize)]),
+        });
+
+        Ok(server_id)
+    }
+
+    /// Sends to a server, dropping it and its documents if it has gone away
+    fn send(&mut self, id: usize, method: &str, params: Value, request: Option<Pending>) -> Result<(), String> {
+        let Some(server) = self.servers.get_mut(&id) else {
+            return Ok(());
+        };
+
+        if server.send(method, params, request).is_err() {
+            return Err(self.stopped(id));
+        }
+
+        Ok(())
+    }
+
+    /// Forgets a server that can't be written to any more, returning a message saying so
+    fn stopped(&mut self, id: usize) -> String {
+        let command = self.servers.remove(&id).map(|server| server.command).unwrap_or_default();
+        self.documents.retain(|_, document| document.server != id);
+        format!("{command} stopped")
+    }
+
+    /// Sends the pane's text if it has changed since it was last sent
+    pub(crate) fn changed(&mut self, uuid: Uuid, text: String) -> Result<(), String> {
+        let Some(document) = self.documents.get_mut(&uuid) else {
+            return Ok(());
+        };
+
+        let hash = hash(&text);
+        if hash == document.hash {
+            return Ok(());
+        }
+
+        document.hash = hash;
+        document.version += 1;
+        let params = json!({
+            "textDocument": { "uri": uri(&document.path), "version": document.version },
+            "contentChanges": [{ "text": text }],
+        });
+
+        let server = document.server;
+        self.send(server, "textDocument/didChange", params, None)
+    }
+
+    pub(crate) fn saved(&mut self, uuid: Uuid) -> Result<(), String> {
+        let Some(document) = self.documents.get(&uuid) else {
+            return Ok(());
+        };
+
+        let params = json!({ "textDocument": { "uri": uri(&document.path) } });
+        self.send(document.server, "textDocument/didSave", params, None)
+    }
+
+    pub(crate) fn close(&mut self, uuid: Uuid) -> Result<(), String> {
+        let Some(document) = self.documents.remove(&uuid) else {
+            return Ok(());
+        };
+
+        let params = json!({ "textDocument": { "uri": uri(&document.path) } });
+        self.send(document.server, "textDocument/didClose", params, None)
+    }
+
+    /// Sends a request about a position in a pane's file
+    fn request(&mut self, uuid: Uuid, method: &str, position: Position, extra: Value, pending: Pending) -> Result<(), String> {
+        let Some(document) = self.documents.get(&uuid) else {
+            return Err("No language server for this file".to_string());
+        };
+
+        let mut params = json!({
+            "textDocument": { "uri": uri(&document.path) },
+            "position": { "line": position.line, "character": position.character },
+        });
+        if let (Some(params), Value::Object(extra)) = (params.as_object_mut(), extra) {
+            params.extend(extra);
+        }
+
+        self.send(document.server, method, params, Some(pending))
+    }
+
+    pub(crate) fn hover(&mut self, uuid: Uuid, position: Position) -> Result<(), String> {
+        self.request(uuid, "textDocument/hover", position, Value::Null, Pending::Hover(uuid))
+    }
+
+    pub(crate) fn definition(&mut self, uuid: Uuid, position: Position) -> Result<(), String> {
+        self.request(uuid, "textDocument/definition", position, Value::Null, Pending::Definition(uuid))
+    }
+
+    pub(crate) fn completion(&mut self, uuid: Uuid, position: Position) -> Result<(), String> {
+        self.request(uuid, "textDocument/completion", position, Value::Null, Pending::Completion(uuid))
+    }
+
+    pub(crate) fn rename(&mut self, uuid: Uuid, position: Position, name: &str) -> Result<(), String> {
+        self.request(uuid, "textDocument/rename", position, json!({ "newName": name }), Pending::Rename)
+    }
+
+    /// Whether a server knows the pane's file
+    pub(crate) fn tracks(&self, uuid: Uuid) -> bool {
+        self.documents.contains_key(&uuid)
+    }
+
+    /// Panes showing the file at `path`
+    pub(crate) fn panes_for<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = Uuid> + 'a {
+        self.documents.iter().filter(move |(_, document)| document.path == path).map(|(uuid, _)| *uuid)
+    }
+
+    /// Messages that servers have sent since the last call, with the server they came from
+    pub(crate) fn poll(&self) -> Vec<(usize, Value)> {
+        self.servers.iter()
+            .flat_map(|(id, server)| server.client.poll().into_iter().map(|message| (*id, message)))
+            .collect()
+    }
+
+    pub(crate) fn handle(&mut self, id: usize, message: Value) -> Vec<LspEvent> {
+        let Some(server) = self.servers.get_mut(&id) else {
+            return vec![];
+        };
+
+        let mut events = vec![];
+        let params = &message["params"];
+
+        let sent = match (message.get("id"), message["method"].as_str()) {
+            // Requests from the server
+            (Some(request), Some(method)) => {
+                let result = match method {
+                    "workspace/configuration" => {
+                        let items = params["items"].as_array().map_or(0, Vec::len);
+                        Value::Array(vec![Value::Null; items])
+                    }
+                    "workspace/applyEdit" => {
+                        events.push(LspEvent::Edit(workspace_edit(&params["edit"])));
+                        json!({ "applied": true })
+                    }
+                    _ => Value::Null,
+                };
+                server.client.respond(request.clone(), result)
+            }
+
+            (None, Some("textDocument/publishDiagnostics")) => {
+                if let Some(path) = params["uri"].as_str().and_then(path_from_uri) {
+                    let diagnostics = serde_json::from_value(params["diagnostics"].clone()).unwrap_or_default();
+                    events.push(LspEvent::Diagnostics(path, diagnostics));
+                }
+                Ok(())
+            }
+            (None, Some("window/showMessage")) if params["type"].as_i64().is_some_and(|kind| kind <= 2) => {
+                events.push(LspEvent::Status(format!("{}: {}", server.command, params["message"].as_str().unwrap_or_default())));
+                Ok(())
+            }
+            (None, Some(_)) => Ok(()),
+
+            // Responses to our requests
+            (Some(response), None) => {
+                let pending = response.as_i64().and_then(|response| server.pending.remove(&response));
+                let result = &message["result"];
+
+                match (pending, message.get("error")) {
+                    (None, _) => Ok(()),
+                    (Some(_), Some(error)) => {
+                        if error["code"].as_i64() != Some(REQUEST_CANCELLED) {
+                            let error = error["message"].as_str().unwrap_or("request failed");
+                            events.push(LspEvent::Status(format!("{}: {}", server.command, error)));
+                        }
+                        Ok(())
+                    }
+                    (Some(Pending::Initialize), None) => {
+                        let queued = server.queued.take().unwrap_or_default();
+                        server.client.notify("initialized", json!({}))
+                            .and_then(|_| queued.into_iter().try_for_each(|(method, params, request)| server.send(&method, params, request)))
+                    }
+                    (Some(Pending::Hover(uuid)), None) => {
+                        let text = hover_text(&result["contents"]);
+                        if !text.is_empty() {
+                            events.push(LspEvent::Hover(uuid, text));
+                        }
+                        Ok(())
+                    }
+                    (Some(Pending::Definition(uuid)), None) => {
+                        match location(result) {
+                            Some(location) => events.push(LspEvent::Definition(uuid, location)),
+                            None => events.push(LspEvent::Status("No definition found".to_string())),
+                        }
+                        Ok(())
+                    }
+                    (Some(Pending::Completion(uuid)), None) => {
+                        events.push(LspEvent::Completion(uuid, completion_items(result)));
+                        Ok(())
+                    }
+                    (Some(Pending::Rename), None) => {
+                        events.push(LspEvent::Edit(workspace_edit(result)));
+                        Ok(())
+                    }
+                }
+            }
+
+            (None, None) => Ok(()),
+        };
+
+        if sent.is_err() {
+            events.push(LspEvent::Status(self.stopped(id)));
+        }
+
+        events
+    }
+}
+
+fn hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The nearest directory above `file` that looks like the root of a project, or the file's own directory
+fn find_root(file: &Path) -> PathBuf {
+    let dir = file.parent().unwrap_or(file);
+
+    dir.ancestors()
+        .find(|dir| ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// `file://` URI for an absolute path
+fn uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+
+    // Windows paths start with a drive letter rather than a slash
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(byte as char),
+            _ => write!(uri, "%{byte:02X}").unwrap(),
+        }
+    }
+
+    uri
+}
+
+fn path_from_uri(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+
+    while i < encoded.len() {
+        let escaped = encoded.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (encoded[i], escaped) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    let path = String::from_utf8(bytes).ok()?;
+    match cfg!(windows) {
+        true => Some(PathBuf::from(path.trim_start_matches('/'))),
+        false => Some(PathBuf::from(path)),
+    }
+}
+
+/// Plain text of hover contents, which may be markup, a marked string or a list of them
+fn hover_text(contents: &Value) -> String {
+    let text = match contents {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts.iter().map(hover_text).collect::<Vec<_>>().join("\n\n"),
+        Value::Object(object) => object.get("value").and_then(Value::as_str).unwrap_or_default().to_owned(),
+        _ => String::new(),
+    };
+
+    // Code fences are just noise in a plain text tooltip
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_owned()
+}
+
+/// The first location in a definition response, which may be a location, a list of them or a list of links
+fn location(result: &Value) -> Option<Location> {
+    let first = match result {
+        Value::Array(locations) => locations.first()?,
+        location => location,
+    };
+
+    let uri = first.get("uri").or(first.get("targetUri"))?.as_str()?;
+    let range = first.get("range").or(first.get("targetSelectionRange"))?;
+
+    Some(Location {
+        path: path_from_uri(uri)?,
+        range: serde_json::from_value(range.clone()).ok()?,
+    })
+}
+
+fn completion_items(result: &Value) -> Vec<CompletionItem> {
+    let items = match result {
+        Value::Array(items) => items,
+        Value::Object(list) => match list.get("items") {
+            Some(Value::Array(items)) => items,
+            _ => return vec![],
+        },
+        _ => return vec![],
+    };
+
+    let mut items = items.iter()
+        .filter_map(|item| {
+            let label = item["label"].as_str()?.to_owned();
+            let edit = &item["textEdit"];
+            let text = edit["newText"].as_str().or(item["insertText"].as_str()).unwrap_or(&label).to_owned();
+            let sort = item["sortText"].as_str().unwrap_or(&label).to_owned();
+
+            Some((sort, CompletionItem { label, detail: item["detail"].as_str().map(str::to_owned), text }))
+        })
+        .collect::<Vec<_>>();
+
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+    items.into_iter().map(|(_, item)| item).collect()
+}
+
+/// Edits per file from a workspace edit, in either its `changes` or `documentChanges` form
+fn workspace_edit(edit: &Value) -> Vec<(PathBuf, Vec<TextEdit>)> {
+    let edits = |edits: &Value| serde_json::from_value::<Vec<TextEdit>>(edits.clone()).unwrap_or_default();
+
+    if let Some(changes) = edit["documentChanges"].as_array() {
+        return changes.iter()
+            .filter_map(|change| Some((path_from_uri(change["textDocument"]["uri"].as_str()?)?, edits(&change["edits"]))))
+            .collect();
+    }
+
+    edit["changes"].as_object().into_iter().flatten()
+        .filter_map(|(uri, changes)| Some((path_from_uri(uri)?, edits(changes))))
+        .collect()
+}
@@ -0,0 +1,143 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+
+/// How long a server gets to answer `shutdown`, and then to exit, before it's killed
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(500);
+
+/// A language server running as a child process, spoken to with JSON-RPC over its stdin and stdout
+pub struct Client {
+    process: Child,
+    stdin: ChildStdin,
+    /// Messages from the server, read on a background thread so the UI never waits on it
+    incoming: Receiver<Value>,
+    next_id: i64,
+}
+
+impl Client {
+    /// Starts `command` with `root` as its working directory
+    pub fn spawn(command: &str, args: &[String], root: &Path) -> io::Result<Self> {
+        let mut process = Command::new(command)
+            .args(args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdin = process.stdin.take().unwrap();
+        let mut stdout = BufReader::new(process.stdout.take().unwrap());
+        let (sender, incoming) = mpsc::channel();
+
+        thread::spawn(move || {
+            while let Ok(message) = read_message(&mut stdout) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { process, stdin, incoming, next_id: 0 })
+    }
+
+    /// Sends a request, returning the id its response will carry
+    pub fn request(&mut self, method: &str, params: Value) -> io::Result<i64> {
+        self.next_id += 1;
+        write_message(&mut self.stdin, &json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id,
+            "method": method,
+            "params": params,
+        }))?;
+
+        Ok(self.next_id)
+    }
+
+    pub fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        write_message(&mut self.stdin, &json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        }))
+    }
+
+    /// Answers a request the server sent
+    pub fn respond(&mut self, id: Value, result: Value) -> io::Result<()> {
+        write_message(&mut self.stdin, &json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": result,
+        }))
+    }
+
+    /// Messages that have arrived since the last call, without waiting for more
+    pub fn poll(&self) -> Vec<Value> {
+        self.incoming.try_iter().collect()
+    }
+
+    /// Waits up to `timeout` for the next message
+    pub fn wait(&self, timeout: Duration) -> Option<Value> {
+        self.incoming.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for Client {
+    /// Shuts the server down the way the protocol asks, killing it only if it doesn't go in time
+    fn drop(&mut self) {
+        if let Ok(id) = self.request("shutdown", Value::Null) {
+            let deadline = Instant::now() + SHUTDOWN_GRACE;
+            while let Some(message) = self.wait(deadline.saturating_duration_since(Instant::now())) {
+                if message["id"] == id {
+                    break;
+                }
+            }
+
+            let _ = self.notify("exit", Value::Null);
+            let deadline = Instant::now() + SHUTDOWN_GRACE;
+            while matches!(self.process.try_wait(), Ok(None)) && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Reads one message framed with a `Content-Length` header
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Value> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "message without a Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
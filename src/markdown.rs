@@ -0,0 +1,888 @@
+use std::path::Path;
+use macroquad::prelude::*;
+use inkjet::{Highlighter, Language};
+use uuid::Uuid;
+use crate::editor;
+use crate::layout::TextLayout;
+use crate::theme::Theme;
+use crate::window::{set_camera_window, set_fullscreen_camera};
+
+const FONT_SIZE: u16 = 16;
+/// Room around the rendered text, in pixels
+const PADDING: f32 = 12.0;
+
+#[derive(Debug)]
+pub enum PreviewMessage {
+    Click(Vec2),
+    Scroll(Vec2),
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct Style {
+    strong: bool,
+    emphasis: bool,
+    code: bool,
+    /// List markers and table borders
+    muted: bool,
+}
+
+/// A run of inline text in one style
+#[derive(Debug, Clone, PartialEq)]
+struct Span {
+    text: String,
+    style: Style,
+    link: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug)]
+enum BlockKind {
+    Heading(usize, Vec<Span>),
+    Paragraph(Vec<Span>),
+    Quote(Vec<Span>),
+    /// Nesting depth, marker and text
+    Item(usize, String, Vec<Span>),
+    /// Highlighted pieces of each line
+    Code(Vec<Vec<(String, Color)>>),
+    /// Header row first
+    Table(Vec<Alignment>, Vec<Vec<Vec<Span>>>),
+    Rule,
+}
+
+#[derive(Debug)]
+struct Block {
+    /// Source line the block starts on, and how many it takes up
+    line: usize,
+    lines: usize,
+    kind: BlockKind,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Decoration {
+    None,
+    Code,
+    Quote,
+    Rule,
+}
+
+/// Text at a cell of a row, colored by its style unless it's highlighted code
+#[derive(Debug)]
+struct Piece {
+    cell: usize,
+    text: String,
+    style: Style,
+    color: Option<Color>,
+    link: Option<String>,
+}
+
+/// One row of the rendered document
+#[derive(Debug)]
+struct Row {
+    /// Source line the row was laid out from
+    line: usize,
+    size: u16,
+    /// Cells from the left edge
+    indent: usize,
+    pieces: Vec<Piece>,
+    decoration: Decoration,
+    heading: bool,
+}
+
+impl Row {
+    fn new(line: usize, size: u16, indent: usize, decoration: Decoration) -> Self {
+        Self { line, size, indent, pieces: vec![], decoration, heading: false }
+    }
+
+    fn height(&self) -> f32 {
+        self.size as f32 * 1.4
+    }
+}
+
+/// A rendered view of a markdown editor's text, kept in step with it
+pub(crate) struct Preview {
+    pub(crate) window: Rect,
+    /// The editor being previewed
+    pub(crate) source: Uuid,
+    filename: String,
+    layout: TextLayout,
+    text: String,
+    blocks: Vec<Block>,
+    /// How far down the document is scrolled, in pixels
+    scroll: f32,
+}
+
+impl Preview {
+    pub(crate) fn new(window: Rect, source: Uuid, filename: String) -> Self {
+        Self {
+            window,
+            source,
+            filename,
+            layout: TextLayout::new(FONT_SIZE),
+            text: String::new(),
+            blocks: vec![],
+            scroll: 0.0,
+        }
+    }
+
+    pub(crate) fn titlebar(&self) -> Rect {
+        let titlebar_height = self.layout.font_size() as f32 + 8.0;
+        Rect::new(self.window.x, self.window.y - titlebar_height, self.window.w, titlebar_height)
+    }
+
+    /// Renders `text` again if it has changed
+    pub(crate) fn set_text(&mut self, text: String, highlighter: &mut Highlighter, theme: &Theme) {
+        if text != self.text || self.blocks.is_empty() {
+            self.blocks = parse(&text, highlighter, theme);
+            self.text = text;
+            self.scroll(Vec2::ZERO);
+        }
+    }
+
+    pub(crate) fn update(&mut self, message: PreviewMessage) {
+        match message {
+            PreviewMessage::Click(_) => {}
+            PreviewMessage::Scroll(offset) => self.scroll(offset),
+        }
+    }
+
+    fn scroll(&mut self, offset: Vec2) {
+        let height = self.rows().iter().map(Row::height).sum::<f32>() + 2.0 * PADDING;
+        self.scroll = (self.scroll + offset.y).clamp(0.0, (height - self.window.h).max(0.0));
+    }
+
+    /// Source line of the row at the top of the window
+    pub(crate) fn top_line(&self) -> usize {
+        let mut y = 0.0;
+        let rows = self.rows();
+        rows.iter()
+            .find(|row| {
+                y += row.height();
+                y > self.scroll
+            })
+            .or(rows.last())
+            .map_or(0, |row| row.line)
+    }
+
+    /// Scrolls so that what source line `line` renders to is at the top of the window
+    pub(crate) fn show_line(&mut self, line: usize) {
+        let mut y = 0.0;
+        for row in self.rows() {
+            if row.line >= line {
+                break;
+            }
+            y += row.height();
+        }
+        self.scroll = y;
+        self.scroll(Vec2::ZERO);
+    }
+
+    /// Where the link under a point on screen points
+    pub(crate) fn link_at(&self, position: Vec2) -> Option<String> {
+        if !self.window.contains(position) {
+            return None;
+        }
+
+        let local = position - self.window.point() + vec2(-PADDING, self.scroll - PADDING);
+        let mut y = 0.0;
+        let row = self.rows().into_iter().find(|row| {
+            y += row.height();
+            y > local.y
+        })?;
+        let cell = local.x / self.cell_width(row.size) - row.indent as f32;
+        row.pieces.into_iter()
+            .find(|piece| (piece.cell as f32..(piece.cell + piece.text.chars().count()) as f32).contains(&cell))
+            .and_then(|piece| piece.link)
+    }
+
+    /// The file a link refers to, relative to the previewed file, if there is one
+    pub(crate) fn link_path(&self, link: &str) -> Option<String> {
+        let path = link.split('#').next()?;
+        if path.is_empty() || path.contains("://") {
+            return None;
+        }
+
+        let path = Path::new(&self.filename).parent().unwrap_or(Path::new("")).join(path);
+        path.is_file().then(|| path.to_string_lossy().into_owned())
+    }
+
+    fn cell_width(&self, size: u16) -> f32 {
+        self.layout.cell_width() * size as f32 / FONT_SIZE as f32
+    }
+
+    /// The document laid out for the current width
+    fn rows(&self) -> Vec<Row> {
+        let width = ((self.window.w - 2.0 * PADDING) / self.layout.cell_width()).max(1.0) as usize;
+        let mut rows: Vec<Row> = vec![];
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            match &block.kind {
+                BlockKind::Heading(level, spans) => {
+                    let size = heading_size(*level);
+                    let width = width * FONT_SIZE as usize / size as usize;
+                    let strong = spans.iter().map(|span| Span { style: Style { strong: true, ..span.style }, ..span.clone() }).collect::<Vec<_>>();
+                    for pieces in wrap(&strong, width) {
+                        rows.push(Row { pieces, heading: true, ..Row::new(block.line, size, 0, Decoration::None) });
+                    }
+                }
+                BlockKind::Paragraph(spans) => rows.extend(text_rows(block, spans, 0, width, Decoration::None)),
+                BlockKind::Quote(spans) => rows.extend(text_rows(block, spans, 2, width.saturating_sub(2), Decoration::Quote)),
+                BlockKind::Item(depth, marker, spans) => {
+                    let indent = depth * 2;
+                    let marker_width = marker.chars().count() + 1;
+                    let mut item = text_rows(block, spans, indent + marker_width, width.saturating_sub(indent + marker_width), Decoration::None);
+                    if item.is_empty() {
+                        item.push(Row::new(block.line, FONT_SIZE, indent + marker_width, Decoration::None));
+                    }
+                    let style = Style { muted: true, ..Style::default() };
+                    item[0].indent = indent;
+                    for piece in &mut item[0].pieces {
+                        piece.cell += marker_width;
+                    }
+                    item[0].pieces.insert(0, Piece { cell: 0, text: marker.clone(), style, color: None, link: None });
+                    rows.extend(item);
+                }
+                BlockKind::Code(lines) => {
+                    for (k, line) in lines.iter().enumerate() {
+                        let mut row = Row::new(block.line + 1 + k, FONT_SIZE, 1, Decoration::Code);
+                        let mut cell = 0;
+                        for (text, color) in line {
+                            row.pieces.push(Piece { cell, text: text.clone(), style: Style::default(), color: Some(*color), link: None });
+                            cell += text.chars().count();
+                        }
+                        rows.push(row);
+                    }
+                }
+                BlockKind::Table(alignments, table) => rows.extend(table_rows(block.line, alignments, table)),
+                BlockKind::Rule => rows.push(Row::new(block.line, FONT_SIZE, 0, Decoration::Rule)),
+            }
+
+            // Blocks are kept apart by a blank row, except for the items of a list
+            let next_is_item = matches!(self.blocks.get(i + 1).map(|next| &next.kind), Some(BlockKind::Item(..)));
+            if !(matches!(block.kind, BlockKind::Item(..)) && next_is_item) {
+                rows.push(Row::new(block.line + block.lines, FONT_SIZE, 0, Decoration::None));
+            }
+        }
+
+        rows
+    }
+
+    pub(crate) fn view(&self, theme: &Theme, font: Option<&Font>, focused: bool) {
+        set_fullscreen_camera();
+
+        let titlebar = self.titlebar();
+        draw_rectangle(titlebar.x, titlebar.y, titlebar.w, titlebar.h, theme.surface1);
+        draw_text_ex(&format!("preview: {}", self.filename), self.window.x, self.window.y - 4.0, TextParams {
+            color: if focused { theme.lavender } else { theme.text },
+            font,
+            font_size: FONT_SIZE,
+            ..Default::default()
+        });
+
+        draw_rectangle(self.window.x, self.window.y, self.window.w, self.window.h, theme.base);
+        set_camera_window(self.window, vec2(-PADDING, self.scroll - PADDING));
+        self.layout.measure(font);
+
+        let mut y = 0.0;
+        for row in self.rows() {
+            let height = row.height();
+            if y + height < self.scroll - PADDING || y > self.scroll + self.window.h {
+                y += height;
+                continue;
+            }
+
+            let cell_width = self.cell_width(row.size);
+            let x = row.indent as f32 * cell_width;
+            let baseline = y + row.size as f32 * 1.1;
+            match row.decoration {
+                Decoration::None => {}
+                Decoration::Code => draw_rectangle(0.0, y, self.window.w - 2.0 * PADDING, height, theme.mantle),
+                Decoration::Quote => draw_rectangle(0.0, y, 3.0, height, theme.overlay0),
+                Decoration::Rule => draw_line(0.0, y + height / 2.0, self.window.w - 2.0 * PADDING, y + height / 2.0, 1.0, theme.surface2),
+            }
+
+            for piece in &row.pieces {
+                let color = match (piece.color, piece.style) {
+                    (Some(color), _) => color,
+                    _ if piece.link.is_some() => theme.blue,
+                    _ if row.heading => theme.mauve,
+                    (_, style) if style.code => theme.green,
+                    (_, style) if style.muted => theme.overlay1,
+                    (_, style) if style.emphasis => theme.rosewater,
+                    _ => theme.text,
+                };
+                let left = x + piece.cell as f32 * cell_width;
+                let width = piece.text.chars().count() as f32 * cell_width;
+                if piece.style.code && row.decoration != Decoration::Code {
+                    draw_rectangle(left, y + 2.0, width, height - 4.0, theme.surface0);
+                }
+
+                let params = TextParams { color, font, font_size: row.size, ..Default::default() };
+                draw_text_ex(&piece.text, left, baseline, params.clone());
+                // There's only the one weight of the font, so strong text is drawn twice, a little apart
+                if piece.style.strong {
+                    draw_text_ex(&piece.text, left + 0.6, baseline, params);
+                }
+                if piece.link.is_some() {
+                    draw_line(left, baseline + 2.0, left + width, baseline + 2.0, 1.0, color);
+                }
+            }
+
+            y += height;
+        }
+    }
+}
+
+fn heading_size(level: usize) -> u16 {
+    match level {
+        1 => 28,
+        2 => 24,
+        3 => 20,
+        _ => 18,
+    }
+}
+
+/// Rows of wrapped text, spreading the block's source lines over them for scroll sync
+fn text_rows(block: &Block, spans: &[Span], indent: usize, width: usize, decoration: Decoration) -> Vec<Row> {
+    let wrapped = wrap(spans, width);
+    let count = wrapped.len();
+    wrapped.into_iter()
+        .enumerate()
+        .map(|(k, pieces)| Row { pieces, ..Row::new(block.line + k * block.lines / count, FONT_SIZE, indent, decoration) })
+        .collect()
+}
+
+/// Breaks spans into rows of at most `width` cells at spaces, except for words longer than that
+fn wrap(spans: &[Span], width: usize) -> Vec<Vec<Piece>> {
+    let mut rows: Vec<Vec<Piece>> = vec![];
+    let mut row: Vec<Piece> = vec![];
+    let mut cell = 0;
+
+    for span in spans {
+        for word in span.text.split_inclusive(' ') {
+            if cell > 0 && cell + word.trim_end().chars().count() > width {
+                rows.push(std::mem::take(&mut row));
+                cell = 0;
+            }
+            if cell == 0 && word.trim().is_empty() {
+                continue;
+            }
+
+            match row.last_mut() {
+                Some(last) if last.style == span.style && last.link == span.link && last.cell + last.text.chars().count() == cell => {
+                    last.text.push_str(word)
+                }
+                _ => row.push(Piece { cell, text: word.to_string(), style: span.style, color: None, link: span.link.clone() }),
+            }
+            cell += word.chars().count();
+        }
+    }
+
+    if !row.is_empty() {
+        rows.push(row);
+    }
+    rows
+}
+
+/// A table drawn with box-drawing characters, each column as wide as its widest cell
+fn table_rows(line: usize, alignments: &[Alignment], table: &[Vec<Vec<Span>>]) -> Vec<Row> {
+    let columns = alignments.len();
+    let text_width = |cell: &Vec<Span>| cell.iter().map(|span| span.text.chars().count()).sum::<usize>();
+    let widths = (0..columns)
+        .map(|c| table.iter().filter_map(|row| row.get(c)).map(text_width).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let border = Style { muted: true, ..Style::default() };
+    let rule = |line: usize, left: &str, middle: &str, right: &str| {
+        let inner = widths.iter().map(|width| "─".repeat(width + 2)).collect::<Vec<_>>().join(middle);
+        let mut row = Row::new(line, FONT_SIZE, 0, Decoration::None);
+        row.pieces.push(Piece { cell: 0, text: format!("{left}{inner}{right}"), style: border, color: None, link: None });
+        row
+    };
+
+    let mut rows = vec![rule(line, "┌", "┬", "┐")];
+    for (r, cells) in table.iter().enumerate() {
+        // The separator under the header is a source line of its own
+        let source = if r == 0 { line } else { line + r + 1 };
+        let mut row = Row::new(source, FONT_SIZE, 0, Decoration::None);
+        let mut cell = 0;
+        for (c, width) in widths.iter().enumerate() {
+            row.pieces.push(Piece { cell, text: "│".to_string(), style: border, color: None, link: None });
+            let spans = cells.get(c).map(Vec::as_slice).unwrap_or_default();
+            let free = width - spans.iter().map(|span| span.text.chars().count()).sum::<usize>();
+            let mut x = cell + 2 + match alignments[c] {
+                Alignment::Left => 0,
+                Alignment::Center => free / 2,
+                Alignment::Right => free,
+            };
+            for span in spans {
+                let style = Style { strong: span.style.strong || r == 0, ..span.style };
+                row.pieces.push(Piece { cell: x, text: span.text.clone(), style, color: None, link: span.link.clone() });
+                x += span.text.chars().count();
+            }
+            cell += width + 3;
+        }
+        row.pieces.push(Piece { cell, text: "│".to_string(), style: border, color: None, link: None });
+        rows.push(row);
+
+        if r == 0 {
+            rows.push(rule(line + 1, "├", "┼", "┤"));
+        }
+    }
+    rows.push(rule(line + table.len(), "└", "┴", "┘"));
+
+    rows
+}
+
+/// Whether a file is markdown, going by its extension
+pub(crate) fn is_markdown(filename: &str) -> bool {
+    matches!(Path::new(filename).extension().and_then(|extension| extension.to_str()), Some("md" | "markdown"))
+}
+
+/// The marker and length of a code fence, and its info string
+fn fence(line: &str) -> Option<(char, usize, &str)> {
+    let marker = line.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let length = line.chars().take_while(|&c| c == marker).count();
+    (length >= 3).then(|| (marker, length, line[length..].trim()))
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let rest = &line[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+
+    // Closing #s only count with a space before them, so `# C#` keeps its #
+    let title = rest.trim();
+    let closed = title.trim_end_matches('#');
+    Some((level, if closed.is_empty() || closed.ends_with(' ') { closed.trim_end() } else { title }))
+}
+
+fn is_rule(line: &str) -> bool {
+    let marks = line.chars().filter(|c| !c.is_whitespace()).collect::<Vec<_>>();
+    marks.len() >= 3 && ['-', '*', '_'].iter().any(|&mark| marks.iter().all(|&c| c == mark))
+}
+
+/// A list item's marker as it's shown, and the text after it
+fn list_item(line: &str) -> Option<(String, &str)> {
+    if let Some(rest) = line.strip_prefix(['-', '*', '+']) {
+        return (rest.is_empty() || rest.starts_with(' ')).then(|| ("•".to_string(), rest.trim_start()));
+    }
+
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = line[digits..].strip_prefix(['.', ')'])?;
+    (digits > 0 && digits < 10 && (rest.is_empty() || rest.starts_with(' ')))
+        .then(|| (format!("{}.", &line[..digits]), rest.trim_start()))
+}
+
+fn table_cells(line: &str) -> Vec<&str> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|').map(str::trim).collect()
+}
+
+/// The alignment of each column, if `line` is the row under a table's header
+fn table_separator(line: &str) -> Option<Vec<Alignment>> {
+    if !line.contains('-') || !line.contains('|') {
+        return None;
+    }
+
+    table_cells(line).into_iter()
+        .map(|cell| {
+            let dashes = cell.trim_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+                (true, true) => Alignment::Center,
+                (false, true) => Alignment::Right,
+                _ => Alignment::Left,
+            })
+        })
+        .collect()
+}
+
+/// Whether a line starts a block of its own rather than carrying on a paragraph
+fn starts_block(line: &str) -> bool {
+    line.is_empty() || fence(line).is_some() || heading(line).is_some() || is_rule(line)
+        || line.starts_with('>') || list_item(line).is_some()
+}
+
+fn parse(text: &str, highlighter: &mut Highlighter, theme: &Theme) -> Vec<Block> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let mut blocks = vec![];
+    let mut i = 0;
+
+    while i < lines.len() {
+        let start = i;
+        let line = lines[i].trim_start();
+        let depth = (lines[i].len() - line.len()) / 2;
+
+        let kind = if line.is_empty() {
+            i += 1;
+            continue;
+        } else if let Some((marker, length, info)) = fence(line) {
+            i += 1;
+            let mut code = vec![];
+            while i < lines.len() && !lines[i].trim_start().starts_with(&marker.to_string().repeat(length)) {
+                code.push(lines[i]);
+                i += 1;
+            }
+            i += 1;
+            BlockKind::Code(highlight_code(info, &code.join("\n"), highlighter, theme))
+        } else if let Some((level, title)) = heading(line) {
+            i += 1;
+            BlockKind::Heading(level, inline(title))
+        } else if is_rule(line) {
+            i += 1;
+            BlockKind::Rule
+        } else if line.starts_with('>') {
+            let mut quote = vec![];
+            while let Some(line) = lines.get(i).and_then(|line| line.trim_start().strip_prefix('>')) {
+                quote.push(line.trim());
+                i += 1;
+            }
+            BlockKind::Quote(inline(&quote.join(" ")))
+        } else if let Some((marker, rest)) = list_item(line) {
+            let mut item = vec![rest];
+            i += 1;
+            while let Some(line) = lines.get(i).map(|line| line.trim_start()).filter(|line| !starts_block(line)) {
+                item.push(line);
+                i += 1;
+            }
+            BlockKind::Item(depth, marker, inline(&item.join(" ")))
+        } else if let Some(alignments) = line.contains('|').then(|| lines.get(i + 1).and_then(|next| table_separator(next))).flatten() {
+            let header = table_cells(line);
+            let alignments = alignments.into_iter().chain(std::iter::repeat(Alignment::Left)).take(header.len()).collect();
+            let mut table = vec![header.into_iter().map(inline).collect::<Vec<_>>()];
+            i += 2;
+            while let Some(line) = lines.get(i).filter(|line| line.contains('|')) {
+                table.push(table_cells(line).into_iter().map(inline).collect());
+                i += 1;
+            }
+            BlockKind::Table(alignments, table)
+        } else {
+            let mut paragraph = vec![line];
+            i += 1;
+            while let Some(line) = lines.get(i).map(|line| line.trim_start()).filter(|line| !starts_block(line)) {
+                paragraph.push(line);
+                i += 1;
+            }
+            BlockKind::Paragraph(inline(&paragraph.join(" ")))
+        };
+
+        blocks.push(Block { line: start, lines: i.min(lines.len()) - start, kind });
+    }
+
+    blocks
+}
+
+/// The lines of a code block, split into pieces by color when its info string names a language
+fn highlight_code(info: &str, code: &str, highlighter: &mut Highlighter, theme: &Theme) -> Vec<Vec<(String, Color)>> {
+    let code = code.replace('\t', "    ");
+    let spans = info.split_whitespace().next()
+        .and_then(Language::from_token)
+        .and_then(|language| editor::highlight(highlighter, language, &code, theme).ok())
+        .unwrap_or_else(|| vec![(0..code.len(), theme.text)]);
+
+    let mut lines = vec![vec![]];
+    for (range, color) in spans {
+        for (k, piece) in code[range].split('\n').enumerate() {
+            if k > 0 {
+                lines.push(vec![]);
+            }
+            if !piece.is_empty() {
+                lines.last_mut().unwrap().push((piece.to_string(), color));
+            }
+        }
+    }
+
+    lines
+}
+
+/// Spans of inline text: `**strong**`, `*emphasis*`, `` `code` ``, `[links](target)`, `<autolinks>` and images,
+/// which are shown as links to them
+fn inline(text: &str) -> Vec<Span> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut spans = vec![];
+    let mut current = String::new();
+    let mut style = Style::default();
+    let mut i = 0;
+
+    let flush = |current: &mut String, spans: &mut Vec<Span>, style: Style| {
+        if !current.is_empty() {
+            spans.push(Span { text: std::mem::take(current), style, link: None });
+        }
+    };
+    let find = |from: usize, target: char| chars[from..].iter().position(|&c| c == target).map(|k| from + k);
+
+    while i < chars.len() {
+        let c = chars[i];
+        let previous = i.checked_sub(1).map(|k| chars[k]);
+
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(char::is_ascii_punctuation) => {
+                current.push(chars[i + 1]);
+                i += 2;
+            }
+            '`' => {
+                let ticks = chars[i..].iter().take_while(|&&c| c == '`').count();
+                let close = (i + ticks..chars.len()).find(|&k| {
+                    chars[k..].iter().take_while(|&&c| c == '`').count() == ticks && chars[k - 1] != '`'
+                });
+                match close {
+                    Some(close) => {
+                        flush(&mut current, &mut spans, style);
+                        let code = chars[i + ticks..close].iter().collect::<String>();
+                        let code = if code.starts_with(' ') && code.ends_with(' ') && code.trim() != "" { code[1..code.len() - 1].to_string() } else { code };
+                        spans.push(Span { text: code, style: Style { code: true, ..style }, link: None });
+                        i = close + ticks;
+                    }
+                    None => {
+                        current.extend(&chars[i..i + ticks]);
+                        i += ticks;
+                    }
+                }
+            }
+            '*' | '_' => {
+                let run = chars[i..].iter().take_while(|&&d| d == c).count();
+                let next = chars.get(i + run).copied();
+                let opening = next.is_some_and(|next| !next.is_whitespace());
+                let closing = previous.is_some_and(|previous| !previous.is_whitespace());
+                // Underscores inside words, as in snake_case, are left alone
+                let intraword = c == '_' && previous.is_some_and(char::is_alphanumeric) && next.is_some_and(char::is_alphanumeric);
+
+                let (strong, emphasis) = match run {
+                    1 => (false, true),
+                    2 => (true, false),
+                    _ => (true, true),
+                };
+                let open = strong && style.strong || emphasis && style.emphasis;
+                if intraword || run > 3 || !(if open { closing } else { opening }) {
+                    current.extend(&chars[i..i + run]);
+                } else {
+                    flush(&mut current, &mut spans, style);
+                    style.strong ^= strong;
+                    style.emphasis ^= emphasis;
+                }
+                i += run;
+            }
+            '!' | '[' => {
+                let image = c == '!';
+                let open = if image { i + 1 } else { i };
+                let link = (chars.get(open) == Some(&'['))
+                    .then(|| find(open, ']'))
+                    .flatten()
+                    .filter(|&close| chars.get(close + 1) == Some(&'('))
+                    .and_then(|close| Some((close, find(close + 1, ')')?)));
+
+                match link {
+                    Some((close, end)) => {
+                        flush(&mut current, &mut spans, style);
+                        let label = chars[open + 1..close].iter().collect::<String>();
+                        let target = chars[close + 2..end].iter().collect::<String>();
+                        let target = target.split_whitespace().next().unwrap_or_default().to_string();
+                        let label = if image { vec![Span { text: format!("[image: {label}]"), style, link: None }] } else { inline(&label) };
+                        for span in label {
+                            let style = Style { strong: span.style.strong || style.strong, emphasis: span.style.emphasis || style.emphasis, ..span.style };
+                            spans.push(Span { style, link: Some(target.clone()), ..span });
+                        }
+                        i = end + 1;
+                    }
+                    None => {
+                        current.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            '<' => {
+                let end = find(i, '>');
+                let target = end.map(|end| chars[i + 1..end].iter().collect::<String>());
+                match target.filter(|target| target.contains("://") && !target.contains(' ')) {
+                    Some(target) => {
+                        flush(&mut current, &mut spans, style);
+                        spans.push(Span { text: target.clone(), style, link: Some(target) });
+                        i = end.unwrap() + 1;
+                    }
+                    None => {
+                        current.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            _ => {
+                current.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    flush(&mut current, &mut spans, style);
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(text: &str) -> Vec<Block> {
+        parse(text, &mut Highlighter::new(), &Theme::mocha())
+    }
+
+    /// Each span as its text, led by `b`, `i`, `c` and `m` for its styles and followed by where it links
+    fn spans(spans: &[Span]) -> Vec<String> {
+        spans.iter()
+            .map(|span| {
+                let Style { strong, emphasis, code, muted } = span.style;
+                let marks = [(strong, 'b'), (emphasis, 'i'), (code, 'c'), (muted, 'm')].into_iter()
+                    .filter_map(|(on, mark)| on.then_some(mark))
+                    .collect::<String>();
+                let text = if marks.is_empty() { span.text.clone() } else { format!("{marks}:{}", span.text) };
+                match &span.link {
+                    Some(link) => format!("{text} @{link}"),
+                    None => text,
+                }
+            })
+            .collect()
+    }
+
+    fn code_lines(code: &[Vec<(String, Color)>]) -> Vec<String> {
+        code.iter().map(|line| line.iter().map(|(piece, _)| piece.as_str()).collect()).collect()
+    }
+
+    #[test]
+    fn headings_drop_closing_hashes_only_after_a_space() {
+        let headings = blocks("# Title ##\n###### six #\n# C#\n## \n#######  seven\n#tag")
+            .into_iter()
+            .map(|block| match block.kind {
+                BlockKind::Heading(level, title) => (level, spans(&title).concat()),
+                BlockKind::Paragraph(text) => (0, spans(&text).concat()),
+                kind => panic!("unexpected {kind:?}"),
+            })
+            .collect::<Vec<_>>();
+
+        let expected = [(1, "Title"), (6, "six"), (1, "C#"), (2, ""), (0, "#######  seven #tag")];
+        assert_eq!(headings, expected.map(|(level, title)| (level, title.to_string())));
+    }
+
+    #[test]
+    fn emphasis_nests_and_leaves_words_and_lone_marks_alone() {
+        let cases: &[(&str, &[&str])] = &[
+            ("*a* **b** ***c***", &["i:a", " ", "b:b", " ", "bi:c"]),
+            ("**bold *both* bold**", &["b:bold ", "bi:both", "b: bold"]),
+            ("_under_ and snake_case_name", &["i:under", " and snake_case_name"]),
+            ("2 * 3 * 4 and ****", &["2 * 3 * 4 and ****"]),
+            ("\\*not\\*", &["*not*"]),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(spans(&inline(text)), *expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn code_spans_close_on_a_matching_run_of_backticks() {
+        let cases: &[(&str, &[&str])] = &[
+            ("a `b` c", &["a ", "c:b", " c"]),
+            ("``a ` b``", &["c:a ` b"]),
+            ("`` ` ``", &["c:`"]),
+            ("`*not emphasis*`", &["c:*not emphasis*"]),
+            ("a `b", &["a `b"]),
+            ("``a`", &["``a`"]),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(spans(&inline(text)), *expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn links_images_and_autolinks_point_at_their_targets() {
+        let cases: &[(&str, &[&str])] = &[
+            ("see [the *docs*](docs.md \"Docs\")", &["see ", "the  @docs.md", "i:docs @docs.md"]),
+            ("![logo](img/logo.png)", &["[image: logo] @img/logo.png"]),
+            ("go to <https://example.com>!", &["go to ", "https://example.com @https://example.com", "!"]),
+            ("<not a link> [no target] and [dangling](", &["<not a link> [no target] and [dangling]("]),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(spans(&inline(text)), *expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn list_items_nest_by_indent_and_take_continuation_lines() {
+        let items = blocks("- a\n  - b\n    continued\n1. c\n10) d\n+ e\n-not an item")
+            .into_iter()
+            .map(|block| match block.kind {
+                BlockKind::Item(depth, marker, text) => (block.line, depth, marker, spans(&text).concat()),
+                BlockKind::Paragraph(text) => (block.line, 0, String::new(), spans(&text).concat()),
+                kind => panic!("unexpected {kind:?}"),
+            })
+            .collect::<Vec<_>>();
+
+        let expected = [(0, 0, "•", "a"), (1, 1, "•", "b continued"), (3, 0, "1.", "c"), (4, 0, "10.", "d"), (5, 0, "•", "e -not an item")];
+        assert_eq!(items, expected.map(|(line, depth, marker, text)| (line, depth, marker.to_string(), text.to_string())));
+    }
+
+    #[test]
+    fn unterminated_fences_run_to_the_end() {
+        let blocks = blocks("````\na\n```\n````\nafter\n~~~rust\nfn main() {}\n\nstill code");
+        let summary = blocks.iter()
+            .map(|block| match &block.kind {
+                BlockKind::Code(code) => (block.line, block.lines, code_lines(code)),
+                BlockKind::Paragraph(text) => (block.line, block.lines, vec![spans(text).concat()]),
+                kind => panic!("unexpected {kind:?}"),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(summary, [
+            (0, 4, vec!["a".to_string(), "```".to_string()]),
+            (4, 1, vec!["after".to_string()]),
+            (5, 4, vec!["fn main() {}".to_string(), String::new(), "still code".to_string()]),
+        ]);
+    }
+
+    #[test]
+    fn tables_align_columns_by_their_separator_row() {
+        let blocks = blocks("| a | b | c | d |\n|---|:--|:-:|--:|\n| 1 | **2** | 3 | 4 |\n\nx | y\n--- |\n1 | 2\n\nno | table");
+        let BlockKind::Table(alignments, table) = &blocks[0].kind else { panic!("{:?}", blocks[0]) };
+        assert_eq!(alignments, &[Alignment::Left, Alignment::Left, Alignment::Center, Alignment::Right]);
+        assert_eq!(table.iter().map(|row| row.iter().map(|cell| spans(cell).concat()).collect::<Vec<_>>()).collect::<Vec<_>>(), [
+            ["a", "b", "c", "d"],
+            ["1", "b:2", "3", "4"],
+        ]);
+
+        // Columns missing from the separator row are left aligned
+        let BlockKind::Table(alignments, table) = &blocks[1].kind else { panic!("{:?}", blocks[1]) };
+        assert_eq!((alignments.as_slice(), table.len()), (&[Alignment::Left, Alignment::Left][..], 2));
+        assert!(matches!(&blocks[2].kind, BlockKind::Paragraph(text) if spans(text).concat() == "no | table"));
+    }
+
+    #[test]
+    fn blocks_know_the_source_lines_they_came_from() {
+        let text = "# H\n\npara\ngraph\n\n> q\n> r\n---\n| a |\n|---|\n| 1 |\n\n```\nx\n```\n- item";
+        let outline = blocks(text).iter()
+            .map(|block| {
+                let kind = match block.kind {
+                    BlockKind::Heading(..) => "heading",
+                    BlockKind::Paragraph(_) => "paragraph",
+                    BlockKind::Quote(_) => "quote",
+                    BlockKind::Item(..) => "item",
+                    BlockKind::Code(_) => "code",
+                    BlockKind::Table(..) => "table",
+                    BlockKind::Rule => "rule",
+                };
+                (block.line, block.lines, kind)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(outline, [
+            (0, 1, "heading"), (2, 2, "paragraph"), (5, 2, "quote"), (7, 1, "rule"),
+            (8, 3, "table"), (12, 3, "code"), (15, 1, "item"),
+        ]);
+    }
+}
@@ -0,0 +1,87 @@
+use std::cell::Cell;
+use macroquad::prelude::*;
+use crate::theme::Theme;
+use crate::window::set_fullscreen_camera;
+
+const WIDTH: f32 = 240.0;
+const FONT_SIZE: u16 = 16;
+const PADDING: f32 = 8.0;
+const LINE_HEIGHT: f32 = FONT_SIZE as f32 + 4.0;
+
+/// A sticky note on the canvas, not attached to any pane
+#[derive(Debug, Clone)]
+pub(crate) struct Note {
+    pub(crate) position: Vec2,
+    text: String,
+    /// How tall the text was when last drawn, which depends on the font.
+    /// Until then it's a line for each paragraph.
+    height: Cell<f32>,
+}
+
+impl Note {
+    pub(crate) fn new(position: Vec2, text: String) -> Self {
+        let mut note = Self { position, text: String::new(), height: Cell::new(0.0) };
+        note.set_text(text);
+        note
+    }
+
+    pub(crate) fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub(crate) fn set_text(&mut self, text: String) {
+        self.height.set(text.split('\n').count() as f32 * LINE_HEIGHT + 2.0 * PADDING);
+        self.text = text;
+    }
+
+    /// The text broken into lines that fit the note
+    fn lines(&self, font: Option<&Font>) -> Vec<String> {
+        let mut lines = vec![];
+
+        for paragraph in self.text.split('\n') {
+            let mut line = String::new();
+            for word in paragraph.split_whitespace() {
+                let candidate = match line.is_empty() {
+                    true => word.to_owned(),
+                    false => format!("{line} {word}"),
+                };
+
+                if !line.is_empty() && measure_text(&candidate, font, FONT_SIZE, 1.0).width > WIDTH - 2.0 * PADDING {
+                    lines.push(std::mem::replace(&mut line, word.to_owned()));
+                } else {
+                    line = candidate;
+                }
+            }
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    /// The area the note covers, which grows downwards to fit its text
+    pub(crate) fn window(&self) -> Rect {
+        Rect::new(self.position.x, self.position.y, WIDTH, self.height.get())
+    }
+
+    pub(crate) fn view(&self, theme: &Theme, font: Option<&Font>, focused: bool) {
+        set_fullscreen_camera();
+
+        let lines = self.lines(font);
+        self.height.set(lines.len() as f32 * LINE_HEIGHT + 2.0 * PADDING);
+        let rect = self.window();
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, theme.yellow);
+        if focused {
+            draw_rectangle_lines(rect.x, rect.y, rect.w, rect.h, 2.0, theme.lavender);
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            let y = rect.y + PADDING + (i + 1) as f32 * LINE_HEIGHT - 4.0;
+            draw_text_ex(line, rect.x + PADDING, y, TextParams {
+                color: theme.crust,
+                font,
+                font_size: FONT_SIZE,
+                ..Default::default()
+            });
+        }
+    }
+}
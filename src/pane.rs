@@ -0,0 +1,234 @@
+use macroquad::prelude::*;
+use crate::editor::{Editor, EditorMessage};
+use crate::image_view::{ImageMessage, ImageView};
+use crate::keymap::Action;
+use crate::markdown::{Preview, PreviewMessage};
+use crate::notes::Note;
+use crate::run::{Run, RunMessage};
+use crate::terminal::{Terminal, TerminalMessage};
+use crate::theme::Theme;
+use crate::tree::{FileTree, TreeMessage};
+use crate::workspace::{PaneKind, PaneState};
+
+/// Anything on the canvas that sits in a window under a titlebar
+// Panes stay put in the app's map, so the size of the largest kind doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Pane {
+    Editor(Editor),
+    Terminal(Terminal),
+    Run(Run),
+    Files(FileTree),
+    Preview(Preview),
+    Image(ImageView),
+    Note(Note),
+}
+
+/// A message for one kind of pane
+#[derive(Debug)]
+pub enum PaneMessage {
+    Editor(EditorMessage),
+    Terminal(TerminalMessage),
+    Run(RunMessage),
+    Files(TreeMessage),
+    Preview(PreviewMessage),
+    Image(ImageMessage),
+}
+
+impl Pane {
+    pub(crate) fn window(&self) -> Rect {
+        match self {
+            Pane::Editor(editor) => editor.window,
+            Pane::Terminal(terminal) => terminal.window,
+            Pane::Run(run) => run.window,
+            Pane::Files(tree) => tree.window,
+            Pane::Preview(preview) => preview.window,
+            Pane::Image(image) => image.window,
+            Pane::Note(note) => note.window(),
+        }
+    }
+
+    pub(crate) fn move_by(&mut self, delta: Vec2) {
+        let window = match self {
+            Pane::Note(note) => {
+                note.position += delta;
+                return;
+            }
+            Pane::Editor(editor) => &mut editor.window,
+            Pane::Terminal(terminal) => &mut terminal.window,
+            Pane::Run(run) => &mut run.window,
+            Pane::Files(tree) => &mut tree.window,
+            Pane::Preview(preview) => &mut preview.window,
+            Pane::Image(image) => &mut image.window,
+        };
+        *window = window.offset(delta);
+    }
+
+    pub(crate) fn titlebar(&self) -> Rect {
+        match self {
+            Pane::Editor(editor) => editor.titlebar(),
+            Pane::Terminal(terminal) => terminal.titlebar(),
+            Pane::Run(run) => run.titlebar(),
+            Pane::Files(tree) => tree.titlebar(),
+            Pane::Preview(preview) => preview.titlebar(),
+            Pane::Image(image) => image.titlebar(),
+            Pane::Note(note) => {
+                let window = note.window();
+                Rect::new(window.x, window.y, window.w, 0.0)
+            }
+        }
+    }
+
+    /// What the pane is dragged by: its titlebar, or all of a note, which has none
+    pub(crate) fn grip(&self) -> Rect {
+        match self {
+            Pane::Note(note) => note.window(),
+            _ => self.titlebar(),
+        }
+    }
+
+    /// Whether a point on screen is over the pane or its titlebar
+    pub(crate) fn hit(&self, point: Vec2) -> bool {
+        self.window().contains(point) || self.titlebar().contains(point)
+    }
+
+    pub(crate) fn view(&self, theme: &Theme, font: Option<&Font>, focused: bool) {
+        match self {
+            Pane::Editor(editor) => editor.view(theme, font, focused),
+            Pane::Terminal(terminal) => terminal.view(theme, font, focused),
+            Pane::Run(run) => run.view(theme, font, focused),
+            Pane::Files(tree) => tree.view(theme, font, focused),
+            Pane::Preview(preview) => preview.view(theme, font, focused),
+            Pane::Image(image) => image.view(theme, font, focused),
+            Pane::Note(note) => note.view(theme, font, focused),
+        }
+    }
+
+    /// Whatever has happened outside the app since the last frame, such as a process writing output
+    pub(crate) fn poll(&self) -> Vec<PaneMessage> {
+        match self {
+            Pane::Editor(_) | Pane::Preview(_) | Pane::Image(_) | Pane::Note(_) => vec![],
+            Pane::Terminal(terminal) => terminal.poll().into_iter().map(PaneMessage::Terminal).collect(),
+            Pane::Run(run) => run.poll().into_iter().map(PaneMessage::Run).collect(),
+            Pane::Files(tree) => tree.poll().into_iter().map(PaneMessage::Files).collect(),
+        }
+    }
+
+    /// What a click at a point on screen tells the pane, if anything. Clicks on the titlebar only focus it.
+    pub(crate) fn click(&self, position: Vec2) -> Option<PaneMessage> {
+        if !self.window().contains(position) {
+            return None;
+        }
+
+        match self {
+            Pane::Editor(_) => Some(PaneMessage::Editor(EditorMessage::Click(position))),
+            Pane::Terminal(_) | Pane::Image(_) | Pane::Note(_) => None,
+            Pane::Run(_) => Some(PaneMessage::Run(RunMessage::Click(position))),
+            Pane::Files(_) => Some(PaneMessage::Files(TreeMessage::Click(position))),
+            Pane::Preview(_) => Some(PaneMessage::Preview(PreviewMessage::Click(position))),
+        }
+    }
+
+    /// What typing a char tells the pane, if anything
+    pub(crate) fn char(&self, character: char) -> Option<PaneMessage> {
+        match self {
+            Pane::Editor(_) => Some(PaneMessage::Editor(EditorMessage::Char(character))),
+            Pane::Terminal(_) => Some(PaneMessage::Terminal(TerminalMessage::Char(character))),
+            Pane::Run(_) | Pane::Preview(_) | Pane::Note(_) => None,
+            Pane::Files(_) => Some(PaneMessage::Files(TreeMessage::Char(character))),
+            Pane::Image(_) => Some(PaneMessage::Image(ImageMessage::Char(character))),
+        }
+    }
+
+    pub(crate) fn scroll(&mut self, offset: Vec2) {
+        match self {
+            Pane::Editor(editor) => editor.scroll(offset),
+            Pane::Terminal(terminal) => terminal.update(TerminalMessage::Scroll(offset)),
+            Pane::Run(run) => run.update(RunMessage::Scroll(offset)),
+            Pane::Files(tree) => {
+                tree.update(TreeMessage::Scroll(offset));
+            }
+            Pane::Preview(preview) => preview.update(PreviewMessage::Scroll(offset)),
+            Pane::Image(image) => image.update(ImageMessage::Scroll(offset)),
+            Pane::Note(_) => {}
+        }
+    }
+
+    /// What a key binding's action tells the pane, if anything, when it isn't one the app handles itself
+    pub(crate) fn action(&self, action: Action) -> Option<PaneMessage> {
+        match self {
+            Pane::Editor(_) => Some(PaneMessage::Editor(EditorMessage::Action(action))),
+            Pane::Files(_) => Some(PaneMessage::Files(TreeMessage::Action(action))),
+            Pane::Image(_) => Some(PaneMessage::Image(ImageMessage::Action(action))),
+            Pane::Terminal(_) | Pane::Run(_) | Pane::Preview(_) | Pane::Note(_) => None,
+        }
+    }
+
+    /// How the pane is kept in a workspace file, if it can be. Editors the app generated aren't kept,
+    /// commands aren't run again when a workspace is opened, previews go with the editors they're of,
+    /// and notes are kept on their own.
+    pub(crate) fn state(&self) -> Option<PaneState> {
+        let Rect { x, y, w, h } = self.window();
+        let (kind, file) = match self {
+            Pane::Editor(editor) if !editor.generated() => (PaneKind::Editor, editor.filename().to_owned()),
+            Pane::Terminal(_) => (PaneKind::Terminal, String::new()),
+            Pane::Image(image) => (PaneKind::Image, image.filename().to_owned()),
+            Pane::Files(_) => (PaneKind::Files, String::new()),
+            Pane::Editor(_) | Pane::Run(_) | Pane::Preview(_) | Pane::Note(_) => return None,
+        };
+
+        Some(PaneState { kind, file, window: [x, y, w, h] })
+    }
+
+    /// The file the pane shows, if it's an editor or an image
+    pub(crate) fn filename(&self) -> Option<&str> {
+        match self {
+            Pane::Editor(editor) => Some(editor.filename()),
+            Pane::Image(image) => Some(image.filename()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn editor(&self) -> Option<&Editor> {
+        match self {
+            Pane::Editor(editor) => Some(editor),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn editor_mut(&mut self) -> Option<&mut Editor> {
+        match self {
+            Pane::Editor(editor) => Some(editor),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workspaces_keep_editors_unless_the_app_generated_them() {
+        let window = Rect::new(1.0, 2.0, 300.0, 400.0);
+        let missing = Pane::Editor(Editor::new(window, 16, "no/such/file.rs".to_string()));
+        let state = PaneState { kind: PaneKind::Editor, file: "no/such/file.rs".to_string(), window: [1.0, 2.0, 300.0, 400.0] };
+        assert_eq!(missing.state(), Some(state));
+
+        let mut generated = Editor::new(window, 16, "usage.md".to_string());
+        generated.set_generated();
+        assert_eq!(Pane::Editor(generated).state(), None);
+    }
+
+    #[test]
+    fn notes_are_dragged_by_any_part_of_them() {
+        let mut note = Pane::Note(Note::new(vec2(10.0, 20.0), "one\ntwo".to_string()));
+        assert_eq!(note.state(), None);
+        assert_eq!(note.titlebar().h, 0.0);
+        assert_eq!(note.grip(), note.window());
+        assert!(note.hit(vec2(15.0, 25.0)));
+
+        note.move_by(vec2(5.0, -5.0));
+        assert_eq!(note.window().point(), vec2(15.0, 15.0));
+        assert!(note.click(vec2(20.0, 20.0)).is_none());
+    }
+}
@@ -1,6 +1,8 @@
 use macroquad::prelude::*;
 use crate::editor::EditorMessage;
+use crate::editorconfig::{Charset, LineEnding};
 use crate::theme::Theme;
+use crate::workspace;
 use crate::window::set_fullscreen_camera;
 
 pub(crate) struct Prompt {
@@ -13,8 +15,36 @@ pub(crate) enum PromptUpdate {
     CloseActiveFile,
     SaveActiveFile,
     OpenHelp,
+    ToggleWrap,
+    ShowKeys,
+    ShowProblems,
+    SetVim(bool),
+    SetRainbow(bool),
+    FoldLevel(usize),
+    UnfoldAll,
+    SetLineEnding(LineEnding),
+    SetCharset(Charset),
+    SetFinalNewline(bool),
     Status(String),
     SaveAs(String),
+    Rename(String),
+    Connect(Option<String>),
+    Disconnect,
+    Note(String),
+    SaveWorkspace(String),
+    OpenWorkspace(String),
+    OpenTerminal,
+    Run(String),
+    ShowFiles,
+    NewPath(String),
+    MovePath(String),
+    Preview,
+    ToggleHex,
+    EditHex,
+    FindBytes(String),
+    ShowHunk,
+    RevertHunk,
+    StageHunk,
 }
 
 impl Prompt {
@@ -29,12 +59,12 @@ impl Prompt {
         match message {
             EditorMessage::Keypress(key) => {
                 match key {
-                    KeyCode::Left => {
-                        if self.cursor > 0 { self.cursor -= 1; }
+                    KeyCode::Left if self.cursor > 0 => {
+                        self.cursor -= 1;
                         None
                     }
-                    KeyCode::Right => {
-                        if self.cursor < self.text.len() { self.cursor += 1 }
+                    KeyCode::Right if self.cursor < self.text.len() => {
+                        self.cursor += 1;
                         None
                     }
                     _ => None
@@ -62,9 +92,16 @@ impl Prompt {
                     }
                 }
             }
+            _ => None,
         }
     }
 
+    /// Replaces what has been typed, leaving the cursor at the end
+    pub(crate) fn set_text(&mut self, text: &str) {
+        self.text = text.to_owned();
+        self.cursor = self.text.len();
+    }
+
     pub(crate) fn view(&self, theme: &Theme, font: Option<&Font>) {
         set_fullscreen_camera();
         let mut x = 0.0;
@@ -102,7 +139,7 @@ impl Prompt {
 
     fn parse_command(&self) -> Option<PromptUpdate> {
         let tokens: Vec<&str> = self.text.split(" ").collect();
-        if tokens.len() < 1 {
+        if tokens.is_empty() {
             return None;
         }
 
@@ -123,6 +160,83 @@ impl Prompt {
                 }
             }
             "help" => Some(PromptUpdate::OpenHelp),
+            "wrap" => Some(PromptUpdate::ToggleWrap),
+            "hunk" => match tokens.get(1).copied() {
+                Some("show") => Some(PromptUpdate::ShowHunk),
+                Some("revert") => Some(PromptUpdate::RevertHunk),
+                Some("stage") => Some(PromptUpdate::StageHunk),
+                _ => Some(PromptUpdate::Status("Usage: hunk show|revert|stage".to_string())),
+            },
+            "hex" => match tokens.get(1).copied() {
+                None | Some("") => Some(PromptUpdate::ToggleHex),
+                Some("edit") => Some(PromptUpdate::EditHex),
+                Some("find") => Some(PromptUpdate::FindBytes(tokens[2..].join(" "))),
+                _ => Some(PromptUpdate::Status("Usage: hex [edit|find <hex bytes>|\"text\"]".to_string())),
+            },
+            "keys" => Some(PromptUpdate::ShowKeys),
+            "problems" => Some(PromptUpdate::ShowProblems),
+            "terminal" => Some(PromptUpdate::OpenTerminal),
+            "files" => Some(PromptUpdate::ShowFiles),
+            "preview" => Some(PromptUpdate::Preview),
+            "new" => match self.text["new".len()..].trim() {
+                "" => Some(PromptUpdate::Status("Usage: new <path>".to_string())),
+                path => Some(PromptUpdate::NewPath(path.to_string())),
+            },
+            "move" => match self.text["move".len()..].trim() {
+                "" => Some(PromptUpdate::Status("Usage: move <path>".to_string())),
+                path => Some(PromptUpdate::MovePath(path.to_string())),
+            },
+            "run" => match self.text["run".len()..].trim() {
+                "" => Some(PromptUpdate::Status("Usage: run <command>".to_string())),
+                command => Some(PromptUpdate::Run(command.to_string())),
+            },
+            "vim" => match tokens.get(1).copied() {
+                Some("on") => Some(PromptUpdate::SetVim(true)),
+                Some("off") => Some(PromptUpdate::SetVim(false)),
+                _ => Some(PromptUpdate::Status("Usage: vim on|off".to_string())),
+            },
+            "rainbow" => match tokens.get(1).copied() {
+                Some("on") => Some(PromptUpdate::SetRainbow(true)),
+                Some("off") => Some(PromptUpdate::SetRainbow(false)),
+                _ => Some(PromptUpdate::Status("Usage: rainbow on|off".to_string())),
+            },
+            "fold" => match tokens.get(1).and_then(|&level| if level == "all" { Some(1) } else { level.parse().ok() }) {
+                Some(level) if level > 0 => Some(PromptUpdate::FoldLevel(level)),
+                _ => Some(PromptUpdate::Status("Usage: fold all|<level>".to_string())),
+            },
+            "unfold" => Some(PromptUpdate::UnfoldAll),
+            "eol" => match tokens.get(1).and_then(|name| LineEnding::parse(name)) {
+                Some(line_ending) => Some(PromptUpdate::SetLineEnding(line_ending)),
+                None => Some(PromptUpdate::Status("Usage: eol lf|crlf|cr".to_string())),
+            },
+            "encoding" => match tokens.get(1).and_then(|name| Charset::parse(name)) {
+                Some(charset) => Some(PromptUpdate::SetCharset(charset)),
+                None => Some(PromptUpdate::Status("Usage: encoding utf-8|utf-8-bom|utf-16le|utf-16be|latin1".to_string())),
+            },
+            "final-newline" => match tokens.get(1).copied() {
+                Some("on") => Some(PromptUpdate::SetFinalNewline(true)),
+                Some("off") => Some(PromptUpdate::SetFinalNewline(false)),
+                _ => Some(PromptUpdate::Status("Usage: final-newline on|off".to_string())),
+            },
+            "rename" => match tokens[1..].join(" ").trim() {
+                "" => Some(PromptUpdate::Status("Usage: rename <name>".to_string())),
+                name => Some(PromptUpdate::Rename(name.to_string())),
+            },
+            "connect" => match tokens[1..].join(" ").trim() {
+                "" => Some(PromptUpdate::Connect(None)),
+                label => Some(PromptUpdate::Connect(Some(label.to_string()))),
+            },
+            "disconnect" => Some(PromptUpdate::Disconnect),
+            // The prompt is a single line, so `\n` stands for a line break in the note
+            "note" => Some(PromptUpdate::Note(tokens[1..].join(" ").trim().replace("\\n", "\n"))),
+            "workspace" => {
+                let path = tokens.get(2).map_or(workspace::DEFAULT_PATH.to_string(), |_| tokens[2..].join(" "));
+                match tokens.get(1).copied() {
+                    Some("save") => Some(PromptUpdate::SaveWorkspace(path)),
+                    Some("open") => Some(PromptUpdate::OpenWorkspace(path)),
+                    _ => Some(PromptUpdate::Status("Usage: workspace save|open [path]".to_string())),
+                }
+            }
             _ => Some(PromptUpdate::Status("Invalid command".to_string()))
         }
     }
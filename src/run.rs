@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use macroquad::prelude::*;
+use crate::layout::TextLayout;
+use crate::theme::Theme;
+use crate::window::{set_camera_window, set_fullscreen_camera};
+
+const FONT_SIZE: u16 = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug)]
+pub enum RunMessage {
+    Line(Stream, String),
+    /// One of the streams has been closed, usually by the process exiting
+    Ended(Stream),
+    /// Both streams are closed, so the process's exit status can be collected once it has exited
+    Reap,
+    Click(Vec2),
+    Scroll(Vec2),
+}
+
+/// A reference to a position in a file, found in the output
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FileLink {
+    /// Byte range of the reference in its line
+    start: usize,
+    end: usize,
+    pub(crate) path: String,
+    /// One-based, as compilers print them
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+struct OutputLine {
+    text: String,
+    stream: Stream,
+    links: Vec<FileLink>,
+}
+
+/// A read-only pane showing the output of a command as it runs
+pub(crate) struct Run {
+    pub(crate) window: Rect,
+    layout: TextLayout,
+    command: String,
+    child: Child,
+    output: Receiver<(Stream, Option<String>)>,
+    lines: Vec<OutputLine>,
+    /// Streams that haven't been closed yet
+    open: usize,
+    /// How the process exited, once it has
+    status: Option<ExitStatus>,
+    /// How far up from the end the output is shown, in pixels
+    scroll: f32,
+    /// Whether each path the output has referred to is a file, so each is only looked up once
+    files: HashMap<String, bool>,
+}
+
+impl Run {
+    /// Starts `command` in the shell, in the working directory
+    pub(crate) fn spawn(command: &str, window: Rect) -> io::Result<Self> {
+        let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+        let mut child = Command::new(shell)
+            .arg(flag)
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (sender, output) = mpsc::channel();
+        read_lines(child.stdout.take().unwrap(), Stream::Stdout, sender.clone());
+        read_lines(child.stderr.take().unwrap(), Stream::Stderr, sender);
+
+        Ok(Self {
+            window,
+            layout: TextLayout::new(FONT_SIZE),
+            command: command.to_string(),
+            child,
+            output,
+            lines: vec![],
+            open: 2,
+            status: None,
+            scroll: 0.0,
+            files: HashMap::new(),
+        })
+    }
+
+    pub(crate) fn titlebar(&self) -> Rect {
+        let titlebar_height = self.layout.font_size() as f32 + 8.0;
+        Rect::new(self.window.x, self.window.y - titlebar_height, self.window.w, titlebar_height)
+    }
+
+    /// Output since the last frame, and whether to check if the process has exited
+    pub(crate) fn poll(&self) -> Vec<RunMessage> {
+        let mut messages = self.output.try_iter()
+            .map(|(stream, line)| match line {
+                Some(text) => RunMessage::Line(stream, text),
+                None => RunMessage::Ended(stream),
+            })
+            .collect::<Vec<_>>();
+
+        if self.open == 0 && self.status.is_none() {
+            messages.push(RunMessage::Reap);
+        }
+        messages
+    }
+
+    pub(crate) fn update(&mut self, message: RunMessage) {
+        match message {
+            RunMessage::Line(stream, text) => {
+                let files = &mut self.files;
+                let links = file_links(&text, |path| *files.entry(path.to_string()).or_insert_with(|| Path::new(path).is_file()));
+                self.lines.push(OutputLine { text, stream, links });
+                // Keep the view still while scrolled up
+                if self.scroll > 0.0 {
+                    self.scroll += self.layout.line_height();
+                }
+            }
+            RunMessage::Ended(_) => self.open -= 1,
+            // The streams can close before the process exits, so it's checked without waiting
+            RunMessage::Reap => self.status = self.child.try_wait().ok().flatten(),
+            RunMessage::Click(_) => {}
+            RunMessage::Scroll(offset) => {
+                let most = (self.lines.len() as f32 * self.layout.line_height() - self.window.h).max(0.0);
+                self.scroll = (self.scroll - offset.y).clamp(0.0, most);
+            }
+        }
+    }
+
+    /// Index of the first row shown, and how many rows fit
+    fn visible_rows(&self) -> (usize, usize) {
+        let line_height = self.layout.line_height();
+        let rows = (self.window.h / line_height) as usize;
+        let bottom = self.lines.len().saturating_sub((self.scroll / line_height).round() as usize);
+        (bottom.saturating_sub(rows), rows)
+    }
+
+    /// The file reference under a point on screen
+    pub(crate) fn link_at(&self, position: Vec2) -> Option<&FileLink> {
+        if !self.window.contains(position) {
+            return None;
+        }
+
+        let (first, _) = self.visible_rows();
+        let row = first + ((position.y - self.window.y) / self.layout.line_height()) as usize;
+        let line = self.lines.get(row)?;
+        let cell = ((position.x - self.window.x) / self.layout.cell_width()) as usize;
+        let column = line.text.char_indices().nth(cell).map_or(line.text.len(), |(column, _)| column);
+
+        line.links.iter().find(|link| (link.start..link.end).contains(&column))
+    }
+
+    pub(crate) fn view(&self, theme: &Theme, font: Option<&Font>, focused: bool) {
+        set_fullscreen_camera();
+
+        let titlebar = self.titlebar();
+        draw_rectangle(titlebar.x, titlebar.y, titlebar.w, titlebar.h, theme.surface1);
+
+        draw_text_ex(&format!("run: {}", self.command), self.window.x, self.window.y - 4.0, TextParams {
+            color: if focused { theme.lavender } else { theme.text },
+            font,
+            font_size: FONT_SIZE,
+            ..Default::default()
+        });
+
+        let (indicator, color) = match self.status.map(|status| status.code()) {
+            None => ("running".to_string(), theme.subtext0),
+            Some(Some(0)) => ("exited 0".to_string(), theme.green),
+            Some(Some(code)) => (format!("exited {code}"), theme.red),
+            Some(None) => ("killed".to_string(), theme.red),
+        };
+        let width = measure_text(&indicator, font, FONT_SIZE, 1.0).width;
+        draw_text_ex(&indicator, titlebar.right() - width - 4.0, self.window.y - 4.0, TextParams {
+            color,
+            font,
+            font_size: FONT_SIZE,
+            ..Default::default()
+        });
+
+        draw_rectangle(self.window.x, self.window.y, self.window.w, self.window.h, theme.mantle);
+        set_camera_window(self.window, Vec2::ZERO);
+
+        let cell_width = self.layout.measure(font);
+        let line_height = self.layout.line_height();
+        let (first, rows) = self.visible_rows();
+
+        for (k, line) in self.lines.iter().skip(first).take(rows + 1).enumerate() {
+            let y = (k + 1) as f32 * line_height;
+            let plain = match line.stream {
+                Stream::Stdout => theme.text,
+                Stream::Stderr => theme.maroon,
+            };
+
+            // The line is drawn in pieces, with links between the plain text
+            let mut pieces = vec![];
+            let mut start = 0;
+            for link in &line.links {
+                pieces.push((start, link.start, false));
+                pieces.push((link.start, link.end, true));
+                start = link.end;
+            }
+            pieces.push((start, line.text.len(), false));
+
+            for (start, end, link) in pieces {
+                let x = line.text[..start].chars().count() as f32 * cell_width;
+                let text = &line.text[start..end];
+                let color = if link { theme.blue } else { plain };
+                draw_text_ex(text, x, y, TextParams { color, font, font_size: FONT_SIZE, ..Default::default() });
+                if link {
+                    let width = text.chars().count() as f32 * cell_width;
+                    draw_line(x, y + 2.0, x + width, y + 2.0, 1.0, color);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Sends each line of a stream, then `None` once it's closed
+fn read_lines(stream: impl Read + Send + 'static, kind: Stream, sender: Sender<(Stream, Option<String>)>) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        let mut buffer = vec![];
+        while reader.read_until(b'\n', &mut buffer).is_ok_and(|read| read > 0) {
+            let line = String::from_utf8_lossy(&buffer);
+            let line = line.trim_end_matches(['\n', '\r']);
+            // Progress bars redraw the line after a carriage return, so only the last one is kept
+            let line = line.rsplit('\r').next().unwrap_or_default().replace('\t', "    ");
+            if sender.send((kind, Some(line))).is_err() {
+                return;
+            }
+            buffer.clear();
+        }
+        let _ = sender.send((kind, None));
+    });
+}
+
+/// References to existing files in a line of compiler output: `path:line:col` as rustc and gcc print them,
+/// `path:line`, and `File "path", line N` from Python tracebacks. `is_file` says whether a path is an existing file.
+pub(crate) fn file_links(text: &str, mut is_file: impl FnMut(&str) -> bool) -> Vec<FileLink> {
+    let mut links = vec![];
+
+    if let Some(quote) = text.find("File \"") {
+        let start = quote + "File \"".len();
+        if let Some((path, rest)) = text[start..].split_once('"') {
+            let digits = rest.strip_prefix(", line ").map_or("", |rest| {
+                &rest[..rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len())]
+            });
+            if let (Ok(line), true) = (digits.parse(), is_file(path)) {
+                links.push(FileLink { start, end: start + path.len(), path: path.to_string(), line, column: 1 });
+            }
+        }
+    }
+
+    let separators = |c: char| c.is_whitespace() || "()[]<>\"'`,;".contains(c);
+    for token in text.split(separators) {
+        let offset = token.as_ptr() as usize - text.as_ptr() as usize;
+
+        let mut parts = token.split(':');
+        let (Some(path), Some(line)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let Ok(line) = line.parse::<usize>() else {
+            continue;
+        };
+        let column = parts.next().and_then(|column| column.parse::<usize>().ok());
+        if path.is_empty() || !is_file(path) {
+            continue;
+        }
+
+        let length = match column {
+            Some(column) => format!("{path}:{line}:{column}").len(),
+            None => format!("{path}:{line}").len(),
+        };
+        let link = FileLink { start: offset, end: offset + length, path: path.to_string(), line, column: column.unwrap_or(1) };
+        if !links.iter().any(|existing: &FileLink| existing.start < link.end && link.start < existing.end) {
+            links.push(link);
+        }
+    }
+
+    links.sort_by_key(|link| link.start);
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_references_to_files() {
+        let mut looked_up = vec![];
+        let links = file_links("error at src/main.rs:12:5 (see src/main.rs:3, not http://host:80)", |path| {
+            looked_up.push(path.to_string());
+            path == "src/main.rs"
+        });
+
+        assert_eq!(links.len(), 2);
+        assert_eq!((links[0].start, links[0].end, links[0].line, links[0].column), (9, 25, 12, 5));
+        assert_eq!((links[1].line, links[1].column), (3, 1));
+        assert!(!looked_up.iter().any(|path| path == "error"));
+
+        let traceback = file_links(r#"  File "app.py", line 7, in main"#, |path| path == "app.py");
+        assert_eq!((traceback[0].path.as_str(), traceback[0].line), ("app.py", 7));
+    }
+}
@@ -0,0 +1,298 @@
+use std::env;
+use std::io;
+use std::process::ExitStatus;
+use macroquad::prelude::*;
+use crate::keymap::{self, Chord};
+use crate::layout::TextLayout;
+use crate::terminal::pty::Pty;
+use crate::terminal::vt::{Screen, Style, TermColor};
+use crate::theme::Theme;
+use crate::window::{set_camera_window, set_fullscreen_camera};
+
+mod pty;
+mod vt;
+
+const FONT_SIZE: u16 = 16;
+
+#[derive(Debug)]
+pub enum TerminalMessage {
+    Output(Vec<u8>),
+    /// The process has closed the terminal, usually by exiting
+    Closed,
+    Char(char),
+    Key(Chord),
+    Scroll(Vec2),
+}
+
+/// A pane running the user's shell on a pseudo-terminal
+pub(crate) struct Terminal {
+    pub(crate) window: Rect,
+    layout: TextLayout,
+    screen: Screen,
+    pty: Pty,
+    program: String,
+    /// How the process exited, once it has
+    status: Option<ExitStatus>,
+    /// How far up the scrollback is shown, in pixels
+    scroll: f32,
+}
+
+impl Terminal {
+    /// Starts `$SHELL`, or `/bin/sh` without one, sized to fit `window`
+    pub(crate) fn spawn(window: Rect) -> io::Result<Self> {
+        let layout = TextLayout::new(FONT_SIZE);
+        let (rows, columns) = Self::fit(&layout, window);
+        let program = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        let pty = Pty::spawn(&program, rows as u16, columns as u16)?;
+
+        Ok(Self { window, layout, screen: Screen::new(rows, columns), pty, program, status: None, scroll: 0.0 })
+    }
+
+    /// How many rows and columns fit in a window
+    fn fit(layout: &TextLayout, window: Rect) -> (usize, usize) {
+        let rows = (window.h / layout.line_height()) as usize;
+        let columns = (window.w / layout.cell_width()) as usize;
+        (rows.max(1), columns.max(1))
+    }
+
+    pub(crate) fn titlebar(&self) -> Rect {
+        let titlebar_height = self.layout.font_size() as f32 + 8.0;
+        Rect::new(self.window.x, self.window.y - titlebar_height, self.window.w, titlebar_height)
+    }
+
+    /// Output and the end of it since the last frame
+    pub(crate) fn poll(&self) -> Vec<TerminalMessage> {
+        let (output, closed) = self.pty.poll();
+        let mut messages = vec![];
+        if !output.is_empty() {
+            messages.push(TerminalMessage::Output(output));
+        }
+        if closed {
+            messages.push(TerminalMessage::Closed);
+        }
+
+        messages
+    }
+
+    pub(crate) fn update(&mut self, message: TerminalMessage) {
+        match message {
+            TerminalMessage::Output(output) => {
+                self.screen.feed(&output);
+                let replies = std::mem::take(&mut self.screen.replies);
+                self.send(&replies);
+            }
+            TerminalMessage::Closed => self.status = self.pty.wait().ok(),
+            TerminalMessage::Char(character) if !character.is_control() => {
+                self.send(character.to_string().as_bytes());
+            }
+            TerminalMessage::Char(_) => {}
+            TerminalMessage::Key(chord) => {
+                if let Some(bytes) = encode_key(chord, self.screen.application_cursor) {
+                    self.send(&bytes);
+                }
+            }
+            TerminalMessage::Scroll(offset) => {
+                let most = self.screen.scrollback_len() as f32 * self.layout.line_height();
+                self.scroll = (self.scroll - offset.y).clamp(0.0, most);
+            }
+        }
+
+        // The window is measured in cells only once the font has been
+        let (rows, columns) = Self::fit(&self.layout, self.window);
+        if (rows, columns) != self.screen.size() {
+            self.screen.resize(rows, columns);
+            self.pty.resize(rows as u16, columns as u16);
+        }
+    }
+
+    /// Writes to the process, jumping back down to the bottom of the scrollback
+    fn send(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() || self.status.is_some() {
+            return;
+        }
+
+        self.scroll = 0.0;
+        // Writing only fails once the process is gone, which `Closed` reports
+        let _ = self.pty.write(bytes);
+    }
+
+    /// The color the theme gives a terminal color, following the Catppuccin terminal palette
+    fn color(color: TermColor, default: Color, theme: &Theme) -> Color {
+        let palette = [
+            theme.surface1, theme.red, theme.green, theme.yellow, theme.blue, theme.pink, theme.teal, theme.subtext1,
+            theme.surface2, theme.red, theme.green, theme.yellow, theme.blue, theme.pink, theme.teal, theme.subtext0,
+        ];
+        let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+
+        match color {
+            TermColor::Default => default,
+            TermColor::Indexed(index @ 0..=15) => palette[index as usize],
+            // A 6x6x6 cube of colors, then a ramp of grays
+            TermColor::Indexed(index @ 16..=231) => {
+                let index = index - 16;
+                Color::from_rgba(level(index / 36), level(index / 6 % 6), level(index % 6), 255)
+            }
+            TermColor::Indexed(index) => {
+                let gray = 8 + (index - 232) * 10;
+                Color::from_rgba(gray, gray, gray, 255)
+            }
+            TermColor::Rgb(red, green, blue) => Color::from_rgba(red, green, blue, 255),
+        }
+    }
+
+    fn colors(style: Style, theme: &Theme) -> (Color, Color) {
+        let foreground = Self::color(style.foreground, theme.text, theme);
+        let background = Self::color(style.background, theme.crust, theme);
+        let (foreground, background) = match style.inverse {
+            true => (background, foreground),
+            false => (foreground, background),
+        };
+
+        match style.dim {
+            true => (Color { a: 0.6, ..foreground }, background),
+            false => (foreground, background),
+        }
+    }
+
+    pub(crate) fn view(&self, theme: &Theme, font: Option<&Font>, focused: bool) {
+        set_fullscreen_camera();
+
+        let titlebar = self.titlebar();
+        draw_rectangle(titlebar.x, titlebar.y, titlebar.w, titlebar.h, theme.surface1);
+
+        let title = self.screen.title.as_deref().unwrap_or(&self.program);
+        draw_text_ex(title, self.window.x, self.window.y - 4.0, TextParams {
+            color: if focused { theme.lavender } else { theme.text },
+            font,
+            font_size: FONT_SIZE,
+            ..Default::default()
+        });
+
+        let line_height = self.layout.line_height();
+        let scrolled = (self.scroll / line_height).round() as usize;
+        let indicator = match (self.status, scrolled) {
+            (Some(status), _) => match status.code() {
+                Some(code) => format!("exited {code}"),
+                None => "killed".to_string(),
+            },
+            (None, 0) => String::new(),
+            (None, rows) => format!("{rows} rows up"),
+        };
+        let width = measure_text(&indicator, font, FONT_SIZE, 1.0).width;
+        draw_text_ex(&indicator, titlebar.right() - width - 4.0, self.window.y - 4.0, TextParams {
+            color: theme.subtext0,
+            font,
+            font_size: FONT_SIZE,
+            ..Default::default()
+        });
+
+        draw_rectangle(self.window.x, self.window.y, self.window.w, self.window.h, theme.crust);
+        set_camera_window(self.window, Vec2::ZERO);
+        let cell_width = self.layout.measure(font);
+
+        for (k, line) in self.screen.visible_rows(scrolled).enumerate() {
+            let y = (k + 1) as f32 * line_height;
+
+            // Consecutive cells of the same style are drawn as a single run
+            let mut start = 0;
+            while start < line.len() {
+                let style = line[start].style;
+                let end = line[start..].iter().position(|cell| cell.style != style).map_or(line.len(), |length| start + length);
+                let (foreground, background) = Self::colors(style, theme);
+                let x = start as f32 * cell_width;
+                let width = (end - start) as f32 * cell_width;
+
+                if background != theme.crust {
+                    draw_rectangle(x, y - line_height, width, line_height, background);
+                }
+                // Words are placed by cell, as a space may not be as wide as a cell in the font
+                let mut column = start;
+                for word in line[start..end].split(|cell| cell.character == ' ') {
+                    if !word.is_empty() {
+                        let text = word.iter().map(|cell| cell.character).collect::<String>();
+                        let params = TextParams { color: foreground, font, font_size: FONT_SIZE, ..Default::default() };
+                        draw_text_ex(&text, column as f32 * cell_width, y, params);
+                    }
+                    column += word.len() + 1;
+                }
+                if style.underline {
+                    draw_line(x, y + 1.0, x + width, y + 1.0, 1.0, foreground);
+                }
+                start = end;
+            }
+        }
+
+        let (row, column) = self.screen.cursor();
+        if focused && self.screen.cursor_visible && self.status.is_none() {
+            // The cursor moves down as the view scrolls up, until it's out of sight
+            let y = (row + scrolled.min(self.screen.scrollback_len())) as f32 * line_height;
+            draw_rectangle(column as f32 * cell_width, y, cell_width, line_height, Color { a: 0.5, ..theme.rosewater });
+        }
+    }
+}
+
+/// What a key sends to the program, in the sequences xterm uses. Keys that type a char arrive as chars instead.
+fn encode_key(chord: Chord, application_cursor: bool) -> Option<Vec<u8>> {
+    let modifiers = 1 + u8::from(chord.shift) + 2 * u8::from(chord.alt) + 4 * u8::from(chord.control);
+    let cursor = |code: char| match (modifiers, application_cursor) {
+        (1, true) => format!("\x1bO{code}"),
+        (1, false) => format!("\x1b[{code}"),
+        _ => format!("\x1b[1;{modifiers}{code}"),
+    };
+    let tilde = |code: u8| match modifiers {
+        1 => format!("\x1b[{code}~"),
+        _ => format!("\x1b[{code};{modifiers}~"),
+    };
+
+    let sequence = match chord.key {
+        KeyCode::Up => cursor('A'),
+        KeyCode::Down => cursor('B'),
+        KeyCode::Right => cursor('C'),
+        KeyCode::Left => cursor('D'),
+        KeyCode::Home => cursor('H'),
+        KeyCode::End => cursor('F'),
+        KeyCode::Insert => tilde(2),
+        KeyCode::Delete => tilde(3),
+        KeyCode::PageUp => tilde(5),
+        KeyCode::PageDown => tilde(6),
+        KeyCode::F1 => "\x1bOP".to_string(),
+        KeyCode::F2 => "\x1bOQ".to_string(),
+        KeyCode::F3 => "\x1bOR".to_string(),
+        KeyCode::F4 => "\x1bOS".to_string(),
+        KeyCode::F5 => tilde(15),
+        KeyCode::F6 => tilde(17),
+        KeyCode::F7 => tilde(18),
+        KeyCode::F8 => tilde(19),
+        KeyCode::F9 => tilde(20),
+        KeyCode::F10 => tilde(21),
+        KeyCode::F11 => tilde(23),
+        KeyCode::F12 => tilde(24),
+        KeyCode::Enter => "\r".to_string(),
+        KeyCode::Escape => "\x1b".to_string(),
+        KeyCode::Tab if chord.shift => "\x1b[Z".to_string(),
+        KeyCode::Tab => "\t".to_string(),
+        KeyCode::Backspace if chord.control => "\x08".to_string(),
+        KeyCode::Backspace => "\x7f".to_string(),
+        key if chord.control => {
+            let code = match keymap::key_name(key)? {
+                "space" | "2" => 0,
+                "[" | "3" => 0x1b,
+                "\\" | "4" => 0x1c,
+                "]" | "5" => 0x1d,
+                "6" => 0x1e,
+                "/" | "-" | "7" => 0x1f,
+                name if name.len() == 1 && name.as_bytes()[0].is_ascii_lowercase() => name.as_bytes()[0] & 0x1f,
+                _ => return None,
+            };
+            let mut bytes = match chord.alt {
+                true => vec![0x1b],
+                false => vec![],
+            };
+            bytes.push(code);
+            return Some(bytes);
+        }
+        _ => return None,
+    };
+
+    Some(sequence.into_bytes())
+}
@@ -0,0 +1,117 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// A process running on a pseudo-terminal, whose output is read on a background thread
+pub(crate) struct Pty {
+    master: File,
+    child: Child,
+    /// Chunks of output, ending with an empty one once the process has closed the terminal
+    output: Receiver<Vec<u8>>,
+}
+
+impl Pty {
+    /// Starts `program` on a new terminal of `rows` by `columns`
+    #[cfg(unix)]
+    pub(crate) fn spawn(program: &str, rows: u16, columns: u16) -> io::Result<Self> {
+        use std::os::fd::{AsRawFd, FromRawFd};
+        use std::os::unix::process::CommandExt;
+
+        let (mut master, mut slave) = (0, 0);
+        let size = libc::winsize { ws_row: rows, ws_col: columns, ws_xpixel: 0, ws_ypixel: 0 };
+        // SAFETY: openpty only writes the two descriptors, and the size outlives the call
+        if unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openpty succeeded, so both descriptors are open and owned by nothing else
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+        // Neither end leaks into other processes started meanwhile; the child gets the slave as its stdio
+        for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+            // SAFETY: the descriptor is open for as long as its File is
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let mut command = Command::new(program);
+        command.env("TERM", "xterm-256color")
+            .env("COLORTERM", "truecolor")
+            .stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave);
+        // SAFETY: only async-signal-safe calls are made between fork and exec
+        unsafe {
+            command.pre_exec(|| {
+                // A session of its own, with the terminal as its controlling one, so job control and ctrl+c work
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+
+        let mut reader = master.try_clone()?;
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            // Reading fails rather than ending once the process has exited, on Linux
+            while let Ok(read) = reader.read(&mut buffer) {
+                if read == 0 || sender.send(buffer[..read].to_vec()).is_err() {
+                    break;
+                }
+            }
+            let _ = sender.send(vec![]);
+        });
+
+        Ok(Self { master, child, output })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn spawn(_program: &str, _rows: u16, _columns: u16) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "terminals are only supported on Unix"))
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.master.write_all(bytes)
+    }
+
+    /// Tells the process the terminal is now `rows` by `columns`
+    #[cfg(unix)]
+    pub(crate) fn resize(&self, rows: u16, columns: u16) {
+        use std::os::fd::AsRawFd;
+
+        let size = libc::winsize { ws_row: rows, ws_col: columns, ws_xpixel: 0, ws_ypixel: 0 };
+        // SAFETY: the descriptor is open for as long as `self`, and the size outlives the call
+        unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn resize(&self, _rows: u16, _columns: u16) {}
+
+    /// Output that has arrived since the last call, and whether the process has closed the terminal
+    pub(crate) fn poll(&self) -> (Vec<u8>, bool) {
+        let mut output = vec![];
+        let mut closed = false;
+        for chunk in self.output.try_iter() {
+            closed |= chunk.is_empty();
+            output.extend(chunk);
+        }
+
+        (output, closed)
+    }
+
+    /// How the process exited, waiting for it once it has closed the terminal
+    pub(crate) fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait()
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
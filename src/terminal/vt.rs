@@ -0,0 +1,558 @@
+use std::collections::VecDeque;
+use std::mem;
+
+/// Most rows kept once they scroll off the top
+const SCROLLBACK: usize = 10_000;
+
+const TAB_WIDTH: usize = 8;
+
+/// A color as the program asked for it, turned into a real one by the theme when drawn
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub(crate) enum TermColor {
+    #[default]
+    Default,
+    /// One of the 256 xterm colors, of which the first 16 come from the theme
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub(crate) struct Style {
+    pub(crate) foreground: TermColor,
+    pub(crate) background: TermColor,
+    pub(crate) bold: bool,
+    pub(crate) dim: bool,
+    pub(crate) underline: bool,
+    pub(crate) inverse: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Cell {
+    pub(crate) character: char,
+    pub(crate) style: Style,
+}
+
+impl Cell {
+    fn blank(style: Style) -> Self {
+        // Erasing fills with the current background, but nothing else of the style
+        Cell { character: ' ', style: Style { background: style.background, ..Style::default() } }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Cursor {
+    row: usize,
+    column: usize,
+    style: Style,
+}
+
+#[derive(Debug)]
+enum State {
+    Ground,
+    Escape,
+    /// `ESC (` and the like choose a character set, which is ignored along with the byte naming it
+    Charset,
+    Csi { private: Option<u8>, params: Vec<u16>, current: Option<u16> },
+    Osc(Vec<u8>),
+    /// An escape inside an OSC string, which may be the start of its terminator
+    OscEscape(Vec<u8>),
+}
+
+/// The grid of cells a program draws on with escape sequences, along with what has scrolled off it
+pub(crate) struct Screen {
+    rows: usize,
+    columns: usize,
+    grid: Vec<Vec<Cell>>,
+    scrollback: VecDeque<Vec<Cell>>,
+    /// The main screen while a full-screen program is using the alternate one
+    main: Option<Vec<Vec<Cell>>>,
+    cursor: Cursor,
+    saved: Cursor,
+    /// A char written in the last column wraps only once another follows it
+    wrap_pending: bool,
+    /// First and last rows that scroll
+    region: (usize, usize),
+    pub(crate) cursor_visible: bool,
+    /// Whether arrow keys send the sequences for application mode
+    pub(crate) application_cursor: bool,
+    pub(crate) title: Option<String>,
+    state: State,
+    /// Bytes of a UTF-8 char still being received
+    partial: Vec<u8>,
+    /// Answers to queries such as the cursor position, to be written back to the program
+    pub(crate) replies: Vec<u8>,
+}
+
+impl Screen {
+    pub(crate) fn new(rows: usize, columns: usize) -> Self {
+        let (rows, columns) = (rows.max(1), columns.max(1));
+        Self {
+            rows,
+            columns,
+            grid: vec![vec![Cell::blank(Style::default()); columns]; rows],
+            scrollback: VecDeque::new(),
+            main: None,
+            cursor: Cursor::default(),
+            saved: Cursor::default(),
+            wrap_pending: false,
+            region: (0, rows - 1),
+            cursor_visible: true,
+            application_cursor: false,
+            title: None,
+            state: State::Ground,
+            partial: vec![],
+            replies: vec![],
+        }
+    }
+
+    pub(crate) fn size(&self) -> (usize, usize) {
+        (self.rows, self.columns)
+    }
+
+    pub(crate) fn cursor(&self) -> (usize, usize) {
+        (self.cursor.row, self.cursor.column)
+    }
+
+    pub(crate) fn scrollback_len(&self) -> usize {
+        self.scrollback.len()
+    }
+
+    /// Rows to show when scrolled back `scroll` rows from the bottom
+    pub(crate) fn visible_rows(&self, scroll: usize) -> impl Iterator<Item = &Vec<Cell>> {
+        let scroll = scroll.min(self.scrollback.len());
+        let history = self.scrollback.iter().skip(self.scrollback.len() - scroll);
+        history.chain(&self.grid).take(self.rows)
+    }
+
+    /// Changes the size of the grid, keeping the rows around the cursor. Lines aren't rewrapped.
+    pub(crate) fn resize(&mut self, rows: usize, columns: usize) {
+        let (rows, columns) = (rows.max(1), columns.max(1));
+
+        if rows < self.rows && self.cursor.row >= rows {
+            let excess = self.cursor.row + 1 - rows;
+            let lines = self.grid.drain(..excess).collect::<Vec<_>>();
+            for line in lines {
+                self.push_scrollback(line);
+            }
+            self.cursor.row -= excess;
+        }
+        self.grid.resize(rows, vec![Cell::blank(Style::default()); columns]);
+        for line in self.grid.iter_mut().chain(self.main.iter_mut().flatten()) {
+            line.resize(columns, Cell::blank(Style::default()));
+        }
+        if let Some(main) = &mut self.main {
+            main.resize(rows, vec![Cell::blank(Style::default()); columns]);
+        }
+
+        self.rows = rows;
+        self.columns = columns;
+        self.region = (0, rows - 1);
+        // The saved cursor is restored later by ESC 8, CSI u and leaving the alternate screen, so it has to fit too
+        for cursor in [&mut self.cursor, &mut self.saved] {
+            cursor.row = cursor.row.min(rows - 1);
+            cursor.column = cursor.column.min(columns - 1);
+        }
+        self.wrap_pending = false;
+    }
+
+    pub(crate) fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.byte(byte);
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        match mem::replace(&mut self.state, State::Ground) {
+            State::Ground => self.ground(byte),
+            State::Escape => self.escape(byte),
+            State::Charset => {}
+            State::Csi { private, mut params, mut current } => match byte {
+                b'0'..=b'9' => {
+                    let digit = u16::from(byte - b'0');
+                    current = Some(current.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                    self.state = State::Csi { private, params, current };
+                }
+                b';' | b':' => {
+                    params.push(current.unwrap_or(0));
+                    self.state = State::Csi { private, params, current: None };
+                }
+                b'<'..=b'?' if params.is_empty() && current.is_none() => {
+                    self.state = State::Csi { private: Some(byte), params, current };
+                }
+                0x40..=0x7e => {
+                    if let Some(current) = current {
+                        params.push(current);
+                    }
+                    self.csi(private, &params, byte);
+                }
+                0x1b => self.state = State::Escape,
+                // Control chars take effect in the middle of a sequence
+                0x00..=0x1f => {
+                    self.control(byte);
+                    self.state = State::Csi { private, params, current };
+                }
+                _ => self.state = State::Csi { private, params, current },
+            },
+            State::Osc(mut text) => match byte {
+                0x07 => self.osc(&text),
+                0x1b => self.state = State::OscEscape(text),
+                _ => {
+                    text.push(byte);
+                    self.state = State::Osc(text);
+                }
+            },
+            State::OscEscape(text) => {
+                self.osc(&text);
+                if byte != b'\\' {
+                    self.escape(byte);
+                }
+            }
+        }
+    }
+
+    fn ground(&mut self, byte: u8) {
+        match byte {
+            0x1b => {
+                self.partial.clear();
+                self.state = State::Escape;
+            }
+            0x00..=0x1f | 0x7f => self.control(byte),
+            0x20..=0x7e => self.print(byte as char),
+            _ => {
+                self.partial.push(byte);
+                let expected = match self.partial[0] {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => 1,
+                };
+                if self.partial.len() >= expected {
+                    let text = String::from_utf8_lossy(&self.partial).into_owned();
+                    self.partial.clear();
+                    for character in text.chars() {
+                        self.print(character);
+                    }
+                }
+            }
+        }
+    }
+
+    fn control(&mut self, byte: u8) {
+        match byte {
+            0x08 => {
+                self.cursor.column = self.cursor.column.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            b'\t' => {
+                let next = (self.cursor.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.cursor.column = next.min(self.columns - 1);
+            }
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            b'\r' => {
+                self.cursor.column = 0;
+                self.wrap_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn escape(&mut self, byte: u8) {
+        match byte {
+            b'[' => self.state = State::Csi { private: None, params: vec![], current: None },
+            b']' => self.state = State::Osc(vec![]),
+            b'(' | b')' | b'*' | b'+' => self.state = State::Charset,
+            b'7' => self.saved = self.cursor,
+            b'8' => {
+                self.cursor = self.saved;
+                self.wrap_pending = false;
+            }
+            b'D' => self.line_feed(),
+            b'E' => {
+                self.cursor.column = 0;
+                self.line_feed();
+            }
+            b'M' => self.reverse_line_feed(),
+            b'c' => *self = Screen { title: self.title.take(), scrollback: mem::take(&mut self.scrollback), ..Screen::new(self.rows, self.columns) },
+            _ => {}
+        }
+    }
+
+    fn osc(&mut self, text: &[u8]) {
+        let text = String::from_utf8_lossy(text);
+        if let Some(("0" | "2", title)) = text.split_once(';') {
+            self.title = Some(title.to_owned());
+        }
+    }
+
+    fn csi(&mut self, private: Option<u8>, params: &[u16], command: u8) {
+        // Missing and zero parameters mean 1 for most commands
+        let count = |index: usize| usize::from(params.get(index).copied().unwrap_or(0).max(1));
+        let param = |index: usize| params.get(index).copied().unwrap_or(0);
+        let (rows, columns) = (self.rows, self.columns);
+
+        if private == Some(b'?') {
+            let set = command == b'h';
+            if command == b'h' || command == b'l' {
+                for mode in params {
+                    self.private_mode(*mode, set);
+                }
+            }
+            return;
+        }
+        if private.is_some() {
+            return;
+        }
+
+        self.wrap_pending = false;
+        match command {
+            b'A' => self.cursor.row = self.cursor.row.saturating_sub(count(0)).max(self.top_limit()),
+            b'B' => self.cursor.row = (self.cursor.row + count(0)).min(self.bottom_limit()),
+            b'C' => self.cursor.column = (self.cursor.column + count(0)).min(columns - 1),
+            b'D' => self.cursor.column = self.cursor.column.saturating_sub(count(0)),
+            b'E' => {
+                self.cursor.row = (self.cursor.row + count(0)).min(self.bottom_limit());
+                self.cursor.column = 0;
+            }
+            b'F' => {
+                self.cursor.row = self.cursor.row.saturating_sub(count(0)).max(self.top_limit());
+                self.cursor.column = 0;
+            }
+            b'G' | b'`' => self.cursor.column = (count(0) - 1).min(columns - 1),
+            b'd' => self.cursor.row = (count(0) - 1).min(rows - 1),
+            b'H' | b'f' => {
+                self.cursor.row = (count(0) - 1).min(rows - 1);
+                self.cursor.column = (count(1) - 1).min(columns - 1);
+            }
+            b'J' => {
+                let (row, column) = (self.cursor.row, self.cursor.column);
+                match param(0) {
+                    0 => {
+                        self.erase(row, column..columns);
+                        (row + 1..rows).for_each(|row| self.erase(row, 0..columns));
+                    }
+                    1 => {
+                        (0..row).for_each(|row| self.erase(row, 0..columns));
+                        self.erase(row, 0..column + 1);
+                    }
+                    2 => (0..rows).for_each(|row| self.erase(row, 0..columns)),
+                    3 => self.scrollback.clear(),
+                    _ => {}
+                }
+            }
+            b'K' => {
+                let (row, column) = (self.cursor.row, self.cursor.column);
+                match param(0) {
+                    0 => self.erase(row, column..columns),
+                    1 => self.erase(row, 0..column + 1),
+                    2 => self.erase(row, 0..columns),
+                    _ => {}
+                }
+            }
+            b'X' => {
+                let column = self.cursor.column;
+                self.erase(self.cursor.row, column..(column + count(0)).min(columns));
+            }
+            b'@' => {
+                let blank = Cell::blank(self.cursor.style);
+                let line = &mut self.grid[self.cursor.row];
+                for _ in 0..count(0).min(columns - self.cursor.column) {
+                    line.insert(self.cursor.column, blank);
+                    line.pop();
+                }
+            }
+            b'P' => {
+                let blank = Cell::blank(self.cursor.style);
+                let line = &mut self.grid[self.cursor.row];
+                for _ in 0..count(0).min(columns - self.cursor.column) {
+                    line.remove(self.cursor.column);
+                    line.push(blank);
+                }
+            }
+            b'L' if (self.region.0..=self.region.1).contains(&self.cursor.row) => {
+                for _ in 0..count(0) {
+                    self.scroll_down(self.cursor.row);
+                }
+            }
+            b'M' if (self.region.0..=self.region.1).contains(&self.cursor.row) => {
+                for _ in 0..count(0) {
+                    self.scroll_up(self.cursor.row);
+                }
+            }
+            b'S' => (0..count(0)).for_each(|_| self.scroll_up(self.region.0)),
+            b'T' => (0..count(0)).for_each(|_| self.scroll_down(self.region.0)),
+            b'm' => self.select_graphic_rendition(params),
+            b'r' => {
+                let top = count(0) - 1;
+                let bottom = params.get(1).filter(|bottom| **bottom > 0).map_or(rows, |bottom| usize::from(*bottom)) - 1;
+                if top < bottom && bottom < rows {
+                    self.region = (top, bottom);
+                    self.cursor.row = 0;
+                    self.cursor.column = 0;
+                }
+            }
+            b's' => self.saved = self.cursor,
+            b'u' => self.cursor = self.saved,
+            b'n' => match param(0) {
+                5 => self.replies.extend(b"\x1b[0n"),
+                6 => self.replies.extend(format!("\x1b[{};{}R", self.cursor.row + 1, self.cursor.column + 1).bytes()),
+                _ => {}
+            },
+            b'c' => self.replies.extend(b"\x1b[?1;2c"),
+            _ => {}
+        }
+    }
+
+    fn private_mode(&mut self, mode: u16, set: bool) {
+        match mode {
+            1 => self.application_cursor = set,
+            25 => self.cursor_visible = set,
+            47 | 1047 | 1049 => {
+                if set && self.main.is_none() {
+                    if mode == 1049 {
+                        self.saved = self.cursor;
+                    }
+                    let blank = vec![vec![Cell::blank(Style::default()); self.columns]; self.rows];
+                    self.main = Some(mem::replace(&mut self.grid, blank));
+                } else if let Some(main) = self.main.take().filter(|_| !set) {
+                    self.grid = main;
+                    if mode == 1049 {
+                        self.cursor = self.saved;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        let style = &mut self.cursor.style;
+        if params.is_empty() {
+            *style = Style::default();
+            return;
+        }
+
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => *style = Style::default(),
+                1 => style.bold = true,
+                2 => style.dim = true,
+                4 => style.underline = true,
+                7 => style.inverse = true,
+                22 => (style.bold, style.dim) = (false, false),
+                24 => style.underline = false,
+                27 => style.inverse = false,
+                30..=37 => style.foreground = TermColor::Indexed((param - 30) as u8),
+                38 => style.foreground = extended_color(&mut params),
+                39 => style.foreground = TermColor::Default,
+                40..=47 => style.background = TermColor::Indexed((param - 40) as u8),
+                48 => style.background = extended_color(&mut params),
+                49 => style.background = TermColor::Default,
+                90..=97 => style.foreground = TermColor::Indexed((param - 90 + 8) as u8),
+                100..=107 => style.background = TermColor::Indexed((param - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+
+    fn print(&mut self, character: char) {
+        if self.wrap_pending {
+            self.cursor.column = 0;
+            self.line_feed();
+        }
+
+        let (row, column) = (self.cursor.row, self.cursor.column);
+        self.grid[row][column] = Cell { character, style: self.cursor.style };
+
+        match column + 1 < self.columns {
+            true => self.cursor.column += 1,
+            false => self.wrap_pending = true,
+        }
+    }
+
+    fn erase(&mut self, row: usize, columns: std::ops::Range<usize>) {
+        let blank = Cell::blank(self.cursor.style);
+        let end = columns.end.min(self.columns);
+        self.grid[row][columns.start.min(end)..end].fill(blank);
+    }
+
+    /// Moves the cursor down a row, scrolling the region when it's at the bottom
+    fn line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.row == self.region.1 {
+            self.scroll_up(self.region.0);
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+    }
+
+    fn reverse_line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.row == self.region.0 {
+            self.scroll_down(self.region.0);
+        } else {
+            self.cursor.row = self.cursor.row.saturating_sub(1);
+        }
+    }
+
+    /// Removes row `top` of the scrolling region, moving the rows below it up. Rows leaving the top of the main
+    /// screen go to the scrollback.
+    fn scroll_up(&mut self, top: usize) {
+        let line = self.grid.remove(top);
+        self.grid.insert(self.region.1, vec![Cell::blank(self.cursor.style); self.columns]);
+        if top == 0 && self.main.is_none() {
+            self.push_scrollback(line);
+        }
+    }
+
+    /// Inserts a blank row at `top`, moving the rows below it down to the bottom of the scrolling region
+    fn scroll_down(&mut self, top: usize) {
+        self.grid.remove(self.region.1);
+        self.grid.insert(top, vec![Cell::blank(self.cursor.style); self.columns]);
+    }
+
+    fn push_scrollback(&mut self, line: Vec<Cell>) {
+        self.scrollback.push_back(line);
+        if self.scrollback.len() > SCROLLBACK {
+            self.scrollback.pop_front();
+        }
+    }
+
+    /// The highest row cursor movement stops at
+    fn top_limit(&self) -> usize {
+        if self.cursor.row >= self.region.0 { self.region.0 } else { 0 }
+    }
+
+    fn bottom_limit(&self) -> usize {
+        if self.cursor.row <= self.region.1 { self.region.1 } else { self.rows - 1 }
+    }
+}
+
+/// The rest of `38;5;n` or `38;2;r;g;b`
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> TermColor {
+    match params.next() {
+        Some(5) => TermColor::Indexed(params.next().unwrap_or(0) as u8),
+        Some(2) => {
+            let mut channel = || params.next().unwrap_or(0) as u8;
+            TermColor::Rgb(channel(), channel(), channel())
+        }
+        _ => TermColor::Default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restoring_a_cursor_saved_before_shrinking_stays_on_screen() {
+        let mut screen = Screen::new(24, 80);
+        screen.feed(b"\x1b[20;70H\x1b7");
+        screen.resize(5, 10);
+
+        screen.feed(b"\x1b8x");
+        assert_eq!(screen.cursor(), (4, 9));
+        screen.feed(b"\x1b[?1049h\x1b[?1049l");
+        assert_eq!(screen.cursor(), (4, 9));
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+use macroquad::prelude::*;
+use crate::keymap::Action;
+use crate::theme::Theme;
+use crate::window::{set_camera_window, set_fullscreen_camera};
+
+const FONT_SIZE: u16 = 16;
+const ROW_HEIGHT: f32 = 22.0;
+const INDENT: f32 = 16.0;
+/// How often the expanded directories are reread, to pick up changes made outside the tree
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub enum TreeMessage {
+    Action(Action),
+    Char(char),
+    Click(Vec2),
+    Scroll(Vec2),
+    Refresh,
+}
+
+/// What the app has to do after a message, much like `PromptUpdate`
+pub(crate) enum TreeUpdate {
+    Open(String),
+    /// Asks for a path in the prompt, starting with this text
+    Prompt(String),
+    Status(String),
+}
+
+struct Entry {
+    path: PathBuf,
+    depth: usize,
+    directory: bool,
+}
+
+/// A pane listing the working directory, with directories that can be expanded in place
+pub(crate) struct FileTree {
+    pub(crate) window: Rect,
+    root: PathBuf,
+    expanded: BTreeSet<PathBuf>,
+    entries: Vec<Entry>,
+    selected: usize,
+    /// How far down the list is scrolled, in pixels
+    scroll: f32,
+    /// A path waiting for `y` to delete it
+    deleting: Option<PathBuf>,
+    refreshed: Instant,
+}
+
+impl FileTree {
+    pub(crate) fn new(window: Rect) -> Self {
+        Self::at(window, PathBuf::from("."))
+    }
+
+    /// A tree listing `root` rather than the working directory
+    fn at(window: Rect, root: PathBuf) -> Self {
+        let mut tree = Self {
+            window,
+            root,
+            expanded: BTreeSet::new(),
+            entries: vec![],
+            selected: 0,
+            scroll: 0.0,
+            deleting: None,
+            refreshed: Instant::now(),
+        };
+        tree.refresh();
+
+        tree
+    }
+
+    pub(crate) fn titlebar(&self) -> Rect {
+        let titlebar_height = FONT_SIZE as f32 + 8.0;
+        Rect::new(self.window.x, self.window.y - titlebar_height, self.window.w, titlebar_height)
+    }
+
+    pub(crate) fn poll(&self) -> Option<TreeMessage> {
+        (self.refreshed.elapsed() > REFRESH_INTERVAL).then_some(TreeMessage::Refresh)
+    }
+
+    /// Rereads the expanded directories, keeping the same path selected
+    fn refresh(&mut self) {
+        let selected = self.entries.get(self.selected).map(|entry| entry.path.clone());
+        self.entries.clear();
+        self.expanded.retain(|path| path.is_dir());
+        self.read(&self.root.clone(), 0);
+        self.refreshed = Instant::now();
+
+        if let Some(selected) = selected {
+            self.select(&selected);
+        }
+        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+    }
+
+    /// Lists a directory's entries, directories first, and those of its expanded subdirectories
+    fn read(&mut self, directory: &Path, depth: usize) {
+        let Ok(read) = fs::read_dir(directory) else {
+            return;
+        };
+
+        let mut children = read.flatten()
+            .filter(|entry| entry.file_name() != ".git")
+            .map(|entry| (entry.path(), entry.path().is_dir()))
+            .collect::<Vec<_>>();
+        children.sort_by_key(|(path, directory)| (!directory, path.file_name().map(|name| name.to_string_lossy().to_lowercase())));
+
+        for (path, directory) in children {
+            let expanded = directory && self.expanded.contains(&path);
+            self.entries.push(Entry { path: path.clone(), depth, directory });
+            if expanded {
+                self.read(&path, depth + 1);
+            }
+        }
+    }
+
+    /// Selects a path if it's listed
+    fn select(&mut self, path: &Path) {
+        if let Some(index) = self.entries.iter().position(|entry| entry.path == path) {
+            self.selected = index;
+        }
+    }
+
+    /// How a path is written in the prompt and the status bar, relative to the working directory
+    fn display(path: &Path) -> String {
+        path.strip_prefix(".").unwrap_or(path).to_string_lossy().into_owned()
+    }
+
+    fn visible_rows(&self) -> usize {
+        ((self.window.h / ROW_HEIGHT) as usize).max(1)
+    }
+
+    /// Moves the selection, scrolling to keep it in view
+    fn move_to(&mut self, index: usize) {
+        self.selected = index.min(self.entries.len().saturating_sub(1));
+        let top = self.selected as f32 * ROW_HEIGHT;
+        self.scroll = self.scroll.clamp((top + ROW_HEIGHT - self.window.h).max(0.0), top);
+    }
+
+    /// Opens the selected file, or expands or collapses the selected directory
+    fn activate(&mut self) -> Option<TreeUpdate> {
+        let entry = self.entries.get(self.selected)?;
+        if !entry.directory {
+            return Some(TreeUpdate::Open(Self::display(&entry.path)));
+        }
+
+        let path = entry.path.clone();
+        if !self.expanded.remove(&path) {
+            self.expanded.insert(path);
+        }
+        self.refresh();
+        None
+    }
+
+    pub(crate) fn update(&mut self, message: TreeMessage) -> Option<TreeUpdate> {
+        // While a delete waits for an answer, `y` confirms it and any other key or click keeps the path
+        if let Some(path) = self.deleting.take() {
+            match message {
+                TreeMessage::Char('y') => return Some(TreeUpdate::Status(match self.delete(&path) {
+                    Ok(()) => format!("Deleted {}", Self::display(&path)),
+                    Err(error) => format!("Couldn't delete {}: {error}", Self::display(&path)),
+                })),
+                TreeMessage::Char(character) if character.is_control() => {
+                    self.deleting = Some(path);
+                    return None;
+                }
+                // Scrolling and rereading go on as usual while the question waits
+                TreeMessage::Scroll(_) | TreeMessage::Refresh => self.deleting = Some(path),
+                _ => return Some(TreeUpdate::Status(format!("Kept {}", Self::display(&path)))),
+            }
+        }
+
+        let selected = self.entries.get(self.selected);
+        match message {
+            TreeMessage::Action(Action::MoveUp) => self.move_to(self.selected.saturating_sub(1)),
+            TreeMessage::Action(Action::MoveDown) => self.move_to(self.selected + 1),
+            TreeMessage::Action(Action::PageUp) => self.move_to(self.selected.saturating_sub(self.visible_rows())),
+            TreeMessage::Action(Action::PageDown) => self.move_to(self.selected + self.visible_rows()),
+            TreeMessage::Action(Action::DocumentStart | Action::LineStart) => self.move_to(0),
+            TreeMessage::Action(Action::DocumentEnd | Action::LineEnd) => self.move_to(self.entries.len()),
+            TreeMessage::Action(Action::MoveRight) => match selected {
+                Some(entry) if entry.directory && self.expanded.contains(&entry.path) => self.move_to(self.selected + 1),
+                Some(entry) if entry.directory => return self.activate(),
+                _ => {}
+            },
+            // Collapses the selected directory, or goes up to the one holding the selection
+            TreeMessage::Action(Action::MoveLeft) => match selected {
+                Some(entry) if entry.directory && self.expanded.contains(&entry.path) => return self.activate(),
+                Some(entry) => {
+                    let parent = entry.path.parent().map(Path::to_path_buf);
+                    if let Some(index) = self.entries.iter().position(|entry| Some(&entry.path) == parent.as_ref()) {
+                        self.move_to(index);
+                    }
+                }
+                None => {}
+            },
+            TreeMessage::Action(Action::Newline) => return self.activate(),
+            TreeMessage::Action(Action::DeleteForward) | TreeMessage::Char('d') => return self.ask_delete(),
+            TreeMessage::Char('a') => {
+                let directory = match selected {
+                    Some(entry) if entry.directory => Some(entry.path.as_path()),
+                    Some(entry) => entry.path.parent(),
+                    None => None,
+                };
+                let directory = directory.map(Self::display).filter(|directory| !directory.is_empty());
+                return Some(TreeUpdate::Prompt(match directory {
+                    Some(directory) => format!("new {directory}/"),
+                    None => "new ".to_string(),
+                }));
+            }
+            TreeMessage::Action(Action::Rename) | TreeMessage::Char('r' | 'm') => {
+                return selected.map(|entry| TreeUpdate::Prompt(format!("move {}", Self::display(&entry.path))));
+            }
+            TreeMessage::Action(_) | TreeMessage::Char(_) => {}
+            TreeMessage::Click(position) => {
+                let row = ((position.y - self.window.y + self.scroll) / ROW_HEIGHT) as usize;
+                if self.window.contains(position) && row < self.entries.len() {
+                    self.move_to(row);
+                    return self.activate();
+                }
+            }
+            TreeMessage::Scroll(offset) => {
+                let most = (self.entries.len() as f32 * ROW_HEIGHT - self.window.h).max(0.0);
+                self.scroll = (self.scroll + offset.y).clamp(0.0, most);
+            }
+            TreeMessage::Refresh => self.refresh(),
+        }
+
+        None
+    }
+
+    fn ask_delete(&mut self) -> Option<TreeUpdate> {
+        let entry = self.entries.get(self.selected)?;
+        let question = match entry.directory {
+            true => format!("Delete {} and everything in it? y/n", Self::display(&entry.path)),
+            false => format!("Delete {}? y/n", Self::display(&entry.path)),
+        };
+        self.deleting = Some(entry.path.clone());
+
+        Some(TreeUpdate::Status(question))
+    }
+
+    fn delete(&mut self, path: &Path) -> io::Result<()> {
+        match path.is_dir() {
+            true => fs::remove_dir_all(path)?,
+            false => fs::remove_file(path)?,
+        }
+        self.refresh();
+        Ok(())
+    }
+
+    /// Where a path typed in the prompt is, refusing ones that would lead out of the tree
+    fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(path.trim_end_matches('/'));
+        match relative.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+            true => Ok(self.root.join(relative)),
+            false => Err(io::Error::new(io::ErrorKind::InvalidInput, "it's outside the working directory")),
+        }
+    }
+
+    /// Creates a file, or a directory when the path ends in `/`, along with any missing directories above it
+    pub(crate) fn create(&mut self, path: &str) -> io::Result<PathBuf> {
+        let target = self.resolve(path)?;
+        if target.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "it already exists"));
+        }
+
+        match path.ends_with('/') {
+            true => fs::create_dir_all(&target)?,
+            false => {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::File::create_new(&target)?;
+            }
+        }
+        self.reveal(&target);
+
+        Ok(target)
+    }
+
+    /// Moves or renames the selected entry, refusing to replace anything. Returns where it was and where it is now.
+    pub(crate) fn move_selected(&mut self, path: &str) -> io::Result<(PathBuf, PathBuf)> {
+        let from = self.entries.get(self.selected)
+            .map(|entry| entry.path.clone())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "nothing is selected"))?;
+        let to = self.resolve(path)?;
+        if to.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{path} already exists")));
+        }
+
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&from, &to)?;
+        if self.expanded.remove(&from) {
+            self.expanded.insert(to.clone());
+        }
+        self.reveal(&to);
+
+        Ok((from, to))
+    }
+
+    /// Expands the directories above a path and selects it
+    fn reveal(&mut self, path: &Path) {
+        let mut ancestors = path.ancestors().skip(1);
+        while let Some(ancestor) = ancestors.next().filter(|ancestor| *ancestor != self.root) {
+            self.expanded.insert(ancestor.to_path_buf());
+        }
+        self.refresh();
+        self.select(path);
+        self.move_to(self.selected);
+    }
+
+    /// The color of a file's icon, by its extension
+    fn icon_color(path: &Path, theme: &Theme) -> Color {
+        match path.extension().and_then(|extension| extension.to_str()).unwrap_or_default() {
+            "rs" => theme.peach,
+            "py" | "js" | "mjs" => theme.yellow,
+            "ts" | "tsx" | "c" | "h" | "cpp" | "hpp" => theme.blue,
+            "go" => theme.sapphire,
+            "md" | "txt" => theme.sky,
+            "toml" | "json" | "yaml" | "yml" | "lock" => theme.teal,
+            "html" | "css" => theme.mauve,
+            "sh" | "bash" | "zsh" => theme.green,
+            "png" | "jpg" | "jpeg" | "gif" | "svg" | "ico" => theme.pink,
+            _ => theme.overlay2,
+        }
+    }
+
+    pub(crate) fn view(&self, theme: &Theme, font: Option<&Font>, focused: bool) {
+        set_fullscreen_camera();
+
+        let titlebar = self.titlebar();
+        draw_rectangle(titlebar.x, titlebar.y, titlebar.w, titlebar.h, theme.surface1);
+        let title = fs::canonicalize(&self.root).ok()
+            .and_then(|root| Some(root.file_name()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "files".to_string());
+        draw_text_ex(&title, self.window.x, self.window.y - 4.0, TextParams {
+            color: if focused { theme.lavender } else { theme.text },
+            font,
+            font_size: FONT_SIZE,
+            ..Default::default()
+        });
+
+        draw_rectangle(self.window.x, self.window.y, self.window.w, self.window.h, theme.mantle);
+        set_camera_window(self.window, Vec2::ZERO);
+
+        let first = (self.scroll / ROW_HEIGHT) as usize;
+        for (i, entry) in self.entries.iter().enumerate().skip(first).take(self.visible_rows() + 2) {
+            let top = i as f32 * ROW_HEIGHT - self.scroll;
+            let middle = top + ROW_HEIGHT / 2.0;
+            let x = 8.0 + entry.depth as f32 * INDENT;
+
+            if i == self.selected {
+                let color = match self.deleting.is_some() {
+                    true => Color { a: 0.4, ..theme.red },
+                    false if focused => theme.surface1,
+                    false => theme.surface0,
+                };
+                draw_rectangle(0.0, top, self.window.w, ROW_HEIGHT, color);
+            }
+
+            if entry.directory {
+                let arrow = match self.expanded.contains(&entry.path) {
+                    true => [vec2(x, middle - 3.0), vec2(x + 8.0, middle - 3.0), vec2(x + 4.0, middle + 3.0)],
+                    false => [vec2(x + 2.0, middle - 4.0), vec2(x + 8.0, middle), vec2(x + 2.0, middle + 4.0)],
+                };
+                draw_triangle(arrow[0], arrow[1], arrow[2], theme.overlay1);
+                // A folder: a tab on top of a wider body
+                draw_rectangle(x + 12.0, middle - 6.0, 6.0, 3.0, theme.blue);
+                draw_rectangle(x + 12.0, middle - 4.0, 14.0, 10.0, theme.blue);
+            } else {
+                // A page with its corner folded over
+                let color = Self::icon_color(&entry.path, theme);
+                draw_rectangle(x + 14.0, middle - 7.0, 10.0, 14.0, color);
+                draw_triangle(vec2(x + 20.0, middle - 7.0), vec2(x + 24.0, middle - 7.0), vec2(x + 24.0, middle - 3.0), theme.mantle);
+            }
+
+            let name = entry.path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+            draw_text_ex(&name, x + 32.0, top + ROW_HEIGHT - 6.0, TextParams {
+                color: if entry.directory { theme.text } else { theme.subtext1 },
+                font,
+                font_size: FONT_SIZE,
+                ..Default::default()
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own for a test, removed again when the test ends, even when it fails
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("benchide-tree-{}", uuid::Uuid::new_v4()));
+            fs::create_dir(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn tree(dir: &TempDir) -> FileTree {
+        FileTree::at(Rect::new(0.0, 0.0, 200.0, 400.0), dir.0.clone())
+    }
+
+    fn selected(tree: &FileTree) -> &Path {
+        &tree.entries[tree.selected].path
+    }
+
+    fn status(update: Option<TreeUpdate>) -> String {
+        match update {
+            Some(TreeUpdate::Status(status)) => status,
+            _ => panic!("expected a status"),
+        }
+    }
+
+    #[test]
+    fn creates_files_and_directories_and_selects_them() {
+        let dir = TempDir::new();
+        let mut tree = tree(&dir);
+
+        let file = tree.create("a/b.txt").unwrap();
+        assert_eq!(file, dir.0.join("a/b.txt"));
+        assert!(file.is_file());
+        assert_eq!(selected(&tree), file);
+        assert!(tree.expanded.contains(&dir.0.join("a")));
+
+        assert!(tree.create("c/").unwrap().is_dir());
+        assert_eq!(tree.create("a/b.txt").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn paths_outside_the_root_are_refused() {
+        let dir = TempDir::new();
+        let mut tree = tree(&dir);
+        fs::write(dir.0.join("file"), "").unwrap();
+        tree.refresh();
+
+        let outside = format!("{}-outside", dir.0.display());
+        for path in ["../x", "a/../../x", &outside, "/x/"] {
+            assert_eq!(tree.create(path).unwrap_err().kind(), io::ErrorKind::InvalidInput, "{path}");
+            assert_eq!(tree.move_selected(path).unwrap_err().kind(), io::ErrorKind::InvalidInput, "{path}");
+        }
+        assert!(!Path::new(&outside).exists());
+        assert!(dir.0.join("file").exists());
+    }
+
+    #[test]
+    fn moves_the_selected_entry_without_replacing_anything() {
+        let dir = TempDir::new();
+        let mut tree = tree(&dir);
+        tree.create("a/b.txt").unwrap();
+        tree.create("taken").unwrap();
+
+        tree.select(&dir.0.join("a"));
+        let (from, to) = tree.move_selected("d/e").unwrap();
+        assert_eq!((from, to.clone()), (dir.0.join("a"), dir.0.join("d/e")));
+        assert!(to.join("b.txt").is_file());
+        assert!(!dir.0.join("a").exists());
+        assert_eq!(selected(&tree), to);
+
+        assert_eq!(tree.move_selected("taken").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert!(to.is_dir());
+    }
+
+    #[test]
+    fn deleting_waits_for_y() {
+        let dir = TempDir::new();
+        let mut tree = tree(&dir);
+        let file = tree.create("file.txt").unwrap();
+        let shown = FileTree::display(&file);
+
+        assert_eq!(status(tree.update(TreeMessage::Char('d'))), format!("Delete {shown}? y/n"));
+        assert_eq!(status(tree.update(TreeMessage::Char('n'))), format!("Kept {shown}"));
+        assert!(file.exists());
+
+        tree.update(TreeMessage::Action(Action::DeleteForward));
+        assert!(tree.update(TreeMessage::Char('\u{8}')).is_none());
+        tree.update(TreeMessage::Refresh);
+        assert_eq!(status(tree.update(TreeMessage::Char('y'))), format!("Deleted {shown}"));
+        assert!(!file.exists());
+
+        tree.create("d/inner").unwrap();
+        tree.select(&dir.0.join("d"));
+        assert_eq!(status(tree.update(TreeMessage::Char('d'))), format!("Delete {}/d and everything in it? y/n", dir.0.display()));
+        tree.update(TreeMessage::Char('y'));
+        assert!(!dir.0.join("d").exists());
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::io::ErrorKind;
+use serde::{Deserialize, Serialize};
+
+/// Where `workspace save` and `workspace open` go without a path, and what is opened on startup
+pub(crate) const DEFAULT_PATH: &str = ".benchide-workspace.toml";
+
+/// The layout of the canvas: which files are open where, and the connectors and notes drawn between them
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Workspace {
+    pub(crate) panes: Vec<PaneState>,
+    pub(crate) links: Vec<LinkState>,
+    pub(crate) notes: Vec<NoteState>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PaneState {
+    #[serde(default)]
+    pub(crate) kind: PaneKind,
+    /// The file an editor or image shows
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) file: String,
+    /// Position and size as `[x, y, width, height]`
+    pub(crate) window: [f32; 4],
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PaneKind {
+    #[default]
+    Editor,
+    Image,
+    Terminal,
+    Files,
+}
+
+/// A connector between rows of two panes, which are indices into `panes`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LinkState {
+    pub(crate) from: usize,
+    /// First and last rows, counting from zero
+    pub(crate) from_rows: [usize; 2],
+    pub(crate) to: usize,
+    pub(crate) to_rows: [usize; 2],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NoteState {
+    pub(crate) text: String,
+    /// Top left corner as `[x, y]`
+    pub(crate) position: [f32; 2],
+}
+
+impl Workspace {
+    /// Reads a workspace file, or `None` if there isn't one at `path`
+    pub(crate) fn load(path: &str) -> Result<Option<Self>, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map(Some).map_err(|error| format!("{path}: {}", error.message())),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(format!("{path}: {error}")),
+        }
+    }
+
+    pub(crate) fn save(&self, path: &str) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|error| format!("{path}: {error}"))?;
+        fs::write(path, text).map_err(|error| format!("{path}: {error}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let workspace = Workspace {
+            panes: vec![
+                PaneState { kind: PaneKind::Editor, file: "src/lib.rs".to_string(), window: [20.0, 20.0, 800.0, 600.0] },
+                PaneState { kind: PaneKind::Editor, file: "no/such/file.rs".to_string(), window: [900.0, 20.0, 400.0, 300.0] },
+                PaneState { kind: PaneKind::Image, file: "logo.png".to_string(), window: [-50.5, 700.0, 200.0, 200.0] },
+                PaneState { kind: PaneKind::Terminal, file: String::new(), window: [0.0, 0.0, 640.0, 480.0] },
+                PaneState { kind: PaneKind::Files, file: String::new(), window: [0.0, 500.0, 300.0, 600.0] },
+            ],
+            links: vec![
+                LinkState { from: 0, from_rows: [3, 3], to: 1, to_rows: [10, 12], label: None },
+                LinkState { from: 1, from_rows: [0, 4], to: 0, to_rows: [7, 7], label: Some("calls \"this\"".to_string()) },
+            ],
+            notes: vec![NoteState { text: "first line\nsecond = line".to_string(), position: [100.0, -20.0] }],
+        };
+
+        let path = std::env::temp_dir().join(format!("benchide-workspace-{}.toml", uuid::Uuid::new_v4()));
+        let path = path.to_string_lossy().into_owned();
+        workspace.save(&path).unwrap();
+        let loaded = Workspace::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), Some(workspace));
+        assert_eq!(Workspace::load(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn fills_in_defaults_and_rejects_unknown_fields() {
+        let workspace = toml::from_str::<Workspace>("[[panes]]\nfile = \"a.rs\"\nwindow = [1, 2, 3, 4]").unwrap();
+        assert_eq!(workspace.panes, [PaneState { kind: PaneKind::Editor, file: "a.rs".to_string(), window: [1.0, 2.0, 3.0, 4.0] }]);
+        assert!(workspace.links.is_empty() && workspace.notes.is_empty());
+
+        assert!(toml::from_str::<Workspace>("[[notes]]\ntext = \"x\"\nposition = [0, 0]\ncolor = \"red\"").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Speaks to a stub language server, which is this same binary run with `--stub`
+
+use std::env;
+use std::io::{self, BufReader};
+use std::time::Duration;
+use benchide::lsp::{read_message, write_message, Client};
+use serde_json::{json, Value};
+
+const URI: &str = "file:///project/main.rs";
+
+fn main() {
+    if env::args().any(|arg| arg == "--stub") {
+        stub();
+        return;
+    }
+
+    let mut client = Client::spawn(env::current_exe().unwrap().to_str().unwrap(), &["--stub".to_string()], &env::temp_dir())
+        .expect("stub server should start");
+
+    let id = client.request("initialize", json!({ "capabilities": {} })).unwrap();
+    let response = next(&client);
+    assert_eq!(response["id"], id);
+    assert_eq!(response["result"]["capabilities"]["hoverProvider"], true);
+    client.notify("initialized", json!({})).unwrap();
+
+    client.notify("textDocument/didOpen", json!({
+        "textDocument": { "uri": URI, "languageId": "rust", "version": 0, "text": "fn main() {\n    todo\n}" }
+    })).unwrap();
+    let diagnostics = next(&client);
+    assert_eq!(diagnostics["method"], "textDocument/publishDiagnostics");
+    assert_eq!(diagnostics["params"]["diagnostics"][0]["range"]["start"], json!({ "line": 1, "character": 4 }));
+
+    client.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": URI, "version": 1 },
+        "contentChanges": [{ "text": "fn main() {}" }],
+    })).unwrap();
+    let diagnostics = next(&client);
+    assert_eq!(diagnostics["params"]["diagnostics"], json!([]));
+
+    let id = client.request("textDocument/hover", json!({
+        "textDocument": { "uri": URI },
+        "position": { "line": 0, "character": 3 },
+    })).unwrap();
+    let hover = next(&client);
+    assert_eq!(hover["id"], id);
+    assert_eq!(hover["result"]["contents"]["value"], "main at 0:3");
+
+    let id = client.request("textDocument/rename", json!({
+        "textDocument": { "uri": URI },
+        "position": { "line": 0, "character": 3 },
+        "newName": "start",
+    })).unwrap();
+    let rename = next(&client);
+    assert_eq!(rename["id"], id);
+    assert_eq!(rename["result"]["changes"][URI][0]["newText"], "start");
+
+    let id = client.request("shutdown", Value::Null).unwrap();
+    assert_eq!(next(&client)["id"], id);
+    assert!(client.poll().is_empty());
+
+    println!("lsp: ok");
+}
+
+fn next(client: &Client) -> Value {
+    client.wait(Duration::from_secs(10)).expect("stub server should answer")
+}
+
+/// Reports `todo` as an error wherever it appears, and answers hovers and renames with canned results
+fn stub() {
+    let mut stdin = BufReader::new(io::stdin());
+    let mut stdout = io::stdout();
+
+    while let Ok(message) = read_message(&mut stdin) {
+        let params = &message["params"];
+        let method = message["method"].as_str().unwrap_or_default();
+
+        let result = match method {
+            "initialize" => json!({ "capabilities": { "textDocumentSync": 1, "hoverProvider": true, "renameProvider": true } }),
+            "textDocument/hover" => {
+                let position = &params["position"];
+                json!({ "contents": { "kind": "plaintext", "value": format!("main at {}:{}", position["line"], position["character"]) } })
+            }
+            "textDocument/rename" => json!({ "changes": { URI: [{
+                "range": { "start": { "line": 0, "character": 3 }, "end": { "line": 0, "character": 7 } },
+                "newText": params["newName"],
+            }] } }),
+            "shutdown" => Value::Null,
+            "exit" => return,
+            _ => {
+                let changed = match method {
+                    "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+                    "textDocument/didChange" => params["contentChanges"][0]["text"].as_str(),
+                    _ => None,
+                };
+
+                if let Some(text) = changed {
+                    let diagnostics = text.lines().enumerate()
+                        .flat_map(|(line, content)| content.match_indices("todo").map(move |(character, _)| json!({
+                            "range": {
+                                "start": { "line": line, "character": character },
+                                "end": { "line": line, "character": character + 4 },
+                            },
+                            "severity": 1,
+                            "message": "unfinished code",
+                        })))
+                        .collect::<Vec<_>>();
+
+                    write_message(&mut stdout, &json!({
+                        "jsonrpc": "2.0",
+                        "method": "textDocument/publishDiagnostics",
+                        "params": { "uri": URI, "diagnostics": diagnostics },
+                    })).unwrap();
+                }
+                continue;
+            }
+        };
+
+        write_message(&mut stdout, &json!({ "jsonrpc": "2.0", "id": message["id"], "result": result })).unwrap();
+    }
+}